@@ -0,0 +1,54 @@
+//! Sync scan against the tokio scan of the same tree. Run with
+//! `cargo bench --features tokio --bench async_scan`.
+//!
+//! Both scans read the real file system, so this measures a local disk; there is no file
+//! system layer to inject network latency into. The async scan gains the most where each
+//! `read_dir` waits on the network.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use linkfield::file_cache::{FileCache, ScanConfig};
+use linkfield::ignore_config::IgnoreConfig;
+
+/// 100 directories of 50 files, two levels deep
+fn synthetic_tree() -> tempfile::TempDir {
+	let root = tempfile::tempdir().unwrap();
+	for d in 0..100 {
+		let dir = root.path().join(format!("group_{}/dir_{d}", d % 10));
+		std::fs::create_dir_all(&dir).unwrap();
+		for f in 0..50 {
+			std::fs::write(dir.join(format!("file_{f}.txt")), b"bench").unwrap();
+		}
+	}
+	root
+}
+
+fn bench_sync_vs_async_scan(c: &mut Criterion) {
+	let tree = synthetic_tree();
+	let ignore = IgnoreConfig::empty();
+	let runtime = tokio::runtime::Runtime::new().unwrap();
+	c.bench_function("scan_sync_5k_files", |b| {
+		b.iter(|| FileCache::populate_from_dir(tree.path(), &ignore).file_map());
+	});
+	for permits in [4, 64] {
+		let config = ScanConfig {
+			max_concurrent_dirs: permits,
+			..ScanConfig::default()
+		};
+		c.bench_function(&format!("scan_async_5k_files_{permits}_dirs"), |b| {
+			b.iter(|| {
+				runtime.block_on(FileCache::scan_dir_async_with_config(
+					tree.path(),
+					&ignore,
+					&config,
+				))
+			});
+		});
+	}
+}
+
+criterion_group! {
+	name = async_scan_benches;
+	config = Criterion::default().sample_size(10);
+	targets = bench_sync_vs_async_scan
+}
+criterion_main!(async_scan_benches);
@@ -0,0 +1,195 @@
+//! Criterion benchmarks for the core cache, heuristics and redb operations.
+//!
+//! Run with `cargo bench`; `cargo bench --no-run` just checks they still compile.
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use linkfield::file_cache::db::{
+	DEFAULT_LOAD_BATCH_SIZE, ensure_file_cache_table, update_redb_batch_commit,
+};
+use linkfield::file_cache::diff::diff_file_maps;
+use linkfield::file_cache::meta::{FileCachePath, FileType};
+use linkfield::file_cache::{FileCache, FileMeta};
+use linkfield::ignore_config::IgnoreConfig;
+use linkfield::move_heuristics::{FileEventKind, make_file_event, score_pair};
+use rand::Rng;
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+fn synthetic_meta(i: usize) -> FileMeta {
+	let path = PathBuf::from(format!("root/dir_{}/file_{i}.txt", i % 100));
+	FileMeta {
+		path: FileCachePath(path),
+		size: i as u64 * 31,
+		modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(i as u64)),
+		created: None,
+		extension: Some("txt".to_string()),
+		inode: None,
+		content_hash: None,
+		is_virtual: false,
+		file_type: FileType::Regular,
+		device: None,
+	}
+}
+
+fn synthetic_map(n: usize) -> HashMap<FileCachePath, FileMeta> {
+	(0..n)
+		.map(synthetic_meta)
+		.map(|m| (m.path.clone(), m))
+		.collect()
+}
+
+fn temp_db(dir: &tempfile::TempDir) -> redb::Database {
+	let db = redb::Database::create(dir.path().join("bench.redb")).unwrap();
+	ensure_file_cache_table(&db).unwrap();
+	db
+}
+
+fn bench_scan_dir(c: &mut Criterion) {
+	let files = tempfile::tempdir().unwrap();
+	for i in 0..10_000 {
+		std::fs::write(files.path().join(format!("file_{i}.txt")), b"bench").unwrap();
+	}
+	let db_dir = tempfile::tempdir().unwrap();
+	let db = temp_db(&db_dir);
+	let ignore = IgnoreConfig::empty();
+	c.bench_function("scan_dir_10k_files", |b| {
+		b.iter(|| {
+			let cache = FileCache::new_root("files");
+			cache.scan_dir_collect_with_ignore_and_commit(
+				&db,
+				files.path(),
+				&ignore,
+				None,
+				1000,
+				None,
+			);
+		});
+	});
+}
+
+fn bench_diff_and_update(c: &mut Criterion) {
+	let old = synthetic_map(10_000);
+	let mut new = old.clone();
+	// 1% change rate: a third each added, removed and modified
+	for i in 0..33 {
+		new.remove(&synthetic_meta(i).path);
+		let added = synthetic_meta(20_000 + i);
+		new.insert(added.path.clone(), added);
+		let mut modified = synthetic_meta(5_000 + i);
+		modified.size += 1;
+		new.insert(modified.path.clone(), modified);
+	}
+	c.bench_function("diff_file_maps_10k_1pct", |b| {
+		b.iter(|| diff_file_maps(black_box(&old), black_box(&new)));
+	});
+	c.bench_function("diff_and_update_10k_1pct", |b| {
+		b.iter_batched(
+			|| {
+				let cache = FileCache::new_root("root");
+				for meta in old.values() {
+					cache.insert_meta(meta.clone());
+				}
+				cache
+			},
+			|cache| cache.diff_and_update(black_box(&new), None),
+			BatchSize::LargeInput,
+		);
+	});
+}
+
+fn bench_score_pair(c: &mut Criterion) {
+	let mut rng = rand::rng();
+	let pairs: Vec<_> = (0..10_000)
+		.map(|i| {
+			let mut remove = synthetic_meta(i);
+			remove.size = rng.random_range(0..4096);
+			let mut create = synthetic_meta(rng.random_range(0..10_000));
+			create.size = rng.random_range(0..4096);
+			(
+				make_file_event(remove.path.0.clone(), FileEventKind::Remove, Some(remove)),
+				make_file_event(create.path.0.clone(), FileEventKind::Create, Some(create)),
+			)
+		})
+		.collect();
+	c.bench_function("score_pair_10k", |b| {
+		b.iter(|| {
+			pairs
+				.iter()
+				.map(|(r, c)| score_pair(black_box(r), black_box(c)))
+				.sum::<f64>()
+		});
+	});
+}
+
+fn bench_filemeta_serialize(c: &mut Criterion) {
+	let metas: Vec<_> = (0..1000).map(synthetic_meta).collect();
+	c.bench_function("filemeta_serialize_1000", |b| {
+		b.iter(|| {
+			for meta in &metas {
+				black_box(black_box(meta).serialize().unwrap());
+			}
+		});
+	});
+}
+
+fn bench_filemeta_deserialize(c: &mut Criterion) {
+	let encoded: Vec<_> = (0..1000)
+		.map(|i| synthetic_meta(i).serialize().unwrap())
+		.collect();
+	c.bench_function("filemeta_deserialize_1000", |b| {
+		b.iter(|| {
+			for bytes in &encoded {
+				black_box(FileMeta::deserialize(black_box(bytes)).unwrap());
+			}
+		});
+	});
+}
+
+fn bench_load_from_redb(c: &mut Criterion) {
+	let db_dir = tempfile::tempdir().unwrap();
+	let db = temp_db(&db_dir);
+	let entries: Vec<_> = synthetic_map(10_000).into_iter().collect();
+	update_redb_batch_commit(&db, &[], &entries).unwrap();
+	c.bench_function("load_from_redb_10k", |b| {
+		b.iter(|| {
+			let cache = FileCache::new_root("root");
+			cache
+				.load_from_redb_batched(&db, DEFAULT_LOAD_BATCH_SIZE, None)
+				.unwrap()
+		});
+	});
+}
+
+fn bench_redb_batch_commit(c: &mut Criterion) {
+	let db_dir = tempfile::tempdir().unwrap();
+	let db = temp_db(&db_dir);
+	let entries: Vec<_> = synthetic_map(1000).into_iter().collect();
+	c.bench_function("redb_batch_commit_1000", |b| {
+		b.iter(|| update_redb_batch_commit(&db, &[], black_box(&entries)).unwrap());
+	});
+}
+
+criterion_group! {
+	name = scan_benches;
+	config = Criterion::default().sample_size(10);
+	targets = bench_scan_dir, bench_diff_and_update
+}
+criterion_group!(heuristics_benches, bench_score_pair);
+criterion_group!(
+	serialization_benches,
+	bench_filemeta_serialize,
+	bench_filemeta_deserialize
+);
+criterion_group! {
+	name = redb_benches;
+	config = Criterion::default().sample_size(10);
+	targets = bench_load_from_redb, bench_redb_batch_commit
+}
+criterion_main!(
+	scan_benches,
+	heuristics_benches,
+	serialization_benches,
+	redb_benches
+);
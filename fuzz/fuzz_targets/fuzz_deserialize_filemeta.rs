@@ -0,0 +1,14 @@
+//! `FileMeta::deserialize` must never panic or abort on malformed input; it returns an error
+//! instead.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use linkfield::file_cache::FileMeta;
+
+fuzz_target!(|data: &[u8]| {
+	// Anything that did decode must re-encode and decode to the same value
+	if let Ok(meta) = FileMeta::deserialize(data) {
+		let bytes = meta.serialize().expect("decoded FileMeta must re-encode");
+		assert_eq!(FileMeta::deserialize(&bytes).ok(), Some(meta));
+	}
+});
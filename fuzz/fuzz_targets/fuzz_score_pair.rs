@@ -0,0 +1,52 @@
+//! `score_pair` must return a finite score in `[0.0, 1.0]` for any pair of events.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use linkfield::file_cache::FileMeta;
+use linkfield::file_cache::meta::{FileCachePath, FileType};
+use linkfield::move_heuristics::{FileEventKind, make_file_event, score_pair};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+#[derive(Arbitrary, Debug)]
+struct EventInput {
+	path: String,
+	meta: Option<MetaInput>,
+}
+
+#[derive(Arbitrary, Debug)]
+struct MetaInput {
+	size: u64,
+	modified_nanos: Option<u64>,
+	extension: Option<String>,
+}
+
+impl EventInput {
+	fn into_event(self, kind: FileEventKind) -> linkfield::move_heuristics::FileEvent {
+		let path = PathBuf::from(self.path);
+		let meta = self.meta.map(|m| FileMeta {
+			path: FileCachePath(path.clone()),
+			size: m.size,
+			modified: m
+				.modified_nanos
+				.map(|n| SystemTime::UNIX_EPOCH + Duration::from_nanos(n)),
+			created: None,
+			extension: m.extension,
+			inode: None,
+			content_hash: None,
+			is_virtual: false,
+			file_type: FileType::Regular,
+			device: None,
+		});
+		make_file_event(path, kind, meta)
+	}
+}
+
+fuzz_target!(|input: (EventInput, EventInput)| {
+	let remove = input.0.into_event(FileEventKind::Remove);
+	let create = input.1.into_event(FileEventKind::Create);
+	let score = score_pair(&remove, &create);
+	assert!(score.is_finite(), "score is not finite: {score}");
+	assert!((0.0..=1.0).contains(&score), "score out of range: {score}");
+});
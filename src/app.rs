@@ -1,107 +1,653 @@
 use std::io::Write;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
 
 use linkfield::args;
+use linkfield::change_journal::ChangeJournal;
 use linkfield::db;
-use linkfield::file_cache::FileCache;
+use linkfield::file_cache::db::{DEFAULT_LOAD_BATCH_SIZE, FILE_CACHE_TABLE};
+use linkfield::file_cache::stats::DirCountThreshold;
+use linkfield::file_cache::{FileCache, ScanConfig, ScanError};
+use linkfield::health::{self, AppState, AppStateTracker};
 use linkfield::ignore_config::IgnoreConfig;
-use linkfield::move_heuristics::MoveHeuristics;
+use linkfield::metrics;
+use linkfield::move_heuristics::{MoveHeuristics, MoveHeuristicsConfig};
+use linkfield::persisted_config::PersistedConfig;
 use linkfield::platform;
+use linkfield::shutdown::{self, ShutdownResult};
 use linkfield::watcher;
+use redb::ReadableTableMetadata;
 use tracing::{info, info_span};
 
-pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+use crate::commands;
+
+/// Ignore patterns read from the working directory, reloaded by the watcher when it changes
+const IGNORE_FILE: &str = linkfield::ignore_config::IGNORE_FILE_NAME;
+
+pub fn run(cli: &args::Cli) -> Result<(), Box<dyn std::error::Error>> {
 	let startup_span = info_span!("app_startup");
 	let _startup_enter = startup_span.enter();
 	platform::handle_platform_startup();
 	info!("Starting linkfield");
 	std::io::stdout().flush()?;
-	let (db_path_buf, watch_root_buf) = args::parse_args();
-	let db_path = db_path_buf.as_path();
-	let watch_root = watch_root_buf.as_path();
+	let args = args::ParsedArgs::from(cli);
+	args::validate_args(&args).map_err(InvalidArgs)?;
+	#[cfg(not(feature = "archives"))]
+	if args.index_archives {
+		tracing::warn!(
+			"--index-archives is ignored, linkfield was built without the archives feature"
+		);
+	}
+	let db_path = args.db_path();
+	let watch_root = args.watch_root();
 	info!(db_path = %db_path.display(), watch_root = %watch_root.display(), "Parsed arguments");
+	log_startup_diagnostics(args.verbose, db_path);
 	std::io::stdout().flush()?;
-	let mut db = {
-		let db_span = info_span!("open_or_create_db");
-		let _db_enter = db_span.enter();
-		db::open_or_create_db(db_path)?
-	};
-	info!("Opened/created redb file");
-	std::io::stdout().flush()?;
-	info!("Ensuring file_cache table exists...");
-	std::io::stdout().flush()?;
-	linkfield::file_cache::ensure_file_cache_table(&db)?;
-	info!("file_cache table ready");
+	let app_state = AppStateTracker::new(Some(health::state_file_path(db_path)));
+	migrate_legacy_database(db_path, watch_root);
+	let db = open_database(db_path)?;
+	let config = load_watch_config(&db, &args);
+	apply_scan_threads(config.scan_threads);
 	std::io::stdout().flush()?;
 	// Use FileCache::new_root with the root dir name
 	let file_cache = FileCache::new_root(watch_root.to_string_lossy().as_ref());
+	file_cache.set_extension_normalizer(cli.extension_normalizer().map(Arc::new));
+	let scan_cancel = file_cache.scan_cancellation_token();
 	let file_cache = Arc::new(Mutex::new(file_cache));
-	let heuristics = Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5))));
+	let heuristics = Arc::new(Mutex::new(restore_heuristics(
+		&db,
+		MoveHeuristicsConfig {
+			use_minhash: args.use_minhash,
+			..config.heuristics_config()
+		},
+	)));
 	info!("Created FileCache and Heuristics");
 	std::io::stdout().flush()?;
-	// Load ignore config from .linkfieldignore and log patterns
-	let (ignore_config, _ignore_patterns) =
-		match IgnoreConfig::from_file_with_patterns(".linkfieldignore") {
-			Ok((cfg, pats)) => {
-				info!(ignore_patterns = ?pats, "Loaded ignore patterns from .linkfieldignore");
-				(cfg, pats)
-			}
-			Err(e) => {
-				tracing::warn!(error = %e, "Failed to load .linkfieldignore, ignoring patterns");
-				(IgnoreConfig::empty(), vec![])
-			}
-		};
-	let ignore_config = Arc::new(ignore_config);
+	let ignore_config = Arc::new(RwLock::new(ignore_config_with_patterns(
+		&config.ignore_patterns,
+	)));
+	// Everything it holds has been applied, so keep it for the next start
+	if let Err(e) = config.save(&db) {
+		tracing::warn!(error = %e, "Failed to save watcher config");
+	}
 	// Start watcher and cache scan in parallel
 	info!("About to start watcher and cache scan in parallel");
 	std::io::stdout().flush()?;
-	let file_cache_clone = file_cache.clone();
-	let heuristics_clone = heuristics;
-	let watch_root_buf_clone = watch_root_buf.clone();
-	let ignore_config_clone = ignore_config.clone();
-	let watcher_handle = std::thread::spawn(move || {
+	let journal = Arc::new(ChangeJournal::new());
+	let (watcher_registered, registered) = std::sync::mpsc::channel();
+	let watcher_start = spawn_watcher(
+		&args,
+		file_cache.clone(),
+		heuristics.clone(),
+		ignore_config.clone(),
+		journal.clone(),
+		app_state.clone(),
+		watcher_registered,
+	);
+	let initial_scan = InitialScan {
+		file_cache: file_cache.clone(),
+		watch_root: watch_root.to_path_buf(),
+		ignore_config,
+		app_state: app_state.clone(),
+		scan_config: initial_scan_config(cli, &db, &app_state),
+		thresholds: cli.alert_dir_count_threshold.clone(),
+		no_scan: args.no_scan,
+		batch_size: args.batch_size,
+		db_path: db_path.to_path_buf(),
+		staleness_threshold: args.staleness_threshold,
+		rescan_interval: args.rescan_interval,
+		#[cfg(feature = "archives")]
+		archives: archive_watcher(&args),
+		watcher_registered: registered,
+	};
+	let scan_handle = std::thread::spawn(move || initial_scan.run(db));
+	start_metrics(args.metrics_port, &app_state, &file_cache);
+	let watcher = watcher_start
+		.join()
+		.map_err(|_| "watcher startup thread panicked")?;
+	if watcher.is_finished() {
+		app_state.set(AppState::Degraded {
+			reason: "file watcher failed to start".to_string(),
+		});
+	}
+	platform::wait_for_exit();
+	app_state.set(AppState::ShuttingDown);
+	let timeout = Duration::from_secs(cli.shutdown_timeout_secs);
+	let (result, scan_finished) = shutdown::shutdown(watcher, scan_handle, &scan_cancel, timeout);
+	report_shutdown(result, cli.shutdown_timeout_secs);
+	if let Some(db) = scan_finished.and_then(|()| detach_db(&file_cache)) {
+		save_heuristics(&db, &heuristics);
+		save_journal(&db, &journal);
+	} else {
+		tracing::warn!(
+			"Database not released by the scan or watcher, move heuristics and journal not saved"
+		);
+	}
+	Ok(())
+}
+
+/// With `--metrics-port`, serve the app state and the cache age over HTTP
+fn start_metrics(
+	port: Option<u16>,
+	app_state: &AppStateTracker,
+	file_cache: &Arc<Mutex<Arc<FileCache>>>,
+) {
+	let Some(port) = port else {
+		return;
+	};
+	let file_cache = Arc::clone(file_cache);
+	let cache_age = Box::new(move || {
+		file_cache
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.cache_age()
+	});
+	if let Err(e) = metrics::serve(port, app_state.clone(), cache_age) {
+		tracing::warn!(port, error = %e, "Failed to start the metrics listener");
+	}
+}
+
+/// Take the database back from the cache once the scan and watcher are done with it
+fn detach_db(file_cache: &Mutex<Arc<FileCache>>) -> Option<redb::Database> {
+	file_cache
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.detach_db()
+}
+
+/// `watch --once`: scan the watch root, write the summary to `output` or stdout and exit.
+/// The database is only opened, and updated with the scan, when `persist` is set.
+pub fn run_once(
+	cli: &args::Cli,
+	output: Option<&Path>,
+	persist: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let (db_path, watch_root) = cli.paths();
+	let start = Instant::now();
+	let cache = commands::scan_without_db(cli, &db_path, &watch_root);
+	let summary = cache.summary(start.elapsed());
+	if persist {
+		let db = open_database(&db_path)?;
+		let stored = FileCache::new_root(watch_root.to_string_lossy().as_ref());
+		stored.load_from_redb_batched(&db, DEFAULT_LOAD_BATCH_SIZE, None)?;
+		let diff = stored.diff_and_update(&cache.file_map(), Some(&db));
+		info!(changes = diff.len(), "Stored the scan in the database");
+	}
+	let Some(text) = summary.render(cli.output_format) else {
+		return Ok(());
+	};
+	if let Some(path) = output {
+		std::fs::write(path, format!("{text}\n"))?;
+	} else {
+		let mut stdout = std::io::stdout().lock();
+		writeln!(stdout, "{text}")?;
+		stdout.flush()?;
+	}
+	Ok(())
+}
+
+/// Start the watcher on its own thread, reloading the ignore patterns when [`IGNORE_FILE`]
+/// changes
+fn spawn_watcher(
+	args: &args::ParsedArgs,
+	file_cache: Arc<Mutex<Arc<FileCache>>>,
+	heuristics: Arc<Mutex<MoveHeuristics>>,
+	ignore_config: Arc<RwLock<IgnoreConfig>>,
+	journal: Arc<ChangeJournal>,
+	app_state: AppStateTracker,
+	registered: std::sync::mpsc::Sender<()>,
+) -> std::thread::JoinHandle<watcher::WatcherHandle> {
+	let watch_root = args.watch_root.clone();
+	let watch_config = watcher::WatchConfig {
+		ignore_file: std::path::absolute(IGNORE_FILE).ok(),
+		hooks: args.hooks.clone(),
+		journal: Some(journal),
+		app_state: Some(app_state),
+		rescan_interval: args.rescan_interval,
+		#[cfg(feature = "archives")]
+		archives: archive_watcher(args),
+		..watcher::WatchConfig::default()
+	};
+	std::thread::spawn(move || {
 		let watcher_span = info_span!("start_watcher");
 		let _watcher_enter = watcher_span.enter();
-		watcher::start_watcher(
-			&watch_root_buf_clone,
-			file_cache_clone,
-			heuristics_clone,
-			ignore_config_clone,
+		let handle = watcher::start_watcher_with_config(
+			&watch_root,
+			file_cache,
+			heuristics,
+			ignore_config,
+			watch_config,
 		);
 		info!("Started watcher");
-	});
-	let file_cache_bg = file_cache;
-	let watch_root_bg = watch_root.to_path_buf();
-	let ignore_config_bg = ignore_config;
-	let scan_handle = std::thread::spawn(move || {
-		if let Ok(cache) = file_cache_bg.lock() {
-			let scan_span = info_span!("scan_dir");
-			let _scan_enter = scan_span.enter();
-			cache.scan_dir_collect_with_ignore_and_commit(
-				&db,
-				&watch_root_bg,
-				&ignore_config_bg,
-				None,
-				1000,
-				None, // No batch callback in production
-			);
+		if !handle.is_finished() {
+			// The scan thread may already be gone, e.g. after a failed scan
+			let _ = registered.send(());
+		}
+		handle
+	})
+}
+
+/// The archive indexing asked for with `--index-archives`
+#[cfg(feature = "archives")]
+fn archive_watcher(args: &args::ParsedArgs) -> Option<linkfield::file_cache::ArchiveWatcher> {
+	args.index_archives
+		.then(linkfield::file_cache::ArchiveWatcher::default)
+}
+
+/// The initial scan, run on its own thread alongside the watcher startup
+struct InitialScan {
+	file_cache: Arc<Mutex<Arc<FileCache>>>,
+	watch_root: std::path::PathBuf,
+	ignore_config: Arc<RwLock<IgnoreConfig>>,
+	app_state: AppStateTracker,
+	scan_config: ScanConfig,
+	thresholds: Vec<DirCountThreshold>,
+	no_scan: bool,
+	batch_size: usize,
+	db_path: std::path::PathBuf,
+	staleness_threshold: f64,
+	/// See `--rescan-interval`
+	rescan_interval: Option<Duration>,
+	/// See `--index-archives`; the watcher keeps the archives current after the scan
+	#[cfg(feature = "archives")]
+	archives: Option<linkfield::file_cache::ArchiveWatcher>,
+	/// Receives once the watcher has registered its watches; closed when it failed to start
+	watcher_registered: std::sync::mpsc::Receiver<()>,
+}
+
+impl InitialScan {
+	/// Scan the watch root unless skipped, then attach the database to the cache for the
+	/// watcher's updates and rescan every `rescan_interval` until shutdown
+	fn run(self, mut db: redb::Database) {
+		self.scan_once(&mut db);
+		let cache = Arc::clone(
+			&self
+				.file_cache
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner),
+		);
+		// Also stores the files the watcher cached during the scan
+		if let Err(e) = cache.set_db(db) {
+			tracing::error!(error = %e, "Failed to attach the database to the cache");
+			return;
+		}
+		#[cfg(feature = "archives")]
+		if let Some(archives) = &self.archives {
 			info!(
-				file_count = cache.all_files().len(),
-				"After scan_dir (background)"
+				indexed = archives.index_cached(&cache),
+				"Indexed the archives found by the scan"
 			);
-			// Optionally compact the database after scan
-			match db::compact_database(&mut db) {
-				Ok(true) => info!("Database compaction performed"),
-				Ok(false) => info!("Database compaction not needed"),
-				Err(e) => tracing::warn!(error = %e, "Database compaction failed"),
+		}
+		self.rescan_periodically(&cache);
+	}
+
+	/// Scan the watch root unless skipped, mark the app ready once the watcher is also
+	/// registered, then retry unreadable directories and compact
+	fn scan_once(&self, db: &mut redb::Database) {
+		if self.no_scan {
+			info!("Skipping initial scan (--no-scan)");
+			self.mark_ready();
+			return;
+		}
+		if stored_cache_is_fresh(
+			db,
+			&self.db_path,
+			&self.watch_root,
+			self.staleness_threshold,
+		) {
+			self.mark_ready();
+			return;
+		}
+		self.app_state.set(AppState::Scanning { progress: 0.0 });
+		let ignore_config = self
+			.ignore_config
+			.read()
+			.unwrap_or_else(PoisonError::into_inner);
+		let Some((cache, errors)) = scan(
+			&self.file_cache,
+			db,
+			&self.watch_root,
+			&ignore_config,
+			self.batch_size,
+			&self.scan_config,
+		) else {
+			return;
+		};
+		check_dir_counts(db, &self.watch_root, &self.thresholds);
+		// Retries back off for seconds, so they run after the app is reported ready
+		self.mark_ready();
+		cache.retry_scan_errors(Some(db), &ignore_config, errors, &self.scan_config);
+		drop(ignore_config);
+		compact(&cache, db);
+	}
+
+	/// With `--rescan-interval`, bring the stored files in line with the disk every
+	/// interval, picking up changes the watcher missed, until shutdown
+	fn rescan_periodically(&self, cache: &FileCache) {
+		let Some(interval) = self.rescan_interval else {
+			return;
+		};
+		let cancel = cache.scan_cancellation_token();
+		while cancel.sleep(interval) {
+			let Some(db) = cache.database() else {
+				return;
+			};
+			let ignore_config = self
+				.ignore_config
+				.read()
+				.unwrap_or_else(PoisonError::into_inner);
+			match cache.rescan_into_db(&db, &self.watch_root, &ignore_config) {
+				Ok(diff) => info!(changes = diff.len(), "Rescanned the watch root"),
+				Err(e) => tracing::warn!(error = %e, "Periodic rescan failed"),
 			}
-		} else {
-			tracing::error!("failed to lock file_cache for background scan");
 		}
-	});
-	watcher_handle.join().ok();
-	scan_handle.join().ok();
-	platform::wait_for_exit();
-	Ok(())
+	}
+
+	/// Move to `Ready` once the watcher has registered; a watcher that failed to start is
+	/// reported as degraded instead
+	fn mark_ready(&self) {
+		if self.watcher_registered.recv().is_ok() {
+			self.app_state.mark_ready();
+		}
+	}
+}
+
+/// Log system information at debug level, or info with `--verbose`
+fn log_startup_diagnostics(verbose: bool, db_path: &Path) {
+	let diagnostics = platform::startup_diagnostics(db_path);
+	if verbose {
+		info!(?diagnostics, "Startup diagnostics");
+	} else {
+		tracing::debug!(?diagnostics, "Startup diagnostics");
+	}
+}
+
+/// Every problem [`args::validate_args`] found; `main` prints them and exits with status 2,
+/// like clap does for usage errors
+#[derive(Debug)]
+pub struct InvalidArgs(pub Vec<args::ValidationError>);
+
+impl std::fmt::Display for InvalidArgs {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "linkfield can't start:")?;
+		for error in &self.0 {
+			write!(f, "\n  - {error}")?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for InvalidArgs {}
+
+/// Copy a database left in the working directory by an earlier version to `db_path`. Such
+/// a database holds the files under the working directory, so this is only done when that
+/// is the watch root.
+fn migrate_legacy_database(db_path: &Path, watch_root: &Path) {
+	let watching_cwd = std::env::current_dir()
+		.and_then(|cwd| cwd.canonicalize())
+		.is_ok_and(|cwd| watch_root.canonicalize().is_ok_and(|root| root == cwd));
+	if !watching_cwd {
+		return;
+	}
+	if let Err(e) = db::migrate_legacy_database(db_path) {
+		tracing::warn!(error = %e, "Failed to migrate the legacy database");
+	}
+}
+
+/// Open or create the database, make sure the tables exist and apply pending migrations
+fn open_database(db_path: &Path) -> Result<redb::Database, Box<dyn std::error::Error>> {
+	let db = {
+		let db_span = info_span!("open_or_create_db");
+		let _db_enter = db_span.enter();
+		db::open_and_migrate(db_path)?
+	};
+	info!("Opened/created redb file");
+	std::io::stdout().flush()?;
+	info!("Ensuring file_cache table exists...");
+	std::io::stdout().flush()?;
+	linkfield::file_cache::ensure_file_cache_table(&db)?;
+	info!("file_cache table ready");
+	Ok(db)
+}
+
+/// Scan settings for the initial scan. Progress is reported to the app state as the
+/// fraction of the files the database tells to expect, or 0.0 without any stored.
+fn initial_scan_config(
+	cli: &args::Cli,
+	db: &redb::Database,
+	app_state: &AppStateTracker,
+) -> ScanConfig {
+	let progress_total = db
+		.begin_read()
+		.ok()
+		.and_then(|txn| txn.open_table(FILE_CACHE_TABLE).ok()?.len().ok())
+		.filter(|&count| count > 0);
+	let app_state = app_state.clone();
+	ScanConfig {
+		output_format: cli.output_format,
+		progress_total,
+		extension_normalizer: cli.extension_normalizer().map(Arc::new),
+		on_progress: Some(Arc::new(move |progress| {
+			#[allow(clippy::cast_precision_loss)]
+			let fraction = progress_total.map_or(0.0, |total| {
+				(progress.files_scanned as f64 / total as f64).min(1.0)
+			});
+			app_state.set_scan_progress(fraction);
+		})),
+		..ScanConfig::default()
+	}
+}
+
+/// Scan the watch root into `db`. Returns the cache and the directories that could not be
+/// read, or `None` when the scan was cancelled or the cache is unusable.
+fn scan(
+	file_cache: &Mutex<Arc<FileCache>>,
+	db: &redb::Database,
+	watch_root: &Path,
+	ignore_config: &IgnoreConfig,
+	batch_size: usize,
+	scan_config: &ScanConfig,
+) -> Option<(Arc<FileCache>, Vec<ScanError>)> {
+	let Ok(guard) = file_cache.lock() else {
+		tracing::error!("failed to lock file_cache for background scan");
+		return None;
+	};
+	let cache = Arc::clone(&guard);
+	let scan_span = info_span!("scan_dir");
+	let _scan_enter = scan_span.enter();
+	let errors = cache.scan_dir_with_config_and_commit(
+		db,
+		watch_root,
+		ignore_config,
+		batch_size,
+		scan_config,
+	);
+	// Let the watcher update the cache while we wait between retries
+	drop(guard);
+	if cache.scan_cancellation_token().is_cancelled() {
+		info!("Scan cancelled");
+		return None;
+	}
+	Some((cache, errors))
+}
+
+/// Compact the database after the scan, unless the scan was cancelled
+fn compact(cache: &FileCache, db: &mut redb::Database) {
+	if cache.scan_cancellation_token().is_cancelled() {
+		info!("Scan cancelled, skipping database compaction");
+		return;
+	}
+	match db::compact_database(db) {
+		Ok(true) => info!("Database compaction performed"),
+		Ok(false) => info!("Database compaction not needed"),
+		Err(e) => tracing::warn!(error = %e, "Database compaction failed"),
+	}
+}
+
+/// Files checked against the disk to decide whether the initial scan can be skipped
+const STALENESS_SAMPLE_SIZE: usize = 100;
+
+/// Whether the stored files match the disk closely enough, judged from a sample, to skip
+/// the initial scan
+fn stored_cache_is_fresh(
+	db: &redb::Database,
+	db_path: &Path,
+	watch_root: &Path,
+	threshold: f64,
+) -> bool {
+	let cache = FileCache::new_root(watch_root.to_string_lossy().as_ref());
+	if let Err(e) = cache.load_from_redb_batched(db, DEFAULT_LOAD_BATCH_SIZE, None) {
+		tracing::warn!(error = %e, "Failed to load stored files, scanning");
+		return false;
+	}
+	// Our own files change on every run, so leave them out of the sample
+	cache.remove_prefix(None, db_path);
+	cache.remove_prefix(None, &health::state_file_path(db_path));
+	let estimate = cache.estimate_staleness(STALENESS_SAMPLE_SIZE);
+	let fresh = estimate.staleness_ratio < threshold;
+	info!(
+		sample_size = estimate.sample_size,
+		mismatches = estimate.mismatches,
+		staleness_ratio = estimate.staleness_ratio,
+		threshold,
+		"Estimated staleness of the stored files"
+	);
+	if fresh {
+		info!("Stored files are fresh, skipping initial scan");
+	}
+	fresh
+}
+
+/// Warn about directories above their `--alert-dir-count-threshold`. The scan drops files
+/// from memory once committed, so the counts come from the database.
+fn check_dir_counts(db: &redb::Database, watch_root: &Path, thresholds: &[DirCountThreshold]) {
+	if thresholds.is_empty() {
+		return;
+	}
+	let cache = FileCache::new_root(watch_root.to_string_lossy().as_ref());
+	match cache.load_from_redb_batched(db, DEFAULT_LOAD_BATCH_SIZE, None) {
+		Ok(_) => {
+			cache.check_dir_count_thresholds(thresholds);
+		}
+		Err(e) => tracing::warn!(error = %e, "Failed to load files for directory count alerts"),
+	}
+}
+
+/// Stored watcher settings with the command line applied on top
+fn load_watch_config(db: &redb::Database, args: &args::ParsedArgs) -> PersistedConfig {
+	let mut config =
+		load_persisted_config(db).merged_with_cli(args.watch_root.clone(), &args.ignore_patterns);
+	if let Some(threshold) = args.move_threshold {
+		config.move_threshold = threshold;
+	}
+	config
+}
+
+fn report_shutdown(result: ShutdownResult, timeout_secs: u64) {
+	match result {
+		ShutdownResult::Clean => info!(?result, "Shutdown complete"),
+		ShutdownResult::TimedOut { .. } => {
+			tracing::warn!(?result, timeout_secs, "Shutdown timed out");
+		}
+	}
+}
+
+/// Stored watcher settings, or the defaults when none were saved or they can't be read
+fn load_persisted_config(db: &redb::Database) -> PersistedConfig {
+	match PersistedConfig::load(db) {
+		Ok(Some(config)) => {
+			info!(config = ?config, "Loaded persisted watcher config");
+			config
+		}
+		Ok(None) => PersistedConfig::default(),
+		Err(e) => {
+			tracing::warn!(error = %e, "Failed to load persisted watcher config, using defaults");
+			PersistedConfig::default()
+		}
+	}
+}
+
+fn apply_scan_threads(scan_threads: Option<usize>) {
+	let Some(threads) = scan_threads else {
+		return;
+	};
+	match rayon::ThreadPoolBuilder::new()
+		.num_threads(threads)
+		.build_global()
+	{
+		Ok(()) => info!(threads, "Configured scan thread pool"),
+		Err(e) => tracing::warn!(error = %e, "Failed to configure scan thread pool"),
+	}
+}
+
+/// Load ignore config from .linkfieldignore, then add the `--ignore` patterns on top
+pub fn load_ignore_config(cli: &args::Cli) -> IgnoreConfig {
+	ignore_config_with_patterns(&cli.ignore)
+}
+
+/// Load ignore config from the .linkfieldignore files in the working directory and its
+/// parents, then add `patterns` on top
+fn ignore_config_with_patterns(patterns: &[String]) -> IgnoreConfig {
+	let loaded = std::env::current_dir()
+		.map_err(Into::into)
+		.and_then(|cwd| IgnoreConfig::from_dir_hierarchy(&cwd));
+	let mut ignore_config = match loaded {
+		Ok(cfg) => {
+			info!(ignore_patterns = ?cfg.patterns(), "Loaded ignore patterns from .linkfieldignore");
+			cfg
+		}
+		Err(e) => {
+			tracing::warn!(error = %e, "Failed to load .linkfieldignore, ignoring patterns");
+			IgnoreConfig::empty()
+		}
+	};
+	// CLI or persisted patterns are applied on top of the file patterns
+	for pat in patterns {
+		if let Err(e) = ignore_config.add_pattern(pat) {
+			tracing::warn!(pattern = %pat, error = %e, "Invalid ignore pattern, skipping");
+		}
+	}
+	if !patterns.is_empty() {
+		info!(ignore_patterns = ?patterns, "Added ignore patterns from command line or config");
+	}
+	ignore_config
+}
+
+/// Restore Remove events that were still waiting for a Create when we last exited.
+/// `config` replaces the settings saved with them.
+fn restore_heuristics(db: &redb::Database, config: MoveHeuristicsConfig) -> MoveHeuristics {
+	match MoveHeuristics::load_from_redb(db) {
+		Ok(Some(mut restored)) => {
+			info!(
+				pending_removes = restored.remove_events.len(),
+				"Restored move heuristics state"
+			);
+			restored.config = config;
+			restored
+		}
+		Ok(None) => MoveHeuristics::with_config(config),
+		Err(e) => {
+			tracing::warn!(error = %e, "Failed to restore move heuristics state");
+			MoveHeuristics::with_config(config)
+		}
+	}
+}
+
+fn save_journal(db: &redb::Database, journal: &ChangeJournal) {
+	match journal.flush(db) {
+		Ok(ids) if !ids.is_empty() => info!(transactions = ids.len(), "Saved change journal"),
+		Ok(_) => {}
+		Err(e) => tracing::warn!(error = %e, "Failed to save change journal"),
+	}
+}
+
+fn save_heuristics(db: &redb::Database, heuristics: &Mutex<MoveHeuristics>) {
+	let Ok(mut heuristics) = heuristics.lock() else {
+		tracing::error!("Failed to lock heuristics for saving");
+		return;
+	};
+	if let Err(e) = heuristics.save_to_redb(db) {
+		tracing::warn!(error = %e, "Failed to save move heuristics state");
+	}
 }
@@ -1,11 +1,559 @@
 // Command-line argument parsing logic
 
+use crate::file_cache::extensions::{ExtensionMapping, ExtensionNormalizer};
+use crate::file_cache::stats::DirCountThreshold;
+use crate::file_cache::summary::OutputFormat;
+use crate::hooks::EventHooks;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-pub fn parse_args() -> (PathBuf, PathBuf) {
-	let args: Vec<String> = std::env::args().collect();
-	if args.len() > 1 {
-		let arg_path = Path::new(&args[1]);
+/// Top-level command line for the `linkfield` binary.
+#[derive(Debug, Parser)]
+#[command(
+	name = "linkfield",
+	version,
+	about = "Watch a directory and track file moves"
+)]
+pub struct Cli {
+	#[command(subcommand)]
+	pub command: Option<Command>,
+	/// Database file or directory to watch
+	pub path: Option<PathBuf>,
+	/// Additional ignore pattern (repeatable). CLI patterns are added on top of
+	/// the patterns loaded from `.linkfieldignore`, they never replace them.
+	#[arg(long = "ignore", value_name = "PATTERN", action = ArgAction::Append, global = true)]
+	pub ignore: Vec<String>,
+	/// Seconds to wait for the scan and watcher threads to stop on exit
+	#[arg(long, value_name = "SECS", default_value_t = 10, global = true)]
+	pub shutdown_timeout_secs: u64,
+	/// How to report the summary after the initial scan
+	#[arg(long, value_enum, default_value_t = OutputFormat::HumanReadable, global = true)]
+	pub output_format: OutputFormat,
+	/// Warn after the initial scan if DIR holds more than N files (repeatable)
+	#[arg(
+		long = "alert-dir-count-threshold",
+		value_name = "DIR:N",
+		action = ArgAction::Append,
+		global = true
+	)]
+	pub alert_dir_count_threshold: Vec<DirCountThreshold>,
+	/// Log more detail, such as the startup diagnostics
+	#[arg(long, short, global = true)]
+	pub verbose: bool,
+	/// Log line format
+	#[arg(long, value_enum, default_value_t = LogFormat::Compact, global = true)]
+	pub log_format: LogFormat,
+	/// Files committed to the database per transaction during the initial scan
+	#[arg(long, value_name = "N", default_value_t = DEFAULT_BATCH_SIZE, global = true)]
+	pub batch_size: usize,
+	/// Minimum score for a remove/create pair to count as a move, overriding the stored
+	/// setting
+	#[arg(long, value_name = "SCORE", global = true)]
+	pub move_threshold: Option<f64>,
+	/// Start watching without the initial scan, trusting the database contents
+	#[arg(long, global = true)]
+	pub no_scan: bool,
+	/// Skip the initial scan when fewer than this fraction of a sample of stored files
+	/// changed on disk; 0 always scans
+	#[arg(long, value_name = "RATIO", default_value_t = DEFAULT_STALENESS_THRESHOLD, global = true)]
+	pub staleness_threshold: f64,
+	/// Shell command to run for each created file; `{path}` is replaced with its path
+	#[arg(long, value_name = "COMMAND", global = true)]
+	pub on_create: Option<String>,
+	/// Shell command to run for each moved or renamed file, with `{from}` and `{to}`
+	#[arg(long, value_name = "COMMAND", global = true)]
+	pub on_move: Option<String>,
+	/// Shell command to run for each removed file, with `{path}`
+	#[arg(long, value_name = "COMMAND", global = true)]
+	pub on_remove: Option<String>,
+	/// Store canonical file extensions: lowercased, with common variants mapped, e.g.
+	/// `jpeg` to `jpg` and `htm` to `html`
+	#[arg(long, global = true)]
+	pub normalize_extensions: bool,
+	/// Store extension FROM as TO (repeatable); implies `--normalize-extensions`
+	#[arg(
+		long = "extension-map",
+		value_name = "FROM=TO",
+		action = ArgAction::Append,
+		global = true
+	)]
+	pub extension_map: Vec<ExtensionMapping>,
+	/// Rescan the watch root every SECS seconds, catching changes the watcher missed. A cache
+	/// unchanged for five intervals is reported.
+	#[arg(long, value_name = "SECS", global = true)]
+	pub rescan_interval: Option<u64>,
+	/// Serve `/health` and the `/metrics` gauges on this localhost port
+	#[arg(long, value_name = "PORT", global = true)]
+	pub metrics_port: Option<u16>,
+	/// Index the files inside `.zip` archives as virtual entries and keep them current
+	/// (needs the `archives` feature)
+	#[arg(long, global = true)]
+	pub index_archives: bool,
+	/// Also pair moves of files whose contents changed a little, by a MinHash signature read
+	/// from up to 128 KB of each created or modified file
+	#[arg(long, global = true)]
+	pub use_minhash: bool,
+	/// Export tracing spans to this OTLP/HTTP collector endpoint
+	/// (needs the `opentelemetry` feature)
+	#[arg(long, value_name = "URL", global = true)]
+	pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+	/// Watch a directory (the default when no subcommand is given)
+	Watch {
+		/// Database file or directory to watch
+		path: Option<PathBuf>,
+		/// Scan once, print the summary in `--output-format` and exit without watching
+		#[arg(long)]
+		once: bool,
+		/// Write the `--once` summary to FILE instead of stdout
+		#[arg(long, value_name = "FILE", requires = "once")]
+		output: Option<PathBuf>,
+		/// Also store the `--once` scan in the database
+		#[arg(long, requires = "once")]
+		persist: bool,
+	},
+	/// Rescan the directory and update the database with the changes, listing each changed
+	/// path as added (A), modified (M) or deleted (D)
+	Scan {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+		/// Only list the changes, leave the database as it is
+		#[arg(long)]
+		dry_run: bool,
+		/// Print a report with the counts and at most N paths of each kind of change instead
+		/// of every path; JSON with `--output-format json`
+		#[arg(
+			long,
+			value_name = "N",
+			num_args = 0..=1,
+			default_missing_value = "10",
+			conflicts_with = "dry_run"
+		)]
+		report: Option<usize>,
+	},
+	/// Print the cached file paths for use by other tools
+	Export {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+		#[arg(long, value_enum, default_value_t = ExportFormat::PathList)]
+		format: ExportFormat,
+	},
+	/// Add the files of a path list, e.g. from `find` or `fd`, without scanning
+	Import {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+		/// File holding one path per line, `-` for standard input
+		#[arg(long, value_name = "FILE")]
+		from_file: PathBuf,
+		/// The paths are separated by NUL bytes (`find -print0`, `fd -0`)
+		#[arg(long)]
+		null_separated: bool,
+	},
+	/// Show the directories using the most space
+	Du {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+		/// Number of directories to list
+		#[arg(long, default_value_t = 10)]
+		top: usize,
+	},
+	/// Show file counts and sizes per extension, largest first
+	Stats {
+		#[command(subcommand)]
+		action: Option<StatsAction>,
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+		/// Number of extensions to list
+		#[arg(long, default_value_t = 10)]
+		top: usize,
+	},
+	/// List files with identical contents, largest savings first
+	FindDuplicates {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+		/// Skip files smaller than this many bytes
+		#[arg(long, default_value_t = 1)]
+		min_size: u64,
+		/// Hash only the first N bytes of each file (faster, but may report false duplicates)
+		#[arg(long, value_name = "N")]
+		hash_bytes: Option<u64>,
+	},
+	/// Hash the contents of stored files that have no hash yet, --batch-size files at a time
+	BackfillHashes {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+	},
+	/// Delete every stored file and rebuild the database from a fresh scan, listing the
+	/// changes like `scan`. For databases that can't be repaired.
+	RebuildCache {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+		/// Confirm that the stored files should be deleted
+		#[arg(long)]
+		force: bool,
+	},
+	/// Report whether a running watcher is ready (exit 0), starting (1) or degraded (2)
+	Health {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+	},
+	/// List applied and pending database migrations
+	Migrations {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+	},
+	/// Check the database for changes made outside linkfield (all checks when none is given)
+	Verify {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+		/// Compare the stored files against the checksum written with them
+		#[arg(long)]
+		checksum: bool,
+	},
+	/// Save or compare named snapshots of the watched directory
+	Checkpoint {
+		#[command(subcommand)]
+		action: CheckpointAction,
+	},
+	/// Show or delete the watcher settings stored in the database
+	Config {
+		#[command(subcommand)]
+		action: ConfigAction,
+	},
+	/// List or undo the file changes the watcher recorded
+	Journal {
+		#[command(subcommand)]
+		action: JournalAction,
+	},
+	/// Print system information for bug reports as JSON
+	Diagnostics {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+	},
+	/// Copy the patterns of a .gitignore that mean the same to linkfield into an ignore file,
+	/// listing the ones left out
+	ImportGitignore {
+		/// The .gitignore to read [default: .gitignore]
+		gitignore: Option<PathBuf>,
+		/// Ignore file to add the patterns to [default: .linkfieldignore next to the .gitignore]
+		#[arg(long, value_name = "FILE")]
+		output: Option<PathBuf>,
+	},
+	/// Replay a JSON list of recorded events through the move heuristics without side effects
+	ReplayEvents {
+		/// JSON file containing a list of file events
+		events: PathBuf,
+	},
+	/// Remove the `.redb` file association registered on Windows
+	Unregister,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum StatsAction {
+	/// Show the move detection counters saved by the watcher
+	Moves {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+	},
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CheckpointAction {
+	/// Scan the directory and store the result under NAME
+	Save {
+		name: String,
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+	},
+	/// Scan the directory and list files added (A), modified (M) or deleted (D) since NAME
+	Diff {
+		name: String,
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+	},
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+	/// Print the stored settings as TOML
+	Show {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+	},
+	/// Delete the stored settings so the next start uses the defaults
+	Reset {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+	},
+}
+
+#[derive(Debug, Subcommand)]
+pub enum JournalAction {
+	/// Print every recorded transaction with its changes
+	List {
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+	},
+	/// Undo the changes of transaction ID: delete created files, move moved files back and
+	/// recreate removed files as empty placeholders
+	Rollback {
+		id: u64,
+		/// Database file or watched directory
+		path: Option<PathBuf>,
+		/// Print the operations without running them
+		#[arg(long)]
+		dry_run: bool,
+	},
+}
+
+/// Default for `--batch-size`
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+/// Default for `--staleness-threshold`
+pub const DEFAULT_STALENESS_THRESHOLD: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum LogFormat {
+	/// One short human readable line per event
+	#[default]
+	Compact,
+	/// One JSON object per event
+	Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+	/// Newline-separated path list
+	PathList,
+	/// NUL-separated path list (for `xargs -0`, `rsync --from0`)
+	NullPathList,
+}
+
+impl Cli {
+	/// The normalizer asked for with `--normalize-extensions` or `--extension-map`
+	pub fn extension_normalizer(&self) -> Option<ExtensionNormalizer> {
+		if !self.normalize_extensions && self.extension_map.is_empty() {
+			return None;
+		}
+		let mut normalizer = ExtensionNormalizer::default();
+		for mapping in &self.extension_map {
+			normalizer.add_mapping(&mapping.from, &mapping.to);
+		}
+		Some(normalizer)
+	}
+
+	/// The path given either to `watch` or as the bare positional argument.
+	pub fn target_path(&self) -> Option<&Path> {
+		match &self.command {
+			Some(
+				Command::Watch { path, .. }
+				| Command::Scan { path, .. }
+				| Command::Export { path, .. }
+				| Command::Import { path, .. }
+				| Command::Du { path, .. }
+				| Command::Stats {
+					action: None | Some(StatsAction::Moves { path: None }),
+					path,
+					..
+				}
+				| Command::Stats {
+					action: Some(StatsAction::Moves {
+						path: path @ Some(_),
+					}),
+					..
+				}
+				| Command::FindDuplicates { path, .. }
+				| Command::BackfillHashes { path }
+				| Command::RebuildCache { path, .. }
+				| Command::Health { path }
+				| Command::Migrations { path }
+				| Command::Verify { path, .. }
+				| Command::Diagnostics { path }
+				| Command::Checkpoint {
+					action:
+						CheckpointAction::Save { path, .. } | CheckpointAction::Diff { path, .. },
+				}
+				| Command::Config {
+					action: ConfigAction::Show { path } | ConfigAction::Reset { path },
+				}
+				| Command::Journal {
+					action: JournalAction::List { path } | JournalAction::Rollback { path, .. },
+				},
+			) => path.as_deref(),
+			None => self.path.as_deref(),
+			Some(
+				Command::ReplayEvents { .. }
+				| Command::ImportGitignore { .. }
+				| Command::Unregister,
+			) => None,
+		}
+	}
+
+	/// True when running the long-lived watcher rather than a one-shot subcommand.
+	pub const fn is_watch(&self) -> bool {
+		matches!(self.command, None | Some(Command::Watch { .. }))
+	}
+
+	/// Resolve the `(db_path, watch_root)` pair for this invocation.
+	pub fn paths(&self) -> (PathBuf, PathBuf) {
+		resolve_paths(self.target_path())
+	}
+}
+
+pub fn parse_cli() -> Cli {
+	Cli::parse()
+}
+
+/// The settings of a watch run, resolved from the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedArgs {
+	pub db_path: PathBuf,
+	pub watch_root: PathBuf,
+	/// The path given on the command line. `db_path` and `watch_root` fall back to the
+	/// working directory when it doesn't exist.
+	pub target_path: Option<PathBuf>,
+	pub verbose: bool,
+	pub log_format: LogFormat,
+	pub batch_size: usize,
+	/// `None` keeps the threshold stored in the database
+	pub move_threshold: Option<f64>,
+	pub no_scan: bool,
+	pub staleness_threshold: f64,
+	pub ignore_patterns: Vec<String>,
+	pub hooks: EventHooks,
+	pub rescan_interval: Option<Duration>,
+	pub metrics_port: Option<u16>,
+	pub index_archives: bool,
+	pub use_minhash: bool,
+}
+
+impl ParsedArgs {
+	pub fn db_path(&self) -> &Path {
+		&self.db_path
+	}
+	pub fn watch_root(&self) -> &Path {
+		&self.watch_root
+	}
+}
+
+impl From<&Cli> for ParsedArgs {
+	fn from(cli: &Cli) -> Self {
+		let (db_path, watch_root) = cli.paths();
+		Self {
+			db_path,
+			watch_root,
+			target_path: cli.target_path().map(Path::to_path_buf),
+			verbose: cli.verbose,
+			log_format: cli.log_format,
+			batch_size: cli.batch_size.max(1),
+			move_threshold: cli.move_threshold,
+			no_scan: cli.no_scan,
+			staleness_threshold: cli.staleness_threshold,
+			ignore_patterns: cli.ignore.clone(),
+			hooks: EventHooks {
+				on_create: cli.on_create.clone(),
+				on_move: cli.on_move.clone(),
+				on_remove: cli.on_remove.clone(),
+			},
+			rescan_interval: cli.rescan_interval.map(Duration::from_secs),
+			metrics_port: cli.metrics_port,
+			index_archives: cli.index_archives,
+			use_minhash: cli.use_minhash,
+		}
+	}
+}
+
+pub fn parse_args() -> ParsedArgs {
+	ParsedArgs::from(&parse_cli())
+}
+
+/// A setting of a watch run that can't work, found by [`validate_args`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+	DbPathIsDirectory,
+	WatchRootIsFile,
+	WatchRootDoesNotExist {
+		path: PathBuf,
+	},
+	DbDirNotWritable {
+		path: PathBuf,
+	},
+	/// Flags `a` and `b` can't be used together
+	IncompatibleFlags {
+		a: String,
+		b: String,
+	},
+}
+
+impl std::fmt::Display for ValidationError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::DbPathIsDirectory => write!(f, "the database path is a directory"),
+			Self::WatchRootIsFile => write!(f, "the directory to watch is a file"),
+			Self::WatchRootDoesNotExist { path } => {
+				write!(f, "{} does not exist", path.display())
+			}
+			Self::DbDirNotWritable { path } => write!(
+				f,
+				"the database can't be created in {}, it is not a writable directory",
+				path.display()
+			),
+			Self::IncompatibleFlags { a, b } => write!(f, "{a} can't be used with {b}"),
+		}
+	}
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Check the paths and flags of a watch run before anything is opened, returning every
+/// problem found
+pub fn validate_args(args: &ParsedArgs) -> Result<(), Vec<ValidationError>> {
+	let mut errors = Vec::new();
+	match &args.target_path {
+		Some(path) if !path.exists() => {
+			errors.push(ValidationError::WatchRootDoesNotExist { path: path.clone() });
+		}
+		_ if args.watch_root.is_file() => errors.push(ValidationError::WatchRootIsFile),
+		_ if !args.watch_root.exists() => errors.push(ValidationError::WatchRootDoesNotExist {
+			path: args.watch_root.clone(),
+		}),
+		_ => {}
+	}
+	if args.db_path.is_dir() {
+		errors.push(ValidationError::DbPathIsDirectory);
+	} else {
+		let db_dir = match args.db_path.parent() {
+			Some(parent) if !parent.as_os_str().is_empty() => parent,
+			_ => Path::new("."),
+		};
+		// Only the permission bits; whether the process may write there shows on open
+		let writable = std::fs::metadata(db_dir)
+			.is_ok_and(|meta| meta.is_dir() && !meta.permissions().readonly());
+		if !writable {
+			errors.push(ValidationError::DbDirNotWritable {
+				path: db_dir.to_path_buf(),
+			});
+		}
+	}
+	if args.no_scan && (args.staleness_threshold - DEFAULT_STALENESS_THRESHOLD).abs() > f64::EPSILON
+	{
+		errors.push(ValidationError::IncompatibleFlags {
+			a: "--no-scan".to_string(),
+			b: "--staleness-threshold".to_string(),
+		});
+	}
+	if errors.is_empty() {
+		Ok(())
+	} else {
+		Err(errors)
+	}
+}
+
+fn resolve_paths(arg_path: Option<&Path>) -> (PathBuf, PathBuf) {
+	if let Some(arg_path) = arg_path {
 		if arg_path.is_file() {
 			(
 				arg_path.to_path_buf(),
@@ -28,3 +576,84 @@ pub fn parse_args() -> (PathBuf, PathBuf) {
 		)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[test]
+	fn test_repeated_ignore_flags() {
+		let cli = Cli::try_parse_from([
+			"linkfield",
+			"watch",
+			"/tmp",
+			"--ignore",
+			"*.tmp",
+			"--ignore",
+			"node_modules/",
+		])
+		.unwrap();
+		assert_eq!(cli.ignore, vec!["*.tmp", "node_modules/"]);
+		assert_eq!(cli.target_path(), Some(Path::new("/tmp")));
+	}
+
+	#[test]
+	fn test_parsed_args_flags() {
+		let cli = Cli::try_parse_from([
+			"linkfield",
+			"--verbose",
+			"--log-format",
+			"json",
+			"--batch-size",
+			"250",
+			"--move-threshold",
+			"0.75",
+			"--no-scan",
+			"--staleness-threshold",
+			"0.2",
+			"--ignore",
+			"*.log",
+			"--rescan-interval",
+			"600",
+			"--metrics-port",
+			"9100",
+			"--on-move",
+			"echo {from} {to}",
+			"--index-archives",
+			"--use-minhash",
+		])
+		.unwrap();
+		let args = ParsedArgs::from(&cli);
+		assert!(args.verbose);
+		assert_eq!(args.log_format, LogFormat::Json);
+		assert_eq!(args.batch_size, 250);
+		assert_eq!(args.move_threshold, Some(0.75));
+		assert!(args.no_scan);
+		assert!((args.staleness_threshold - 0.2).abs() < f64::EPSILON);
+		assert_eq!(args.ignore_patterns, vec!["*.log"]);
+		assert_eq!(args.rescan_interval, Some(Duration::from_secs(600)));
+		assert_eq!(args.metrics_port, Some(9100));
+		assert!(args.index_archives);
+		assert!(args.use_minhash);
+		assert_eq!(args.hooks.on_move.as_deref(), Some("echo {from} {to}"));
+		assert!(args.hooks.on_create.is_none());
+		assert_eq!(args.db_path(), Path::new("test.redb"));
+		assert_eq!(args.watch_root(), Path::new("."));
+
+		let defaults = ParsedArgs::from(&Cli::try_parse_from(["linkfield"]).unwrap());
+		assert!(!defaults.verbose);
+		assert_eq!(defaults.log_format, LogFormat::Compact);
+		assert_eq!(defaults.batch_size, DEFAULT_BATCH_SIZE);
+		assert_eq!(defaults.move_threshold, None);
+		assert!(!defaults.no_scan);
+		assert!((defaults.staleness_threshold - DEFAULT_STALENESS_THRESHOLD).abs() < f64::EPSILON);
+		assert_eq!(defaults.rescan_interval, None);
+		assert_eq!(defaults.metrics_port, None);
+		assert!(!defaults.index_archives);
+		assert!(!defaults.use_minhash);
+
+		// Flags are global, so they also parse after a subcommand
+		let cli = Cli::try_parse_from(["linkfield", "watch", "--batch-size", "10"]).unwrap();
+		assert_eq!(ParsedArgs::from(&cli).batch_size, 10);
+		assert!(Cli::try_parse_from(["linkfield", "--log-format", "xml"]).is_err());
+	}
+}
@@ -0,0 +1,273 @@
+// Journal of the file changes the watcher saw, grouped into transactions that can be undone
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileMeta;
+use crate::watcher::WatchEvent;
+use bincode::{Decode, Encode, decode_from_slice, encode_to_vec};
+use redb::{Database, MultimapTableDefinition, ReadableMultimapTable};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// Transaction id -> the entries recorded in it. Values start with the entry's index in
+/// the transaction as big-endian bytes, since multimap values are kept in byte order.
+pub const JOURNAL_TABLE: MultimapTableDefinition<u64, &[u8]> =
+	MultimapTableDefinition::new("journal");
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum JournalEntryKind {
+	Create,
+	Remove,
+	/// Moved or renamed from `from` to the entry's path
+	Move {
+		from: PathBuf,
+	},
+}
+
+/// One detected change. For moves `path` is the new location.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct JournalEntry {
+	pub path: PathBuf,
+	pub kind: JournalEntryKind,
+	/// The cached metadata from before the change, if the file was cached
+	pub metadata_before: Option<FileMeta>,
+}
+
+impl JournalEntry {
+	/// The entry for `event`, looking up the metadata from before it in `before`
+	pub fn from_event(event: &WatchEvent, before: &HashMap<PathBuf, FileMeta>) -> Self {
+		let (path, kind, previous) = match event {
+			WatchEvent::Create(path) => (path, JournalEntryKind::Create, path),
+			WatchEvent::Remove(path) => (path, JournalEntryKind::Remove, path),
+			WatchEvent::Rename { from, to } | WatchEvent::Move { from, to, .. } => {
+				(to, JournalEntryKind::Move { from: from.clone() }, from)
+			}
+		};
+		Self {
+			path: path.clone(),
+			kind,
+			metadata_before: before.get(previous).cloned(),
+		}
+	}
+}
+
+/// A file operation that undoes one journal entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InverseOp {
+	/// Delete a file that was created
+	Delete { path: PathBuf },
+	/// Move a file back to where it came from
+	Move { from: PathBuf, to: PathBuf },
+	/// Recreate a removed file. Its contents are gone, so this is a zero-filled placeholder
+	/// of the recorded size. It keeps the current time as its modification time, so the
+	/// next scan doesn't take it for the old file and keep its content hash.
+	Restore { path: PathBuf, size: u64 },
+}
+
+/// The operations undoing a transaction, latest change first, from [`ChangeJournal::rollback`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RollbackPlan {
+	pub transaction_id: u64,
+	pub ops: Vec<InverseOp>,
+}
+
+/// Collects the watcher's changes in memory until [`ChangeJournal::flush`] writes them
+#[derive(Debug, Default)]
+pub struct ChangeJournal {
+	pending: Mutex<Vec<Vec<JournalEntry>>>,
+}
+
+impl ChangeJournal {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Queue `entries` as one transaction; nothing is queued when empty
+	pub fn record(&self, entries: Vec<JournalEntry>) {
+		if entries.is_empty() {
+			return;
+		}
+		self.pending
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.push(entries);
+	}
+
+	/// Transactions recorded and not yet flushed
+	pub fn pending_len(&self) -> usize {
+		self.pending
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.len()
+	}
+
+	/// Write the queued transactions to `db`, returning their ids. They stay queued if
+	/// writing fails.
+	pub fn flush(&self, db: &Database) -> LinkfieldResult<Vec<u64>> {
+		let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+		let ids = Self::append_all(db, &pending)?;
+		pending.clear();
+		Ok(ids)
+	}
+
+	/// Store `entries` as a new transaction, returning its id
+	pub fn append(db: &Database, entries: &[JournalEntry]) -> LinkfieldResult<u64> {
+		let ids = Self::append_all(db, std::slice::from_ref(&entries.to_vec()))?;
+		Ok(ids[0])
+	}
+
+	fn append_all(db: &Database, transactions: &[Vec<JournalEntry>]) -> LinkfieldResult<Vec<u64>> {
+		let write_txn = db.begin_write()?;
+		let mut ids = Vec::with_capacity(transactions.len());
+		{
+			let mut table = write_txn.open_multimap_table(JOURNAL_TABLE)?;
+			let next_id = table
+				.iter()?
+				.next_back()
+				.transpose()?
+				.map_or(1, |(id, _)| id.value() + 1);
+			for (id, entries) in (next_id..).zip(transactions) {
+				for (index, entry) in (0u32..).zip(entries) {
+					let mut value = index.to_be_bytes().to_vec();
+					value.extend(encode_to_vec(entry, bincode::config::standard())?);
+					table.insert(id, value.as_slice())?;
+				}
+				ids.push(id);
+			}
+		}
+		write_txn.commit()?;
+		Ok(ids)
+	}
+
+	/// Every stored transaction by id, entries in the order they were recorded
+	pub fn transactions(db: &Database) -> LinkfieldResult<BTreeMap<u64, Vec<JournalEntry>>> {
+		let read_txn = db.begin_read()?;
+		let table = match read_txn.open_multimap_table(JOURNAL_TABLE) {
+			Ok(table) => table,
+			Err(redb::TableError::TableDoesNotExist(_)) => return Ok(BTreeMap::new()),
+			Err(e) => return Err(e.into()),
+		};
+		let mut transactions = BTreeMap::new();
+		for row in table.iter()? {
+			let (id, values) = row?;
+			transactions.insert(id.value(), decode_entries(values)?);
+		}
+		Ok(transactions)
+	}
+
+	/// The entries of one transaction, empty when there is no such transaction
+	pub fn entries(db: &Database, transaction_id: u64) -> LinkfieldResult<Vec<JournalEntry>> {
+		let read_txn = db.begin_read()?;
+		let table = match read_txn.open_multimap_table(JOURNAL_TABLE) {
+			Ok(table) => table,
+			Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+			Err(e) => return Err(e.into()),
+		};
+		decode_entries(table.get(transaction_id)?)
+	}
+
+	/// The operations that would undo `transaction_id`, without running them
+	pub fn rollback(db: &Database, transaction_id: u64) -> LinkfieldResult<RollbackPlan> {
+		let ops = Self::entries(db, transaction_id)?
+			.into_iter()
+			.rev()
+			.map(|entry| match entry.kind {
+				JournalEntryKind::Create => InverseOp::Delete { path: entry.path },
+				JournalEntryKind::Move { from } => InverseOp::Move {
+					from: entry.path,
+					to: from,
+				},
+				JournalEntryKind::Remove => InverseOp::Restore {
+					size: entry.metadata_before.map_or(0, |meta| meta.size),
+					path: entry.path,
+				},
+			})
+			.collect();
+		Ok(RollbackPlan {
+			transaction_id,
+			ops,
+		})
+	}
+
+	/// Run the operations of `plan` in order, stopping at the first that fails. Neither
+	/// moves nor restores ever write over an existing file.
+	pub fn execute_rollback(plan: RollbackPlan) -> LinkfieldResult<()> {
+		for op in plan.ops {
+			tracing::info!(?op, transaction_id = plan.transaction_id, "Rolling back");
+			match op {
+				InverseOp::Delete { path } => std::fs::remove_file(path)?,
+				InverseOp::Move { from, to } => {
+					// `rename` would silently replace it
+					if std::fs::symlink_metadata(&to).is_ok() {
+						return Err(std::io::Error::new(
+							std::io::ErrorKind::AlreadyExists,
+							format!("{} already exists", to.display()),
+						)
+						.into());
+					}
+					create_parent(&to)?;
+					std::fs::rename(from, to)?;
+				}
+				InverseOp::Restore { path, size } => {
+					create_parent(&path)?;
+					std::fs::File::create_new(path)?.set_len(size)?;
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Re-encode the metadata in every journal entry from the `Old` `FileMeta` layout to the
+/// `New` one, for the migrations that change it. Entries that don't decode are dropped.
+pub(crate) fn upgrade_journal_metas<Old: Decode<()>, New: From<Old> + Encode>(
+	txn: &redb::WriteTransaction,
+) -> LinkfieldResult<()> {
+	let config = bincode::config::standard();
+	let mut table = txn.open_multimap_table(JOURNAL_TABLE)?;
+	let mut stored = Vec::new();
+	for row in table.iter()? {
+		let (id, values) = row?;
+		let values = values
+			.map(|value| value.map(|value| value.value().to_vec()))
+			.collect::<Result<Vec<_>, _>>()?;
+		stored.push((id.value(), values));
+	}
+	for (id, values) in stored {
+		table.remove_all(id)?;
+		for value in values {
+			let (index, bytes) = value.split_at(value.len().min(4));
+			// The fields of a `JournalEntry`, with the metadata in the old layout
+			match decode_from_slice::<(PathBuf, JournalEntryKind, Option<Old>), _>(bytes, config) {
+				Ok(((path, kind, meta), _)) => {
+					let mut upgraded = index.to_vec();
+					upgraded.extend(encode_to_vec((path, kind, meta.map(New::from)), config)?);
+					table.insert(id, upgraded.as_slice())?;
+				}
+				Err(e) => {
+					tracing::warn!(transaction_id = id, error = %e, "Dropping unreadable journal entry");
+				}
+			}
+		}
+	}
+	Ok(())
+}
+
+fn create_parent(path: &Path) -> std::io::Result<()> {
+	match path.parent() {
+		Some(parent) => std::fs::create_dir_all(parent),
+		None => Ok(()),
+	}
+}
+
+fn decode_entries(values: redb::MultimapValue<'_, &[u8]>) -> LinkfieldResult<Vec<JournalEntry>> {
+	let mut entries = Vec::new();
+	for value in values {
+		let value = value?;
+		// Skip the index prefix; the values already come in index order
+		let bytes = value.value().get(4..).unwrap_or_default();
+		let (entry, _) = decode_from_slice(bytes, bincode::config::standard())?;
+		entries.push(entry);
+	}
+	Ok(entries)
+}
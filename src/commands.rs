@@ -0,0 +1,527 @@
+// One-shot subcommands that operate on an existing database and exit
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use linkfield::args::{
+	CheckpointAction, Cli, Command, ConfigAction, ExportFormat, JournalAction, StatsAction,
+};
+use linkfield::change_journal::{ChangeJournal, InverseOp, JournalEntryKind};
+use linkfield::db::{self, DbOptions};
+use linkfield::file_cache::db::DEFAULT_LOAD_BATCH_SIZE;
+use linkfield::file_cache::dedup::HashGranularity;
+use linkfield::file_cache::stats::{
+	DEFAULT_SIZE_BUCKETS, format_extension_table, format_size_distribution,
+};
+use linkfield::file_cache::summary::OutputFormat;
+use linkfield::file_cache::{
+	DiffReport, DiffResult, FileCache, HashWorkerPool, ensure_file_cache_table,
+};
+use linkfield::health::{self, AppState};
+use linkfield::ignore_config::{IGNORE_FILE_NAME, IgnoreConfig};
+use linkfield::move_heuristics::{FileEvent, MoveHeuristics, MoveHeuristicsConfig};
+use linkfield::persisted_config::PersistedConfig;
+use linkfield::platform;
+use tracing::info;
+
+use crate::app;
+
+type CommandResult = Result<(), Box<dyn std::error::Error>>;
+
+pub fn run(cli: &Cli, command: &Command) -> CommandResult {
+	let (db_path, watch_root) = cli.paths();
+	match command {
+		Command::Watch { .. } => unreachable!("watch is handled by app::run"),
+		Command::Scan {
+			dry_run, report, ..
+		} => scan(cli, &db_path, &watch_root, *dry_run, *report),
+		Command::Export { format, .. } => export(&db_path, &watch_root, *format),
+		Command::Import {
+			from_file,
+			null_separated,
+			..
+		} => import(cli, &db_path, &watch_root, from_file, *null_separated),
+		Command::Du { top, .. } => du(&db_path, &watch_root, *top),
+		Command::Stats {
+			action: Some(StatsAction::Moves { .. }),
+			..
+		} => stats_moves(&db_path),
+		Command::Stats { top, .. } => stats(&db_path, &watch_root, *top),
+		Command::FindDuplicates {
+			min_size,
+			hash_bytes,
+			..
+		} => find_duplicates(&db_path, &watch_root, *min_size, *hash_bytes),
+		Command::BackfillHashes { .. } => backfill_hashes(&db_path, &watch_root, cli.batch_size),
+		Command::RebuildCache { force, .. } => rebuild_cache(cli, &db_path, &watch_root, *force),
+		Command::Health { .. } => health(&db_path),
+		Command::Migrations { .. } => migrations(&db_path),
+		// The checksum is the only check so far, so it runs whether or not it was selected
+		Command::Verify { .. } => verify(&db_path),
+		Command::Checkpoint { action } => checkpoint(cli, &db_path, &watch_root, action),
+		Command::Config { action } => config(&db_path, action),
+		Command::Journal { action } => journal(&db_path, action),
+		Command::Diagnostics { .. } => diagnostics(&db_path),
+		Command::ReplayEvents { events } => replay_events(events),
+		Command::ImportGitignore { gitignore, output } => {
+			import_gitignore(gitignore.as_deref(), output.as_deref())
+		}
+		Command::Unregister => unregister(),
+	}
+}
+
+fn scan(
+	cli: &Cli,
+	db_path: &Path,
+	watch_root: &Path,
+	dry_run: bool,
+	report: Option<usize>,
+) -> CommandResult {
+	if dry_run {
+		let cache = load_cache(db_path, watch_root)?;
+		let own_files = own_files(db_path);
+		let result = cache.dry_run_scan(watch_root, &app::load_ignore_config(cli));
+		let mut lines: Vec<_> = result
+			.would_add
+			.iter()
+			.map(|path| ('A', path))
+			.chain(result.would_update.iter().map(|path| ('M', path)))
+			.chain(result.would_remove.iter().map(|path| ('D', path)))
+			.filter(|(_, path)| !is_own_file(&own_files, &path.0))
+			.collect();
+		lines.sort_by(|a, b| a.1.cmp(b.1));
+		for (status, path) in lines {
+			println!("{status} {}", path.0.display());
+		}
+		return Ok(());
+	}
+	let db = db::open_and_migrate(db_path)?;
+	ensure_file_cache_table(&db)?;
+	let cache = FileCache::new_root(watch_root.to_string_lossy().as_ref());
+	cache.load_from_redb_batched(&db, DEFAULT_LOAD_BATCH_SIZE, None)?;
+	let scanned = scan_without_db(cli, db_path, watch_root).file_map();
+	let diff = cache.diff_and_update(&scanned, Some(&db));
+	match report {
+		Some(max_items) if cli.output_format == OutputFormat::Json => {
+			println!("{}", DiffReport::new(&diff, max_items).to_json());
+		}
+		Some(max_items) => print!("{}", FileCache::diff_report(&diff, max_items)),
+		None => print_diff(&diff),
+	}
+	Ok(())
+}
+
+fn export(db_path: &Path, watch_root: &Path, format: ExportFormat) -> CommandResult {
+	let cache = load_cache(db_path, watch_root)?;
+	let separator = match format {
+		ExportFormat::PathList => b'\n',
+		ExportFormat::NullPathList => b'\0',
+	};
+	let stdout = std::io::stdout();
+	cache.export_path_list(stdout.lock(), separator)?;
+	std::io::stdout().flush()?;
+	Ok(())
+}
+
+fn import(
+	cli: &Cli,
+	db_path: &Path,
+	watch_root: &Path,
+	from_file: &Path,
+	null_separated: bool,
+) -> CommandResult {
+	let db = db::open_and_migrate(db_path)?;
+	ensure_file_cache_table(&db)?;
+	let cache = FileCache::new_root(watch_root.to_string_lossy().as_ref());
+	cache.set_extension_normalizer(cli.extension_normalizer().map(Arc::new));
+	let ignore = app::load_ignore_config(cli);
+	let separator = if null_separated { b'\0' } else { b'\n' };
+	let imported = if from_file == Path::new("-") {
+		cache.import_path_list(
+			&db,
+			std::io::stdin().lock(),
+			separator,
+			cli.batch_size,
+			&ignore,
+		)?
+	} else {
+		let reader = std::io::BufReader::new(std::fs::File::open(from_file)?);
+		cache.import_path_list(&db, reader, separator, cli.batch_size, &ignore)?
+	};
+	// Like a scan, never track the database and state files themselves
+	let own_files = own_files(db_path);
+	let imported = imported
+		- cache.remove_files_where(Some(&db), |meta| is_own_file(&own_files, &meta.path.0));
+	println!("imported {imported} files");
+	Ok(())
+}
+
+fn du(db_path: &Path, watch_root: &Path, top: usize) -> CommandResult {
+	let cache = load_cache(db_path, watch_root)?;
+	let mut out = std::io::stdout().lock();
+	for (dir, size) in cache.top_n_directories(watch_root, top) {
+		writeln!(
+			out,
+			"{:>14}  {:>8}  {}",
+			size.total_bytes,
+			size.file_count,
+			dir.display()
+		)?;
+	}
+	Ok(())
+}
+
+fn stats(db_path: &Path, watch_root: &Path, top: usize) -> CommandResult {
+	let cache = load_cache(db_path, watch_root)?;
+	print!("{}", format_extension_table(&cache.top_extensions(top)));
+	println!();
+	println!("total bytes    {:>14}", cache.total_size_bytes());
+	println!("average bytes  {:>14.0}", cache.average_file_size());
+	println!("median bytes   {:>14}", cache.median_file_size());
+	println!();
+	print!(
+		"{}",
+		format_size_distribution(&cache.size_distribution(&DEFAULT_SIZE_BUCKETS))
+	);
+	Ok(())
+}
+
+fn stats_moves(db_path: &Path) -> CommandResult {
+	let db = db::open_or_create_db_with_options(db_path, &DbOptions::read_only())?;
+	println!("{:<26} {:>12}", "counter", "value");
+	for (key, value) in MoveHeuristics::stored_stats(&db)? {
+		println!("{key:<26} {value:>12}");
+	}
+	Ok(())
+}
+
+fn find_duplicates(
+	db_path: &Path,
+	watch_root: &Path,
+	min_size: u64,
+	hash_bytes: Option<u64>,
+) -> CommandResult {
+	let cache = load_cache(db_path, watch_root)?;
+	let granularity = hash_bytes.map_or(HashGranularity::Full, HashGranularity::Prefix);
+	let report = cache.deduplication_report_with(granularity, min_size);
+	print!("{}", report.format_table());
+	Ok(())
+}
+
+/// Drop every stored file and rescan the watch root from scratch. Requires `force`.
+fn rebuild_cache(cli: &Cli, db_path: &Path, watch_root: &Path, force: bool) -> CommandResult {
+	if !force {
+		return Err(
+			"rebuild-cache deletes every stored file before rescanning; \
+			rerun with --force to confirm"
+				.into(),
+		);
+	}
+	let db = db::open_and_migrate(db_path)?;
+	let cache = FileCache::new_root(watch_root.to_string_lossy().as_ref());
+	let diff = cache.rebuild_from_scratch(&db, watch_root, &app::load_ignore_config(cli))?;
+	print_diff(&diff);
+	Ok(())
+}
+
+/// Hash the stored files without a content hash in batches, printing progress after each
+fn backfill_hashes(db_path: &Path, watch_root: &Path, batch_size: usize) -> CommandResult {
+	let db = db::open_and_migrate(db_path)?;
+	ensure_file_cache_table(&db)?;
+	let cache = FileCache::new_root(watch_root.to_string_lossy().as_ref());
+	cache.load_from_redb_batched(&db, DEFAULT_LOAD_BATCH_SIZE, None)?;
+	// The database changes while we write to it, so it could never be hashed
+	let own_files = own_files(db_path);
+	cache.remove_files_where(None, |meta| is_own_file(&own_files, &meta.path.0));
+	let pool = HashWorkerPool::new(0)?;
+	let total = cache.files_missing_hash().count();
+	let mut done = 0;
+	loop {
+		let tried = cache.populate_missing_hashes(Some(&db), batch_size, &pool);
+		if tried == 0 {
+			break;
+		}
+		done += tried;
+		println!("{done}/{total} files processed");
+	}
+	let missing = cache.files_missing_hash().count();
+	println!(
+		"hashed {} files, {missing} could not be hashed",
+		total - missing
+	);
+	Ok(())
+}
+
+/// Print the state of a running watcher and exit with its health code. A watcher whose
+/// process is gone or whose heartbeat is stale is reported as not ready.
+fn health(db_path: &Path) -> CommandResult {
+	let state_file = health::state_file_path(db_path);
+	let code = match health::read_state_file(&state_file) {
+		Ok(file) => {
+			let running = platform::is_process_running(file.pid);
+			let code = file.exit_code(running);
+			if !running {
+				println!("{:?} (process {} is not running)", file.state, file.pid);
+			} else if code != file.state.exit_code() {
+				println!("{:?} (heartbeat is stale)", file.state);
+			} else {
+				println!("{:?}", file.state);
+			}
+			code
+		}
+		Err(e) => {
+			println!("No state available from {}: {e}", state_file.display());
+			AppState::Starting.exit_code()
+		}
+	};
+	std::io::stdout().flush()?;
+	std::process::exit(code);
+}
+
+fn verify(db_path: &Path) -> CommandResult {
+	let db = db::open_or_create_db_with_options(db_path, &DbOptions::read_only())?;
+	FileCache::verify_cache_checksum(&db)?;
+	println!("checksum: ok");
+	Ok(())
+}
+
+fn migrations(db_path: &Path) -> CommandResult {
+	let db = db::open_or_create_db_with_options(db_path, &DbOptions::read_only())?;
+	let applied = db::migration_status(&db);
+	for migration in db::MIGRATIONS {
+		let applied_at = applied
+			.iter()
+			.find(|(version, _)| *version == migration.version)
+			.map(|(_, applied_at)| applied_at.as_str());
+		println!(
+			"{:>4}  {:<7}  {:<20}  {}",
+			migration.version,
+			if applied_at.is_some() {
+				"applied"
+			} else {
+				"pending"
+			},
+			applied_at.unwrap_or("-"),
+			migration.description
+		);
+	}
+	Ok(())
+}
+
+fn checkpoint(
+	cli: &Cli,
+	db_path: &Path,
+	watch_root: &Path,
+	action: &CheckpointAction,
+) -> CommandResult {
+	let db = db::open_and_migrate(db_path)?;
+	let cache = scan_without_db(cli, db_path, watch_root);
+	match action {
+		CheckpointAction::Save { name, .. } => {
+			let count = cache.record_checkpoint(&db, name)?;
+			println!("Saved checkpoint '{name}' with {count} files");
+		}
+		CheckpointAction::Diff { name, .. } => {
+			let diff = cache.changed_since_checkpoint(&db, name)?;
+			print_diff(&diff);
+		}
+	}
+	Ok(())
+}
+
+fn config(db_path: &Path, action: &ConfigAction) -> CommandResult {
+	match action {
+		ConfigAction::Show { .. } => {
+			let db = db::open_or_create_db_with_options(db_path, &DbOptions::read_only())?;
+			match PersistedConfig::load(&db)? {
+				Some(config) => print!("{}", config.to_toml()?),
+				None => println!("No persisted config in {}", db_path.display()),
+			}
+		}
+		ConfigAction::Reset { .. } => {
+			let db = db::open_and_migrate(db_path)?;
+			if PersistedConfig::reset(&db)? {
+				println!("Deleted persisted config from {}", db_path.display());
+			} else {
+				println!("No persisted config in {}", db_path.display());
+			}
+		}
+	}
+	Ok(())
+}
+
+fn journal(db_path: &Path, action: &JournalAction) -> CommandResult {
+	match action {
+		JournalAction::List { .. } => {
+			let db = db::open_or_create_db_with_options(db_path, &DbOptions::read_only())?;
+			let transactions = ChangeJournal::transactions(&db)?;
+			if transactions.is_empty() {
+				println!("No journal entries in {}", db_path.display());
+			}
+			for (id, entries) in transactions {
+				println!("{id}:");
+				for entry in entries {
+					match entry.kind {
+						JournalEntryKind::Create => println!("  A {}", entry.path.display()),
+						JournalEntryKind::Remove => println!("  D {}", entry.path.display()),
+						JournalEntryKind::Move { from } => {
+							println!("  R {} -> {}", from.display(), entry.path.display());
+						}
+					}
+				}
+			}
+		}
+		JournalAction::Rollback { id, dry_run, .. } => {
+			let db = db::open_or_create_db_with_options(db_path, &DbOptions::read_only())?;
+			let plan = ChangeJournal::rollback(&db, *id)?;
+			if plan.ops.is_empty() {
+				return Err(format!("No journal transaction {id}").into());
+			}
+			for op in &plan.ops {
+				match op {
+					InverseOp::Delete { path } => println!("delete {}", path.display()),
+					InverseOp::Move { from, to } => {
+						println!("move {} -> {}", from.display(), to.display());
+					}
+					InverseOp::Restore { path, size } => {
+						println!("restore {} ({size} bytes, empty)", path.display());
+					}
+				}
+			}
+			if !dry_run {
+				ChangeJournal::execute_rollback(plan)?;
+			}
+		}
+	}
+	Ok(())
+}
+
+fn diagnostics(db_path: &Path) -> CommandResult {
+	let diagnostics = platform::startup_diagnostics(db_path);
+	serde_json::to_writer_pretty(std::io::stdout().lock(), &diagnostics)?;
+	println!();
+	Ok(())
+}
+
+fn replay_events(events: &Path) -> CommandResult {
+	let file = std::fs::File::open(events)?;
+	let events: Vec<FileEvent> = serde_json::from_reader(std::io::BufReader::new(file))?;
+	let result = MoveHeuristics::dry_run_from_events(&events, MoveHeuristicsConfig::default());
+	info!(
+		moves = result.moves_detected.len(),
+		unmatched_removes = result.unmatched_removes.len(),
+		unmatched_creates = result.unmatched_creates.len(),
+		"Replayed {} events",
+		events.len()
+	);
+	serde_json::to_writer_pretty(std::io::stdout().lock(), &result)?;
+	println!();
+	Ok(())
+}
+
+fn import_gitignore(gitignore: Option<&Path>, output: Option<&Path>) -> CommandResult {
+	let gitignore = gitignore.unwrap_or_else(|| Path::new(".gitignore"));
+	let output = output.map_or_else(
+		|| {
+			gitignore
+				.parent()
+				.unwrap_or_else(|| Path::new(""))
+				.join(IGNORE_FILE_NAME)
+		},
+		Path::to_path_buf,
+	);
+	let (imported, skipped) = IgnoreConfig::from_gitignore_file(gitignore)
+		.map_err(|e| e as Box<dyn std::error::Error>)?;
+	// Patterns already in the ignore file are kept and not repeated
+	let existing = std::fs::read_to_string(&output).unwrap_or_default();
+	let known: HashSet<_> = existing.lines().map(str::trim).collect();
+	let added: Vec<_> = imported
+		.patterns()
+		.iter()
+		.filter(|pattern| !known.contains(pattern.as_str()))
+		.collect();
+	if !added.is_empty() {
+		let mut file = std::fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&output)?;
+		if !existing.is_empty() && !existing.ends_with('\n') {
+			writeln!(file)?;
+		}
+		writeln!(file, "# Imported from {}", gitignore.display())?;
+		for pattern in &added {
+			writeln!(file, "{pattern}")?;
+		}
+	}
+	println!(
+		"Added {} patterns to {}, skipped {}",
+		added.len(),
+		output.display(),
+		skipped.len()
+	);
+	for skipped in &skipped {
+		println!("skipped {skipped}");
+	}
+	Ok(())
+}
+
+fn unregister() -> CommandResult {
+	if platform::unregister_file_association()? {
+		println!("Removed the .redb file association");
+	} else {
+		println!("No .redb file association was registered");
+	}
+	Ok(())
+}
+
+/// Open the existing database read-only and load its cached files into a fresh `FileCache`
+fn load_cache(
+	db_path: &Path,
+	watch_root: &Path,
+) -> Result<Arc<FileCache>, Box<dyn std::error::Error>> {
+	let db = db::open_or_create_db_with_options(db_path, &DbOptions::read_only())?;
+	let cache = FileCache::new_root(watch_root.to_string_lossy().as_ref());
+	cache.load_from_redb_batched(&db, DEFAULT_LOAD_BATCH_SIZE, None)?;
+	Ok(cache)
+}
+
+/// Fresh scan of the watch root, leaving out linkfield's own database and state files
+pub fn scan_without_db(cli: &Cli, db_path: &Path, watch_root: &Path) -> Arc<FileCache> {
+	let cache = FileCache::new_root(watch_root.to_string_lossy().as_ref());
+	cache.set_extension_normalizer(cli.extension_normalizer().map(Arc::new));
+	cache.scan_dir_collect_with_ignore(watch_root, &app::load_ignore_config(cli), None);
+	let own_files = own_files(db_path);
+	cache.remove_files_where(None, |meta| is_own_file(&own_files, &meta.path.0));
+	cache
+}
+
+/// Canonical paths of linkfield's database and state files
+fn own_files(db_path: &Path) -> Vec<PathBuf> {
+	[db_path.to_path_buf(), health::state_file_path(db_path)]
+		.iter()
+		.filter_map(|path| path.canonicalize().ok())
+		.collect()
+}
+
+fn is_own_file(own_files: &[PathBuf], path: &Path) -> bool {
+	own_files.iter().any(|own| {
+		own.file_name() == path.file_name() && path.canonicalize().ok().as_ref() == Some(own)
+	})
+}
+
+/// Print one `A`/`M`/`D` line per changed path, sorted by path
+fn print_diff(diff: &DiffResult) {
+	let mut lines: Vec<_> = diff
+		.added
+		.iter()
+		.map(|meta| ('A', &meta.path.0))
+		.chain(diff.modified.iter().map(|(_, meta)| ('M', &meta.path.0)))
+		.chain(diff.removed.iter().map(|meta| ('D', &meta.path.0)))
+		.collect();
+	lines.sort_by(|a, b| a.1.cmp(b.1));
+	for (status, path) in lines {
+		println!("{status} {}", path.display());
+	}
+}
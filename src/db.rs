@@ -1,31 +1,297 @@
 // Database setup and table creation logic
 
-use redb::{Builder, Database};
-use std::error::Error;
+use crate::error::LinkfieldResult;
+use redb::{Builder, Database, ReadableTable, TableDefinition, WriteTransaction};
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn open_or_create_db(db_path: &Path) -> Result<Database, Box<dyn Error>> {
-	let db = if db_path.exists() {
-		Builder::new()
-			.create_with_file_format_v3(true)
-			.open(db_path)
-			.map_err(|e| {
-				tracing::error!(error = %e, path = %db_path.display(), "Failed to open redb file");
-				e
-			})?
+/// How [`open_or_create_db_with_options`] opens the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DbOptions {
+	/// Create new databases in redb's v3 file format
+	pub file_format_v3: bool,
+	/// Page cache size; redb's default when `None`
+	pub cache_size_bytes: Option<u64>,
+	/// Only open an existing database, never create one. redb 2 has no read-only mode, so
+	/// this does not stop writes through the returned `Database`; callers must not write.
+	pub read_only: bool,
+}
+
+impl Default for DbOptions {
+	fn default() -> Self {
+		Self {
+			file_format_v3: true,
+			cache_size_bytes: None,
+			read_only: false,
+		}
+	}
+}
+
+impl DbOptions {
+	pub fn read_only() -> Self {
+		Self {
+			read_only: true,
+			..Self::default()
+		}
+	}
+	#[must_use]
+	pub const fn with_cache_size(mut self, bytes: u64) -> Self {
+		self.cache_size_bytes = Some(bytes);
+		self
+	}
+	#[must_use]
+	pub const fn with_file_format_v3(mut self, enabled: bool) -> Self {
+		self.file_format_v3 = enabled;
+		self
+	}
+}
+
+pub fn open_or_create_db(db_path: &Path) -> LinkfieldResult<Database> {
+	open_or_create_db_with_options(db_path, &DbOptions::default())
+}
+
+pub fn open_or_create_db_with_options(
+	db_path: &Path,
+	opts: &DbOptions,
+) -> LinkfieldResult<Database> {
+	let mut builder = Builder::new();
+	builder.create_with_file_format_v3(opts.file_format_v3);
+	if let Some(bytes) = opts.cache_size_bytes {
+		builder.set_cache_size(usize::try_from(bytes).unwrap_or(usize::MAX));
+	}
+	let db = if db_path.exists() || opts.read_only {
+		builder.open(db_path).map_err(|e| {
+			tracing::error!(error = %e, path = %db_path.display(), "Failed to open redb file");
+			e
+		})?
 	} else {
-		Builder::new()
-			.create_with_file_format_v3(true)
-			.create(db_path)
-			.map_err(|e| {
-				tracing::error!(error = %e, path = %db_path.display(), "Failed to create redb file");
-				e
-			})?
+		builder.create(db_path).map_err(|e| {
+			tracing::error!(error = %e, path = %db_path.display(), "Failed to create redb file");
+			e
+		})?
 	};
 	Ok(db)
 }
 
+/// Open or create the database for writing and apply pending [`MIGRATIONS`], so that what
+/// is written through it is in the current layout
+pub fn open_and_migrate(db_path: &Path) -> LinkfieldResult<Database> {
+	let db = open_or_create_db(db_path)?;
+	migrate(&db)?;
+	Ok(db)
+}
+
 /// Compact the redb database file, returning true if compaction was performed
 pub fn compact_database(db: &mut Database) -> Result<bool, redb::CompactionError> {
 	db.compact()
 }
+
+/// Database file names used by earlier versions in the working directory, oldest first
+pub const LEGACY_DB_NAMES: [&str; 2] = ["test.redb", "linkfield.redb"];
+
+/// Copy a database left by an earlier version in the working directory to `new_path`. See
+/// [`migrate_legacy_database_from`].
+pub fn migrate_legacy_database(new_path: &Path) -> LinkfieldResult<bool> {
+	migrate_legacy_database_from(&std::env::current_dir()?, new_path)
+}
+
+/// If `new_path` does not exist yet, copy the first of [`LEGACY_DB_NAMES`] found in
+/// `legacy_dir` there and rename the original with a `.migrated` suffix, keeping it as a
+/// backup. Names from `new_path` onwards are newer and never migrated. Returns true when a
+/// database was migrated.
+pub fn migrate_legacy_database_from(legacy_dir: &Path, new_path: &Path) -> LinkfieldResult<bool> {
+	if new_path.exists() {
+		return Ok(false);
+	}
+	let new_path = std::path::absolute(new_path)?;
+	let legacy = LEGACY_DB_NAMES
+		.iter()
+		.map(|name| legacy_dir.join(name))
+		.take_while(|path| std::path::absolute(path).is_ok_and(|p| p != new_path))
+		.find(|path| path.is_file());
+	let Some(legacy) = legacy else {
+		return Ok(false);
+	};
+	if let Some(parent) = new_path.parent() {
+		std::fs::create_dir_all(parent)?;
+	}
+	std::fs::copy(&legacy, &new_path)?;
+	let mut backup = legacy.clone().into_os_string();
+	backup.push(".migrated");
+	if let Err(e) = std::fs::rename(&legacy, &backup) {
+		tracing::warn!(error = %e, path = %legacy.display(), "Failed to rename the migrated database");
+	}
+	tracing::warn!(
+		from = %legacy.display(),
+		to = %new_path.display(),
+		"Migrated the database from its legacy location; the original was kept with a .migrated suffix"
+	);
+	Ok(true)
+}
+
+/// Applied migrations: version -> ISO-8601 UTC timestamp of when it was applied
+pub const MIGRATION_HISTORY_TABLE: TableDefinition<u32, &str> =
+	TableDefinition::new("migration_history");
+
+/// One schema change, applied inside its own write transaction
+pub struct Migration {
+	pub version: u32,
+	pub description: &'static str,
+	pub apply: fn(&WriteTransaction) -> LinkfieldResult<()>,
+}
+
+/// All migrations in the order they are applied
+pub const MIGRATIONS: &[Migration] = &[
+	Migration {
+		version: 1,
+		description: "initial schema: file_cache, pending_moves and checkpoints tables",
+		apply: |txn| {
+			txn.open_table(crate::file_cache::db::FILE_CACHE_TABLE)?;
+			txn.open_table(crate::move_heuristics::PENDING_MOVES_TABLE)?;
+			txn.open_table(crate::file_cache::checkpoint::CHECKPOINTS_TABLE)?;
+			Ok(())
+		},
+	},
+	Migration {
+		version: 2,
+		description: "watcher_config table",
+		apply: |txn| {
+			txn.open_table(crate::persisted_config::WATCHER_CONFIG_TABLE)?;
+			Ok(())
+		},
+	},
+	Migration {
+		version: 3,
+		description: "add inode to stored file metadata",
+		apply: crate::file_cache::db::upgrade_file_metas_v1,
+	},
+	Migration {
+		version: 4,
+		description: "dir_index table for per-directory lookups",
+		apply: crate::file_cache::dir_index::rebuild_dir_index,
+	},
+	Migration {
+		version: 5,
+		description: "add content hash to stored file metadata",
+		apply: crate::file_cache::db::upgrade_file_metas_v2,
+	},
+	Migration {
+		version: 6,
+		description: "journal table for undoing detected changes",
+		apply: |txn| {
+			txn.open_multimap_table(crate::change_journal::JOURNAL_TABLE)?;
+			Ok(())
+		},
+	},
+	Migration {
+		version: 7,
+		description: "add is_virtual to stored file metadata",
+		apply: crate::file_cache::db::upgrade_file_metas_v3,
+	},
+	Migration {
+		version: 8,
+		description: "add file_type to stored file metadata",
+		apply: crate::file_cache::db::upgrade_file_metas_v4,
+	},
+	Migration {
+		version: 9,
+		description: "add device to stored file metadata",
+		apply: crate::file_cache::db::upgrade_file_metas_v5,
+	},
+	Migration {
+		version: 10,
+		description: "prefix stored file metadata with its format version",
+		apply: crate::file_cache::db::upgrade_file_metas_v6,
+	},
+];
+
+/// Apply every migration that is not yet in the history table, returning the versions applied.
+///
+/// Each step and its history record are committed together, so after a crash the history
+/// matches exactly the steps that took effect.
+pub fn migrate(db: &Database) -> LinkfieldResult<Vec<u32>> {
+	let applied: Vec<u32> = migration_history(db)?.into_iter().map(|(v, _)| v).collect();
+	let mut newly_applied = Vec::new();
+	for migration in MIGRATIONS {
+		if applied.contains(&migration.version) {
+			continue;
+		}
+		let write_txn = db.begin_write()?;
+		(migration.apply)(&write_txn)?;
+		{
+			let mut history = write_txn.open_table(MIGRATION_HISTORY_TABLE)?;
+			history.insert(migration.version, iso8601_utc(SystemTime::now()).as_str())?;
+		}
+		write_txn.commit()?;
+		tracing::info!(
+			version = migration.version,
+			description = migration.description,
+			"Applied migration"
+		);
+		newly_applied.push(migration.version);
+	}
+	Ok(newly_applied)
+}
+
+/// Applied migrations as `(version, applied_at)`, sorted by version
+pub fn migration_history(db: &Database) -> LinkfieldResult<Vec<(u32, String)>> {
+	let read_txn = db.begin_read()?;
+	let table = match read_txn.open_table(MIGRATION_HISTORY_TABLE) {
+		Ok(table) => table,
+		Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+		Err(e) => return Err(e.into()),
+	};
+	let mut status = Vec::new();
+	for entry in table.iter()? {
+		let (version, applied_at) = entry?;
+		status.push((version.value(), applied_at.value().to_string()));
+	}
+	Ok(status)
+}
+
+/// [`migration_history`] for listing with `linkfield migrations`, logging a failed read and
+/// listing nothing
+pub fn migration_status(db: &Database) -> Vec<(u32, String)> {
+	migration_history(db).unwrap_or_else(|e| {
+		tracing::error!(error = %e, "Failed to read migration history");
+		Vec::new()
+	})
+}
+
+/// Format as `YYYY-MM-DDTHH:MM:SSZ`; times before the Unix epoch are clamped to it
+fn iso8601_utc(time: SystemTime) -> String {
+	let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+	let days = secs / 86_400;
+	let rem = secs % 86_400;
+	// Civil date from days since epoch (Howard Hinnant's algorithm)
+	let z = days + 719_468;
+	let era = z / 146_097;
+	let doe = z - era * 146_097;
+	let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + u64::from(month <= 2);
+	format!(
+		"{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+		rem / 3600,
+		rem % 3600 / 60,
+		rem % 60
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn test_iso8601_utc() {
+		assert_eq!(iso8601_utc(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+		let t = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+		assert_eq!(iso8601_utc(t), "2023-11-14T22:13:20Z");
+		let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+		assert_eq!(iso8601_utc(leap_day), "2000-02-29T00:00:00Z");
+	}
+}
@@ -0,0 +1,104 @@
+//! Error type of the database, serialization and watcher helpers
+
+use crate::file_cache::integrity::IntegrityError;
+use std::error::Error;
+use std::fmt;
+
+/// What went wrong in a linkfield library call, wrapping the underlying error
+#[derive(Debug)]
+pub enum LinkfieldError {
+	/// Encoding a value with bincode failed
+	Serialization(bincode::error::EncodeError),
+	/// Stored bytes could not be decoded, e.g. because they are corrupt
+	Deserialization(bincode::error::DecodeError),
+	/// Boxed because `redb::Error` is large and every fallible database call returns this
+	Database(Box<redb::Error>),
+	Io(std::io::Error),
+	/// The file system watcher could not be set up
+	Watcher(notify_debouncer_full::notify::Error),
+	/// The `file_cache` table failed its checksum check
+	Integrity(IntegrityError),
+	/// A configuration value or argument was rejected, e.g. the name of a checkpoint that
+	/// doesn't exist
+	Invalid(String),
+}
+
+pub type LinkfieldResult<T> = Result<T, LinkfieldError>;
+
+impl fmt::Display for LinkfieldError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Serialization(e) => write!(f, "serialization failed: {e}"),
+			Self::Deserialization(e) => write!(f, "deserialization failed: {e}"),
+			Self::Database(e) => write!(f, "database error: {e}"),
+			Self::Io(e) => write!(f, "I/O error: {e}"),
+			Self::Watcher(e) => write!(f, "watcher error: {e}"),
+			Self::Integrity(e) => write!(f, "integrity check failed: {e}"),
+			Self::Invalid(message) => write!(f, "invalid input: {message}"),
+		}
+	}
+}
+
+impl Error for LinkfieldError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::Serialization(e) => Some(e),
+			Self::Deserialization(e) => Some(e),
+			Self::Database(e) => Some(e.as_ref()),
+			Self::Io(e) => Some(e),
+			Self::Watcher(e) => Some(e),
+			Self::Integrity(e) => Some(e),
+			Self::Invalid(_) => None,
+		}
+	}
+}
+
+impl From<bincode::error::EncodeError> for LinkfieldError {
+	fn from(e: bincode::error::EncodeError) -> Self {
+		Self::Serialization(e)
+	}
+}
+
+impl From<bincode::error::DecodeError> for LinkfieldError {
+	fn from(e: bincode::error::DecodeError) -> Self {
+		Self::Deserialization(e)
+	}
+}
+
+impl From<std::io::Error> for LinkfieldError {
+	fn from(e: std::io::Error) -> Self {
+		Self::Io(e)
+	}
+}
+
+impl From<notify_debouncer_full::notify::Error> for LinkfieldError {
+	fn from(e: notify_debouncer_full::notify::Error) -> Self {
+		Self::Watcher(e)
+	}
+}
+
+impl From<IntegrityError> for LinkfieldError {
+	fn from(e: IntegrityError) -> Self {
+		Self::Integrity(e)
+	}
+}
+
+/// The specific redb errors convert through [`redb::Error`], so `?` works on every redb call
+macro_rules! from_redb_error {
+	($($error:ty),*) => {
+		$(impl From<$error> for LinkfieldError {
+			fn from(e: $error) -> Self {
+				Self::Database(Box::new(e.into()))
+			}
+		})*
+	};
+}
+
+from_redb_error!(
+	redb::Error,
+	redb::DatabaseError,
+	redb::TransactionError,
+	redb::TableError,
+	redb::StorageError,
+	redb::CommitError
+);
@@ -0,0 +1,98 @@
+// Record raw watcher events to a JSON lines file and replay them for offline debugging
+
+use notify_debouncer_full::DebouncedEvent;
+use notify_debouncer_full::notify::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// One line of a recording
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedEvent {
+	/// Milliseconds since the first event of the recording
+	offset_ms: u64,
+	event: Event,
+}
+
+/// Appends every event it is given to a file, one JSON object per line
+pub struct EventRecorder {
+	writer: BufWriter<File>,
+	first: Option<Instant>,
+}
+
+impl EventRecorder {
+	/// Start recording to `path`, appending if the file exists
+	pub fn create(path: &Path) -> std::io::Result<Self> {
+		let file = OpenOptions::new().create(true).append(true).open(path)?;
+		Ok(Self {
+			writer: BufWriter::new(file),
+			first: None,
+		})
+	}
+
+	/// Write `event` and flush, so a crash loses at most the event being written
+	pub fn record(&mut self, event: &DebouncedEvent) -> Result<(), Box<dyn Error>> {
+		let first = *self.first.get_or_insert(event.time);
+		let offset = event.time.saturating_duration_since(first);
+		let line = RecordedEvent {
+			offset_ms: u64::try_from(offset.as_millis()).unwrap_or(u64::MAX),
+			event: event.event.clone(),
+		};
+		serde_json::to_writer(&mut self.writer, &line)?;
+		self.writer.write_all(b"\n")?;
+		self.writer.flush()?;
+		Ok(())
+	}
+}
+
+/// Yields the events of a recording, spaced out like they originally were
+pub struct EventReplayer {
+	events: VecDeque<(Duration, Event)>,
+	started: Option<Instant>,
+	realtime: bool,
+}
+
+impl EventReplayer {
+	pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+		let mut events = VecDeque::new();
+		for line in BufReader::new(File::open(path)?).lines() {
+			let line = line?;
+			if line.trim().is_empty() {
+				continue;
+			}
+			let recorded: RecordedEvent = serde_json::from_str(&line)?;
+			events.push_back((Duration::from_millis(recorded.offset_ms), recorded.event));
+		}
+		Ok(Self {
+			events,
+			started: None,
+			realtime: true,
+		})
+	}
+
+	/// Yield events immediately instead of waiting for their original offsets. Event times
+	/// keep the recorded spacing either way.
+	#[must_use]
+	pub const fn without_delays(mut self) -> Self {
+		self.realtime = false;
+		self
+	}
+}
+
+impl Iterator for EventReplayer {
+	type Item = DebouncedEvent;
+
+	fn next(&mut self) -> Option<DebouncedEvent> {
+		let (offset, event) = self.events.pop_front()?;
+		let started = *self.started.get_or_insert_with(Instant::now);
+		let due = started + offset;
+		if self.realtime {
+			std::thread::sleep(due.saturating_duration_since(Instant::now()));
+		}
+		Some(DebouncedEvent::new(event, due))
+	}
+}
@@ -0,0 +1,133 @@
+// Fan-out of watch events to any number of in-process subscribers
+
+use crate::watcher::WatchEvent;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// Events are shared between subscribers, so cloning one is a reference count bump
+pub type SharedWatchEvent = Arc<WatchEvent>;
+
+/// Called on the publishing thread for every event, see [`EventBroadcaster::add_handler`]
+pub type EventHandler = Arc<dyn Fn(&WatchEvent) + Send + Sync>;
+
+/// Events a subscriber can fall behind by. Once its channel is full, further events are
+/// dropped for that subscriber until it catches up, so a slow reader never stalls the
+/// watcher or grows memory without bound.
+pub const SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// Delivers every published event to each live subscriber. Subscribers only receive events
+/// published after they subscribed; dropped receivers are pruned on the next publish.
+#[derive(Default)]
+pub struct EventBroadcaster {
+	state: Mutex<BroadcasterState>,
+}
+
+impl std::fmt::Debug for EventBroadcaster {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let state = self.lock_state();
+		f.debug_struct("EventBroadcaster")
+			.field("handlers", &state.handlers.len())
+			.field("subscribers", &state.subscribers.len())
+			.field("closed", &state.closed)
+			.finish()
+	}
+}
+
+#[derive(Default)]
+struct BroadcasterState {
+	handlers: Vec<EventHandler>,
+	subscribers: Vec<SyncSender<SharedWatchEvent>>,
+	closed: bool,
+}
+
+impl EventBroadcaster {
+	pub fn new() -> Arc<Self> {
+		Arc::new(Self::default())
+	}
+
+	/// Subscribing after [`EventBroadcaster::close`] yields a receiver that is already disconnected
+	pub fn subscribe(self: &Arc<Self>) -> EventReceiver {
+		let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_CAPACITY);
+		let mut state = self.lock_state();
+		if !state.closed {
+			state.subscribers.push(tx);
+		}
+		EventReceiver {
+			rx,
+			broadcaster: self.clone(),
+		}
+	}
+
+	/// Run `handler` on the publishing thread for every event, before it is sent to the
+	/// subscribed receivers. Handlers run in the order they were added and must not block.
+	pub fn add_handler(&self, handler: EventHandler) {
+		let mut state = self.lock_state();
+		if !state.closed {
+			state.handlers.push(handler);
+		}
+	}
+
+	pub fn publish(&self, event: WatchEvent) {
+		let event = Arc::new(event);
+		// Run without the lock, so a handler may subscribe
+		let handlers = self.lock_state().handlers.clone();
+		for handler in &handlers {
+			handler(&event);
+		}
+		self.lock_state()
+			.subscribers
+			.retain(|tx| match tx.try_send(event.clone()) {
+				Ok(()) => true,
+				Err(TrySendError::Full(_)) => {
+					tracing::warn!(event = ?event, "Subscriber is not keeping up, dropping event");
+					true
+				}
+				Err(TrySendError::Disconnected(_)) => false,
+			});
+	}
+
+	/// Disconnect all subscribers once no more events will be published
+	pub fn close(&self) {
+		let mut state = self.lock_state();
+		state.closed = true;
+		state.handlers.clear();
+		state.subscribers.clear();
+	}
+
+	/// Receivers currently subscribed, not counting handlers
+	pub fn subscriber_count(&self) -> usize {
+		self.lock_state().subscribers.len()
+	}
+
+	fn lock_state(&self) -> MutexGuard<'_, BroadcasterState> {
+		self.state.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+}
+
+/// Receiving end of a subscription. Cloning subscribes again, so the clone independently
+/// receives every event published from then on.
+#[derive(Debug)]
+pub struct EventReceiver {
+	rx: Receiver<SharedWatchEvent>,
+	broadcaster: Arc<EventBroadcaster>,
+}
+
+impl Clone for EventReceiver {
+	fn clone(&self) -> Self {
+		self.broadcaster.subscribe()
+	}
+}
+
+impl EventReceiver {
+	/// Block until the next event; `None` once the watcher has shut down
+	pub fn recv(&self) -> Option<SharedWatchEvent> {
+		self.rx.recv().ok()
+	}
+	pub fn try_recv(&self) -> Result<SharedWatchEvent, TryRecvError> {
+		self.rx.try_recv()
+	}
+	pub fn recv_timeout(&self, timeout: Duration) -> Result<SharedWatchEvent, RecvTimeoutError> {
+		self.rx.recv_timeout(timeout)
+	}
+}
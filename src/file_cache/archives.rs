@@ -0,0 +1,188 @@
+//! The contents of `.zip` files as virtual entries, kept current by [`ArchiveWatcher`]
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileCache;
+use crate::file_cache::diff::{DiffResult, diff_file_maps};
+use crate::file_cache::meta::{FileCachePath, FileMeta, FileType};
+use notify_debouncer_full::DebouncedEvent;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+impl FileCache {
+	/// Re-read the central directory of the zip file at `archive` and replace its
+	/// [virtual files](FileCache::virtual_files), returning how they changed. Only the
+	/// directory is read, nothing is decompressed. On error the previous entries are kept.
+	pub fn index_archive(&self, archive: &Path) -> LinkfieldResult<DiffResult> {
+		let mut files = read_archive(archive)?;
+		for meta in &mut files {
+			self.normalize_extension(meta);
+		}
+		let key = FileCachePath::from(archive);
+		let old: HashMap<_, _> = self
+			.virtual_files
+			.get(&key)
+			.map(|files| {
+				files
+					.iter()
+					.map(|meta| (meta.path.clone(), meta.clone()))
+					.collect()
+			})
+			.unwrap_or_default();
+		let new: HashMap<_, _> = files
+			.iter()
+			.map(|meta| (meta.path.clone(), meta.clone()))
+			.collect();
+		let diff = diff_file_maps(&old, &new);
+		self.virtual_files.insert(key, files);
+		Ok(diff)
+	}
+
+	/// Drop the virtual files of `archive`, returning them
+	pub fn remove_archive(&self, archive: &Path) -> Vec<FileMeta> {
+		self.virtual_files
+			.remove(&FileCachePath::from(archive))
+			.map(|(_, files)| files)
+			.unwrap_or_default()
+	}
+}
+
+/// Indexes archives as the watcher sees them created, modified, moved and removed. Set it
+/// as [`WatchConfig::archives`](crate::watcher::WatchConfig::archives).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveWatcher {
+	/// Extensions of the files read as zip archives, without the dot and matched regardless
+	/// of case. Formats such as `jar`, `docx` or `epub` are zip files too.
+	pub extensions: Vec<String>,
+}
+
+impl Default for ArchiveWatcher {
+	fn default() -> Self {
+		Self {
+			extensions: vec!["zip".to_string()],
+		}
+	}
+}
+
+impl ArchiveWatcher {
+	pub fn is_archive(&self, path: &Path) -> bool {
+		path.extension().is_some_and(|ext| {
+			self.extensions
+				.iter()
+				.any(|archive| ext.eq_ignore_ascii_case(archive))
+		})
+	}
+
+	/// Index every known archive: the ones in memory and, with a database attached, the
+	/// ones the scan stored there. Run it once the initial scan is done. Returns how many
+	/// were indexed.
+	pub fn index_cached(&self, cache: &FileCache) -> usize {
+		let mut archives: HashSet<PathBuf> = cache
+			.all_paths()
+			.filter(|path| self.is_archive(&path.0))
+			.map(|path| path.0)
+			.collect();
+		if let Some(db) = cache.database() {
+			let stored = FileCache::stream_from_redb(&db, |path, _| {
+				if self.is_archive(&path.0) {
+					archives.insert(path.0);
+				}
+			});
+			if let Err(e) = stored {
+				tracing::warn!(error = %e, "Failed to read the stored archives");
+			}
+		}
+		archives
+			.iter()
+			.filter(|archive| self.reindex(cache, archive))
+			.count()
+	}
+
+	/// Reindex the archives among the paths of `events`, dropping the ones that are gone
+	pub fn handle_events(&self, cache: &FileCache, events: &[DebouncedEvent]) {
+		for event in events.iter().filter(|event| !event.kind.is_access()) {
+			for path in event.paths.iter().filter(|path| self.is_archive(path)) {
+				if path.is_file() {
+					self.reindex(cache, path);
+				} else {
+					let removed = cache.remove_archive(path);
+					if !removed.is_empty() {
+						tracing::debug!(archive = %path.display(), removed = removed.len(), "Dropped archive entries");
+					}
+				}
+			}
+		}
+	}
+
+	/// Index one archive, returning whether that worked
+	fn reindex(&self, cache: &FileCache, archive: &Path) -> bool {
+		match cache.index_archive(archive) {
+			Ok(diff) => {
+				tracing::debug!(
+					archive = %archive.display(),
+					added = diff.added.len(),
+					modified = diff.modified.len(),
+					removed = diff.removed.len(),
+					"Indexed archive"
+				);
+				true
+			}
+			// Also happens while the archive is still being written
+			Err(e) => {
+				tracing::warn!(archive = %archive.display(), error = %e, "Failed to index archive");
+				false
+			}
+		}
+	}
+}
+
+/// The file entries of the zip at `archive`. Entries whose names would end up outside the
+/// archive, e.g. with `..`, are left out.
+fn read_archive(archive: &Path) -> LinkfieldResult<Vec<FileMeta>> {
+	let mut zip = zip::ZipArchive::new(File::open(archive)?).map_err(std::io::Error::from)?;
+	let mut files = Vec::with_capacity(zip.len());
+	for index in 0..zip.len() {
+		let entry = zip.by_index_raw(index).map_err(std::io::Error::from)?;
+		if entry.is_dir() {
+			continue;
+		}
+		let Some(inner) = entry.enclosed_name() else {
+			continue;
+		};
+		files.push(FileMeta {
+			path: FileCachePath(archive.join(&inner)),
+			size: entry.size(),
+			modified: entry.last_modified().and_then(zip_time),
+			created: None,
+			extension: inner
+				.extension()
+				.and_then(|e| e.to_str())
+				.map(str::to_string),
+			inode: None,
+			content_hash: None,
+			is_virtual: true,
+			file_type: FileType::Regular,
+			device: None,
+		});
+	}
+	Ok(files)
+}
+
+/// Zip timestamps have no time zone; they are read as UTC
+fn zip_time(time: zip::DateTime) -> Option<SystemTime> {
+	// Days since the epoch from the civil date (Howard Hinnant's algorithm)
+	let (month, day) = (u64::from(time.month()), u64::from(time.day()));
+	let year = u64::from(time.year()) - u64::from(month <= 2);
+	let era = year / 400;
+	let yoe = year - era * 400;
+	let mp = (month + 9) % 12;
+	let doy = (153 * mp + 2) / 5 + day - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	let days = (era * 146_097 + doe).checked_sub(719_468)?;
+	let secs = days * 86_400
+		+ u64::from(time.hour()) * 3600
+		+ u64::from(time.minute()) * 60
+		+ u64::from(time.second());
+	Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
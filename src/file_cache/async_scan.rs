@@ -0,0 +1,111 @@
+//! Scanning with `tokio::fs`, so slow directory reads on network file systems overlap
+//! instead of holding up rayon threads
+
+use crate::file_cache::FileCache;
+use crate::file_cache::meta::{FileCachePath, FileMeta, FileType};
+use crate::file_cache::scan::ScanConfig;
+use crate::ignore_config::IgnoreConfig;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// The entries of one directory with their metadata, symlinks left out
+type Listing = (PathBuf, std::io::Result<Vec<(PathBuf, Metadata)>>);
+
+impl FileCache {
+	/// Scan `dir` like [`FileCache::populate_from_dir`] without building a cache, returning
+	/// the files found. Needs a tokio runtime; the result can go straight into
+	/// [`FileCache::diff_and_update`].
+	pub async fn scan_dir_async(
+		dir: &Path,
+		ignore: &IgnoreConfig,
+	) -> HashMap<FileCachePath, FileMeta> {
+		Self::scan_dir_async_with_config(dir, ignore, &ScanConfig::default()).await
+	}
+
+	/// Like [`FileCache::scan_dir_async`], with the extension normalizer, special file
+	/// handling and [`ScanConfig::max_concurrent_dirs`] of `config`. Each directory is read
+	/// in its own task. Symlinks are always skipped and unreadable directories are logged
+	/// and left out.
+	pub async fn scan_dir_async_with_config(
+		dir: &Path,
+		ignore: &IgnoreConfig,
+		config: &ScanConfig,
+	) -> HashMap<FileCachePath, FileMeta> {
+		let mut files = HashMap::new();
+		// `IgnoreConfig::is_ignored` would stat on the runtime thread
+		let is_dir = tokio::fs::metadata(dir).await.is_ok_and(|m| m.is_dir());
+		if ignore.is_ignored_by_pattern(dir, is_dir) {
+			return files;
+		}
+		let permits = Arc::new(Semaphore::new(config.max_concurrent_dirs.max(1)));
+		let mut pending = JoinSet::new();
+		pending.spawn(read_listing(dir.to_path_buf(), permits.clone()));
+		while let Some(joined) = pending.join_next().await {
+			let (dir, listing) = match joined {
+				Ok(listing) => listing,
+				Err(e) => {
+					tracing::error!(error = %e, "Directory read task failed");
+					continue;
+				}
+			};
+			let entries = match listing {
+				Ok(entries) => entries,
+				Err(e) => {
+					tracing::warn!(error = %e, dir = %dir.display(), "Error reading dir");
+					continue;
+				}
+			};
+			for (path, metadata) in entries {
+				if metadata.is_dir() {
+					if !ignore.is_ignored_by_pattern(&path, true) {
+						pending.spawn(read_listing(path, permits.clone()));
+					}
+					continue;
+				}
+				let mut meta = FileMeta::from_metadata(&path, &metadata);
+				if meta.file_type != FileType::Regular && !config.include_special_files {
+					continue;
+				}
+				if ignore.is_ignored_with_size(&path, Some(meta.size)) {
+					continue;
+				}
+				if let Some(normalizer) = &config.extension_normalizer {
+					normalizer.normalize_meta(&mut meta);
+				}
+				files.insert(meta.path.clone(), meta);
+			}
+		}
+		files
+	}
+}
+
+/// Read `dir` once a permit is free, releasing it before the subdirectories are read
+async fn read_listing(dir: PathBuf, permits: Arc<Semaphore>) -> Listing {
+	let Ok(_permit) = permits.acquire().await else {
+		return (dir, Err(std::io::Error::other("scan semaphore closed")));
+	};
+	let listing = read_entries(&dir).await;
+	(dir, listing)
+}
+
+async fn read_entries(dir: &Path) -> std::io::Result<Vec<(PathBuf, Metadata)>> {
+	let mut read_dir = tokio::fs::read_dir(dir).await?;
+	let mut entries = Vec::new();
+	while let Some(entry) = read_dir.next_entry().await? {
+		// Entries that vanish while the directory is read are skipped
+		let Ok(file_type) = entry.file_type().await else {
+			continue;
+		};
+		if file_type.is_symlink() {
+			continue;
+		}
+		if let Ok(metadata) = entry.metadata().await {
+			entries.push((entry.path(), metadata));
+		}
+	}
+	Ok(entries)
+}
@@ -0,0 +1,138 @@
+//! Self-contained binary blob of the whole cache, for moving it between machines
+
+use crate::error::{LinkfieldError, LinkfieldResult};
+use crate::file_cache::FileCache;
+use crate::file_cache::meta::{FileCachePath, FileMeta};
+use bincode::{Decode, Encode, decode_from_slice, encode_to_vec};
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+/// Start of every blob. The last byte is the format version, bumped whenever the payload
+/// layout changes so older blobs can still be told apart and converted.
+pub const BLOB_MAGIC: &[u8; 8] = b"LFCACHE\x01";
+
+/// Magic, a flags byte and the little-endian `u64` payload length
+const HEADER_LEN: usize = BLOB_MAGIC.len() + 1 + 8;
+
+/// Flag bit set when the payload is zstd compressed
+const FLAG_ZSTD: u8 = 1;
+
+/// How [`FileCache::to_blob_with_options`] encodes the blob
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheBlobOptions {
+	/// Compress the payload with zstd; needs the `zstd` feature
+	pub compress: bool,
+}
+
+/// Payload of a version 1 blob
+#[derive(Encode, Decode)]
+struct BlobPayloadV1 {
+	root_name: String,
+	files: Vec<(FileCachePath, FileMeta)>,
+}
+
+fn invalid(message: &str) -> LinkfieldError {
+	Error::new(
+		ErrorKind::InvalidData,
+		format!("invalid cache blob: {message}"),
+	)
+	.into()
+}
+
+impl FileCache {
+	/// Encode every cached file into an uncompressed blob that doesn't depend on a database
+	pub fn to_blob(&self) -> LinkfieldResult<Vec<u8>> {
+		self.to_blob_with_options(CacheBlobOptions::default())
+	}
+
+	/// Like [`FileCache::to_blob`], encoded as set in `options`
+	pub fn to_blob_with_options(&self, options: CacheBlobOptions) -> LinkfieldResult<Vec<u8>> {
+		let root_name = self
+			.entries
+			.get(&self.root)
+			.map(|root| root.name.clone())
+			.unwrap_or_default();
+		let mut files: Vec<_> = self
+			.tree_files()
+			.into_iter()
+			.map(|meta| (meta.path.clone(), meta))
+			.collect();
+		files.sort_by(|a, b| a.0.cmp(&b.0));
+		let payload = encode_to_vec(
+			BlobPayloadV1 { root_name, files },
+			bincode::config::standard(),
+		)?;
+		let (flags, payload) = if options.compress {
+			(FLAG_ZSTD, compress(&payload)?)
+		} else {
+			(0, payload)
+		};
+		let mut blob = Vec::with_capacity(HEADER_LEN + payload.len());
+		blob.extend_from_slice(BLOB_MAGIC);
+		blob.push(flags);
+		blob.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+		blob.extend_from_slice(&payload);
+		Ok(blob)
+	}
+
+	/// New in-memory cache holding the files of a blob written by [`FileCache::to_blob`]
+	pub fn from_blob(bytes: &[u8]) -> LinkfieldResult<Arc<Self>> {
+		let magic = BLOB_MAGIC.len();
+		if bytes.len() < HEADER_LEN || bytes[..magic - 1] != BLOB_MAGIC[..magic - 1] {
+			return Err(invalid("missing header"));
+		}
+		let version = bytes[magic - 1];
+		if version != BLOB_MAGIC[magic - 1] {
+			return Err(invalid(&format!("unsupported version {version}")));
+		}
+		let flags = bytes[magic];
+		let mut len = [0; 8];
+		len.copy_from_slice(&bytes[magic + 1..HEADER_LEN]);
+		let payload = &bytes[HEADER_LEN..];
+		if u64::try_from(payload.len()).ok() != Some(u64::from_le_bytes(len)) {
+			return Err(invalid("payload length does not match the header"));
+		}
+		let decompressed;
+		let payload = if flags & FLAG_ZSTD == 0 {
+			payload
+		} else {
+			decompressed = decompress(payload)?;
+			&decompressed
+		};
+		let (decoded, _): (BlobPayloadV1, _) =
+			decode_from_slice(payload, bincode::config::standard())?;
+		let cache = Self::new_root(&decoded.root_name);
+		for (_, meta) in decoded.files {
+			cache.insert_meta(meta);
+		}
+		Ok(cache)
+	}
+}
+
+#[cfg(feature = "zstd")]
+fn compress(payload: &[u8]) -> LinkfieldResult<Vec<u8>> {
+	Ok(zstd::encode_all(payload, 0)?)
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(payload: &[u8]) -> LinkfieldResult<Vec<u8>> {
+	Ok(zstd::decode_all(payload)?)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress(_payload: &[u8]) -> LinkfieldResult<Vec<u8>> {
+	Err(Error::new(
+		ErrorKind::Unsupported,
+		"cache blob compression needs the `zstd` feature",
+	)
+	.into())
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_payload: &[u8]) -> LinkfieldResult<Vec<u8>> {
+	Err(Error::new(
+		ErrorKind::Unsupported,
+		"compressed cache blobs need the `zstd` feature",
+	)
+	.into())
+}
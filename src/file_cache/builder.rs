@@ -0,0 +1,136 @@
+//! [`FileCacheBuilder`]: configuring a [`FileCache`] before it is created
+
+use crate::file_cache::FileCache;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+/// How cached paths are compared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseSensitivity {
+	/// `Foo.txt` and `foo.txt` are different files
+	#[default]
+	Sensitive,
+	/// `Foo.txt` and `foo.txt` are the same file, as on default NTFS and APFS volumes. Paths
+	/// keep the case they were first seen with.
+	Insensitive,
+	/// Whatever the file system does, probed with [`CaseSensitivity::detect`] in the root,
+	/// see [`FileCacheBuilder::case_probe_dir`] for where else
+	Auto,
+}
+
+impl CaseSensitivity {
+	/// Whether the file system of `dir` tells names apart by case, found by creating a
+	/// lowercase probe file in `dir` and looking it up by its uppercase name
+	pub fn detect(dir: &Path) -> std::io::Result<Self> {
+		let name = format!(".linkfield-case-probe-{}", std::process::id());
+		let probe = dir.join(&name);
+		std::fs::File::create(&probe)?;
+		let found = dir.join(name.to_uppercase()).exists();
+		std::fs::remove_file(&probe)?;
+		Ok(if found {
+			Self::Insensitive
+		} else {
+			Self::Sensitive
+		})
+	}
+
+	/// Turn `Auto` into `Sensitive` or `Insensitive` for the cache rooted at `root` by probing
+	/// `probe_dir`, or the system temporary directory when `probe_dir` isn't writable. When
+	/// the root isn't a directory or both probes fail the cache is case-sensitive.
+	fn resolve(self, root: &Path, probe_dir: &Path) -> Self {
+		match self {
+			Self::Auto if root.is_dir() => Self::detect(probe_dir)
+				.or_else(|e| {
+					let temp = std::env::temp_dir();
+					tracing::debug!(
+						dir = %probe_dir.display(),
+						error = %e,
+						"Case probe failed, probing the temporary directory instead"
+					);
+					Self::detect(&temp)
+				})
+				.unwrap_or_else(|e| {
+					tracing::warn!(dir = %probe_dir.display(), error = %e, "Failed to detect case sensitivity");
+					Self::Sensitive
+				}),
+			Self::Auto => Self::Sensitive,
+			other => other,
+		}
+	}
+}
+
+/// Builds a [`FileCache`] with settings that can't change once it holds entries
+#[derive(Debug, Clone)]
+pub struct FileCacheBuilder {
+	root_name: String,
+	case_sensitivity: CaseSensitivity,
+	probe_dir: Option<PathBuf>,
+	probe_outside_root: bool,
+	hash_index: bool,
+}
+
+impl FileCacheBuilder {
+	pub fn new(root_name: &str) -> Self {
+		Self {
+			root_name: root_name.to_string(),
+			case_sensitivity: CaseSensitivity::default(),
+			probe_dir: None,
+			probe_outside_root: false,
+			hash_index: false,
+		}
+	}
+
+	pub fn case_sensitivity(mut self, case_sensitivity: CaseSensitivity) -> Self {
+		self.case_sensitivity = case_sensitivity;
+		self
+	}
+
+	/// Directory [`CaseSensitivity::Auto`] creates its probe file in, e.g. the one holding the
+	/// database, which should be on the same file system as the root. Defaults to the root
+	/// itself, or its parent with [`FileCacheBuilder::probe_outside_root`]. When the
+	/// directory isn't writable the probe falls back to the system temporary directory,
+	/// which may be on another file system.
+	pub fn case_probe_dir(mut self, dir: &Path) -> Self {
+		self.probe_dir = Some(dir.to_path_buf());
+		self
+	}
+
+	/// Probe case sensitivity in the root's parent instead of the root, for roots that must
+	/// stay untouched, e.g. while a watcher on them would report the probe file
+	pub fn probe_outside_root(mut self, enabled: bool) -> Self {
+		self.probe_outside_root = enabled;
+		self
+	}
+
+	/// Index the files stored in the database attached with [`FileCache::set_db`] by
+	/// content, see [`FileCache::hash_index`]. Files are indexed once they are stored with a
+	/// hash, e.g. by [`FileCache::populate_missing_hashes`].
+	pub fn enable_hash_index(mut self, enabled: bool) -> Self {
+		self.hash_index = enabled;
+		self
+	}
+
+	pub fn build(self) -> Arc<FileCache> {
+		let root = Path::new(&self.root_name);
+		let probe_dir = self.probe_dir.unwrap_or_else(|| match root.parent() {
+			Some(parent) if self.probe_outside_root && !parent.as_os_str().is_empty() => {
+				parent.to_path_buf()
+			}
+			_ => root.to_path_buf(),
+		});
+		let case_sensitivity = self.case_sensitivity.resolve(root, &probe_dir);
+		let cache = FileCache::with_case_sensitivity(&self.root_name, case_sensitivity);
+		cache
+			.hash_index_enabled
+			.store(self.hash_index, Ordering::Relaxed);
+		cache
+	}
+}
+
+impl FileCache {
+	/// Start configuring a cache rooted at `root_name`, see [`FileCacheBuilder`]
+	pub fn builder(root_name: &str) -> FileCacheBuilder {
+		FileCacheBuilder::new(root_name)
+	}
+}
@@ -1,8 +1,19 @@
 //! `FileCache`: in-memory and persistent file metadata cache
 
+use crate::file_cache::builder::CaseSensitivity;
+use crate::file_cache::event_stats::EventTracker;
+use crate::file_cache::extensions::ExtensionNormalizer;
+use crate::file_cache::freshness::LastUpdated;
+use crate::file_cache::meta::{CaseInsensitivePath, FileCachePath, FileType, fold_case};
+use crate::file_cache::scan::{ScanConfig, ScanError, ScanProgressReporter, ScanState};
+use crate::file_cache::sync::NEVER_SYNCED;
 use crate::ignore_config::IgnoreConfig;
-use dashmap::DashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
+use crate::shutdown::CancellationToken;
+use dashmap::{DashMap, DashSet};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 #[derive(Debug, Clone)]
 pub enum EntryKind {
@@ -27,16 +38,84 @@ pub struct DirEntry {
 	pub kind: EntryKind,
 }
 
+impl DirEntry {
+	pub fn is_file(&self) -> bool {
+		matches!(self.kind, EntryKind::File(_))
+	}
+}
+
+/// Key of the directory set, comparing paths the way the cache does
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum DirectoryKey {
+	Sensitive(FileCachePath),
+	Insensitive(CaseInsensitivePath),
+}
+
+impl DirectoryKey {
+	fn path(&self) -> &FileCachePath {
+		match self {
+			Self::Sensitive(path) | Self::Insensitive(CaseInsensitivePath(path)) => path,
+		}
+	}
+}
+
 /// `FileCache`: stores file and directory metadata in a tree using slotmap keys
 pub struct FileCache {
+	/// The tree of files and directories. Change it through the `FileCache` methods, which
+	/// keep [`FileCache::len`] in step.
 	pub entries: DashMap<u64, DirEntry>,
 	pub root: u64,
+	/// File entries in `entries`, so [`FileCache::len`] doesn't have to walk the tree
+	file_count: AtomicUsize,
 	key_counter: AtomicU64,
+	/// Paths of every directory seen while scanning or inserting files
+	directories: DashSet<DirectoryKey>,
+	/// `Sensitive` or `Insensitive`, see [`FileCacheBuilder`](crate::file_cache::FileCacheBuilder)
+	case_sensitivity: CaseSensitivity,
+	/// Cancelled on shutdown; scans stop descending and commit what they have
+	scan_cancel: CancellationToken,
+	/// Files and directories the last scan skipped because of ignore rules
+	last_scan_ignored: AtomicUsize,
+	/// Database attached with [`FileCache::set_db`]
+	pub(crate) attached_db: Mutex<Option<Arc<redb::Database>>>,
+	/// File events applied by the watcher
+	pub(crate) events: EventTracker,
+	/// Files [`FileCache::populate_missing_hashes`] could not hash
+	pub(crate) unhashable: DashSet<FileCachePath>,
+	/// Database generation the cache was last loaded or synced at, see
+	/// [`FileCache::is_stale_with_db`]
+	pub(crate) synced_generation: AtomicU64,
+	/// See [`FileCache::hash_index`]
+	pub(crate) hash_index_enabled: AtomicBool,
+	/// See [`FileCache::set_created_fallback_to_modified`]
+	pub(crate) created_fallback_to_modified: AtomicBool,
+	/// See [`FileCache::set_skip_locked_files`]
+	pub(crate) skip_locked_files: AtomicBool,
+	/// See [`FileCache::set_follow_symlinks`]
+	pub(crate) follow_symlinks: AtomicBool,
+	/// See [`FileCache::set_include_special_files`]
+	pub(crate) include_special_files: AtomicBool,
+	/// See [`FileCache::set_extension_normalizer`]
+	pub(crate) extension_normalizer: RwLock<Option<Arc<ExtensionNormalizer>>>,
+	/// See [`FileCache::last_updated_at`]
+	pub(crate) last_updated: Mutex<LastUpdated>,
+	/// Archive path -> the entries inside it, see [`FileCache::virtual_files`]
+	pub(crate) virtual_files: DashMap<FileCachePath, Vec<crate::file_cache::meta::FileMeta>>,
+	/// See [`FileCache::update_signature`]
+	pub(crate) signatures: DashMap<FileCachePath, crate::minhash::MinHash>,
 }
 
 impl FileCache {
 	/// Create a new file cache with a root directory
 	pub fn new_root(root_name: &str) -> std::sync::Arc<Self> {
+		Self::with_case_sensitivity(root_name, CaseSensitivity::Sensitive)
+	}
+	/// Create a new file cache comparing paths as `case_sensitivity` says, which must not
+	/// be `Auto`
+	pub(crate) fn with_case_sensitivity(
+		root_name: &str,
+		case_sensitivity: CaseSensitivity,
+	) -> std::sync::Arc<Self> {
 		let entries = DashMap::new();
 		let key_counter = AtomicU64::new(2); // Start at 2, root is 1
 		let root_key = 1u64;
@@ -51,16 +130,79 @@ impl FileCache {
 		std::sync::Arc::new(Self {
 			entries,
 			root: root_key,
+			file_count: AtomicUsize::new(0),
 			key_counter,
+			directories: DashSet::new(),
+			case_sensitivity,
+			scan_cancel: CancellationToken::new(),
+			last_scan_ignored: AtomicUsize::new(0),
+			attached_db: Mutex::new(None),
+			events: EventTracker::new(),
+			unhashable: DashSet::new(),
+			synced_generation: AtomicU64::new(NEVER_SYNCED),
+			hash_index_enabled: AtomicBool::new(false),
+			created_fallback_to_modified: AtomicBool::new(false),
+			skip_locked_files: AtomicBool::new(true),
+			follow_symlinks: AtomicBool::new(false),
+			include_special_files: AtomicBool::new(false),
+			extension_normalizer: RwLock::new(None),
+			last_updated: Mutex::new(LastUpdated::now()),
+			virtual_files: DashMap::new(),
+			signatures: DashMap::new(),
 		})
 	}
+	/// How the cache compares paths, never `Auto`
+	pub fn case_sensitivity(&self) -> CaseSensitivity {
+		self.case_sensitivity
+	}
+	fn directory_key(&self, dir: &Path) -> DirectoryKey {
+		match self.case_sensitivity {
+			CaseSensitivity::Insensitive => {
+				DirectoryKey::Insensitive(CaseInsensitivePath::from(dir))
+			}
+			_ => DirectoryKey::Sensitive(FileCachePath::from(dir)),
+		}
+	}
+	/// `path` without its leading `prefix`, comparing components regardless of case in
+	/// case-insensitive mode
+	pub(crate) fn strip_path_prefix<'a>(&self, path: &'a Path, prefix: &Path) -> Option<&'a Path> {
+		if self.case_sensitivity != CaseSensitivity::Insensitive {
+			return path.strip_prefix(prefix).ok();
+		}
+		let mut components = path.components();
+		for expected in prefix.components() {
+			let component = components.next()?;
+			if fold_case(&component.as_os_str().to_string_lossy())
+				!= fold_case(&expected.as_os_str().to_string_lossy())
+			{
+				return None;
+			}
+		}
+		Some(components.as_path())
+	}
+	/// `Path::starts_with`, comparing paths the way the cache does
+	pub(crate) fn path_starts_with(&self, path: &Path, prefix: &Path) -> bool {
+		self.strip_path_prefix(path, prefix).is_some()
+	}
+	/// Drop every file and directory from memory, keeping only the root
+	pub fn clear(&self) {
+		self.retain_entries(|&key, _| key == self.root);
+		self.directories.clear();
+		self.unhashable.clear();
+		self.virtual_files.clear();
+		self.signatures.clear();
+	}
+	/// Token that stops any running or future scan of this cache once cancelled
+	pub fn scan_cancellation_token(&self) -> CancellationToken {
+		self.scan_cancel.clone()
+	}
 	fn next_key(&self) -> u64 {
 		self.key_counter.fetch_add(1, Ordering::Relaxed)
 	}
 	/// Add a directory under a parent
 	pub fn add_dir(&self, name: &str, parent: u64) -> u64 {
 		let key = self.next_key();
-		self.entries.insert(
+		self.insert_entry(
 			key,
 			DirEntry {
 				name: name.to_string(),
@@ -78,13 +220,26 @@ impl FileCache {
 		meta: crate::file_cache::meta::FileMeta,
 	) -> u64 {
 		if let Some(existing) = self.find_child_by_name(parent, name) {
+			let mut renamed = None;
 			if let Some(mut entry) = self.entries.get_mut(&existing) {
-				entry.kind = EntryKind::File(meta);
+				// Keeps up with case-only renames in case-insensitive mode
+				if entry.name != name {
+					entry.name = name.to_string();
+				}
+				if let EntryKind::File(old) = &entry.kind
+					&& old.path != meta.path
+				{
+					renamed = Some(old.path.clone());
+				}
+				self.set_entry_kind(&mut entry, EntryKind::File(meta));
+			}
+			if let Some(old_path) = renamed {
+				self.forget_stored_case(&old_path);
 			}
 			existing
 		} else {
 			let key = self.next_key();
-			self.entries.insert(
+			self.insert_entry(
 				key,
 				DirEntry {
 					name: name.to_string(),
@@ -95,6 +250,15 @@ impl FileCache {
 			key
 		}
 	}
+	/// Delete the stored row of a file that is now cached under a path differing only in
+	/// case, as database keys are compared case-sensitively
+	fn forget_stored_case(&self, old_path: &FileCachePath) {
+		if let Some(db) = self.database()
+			&& let Err(e) = crate::file_cache::db::update_redb_single_remove(&db, old_path)
+		{
+			tracing::error!(path = %old_path, error = %e, "Failed to remove the stored path");
+		}
+	}
 	/// Remove an entry and all its descendants
 	pub fn remove_entry(&self, key: u64) {
 		let children: Vec<_> = self
@@ -106,10 +270,57 @@ impl FileCache {
 		for child in children {
 			self.remove_entry(child);
 		}
-		self.entries.remove(&key);
+		self.remove_key(key);
 	}
-	/// Find a child entry by name under a parent
+	/// Insert `entry` at `key`, counting it if it is a file
+	fn insert_entry(&self, key: u64, entry: DirEntry) {
+		let is_file = entry.is_file();
+		let replaced = self.entries.insert(key, entry);
+		self.count_files(replaced.as_ref().is_some_and(DirEntry::is_file), is_file);
+	}
+	/// Remove the entry at `key` only, not its descendants
+	fn remove_key(&self, key: u64) -> Option<DirEntry> {
+		let (_, removed) = self.entries.remove(&key)?;
+		self.count_files(removed.is_file(), false);
+		Some(removed)
+	}
+	/// `DashMap::retain` on the entries, uncounting the files dropped
+	pub(crate) fn retain_entries(&self, mut keep: impl FnMut(&u64, &mut DirEntry) -> bool) {
+		self.entries.retain(|key, entry| {
+			let kept = keep(key, entry);
+			if !kept {
+				self.count_files(entry.is_file(), false);
+			}
+			kept
+		});
+	}
+	/// Replace the kind of an entry held through `entries.get_mut`
+	pub(crate) fn set_entry_kind(&self, entry: &mut DirEntry, kind: EntryKind) {
+		let was_file = entry.is_file();
+		entry.kind = kind;
+		self.count_files(was_file, entry.is_file());
+	}
+	fn count_files(&self, was_file: bool, is_file: bool) {
+		match (was_file, is_file) {
+			(false, true) => {
+				self.file_count.fetch_add(1, Ordering::Relaxed);
+			}
+			(true, false) => {
+				self.file_count.fetch_sub(1, Ordering::Relaxed);
+			}
+			_ => {}
+		}
+	}
+	/// Find a child entry by name under a parent, regardless of case in case-insensitive mode
 	pub fn find_child_by_name(&self, parent: u64, name: &str) -> Option<u64> {
+		if self.case_sensitivity == CaseSensitivity::Insensitive {
+			let name = fold_case(name);
+			return self
+				.entries
+				.iter()
+				.find(|entry| entry.parent == Some(parent) && fold_case(&entry.name) == name)
+				.map(|entry| *entry.key());
+		}
 		self.entries
 			.iter()
 			.find(|entry| entry.parent == Some(parent) && entry.name == name)
@@ -160,97 +371,279 @@ impl FileCache {
 			_ => None,
 		}
 	}
+	/// Whether a file is cached at `path`, like `get(path).is_some()` without the clone
+	pub fn contains_path(&self, path: &Path) -> bool {
+		self.find_entry_by_path(path)
+			.and_then(|key| self.entries.get(&key))
+			.is_some_and(|entry| matches!(entry.kind, EntryKind::File(_)))
+	}
+	/// Whether any of `paths` is cached, stopping at the first one found
+	pub fn contains_any_of(&self, paths: &[&Path]) -> bool {
+		paths.iter().any(|path| self.contains_path(path))
+	}
+	/// Whether every one of `paths` is cached, stopping at the first one missing
+	pub fn contains_all_of(&self, paths: &[&Path]) -> bool {
+		paths.iter().all(|path| self.contains_path(path))
+	}
 	/// Remove a file or directory by path
 	pub fn remove_file(&self, path: &std::path::Path) {
 		if let Some(key) = self.find_entry_by_path(path) {
 			self.remove_entry(key);
 		}
+		self.mark_updated();
 	}
-	/// Update or insert a file by path
+	/// Update or insert a file by path. Directories are only recorded as known directories.
 	pub fn update_file(&self, path: &std::path::Path) {
-		if let Some(meta) = crate::file_cache::meta::FileMeta::from_path(path) {
-			let mut current = self.root;
-			let components: Vec<_> = path.components().collect();
-			let mut idx = 0;
-			// Skip root if it matches
-			if let Some(root_entry) = self.entries.get(&self.root) {
-				if !components.is_empty()
-					&& components[0].as_os_str().to_string_lossy() == root_entry.name
-				{
-					idx += 1;
-				}
+		self.mark_updated();
+		match self.read_single_meta(path) {
+			Some(meta) if meta.file_type == FileType::Directory => self.track_directory(path),
+			Some(meta) => {
+				self.insert_meta(meta);
 			}
-			for (i, comp) in components[idx..].iter().enumerate() {
-				let name = comp.as_os_str().to_string_lossy();
-				if i < components.len() - idx - 1 {
-					// Directory
-					if let Some(child) = self.find_child_by_name(current, &name) {
-						current = child;
-					} else {
-						current = self.add_dir(&name, current);
-					}
-				} else {
-					// Last component is file
-					self.update_or_insert_file(&name, current, meta.clone());
-				}
+			None => {}
+		}
+	}
+	/// Insert already-known metadata at its path, creating intermediate directories
+	pub fn insert_meta(&self, meta: crate::file_cache::meta::FileMeta) -> Option<u64> {
+		let mut current = self.root;
+		let path = meta.path.0.clone();
+		let components: Vec<_> = path.components().collect();
+		let mut idx = 0;
+		// Skip root if it matches
+		if let Some(root_entry) = self.entries.get(&self.root) {
+			if !components.is_empty()
+				&& components[0].as_os_str().to_string_lossy() == root_entry.name
+			{
+				idx += 1;
+			}
+		}
+		let (last, dirs) = components[idx..].split_last()?;
+		if let Some(parent) = path.parent() {
+			self.track_directory(parent);
+		}
+		for comp in dirs {
+			let name = comp.as_os_str().to_string_lossy();
+			if let Some(child) = self.find_child_by_name(current, &name) {
+				current = child;
+			} else {
+				current = self.add_dir(&name, current);
 			}
 		}
+		Some(self.update_or_insert_file(&last.as_os_str().to_string_lossy(), current, meta))
 	}
-	/// Recursively scan a directory and populate the tree, respecting ignore rules, using Rayon for parallelism
+	/// Scan `dir` and every subdirectory below it that isn't ignored, in parallel with Rayon,
+	/// and populate the tree. Returns the directories that could not be read.
 	pub fn scan_dir_collect_with_ignore(
 		&self,
 		dir: &std::path::Path,
 		ignore: &IgnoreConfig,
 		parent: Option<u64>,
-	) {
-		use rayon::prelude::*;
-		use std::fs;
-		let parent_key = parent.unwrap_or(self.root);
+	) -> Vec<ScanError> {
+		let state = ScanState::new(None);
+		let errors = self.scan_collect(dir, ignore, parent, &state);
+		self.set_ignored_count(state.ignored_count());
+		errors
+	}
+	/// Like [`FileCache::scan_dir_collect_with_ignore`] from the cache root, reporting progress
+	/// and the final summary as configured in `config`
+	pub fn scan_dir_with_config(
+		&self,
+		dir: &std::path::Path,
+		ignore: &IgnoreConfig,
+		config: &ScanConfig,
+	) -> Vec<ScanError> {
+		self.apply_scan_config(config);
+		let progress = ScanProgressReporter::new(config);
+		let state = ScanState::new(Some(&progress))
+			.with_follow_symlinks(config.follow_symlinks)
+			.with_include_special_files(config.include_special_files);
+		let errors = self.scan_collect(dir, ignore, None, &state);
+		let ignored = state.ignored_count();
+		drop(state);
+		self.set_ignored_count(ignored);
+		progress.finish(ignored);
+		errors
+	}
+	/// New in-memory cache rooted at `dir` and filled by scanning it, for querying without a
+	/// database. Unreadable directories are logged and left out.
+	pub fn populate_from_dir(dir: &Path, ignore: &IgnoreConfig) -> std::sync::Arc<Self> {
+		let cache = Self::new_root(dir.to_string_lossy().as_ref());
+		cache.scan_dir_collect_with_ignore(dir, ignore, None);
+		cache
+	}
+	/// Like [`FileCache::populate_from_dir`], reporting progress and the summary as
+	/// configured in `config`
+	pub fn populate_from_dir_with_config(
+		dir: &Path,
+		ignore: &IgnoreConfig,
+		config: &ScanConfig,
+	) -> std::sync::Arc<Self> {
+		let cache = Self::new_root(dir.to_string_lossy().as_ref());
+		cache.scan_dir_with_config(dir, ignore, config);
+		cache
+	}
+	/// Settings from `config` that stay with the cache after the scan
+	fn apply_scan_config(&self, config: &ScanConfig) {
+		if let Some(normalizer) = &config.extension_normalizer {
+			self.set_extension_normalizer(Some(normalizer.clone()));
+		}
+		self.set_skip_locked_files(config.skip_locked_files);
+		self.set_follow_symlinks(config.follow_symlinks);
+		self.set_include_special_files(config.include_special_files);
+	}
+	/// Whether [`FileCache::update_file`] and the other single-path updates record
+	/// symlinked files, like [`ScanConfig::follow_symlinks`]. Off by default; scans set it
+	/// from their config.
+	pub fn set_follow_symlinks(&self, follow: bool) {
+		self.follow_symlinks.store(follow, Ordering::Relaxed);
+	}
+	/// Whether [`FileCache::update_file`] and the other single-path updates record devices,
+	/// sockets and pipes, like [`ScanConfig::include_special_files`]. Off by default; scans
+	/// set it from their config.
+	pub fn set_include_special_files(&self, include: bool) {
+		self.include_special_files.store(include, Ordering::Relaxed);
+	}
+	/// The metadata of a single `path`, e.g. from a watcher event, kept or skipped the way a
+	/// scan would: symlinks are told apart with `symlink_metadata` and skipped unless
+	/// followed, other special files are skipped unless included. Directories are returned
+	/// so callers can track them.
+	pub(crate) fn read_single_meta(
+		&self,
+		path: &Path,
+	) -> Option<crate::file_cache::meta::FileMeta> {
+		let mut metadata = std::fs::symlink_metadata(path).ok()?;
+		if metadata.file_type().is_symlink() {
+			if !self.follow_symlinks.load(Ordering::Relaxed) {
+				tracing::debug!(path = %path.display(), "Skipping symlink");
+				return None;
+			}
+			metadata = std::fs::metadata(path).ok()?;
+		}
+		let mut meta = crate::file_cache::meta::FileMeta::from_metadata(path, &metadata);
+		let special = !matches!(meta.file_type, FileType::Regular | FileType::Directory);
+		if special && !self.include_special_files.load(Ordering::Relaxed) {
+			tracing::debug!(path = %path.display(), file_type = ?meta.file_type, "Skipping special file");
+			return None;
+		}
+		self.normalize_extension(&mut meta);
+		Some(meta)
+	}
+	/// Files and directories the most recent scan skipped because of ignore rules. A
+	/// skipped directory counts once, its contents are not visited.
+	pub fn last_scan_ignored_count(&self) -> usize {
+		self.last_scan_ignored.load(Ordering::Relaxed)
+	}
+	pub(crate) fn set_ignored_count(&self, count: usize) {
+		self.last_scan_ignored.store(count, Ordering::Relaxed);
+		if count > 0 {
+			tracing::info!(ignored = count, "Skipped entries matching ignore rules");
+		}
+	}
+	pub(crate) fn add_ignored_count(&self, count: usize) {
+		self.last_scan_ignored.fetch_add(count, Ordering::Relaxed);
+	}
+	pub(crate) fn scan_collect(
+		&self,
+		dir: &std::path::Path,
+		ignore: &IgnoreConfig,
+		parent: Option<u64>,
+		state: &ScanState<'_>,
+	) -> Vec<ScanError> {
+		if self.scan_cancel.is_cancelled() {
+			return Vec::new();
+		}
 		if ignore.is_ignored(dir) {
 			tracing::info!(ignore_match = %dir.display(), "ignoring directory due to ignore config");
-			return;
+			state.entry_ignored();
+			return Vec::new();
+		}
+		let parent_key = parent.unwrap_or(self.root);
+		self.scan_collect_dir(dir, ignore, parent_key, state)
+	}
+	/// Scan `dir`, which is known not to be ignored, and recurse into its subdirectories.
+	/// Entries are checked against the ignore patterns before their metadata is read.
+	fn scan_collect_dir(
+		&self,
+		dir: &std::path::Path,
+		ignore: &IgnoreConfig,
+		parent_key: u64,
+		state: &ScanState<'_>,
+	) -> Vec<ScanError> {
+		use rayon::prelude::*;
+		use std::fs;
+		if self.scan_cancel.is_cancelled() {
+			return Vec::new();
+		}
+		if !state.first_visit(dir) {
+			tracing::warn!(path = %dir.display(), "Circular symlink detected, skipping");
+			return Vec::new();
 		}
+		self.track_directory(dir);
 		let entries = match fs::read_dir(dir) {
-			Ok(e) => e.filter_map(Result::ok).collect::<Vec<_>>(),
+			Ok(e) => e
+				.filter_map(Result::ok)
+				.filter_map(|entry| state.walk_entry(&entry, ignore))
+				.collect::<Vec<_>>(),
 			Err(e) => {
 				tracing::warn!(error = %e, dir = %dir.display(), "Error reading dir");
-				return;
+				return vec![ScanError {
+					path: dir.to_path_buf(),
+					entry: parent_key,
+					error: e,
+				}];
 			}
 		};
 		// Collect file metas in parallel
 		let file_metas: Vec<_> = entries
 			.par_iter()
-			.filter_map(|entry| {
-				let path = entry.path();
-				if path.is_dir() || ignore.is_ignored(&path) {
+			.filter(|(_, is_dir)| !is_dir)
+			.filter_map(|(path, _)| {
+				let name = path.file_name().map(|n| n.to_string_lossy())?;
+				let started = std::time::Instant::now();
+				let meta = self.read_meta(path);
+				if let Some(progress) = state.progress {
+					let extension = path
+						.extension()
+						.and_then(|e| e.to_str())
+						.map(str::to_string);
+					progress.metadata_read(extension, started.elapsed());
+				}
+				let meta = meta.filter(|meta| !state.skip_special(meta))?;
+				if ignore.is_ignored_by_size(meta.size) {
+					state.entry_ignored();
 					return None;
 				}
-				let name = path.file_name().map(|n| n.to_string_lossy())?;
-				let meta = crate::file_cache::meta::FileMeta::from_path(&path)?;
 				Some((name.to_string(), meta))
 			})
 			.collect();
+		if let Some(progress) = state.progress {
+			progress.dir_scanned();
+		}
 		for (name, meta) in file_metas {
+			if let Some(progress) = state.progress {
+				progress.file_scanned(&meta);
+			}
 			self.update_or_insert_file(&name, parent_key, meta);
 		}
 		// Collect subdirs in parallel
 		let subdirs: Vec<_> = entries
 			.par_iter()
-			.filter_map(|entry| {
-				let path = entry.path();
-				if !path.is_dir() {
-					return None;
-				}
+			.filter(|(_, is_dir)| *is_dir)
+			.filter_map(|(path, _)| {
 				let name = path.file_name().map(|n| n.to_string_lossy())?;
 				Some((path.clone(), name.to_string()))
 			})
 			.collect();
-		for (_path, _name) in subdirs {
-			let _dir_key = self.add_dir(&_name, parent_key);
-			// self.scan_dir_collect_with_ignore_and_commit(&path, ignore, Some(dir_key));
-		}
+		subdirs
+			.par_iter()
+			.flat_map_iter(|(path, name)| {
+				let dir_key = self.add_dir(name, parent_key);
+				self.scan_collect_dir(path, ignore, dir_key, state)
+			})
+			.collect()
 	}
 	/// Parallel recursive scan and commit using Rayon. Thread-safe, full parallelism.
+	/// Returns the directories that could not be read.
 	pub fn scan_dir_collect_with_ignore_and_commit(
 		self: &std::sync::Arc<Self>,
 		db: &redb::Database,
@@ -258,42 +651,134 @@ impl FileCache {
 		ignore: &IgnoreConfig,
 		parent: Option<u64>,
 		batch_size: usize,
+		on_batch: Option<&mut dyn FnMut(usize)>,
+	) -> Vec<ScanError> {
+		let state = ScanState::new(None);
+		let errors = self.scan_commit(db, dir, ignore, parent, batch_size, on_batch, &state);
+		self.set_ignored_count(state.ignored_count());
+		errors
+	}
+	/// Like [`FileCache::scan_dir_collect_with_ignore_and_commit`] from the cache root,
+	/// reporting progress and the final summary as configured in `config`
+	pub fn scan_dir_with_config_and_commit(
+		self: &std::sync::Arc<Self>,
+		db: &redb::Database,
+		dir: &std::path::Path,
+		ignore: &IgnoreConfig,
+		batch_size: usize,
+		config: &ScanConfig,
+	) -> Vec<ScanError> {
+		self.apply_scan_config(config);
+		let progress = ScanProgressReporter::new(config);
+		let state = ScanState::new(Some(&progress))
+			.with_follow_symlinks(config.follow_symlinks)
+			.with_include_special_files(config.include_special_files);
+		let errors = self.scan_commit(db, dir, ignore, None, batch_size, None, &state);
+		let ignored = state.ignored_count();
+		drop(state);
+		self.set_ignored_count(ignored);
+		progress.finish(ignored);
+		errors
+	}
+	#[allow(clippy::too_many_arguments)]
+	pub(crate) fn scan_commit(
+		self: &std::sync::Arc<Self>,
+		db: &redb::Database,
+		dir: &std::path::Path,
+		ignore: &IgnoreConfig,
+		parent: Option<u64>,
+		batch_size: usize,
+		on_batch: Option<&mut dyn FnMut(usize)>,
+		state: &ScanState<'_>,
+	) -> Vec<ScanError> {
+		if self.scan_cancel.is_cancelled() {
+			return Vec::new();
+		}
+		if ignore.is_ignored(dir) {
+			tracing::info!(ignore_match = %dir.display(), "ignoring directory due to ignore config");
+			state.entry_ignored();
+			return Vec::new();
+		}
+		let parent_key = parent.unwrap_or(self.root);
+		self.scan_commit_dir(db, dir, ignore, parent_key, batch_size, on_batch, state)
+	}
+	/// Like [`FileCache::scan_collect_dir`], committing files to `db` in batches
+	#[allow(clippy::too_many_arguments)]
+	fn scan_commit_dir(
+		self: &std::sync::Arc<Self>,
+		db: &redb::Database,
+		dir: &std::path::Path,
+		ignore: &IgnoreConfig,
+		parent_key: u64,
+		batch_size: usize,
 		mut on_batch: Option<&mut dyn FnMut(usize)>,
-	) {
+		state: &ScanState<'_>,
+	) -> Vec<ScanError> {
 		use rayon::prelude::*;
 		use std::fs;
-		let parent_key = parent.unwrap_or(self.root);
-		if ignore.is_ignored(dir) {
-			tracing::info!(ignore_match = %dir.display(), "ignoring directory due to ignore config");
-			return;
+		if self.scan_cancel.is_cancelled() {
+			return Vec::new();
 		}
+		if !state.first_visit(dir) {
+			tracing::warn!(path = %dir.display(), "Circular symlink detected, skipping");
+			return Vec::new();
+		}
+		self.track_directory(dir);
 		let entries = match fs::read_dir(dir) {
-			Ok(e) => e.filter_map(Result::ok).collect::<Vec<_>>(),
+			Ok(e) => e
+				.filter_map(Result::ok)
+				.filter_map(|entry| state.walk_entry(&entry, ignore))
+				.collect::<Vec<_>>(),
 			Err(e) => {
 				tracing::warn!(error = %e, dir = %dir.display(), "Error reading dir");
-				return;
+				return vec![ScanError {
+					path: dir.to_path_buf(),
+					entry: parent_key,
+					error: e,
+				}];
 			}
 		};
+		if let Some(progress) = state.progress {
+			progress.dir_scanned();
+		}
 		let mut batch = Vec::with_capacity(batch_size);
 		let mut batch_keys = Vec::with_capacity(batch_size);
 		let mut batch_count = 0;
-		for entry in &entries {
-			let path = entry.path();
-			if path.is_dir() || ignore.is_ignored(&path) {
+		for (path, is_dir) in &entries {
+			if self.scan_cancel.is_cancelled() {
+				tracing::info!(dir = %dir.display(), "Scan cancelled, committing pending batch");
+				break;
+			}
+			if *is_dir {
 				continue;
 			}
 			let name = match path.file_name().map(|n| n.to_string_lossy()) {
 				Some(n) => n.to_string(),
 				None => continue,
 			};
-			if let Some(meta) = crate::file_cache::meta::FileMeta::from_path(&path) {
+			if let Some(meta) = self.read_meta(path).filter(|meta| {
+				if state.skip_special(meta) {
+					return false;
+				}
+				let ignored = ignore.is_ignored_by_size(meta.size);
+				if ignored {
+					state.entry_ignored();
+				}
+				!ignored
+			}) {
 				let key = self.update_or_insert_file(&name, parent_key, meta.clone());
 				batch.push((meta.path.clone(), meta.clone()));
 				batch_keys.push(key);
+				if let Some(progress) = state.progress {
+					progress.file_scanned(&meta);
+				}
 				if batch.len() >= batch_size {
-					crate::file_cache::db::update_redb_batch_commit(db, &[], &batch);
+					if let Err(e) = crate::file_cache::db::update_redb_batch_commit(db, &[], &batch)
+					{
+						tracing::error!(error = %e, dir = %dir.display(), "Failed to commit scanned batch");
+					}
 					for key in &batch_keys {
-						self.entries.remove(key);
+						self.remove_key(*key);
 					}
 					batch.clear();
 					batch_keys.clear();
@@ -305,9 +790,11 @@ impl FileCache {
 			}
 		}
 		if !batch.is_empty() {
-			crate::file_cache::db::update_redb_batch_commit(db, &[], &batch);
+			if let Err(e) = crate::file_cache::db::update_redb_batch_commit(db, &[], &batch) {
+				tracing::error!(error = %e, dir = %dir.display(), "Failed to commit scanned batch");
+			}
 			for key in &batch_keys {
-				self.entries.remove(key);
+				self.remove_key(*key);
 			}
 			batch_count += 1;
 			if let Some(cb) = on_batch.as_mut() {
@@ -317,35 +804,346 @@ impl FileCache {
 		// Collect subdirs and recurse in parallel
 		let subdirs: Vec<_> = entries
 			.iter()
-			.filter_map(|entry| {
-				let path = entry.path();
-				if !path.is_dir() {
-					return None;
-				}
+			.filter(|(_, is_dir)| *is_dir)
+			.filter_map(|(path, _)| {
 				let name = path.file_name().map(|n| n.to_string_lossy())?;
 				Some((path.clone(), name.to_string()))
 			})
 			.collect();
-		subdirs.par_iter().for_each(|(path, name)| {
-			let dir_key = self.add_dir(name, parent_key);
-			self.clone().scan_dir_collect_with_ignore_and_commit(
-				db,
-				path,
-				ignore,
-				Some(dir_key),
-				batch_size,
-				None, // Don't propagate callback to subdirs for simplicity
-			);
+		subdirs
+			.par_iter()
+			.flat_map_iter(|(path, name)| {
+				let dir_key = self.add_dir(name, parent_key);
+				self.scan_commit_dir(
+					db, path, ignore, dir_key, batch_size,
+					None, // Don't propagate callback to subdirs for simplicity
+					state,
+				)
+			})
+			.collect()
+	}
+	/// Record `dir` as a known directory
+	pub fn track_directory(&self, dir: &Path) {
+		if !dir.as_os_str().is_empty() {
+			self.directories.insert(self.directory_key(dir));
+		}
+	}
+	/// Number of known directories, i.e. the number of watches a recursive watcher needs
+	pub fn watch_dir_count(&self) -> usize {
+		self.directories.len()
+	}
+	pub fn contains_directory(&self, dir: &Path) -> bool {
+		self.directories.contains(&self.directory_key(dir))
+	}
+	/// All known directories below `dir` at any depth, not including `dir` itself
+	pub fn subdirectories_of<'a>(&'a self, dir: &Path) -> impl Iterator<Item = FileCachePath> + 'a {
+		// Collect first so no shard lock is held while the caller iterates
+		let subdirs: Vec<_> = self
+			.directories
+			.iter()
+			.map(|d| d.path().clone())
+			.filter(|d| {
+				self.strip_path_prefix(&d.0, dir)
+					.is_some_and(|below| below.components().next().is_some())
+			})
+			.collect();
+		subdirs.into_iter()
+	}
+	/// Forget a deleted directory: drops it and its subdirectories from the directory set and
+	/// removes every cached file under it in a single pass over the entries.
+	pub fn remove_directory(&self, dir: &Path) {
+		self.remove_prefix(None, dir);
+	}
+	/// Like [`FileCache::remove_directory`], also deleting the removed files from `db` in a
+	/// single transaction. Returns the number of files removed from the cache.
+	pub fn remove_prefix(&self, db: Option<&redb::Database>, prefix: &Path) -> usize {
+		self.directories
+			.retain(|d| !self.path_starts_with(&d.path().0, prefix));
+		let subtrees = self.entries_at(prefix);
+		let removed =
+			self.remove_files_where(db, |meta| self.path_starts_with(&meta.path.0, prefix));
+		for key in subtrees {
+			self.remove_entry(key);
+		}
+		removed
+	}
+	/// Keys of the entries for `prefix`, so the whole subtree can be dropped with them.
+	/// Scanned directories hang from the root by name only and can't always be found by
+	/// path, so they are also found by walking up from the files below `prefix`.
+	fn entries_at(&self, prefix: &Path) -> HashSet<u64> {
+		let files: Vec<_> = self
+			.entries
+			.iter()
+			.filter_map(|entry| match &entry.kind {
+				EntryKind::File(meta) => {
+					let below = self.strip_path_prefix(&meta.path.0, prefix)?;
+					Some((*entry.key(), below.components().count()))
+				}
+				EntryKind::Directory => None,
+			})
+			.collect();
+		let mut keys: HashSet<u64> = self.find_entry_by_path(prefix).into_iter().collect();
+		for (mut key, depth) in files {
+			for _ in 0..depth {
+				match self.entries.get(&key).and_then(|entry| entry.parent) {
+					Some(parent) => key = parent,
+					None => break,
+				}
+			}
+			keys.insert(key);
+		}
+		keys.remove(&self.root);
+		keys
+	}
+	/// Drop the cached files below `root` that `ignore` matches, themselves or through one of
+	/// their directories, e.g. after patterns were added to the ignore file. With a database
+	/// attached their stored rows are deleted too, including the ones of files no longer held
+	/// in memory. Returns the number of files removed, counting the stored rows when a
+	/// database is attached.
+	pub fn remove_ignored(&self, root: &Path, ignore: &IgnoreConfig) -> usize {
+		let ignored_dirs: Vec<PathBuf> = self
+			.directories
+			.iter()
+			.map(|dir| dir.path().0.clone())
+			.filter(|dir| {
+				self.strip_path_prefix(dir, root)
+					.is_some_and(|below| below.components().next().is_some())
+					&& ignore.is_ignored(dir)
+			})
+			.collect();
+		let mut removed = 0;
+		for dir in ignored_dirs {
+			removed += self.remove_prefix(None, &dir);
+		}
+		removed += self.remove_files_where(None, |meta| {
+			self.path_starts_with(&meta.path.0, root)
+				&& ignore.is_ignored_with_size(&meta.path.0, Some(meta.size))
+		});
+		let Some(db) = self.database() else {
+			return removed;
+		};
+		let mut stored = Vec::new();
+		let result = Self::stream_from_redb(&db, |path, meta| {
+			if path.0.starts_with(root)
+				&& (ignore.is_ignored_below(root, &path.0, false)
+					|| ignore.is_ignored_by_size(meta.size))
+			{
+				stored.push(path);
+			}
+		})
+		.and_then(|_| crate::file_cache::db::update_redb_batch_commit(&db, &stored, &[]));
+		if let Err(e) = result {
+			tracing::error!(error = %e, "Failed to remove ignored files from the database");
+		}
+		stored.len()
+	}
+	/// Like [`FileCache::update_file`] for several files, writing them to the attached
+	/// database in one batch. Returns the number of files added or updated.
+	pub fn update_files(&self, paths: &[PathBuf]) -> usize {
+		self.mark_updated();
+		let mut batch = Vec::new();
+		for path in paths {
+			match self.read_single_meta(path) {
+				Some(meta) if meta.file_type == FileType::Directory => self.track_directory(path),
+				Some(meta) => {
+					self.insert_meta(meta.clone());
+					batch.push((meta.path.clone(), meta));
+				}
+				None => {}
+			}
+		}
+		if let Some(db) = self.database().filter(|_| !batch.is_empty())
+			&& let Err(e) = crate::file_cache::db::update_redb_batch_commit(&db, &[], &batch)
+		{
+			tracing::error!(error = %e, "Failed to store updated files");
+		}
+		batch.len()
+	}
+	/// Remove every cached file whose full path matches the glob `pattern`, e.g. `**/*.o`
+	/// after a `git clean`. With `db` the rows are deleted in a single batch commit. Returns
+	/// the number of files removed.
+	pub fn glob_remove(
+		&self,
+		db: Option<&redb::Database>,
+		pattern: &str,
+	) -> Result<usize, Box<dyn std::error::Error>> {
+		let matcher = globset::Glob::new(pattern)?.compile_matcher();
+		Ok(self.remove_files_where(db, |meta| matcher.is_match(&meta.path.0)))
+	}
+	/// Remove every cached file with extension `ext`, given with or without the leading dot,
+	/// without compiling a glob. Returns the number of files removed.
+	pub fn extension_remove(&self, db: Option<&redb::Database>, ext: &str) -> usize {
+		let ext = ext.strip_prefix('.').unwrap_or(ext);
+		self.remove_files_where(db, |meta| meta.extension.as_deref() == Some(ext))
+	}
+	/// Remove the cached files matching `matches` in one pass over the entries, deleting them
+	/// from `db` in a single batch commit. Returns the number of files removed.
+	pub fn remove_files_where(
+		&self,
+		db: Option<&redb::Database>,
+		matches: impl Fn(&crate::file_cache::meta::FileMeta) -> bool,
+	) -> usize {
+		let mut removed = Vec::new();
+		self.retain_entries(|_, entry| match &entry.kind {
+			EntryKind::File(meta) if matches(meta) => {
+				removed.push(meta.path.clone());
+				false
+			}
+			_ => true,
 		});
+		if let Some(db) = db.filter(|_| !removed.is_empty())
+			&& let Err(e) = crate::file_cache::db::update_redb_batch_commit(db, &removed, &[])
+		{
+			tracing::error!(error = %e, "Failed to remove files from the database");
+		}
+		removed.len()
+	}
+	/// Move every cached file and directory under `old_prefix` to `new_prefix`, e.g. after a
+	/// directory rename. With `db` the old rows are deleted and the new ones written in a
+	/// single batch commit. Returns the number of files moved.
+	pub fn apply_rename(
+		&self,
+		db: Option<&redb::Database>,
+		old_prefix: &Path,
+		new_prefix: &Path,
+	) -> usize {
+		let moved_path = |path: &Path| {
+			self.strip_path_prefix(path, old_prefix)
+				.map(|relative| new_prefix.join(relative))
+		};
+		let dirs: Vec<_> = self
+			.directories
+			.iter()
+			.filter_map(|dir| moved_path(&dir.path().0))
+			.collect();
+		let files: Vec<_> = self
+			.tree_files()
+			.into_iter()
+			.filter_map(|mut meta| {
+				let old_path = meta.path.clone();
+				meta.path = FileCachePath(moved_path(&old_path.0)?);
+				Some((old_path, meta))
+			})
+			.collect();
+		self.remove_prefix(None, old_prefix);
+		for dir in &dirs {
+			self.track_directory(dir);
+		}
+		for (_, meta) in &files {
+			self.insert_meta(meta.clone());
+		}
+		if let Some(db) = db.filter(|_| !files.is_empty()) {
+			let old_paths: Vec<_> = files.iter().map(|(old, _)| old.clone()).collect();
+			let new_entries: Vec<_> = files
+				.into_iter()
+				.map(|(_, meta)| (meta.path.clone(), meta))
+				.collect();
+			if let Err(e) =
+				crate::file_cache::db::update_redb_batch_commit(db, &old_paths, &new_entries)
+			{
+				tracing::error!(error = %e, "Failed to commit renamed files");
+			}
+			return new_entries.len();
+		}
+		files.len()
 	}
-	/// Return all file metas in the tree
+	/// Return the regular files in the tree, followed by the
+	/// [virtual files](FileCache::virtual_files). Devices, sockets, pipes and anything else
+	/// cached are left out; see [`FileCache::all_entries`].
 	pub fn all_files(&self) -> Vec<crate::file_cache::meta::FileMeta> {
+		let mut files = self.all_entries();
+		files.retain(|meta| meta.file_type == FileType::Regular);
+		files
+	}
+	/// Like [`FileCache::all_files`], including entries of every file type
+	pub fn all_entries(&self) -> Vec<crate::file_cache::meta::FileMeta> {
+		let mut files = self.tree_files();
+		files.extend(self.virtual_files());
+		files
+	}
+	/// The file entries of the tree, of every file type and without virtual files: what
+	/// gets stored, copied or moved
+	pub(crate) fn tree_files(&self) -> Vec<crate::file_cache::meta::FileMeta> {
 		self.entries
 			.iter()
 			.filter_map(|entry| match &entry.kind {
 				EntryKind::File(meta) => Some(meta.clone()),
-				_ => None,
+				EntryKind::Directory => None,
 			})
 			.collect()
 	}
+	/// Entries of indexed archives, with `is_virtual` set and paths below the archive such
+	/// as `photos.zip/2024/a.jpg`. They are kept in memory only: scans and the database
+	/// never see them.
+	pub fn virtual_files(&self) -> Vec<crate::file_cache::meta::FileMeta> {
+		self.virtual_files
+			.iter()
+			.flat_map(|archive| archive.value().clone())
+			.collect()
+	}
+	/// Paths of all cached files, cloning only the path rather than the whole `FileMeta`.
+	///
+	/// The entries live in a `DashMap`, so the paths are collected up front instead of
+	/// borrowed; no shard lock is held while the caller iterates.
+	///
+	/// ```
+	/// use linkfield::file_cache::FileCache;
+	/// use std::path::Path;
+	///
+	/// let cache = FileCache::new_root("root");
+	/// let tracked = cache.all_paths().any(|p| p.0 == Path::new("root/a.txt"));
+	/// assert!(!tracked);
+	/// ```
+	pub fn all_paths(&self) -> impl Iterator<Item = FileCachePath> {
+		let paths: Vec<_> = self
+			.entries
+			.iter()
+			.filter_map(|entry| match &entry.kind {
+				EntryKind::File(meta) => Some(meta.path.clone()),
+				EntryKind::Directory => None,
+			})
+			.collect();
+		paths.into_iter()
+	}
+	/// Number of cached files, not counting directories
+	pub fn len(&self) -> usize {
+		self.file_count.load(Ordering::Relaxed)
+	}
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+	/// Capacity of the underlying entry map, files and directories together
+	pub fn capacity(&self) -> usize {
+		self.entries.capacity()
+	}
+	/// Paths of all cached files, sorted
+	///
+	/// ```
+	/// use linkfield::file_cache::FileCache;
+	///
+	/// let cache = FileCache::new_root("root");
+	/// for path in cache.all_paths_vec() {
+	///     println!("{path}");
+	/// }
+	/// ```
+	pub fn all_paths_vec(&self) -> Vec<FileCachePath> {
+		let mut paths: Vec<_> = self.all_paths().collect();
+		paths.sort_unstable();
+		paths
+	}
+}
+
+/// Iterate over `(path, meta)` pairs of every cached file. Entries live in a `DashMap`, so the
+/// pairs are cloned out rather than borrowed.
+impl IntoIterator for &FileCache {
+	type Item = (FileCachePath, crate::file_cache::meta::FileMeta);
+	type IntoIter = std::vec::IntoIter<Self::Item>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.all_files()
+			.into_iter()
+			.map(|meta| (meta.path.clone(), meta))
+			.collect::<Vec<_>>()
+			.into_iter()
+	}
 }
@@ -0,0 +1,53 @@
+//! Named snapshots of the cache for "what changed since ..." queries
+
+use crate::error::{LinkfieldError, LinkfieldResult};
+use crate::file_cache::FileCache;
+use crate::file_cache::diff::{DiffResult, diff_file_maps};
+use crate::file_cache::meta::FileMeta;
+use bincode::{decode_from_slice, encode_to_vec};
+
+/// Checkpoint name -> bincode-encoded list of `FileMeta` sorted by path
+pub const CHECKPOINTS_TABLE: redb::TableDefinition<&str, &[u8]> =
+	redb::TableDefinition::new("checkpoints");
+
+impl FileCache {
+	/// Store the current cached files under `name`, replacing any checkpoint with that name.
+	/// Returns the number of files recorded.
+	pub fn record_checkpoint(&self, db: &redb::Database, name: &str) -> LinkfieldResult<usize> {
+		let mut files = self.tree_files();
+		files.sort_by(|a, b| a.path.0.cmp(&b.path.0));
+		let bytes = encode_to_vec(&files, bincode::config::standard())?;
+		let write_txn = db.begin_write()?;
+		{
+			let mut table = write_txn.open_table(CHECKPOINTS_TABLE)?;
+			table.insert(name, bytes.as_slice())?;
+		}
+		write_txn.commit()?;
+		tracing::info!(
+			checkpoint = name,
+			files = files.len(),
+			"Recorded checkpoint"
+		);
+		Ok(files.len())
+	}
+
+	/// Files added, modified or removed since the checkpoint `name` was recorded
+	pub fn changed_since_checkpoint(
+		&self,
+		db: &redb::Database,
+		name: &str,
+	) -> LinkfieldResult<DiffResult> {
+		let read_txn = db.begin_read()?;
+		let table = read_txn.open_table(CHECKPOINTS_TABLE)?;
+		let bytes = table
+			.get(name)?
+			.ok_or_else(|| LinkfieldError::Invalid(format!("no checkpoint named '{name}'")))?;
+		let (files, _): (Vec<FileMeta>, _) =
+			decode_from_slice(bytes.value(), bincode::config::standard())?;
+		let old = files
+			.into_iter()
+			.map(|meta| (meta.path.clone(), meta))
+			.collect();
+		Ok(diff_file_maps(&old, &self.file_map()))
+	}
+}
@@ -1,30 +1,28 @@
 //! redb helpers for file cache
-use crate::file_cache::meta::{FileCachePath, FileMeta};
+use crate::error::LinkfieldResult;
+use crate::file_cache::dir_index::{remove_dir_index_prefix, update_dir_index};
+use crate::file_cache::hash_index::{
+	HashIndexUpdate, ensure_hash_index, rebuild_hash_index_if_kept,
+};
+use crate::file_cache::integrity::{self, Checksum, ChecksumUpdate};
+use crate::file_cache::meta::{FileCachePath, FileMeta, FileType};
+use bincode::{Decode, Encode, decode_from_slice, encode_to_vec};
+use redb::ReadableTable;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
 use tracing::debug;
 
 pub const FILE_CACHE_TABLE: redb::TableDefinition<&str, &[u8]> =
 	redb::TableDefinition::new("file_cache");
 
 /// Ensure the `file_cache` table exists in the database
-pub fn ensure_file_cache_table(db: &redb::Database) -> Result<(), Box<dyn std::error::Error>> {
-	let write_txn = match db.begin_write() {
-		Ok(txn) => txn,
-		Err(e) => {
-			tracing::error!(error = %e, "Failed to begin write txn");
-			return Err(Box::new(e));
-		}
-	};
-	match write_txn.open_table(FILE_CACHE_TABLE) {
-		Ok(_) => tracing::info!("file_cache table opened/created successfully"),
-		Err(e) => {
-			tracing::error!(error = %e, "Failed to open/create file_cache table");
-			std::process::exit(1);
-		}
-	}
-	if let Err(e) = write_txn.commit() {
-		tracing::error!(error = %e, "Failed to commit table creation");
-		std::process::exit(1);
-	}
+pub fn ensure_file_cache_table(db: &redb::Database) -> LinkfieldResult<()> {
+	let write_txn = db.begin_write()?;
+	write_txn.open_table(FILE_CACHE_TABLE)?;
+	write_txn.commit()?;
+	tracing::info!("file_cache table opened/created successfully");
 	Ok(())
 }
 
@@ -39,90 +37,575 @@ pub fn serialize_path(path: &FileCachePath) -> std::borrow::Cow<'_, str> {
 	path.0.to_string_lossy()
 }
 
+/// Remove `to_remove` and write `to_add_or_update` in one transaction, together with the
+/// checksum, the directory index and the hash index. Nothing is written if any step fails.
 pub fn update_redb_batch_commit(
 	db: &redb::Database,
 	to_remove: &[FileCachePath],
 	to_add_or_update: &[(FileCachePath, FileMeta)],
-) {
+) -> LinkfieldResult<()> {
 	debug!(
 		"Committing batch of {} files, removing {}",
 		to_add_or_update.len(),
 		to_remove.len()
 	);
-	let write_txn = match db.begin_write() {
-		Ok(txn) => txn,
-		Err(e) => {
-			tracing::error!(error = %e, "Failed to begin write txn");
-			return;
-		}
+	let write_txn = db.begin_write()?;
+	let mut hashes = HashIndexUpdate::new(&write_txn)?;
+	let mut table = write_txn.open_table(FILE_CACHE_TABLE)?;
+	let mut checksum = ChecksumUpdate::default();
+	for path in to_remove {
+		let key = serialize_path(path);
+		let old = table.remove(key.as_ref())?;
+		checksum.remove(&key, old.as_ref().map(|v| v.value()));
+		hashes.remove(&key, old.as_ref().map(|v| v.value()));
+	}
+	for (path, meta) in to_add_or_update {
+		let key = serialize_path(path);
+		let meta = with_stored_hash(&table, &key, meta)?;
+		let value = meta.serialize()?;
+		let old = table.insert(key.as_ref(), value.as_slice())?;
+		checksum.insert(&key, old.as_ref().map(|v| v.value()), &value);
+		hashes.insert(&key, old.as_ref().map(|v| v.value()), &meta);
+	}
+	drop(table);
+	checksum.commit(&write_txn)?;
+	hashes.commit(&write_txn)?;
+	let removed: Vec<_> = to_remove.iter().map(serialize_path).collect();
+	let added: Vec<_> = to_add_or_update
+		.iter()
+		.map(|(path, _)| serialize_path(path))
+		.collect();
+	update_dir_index(
+		&write_txn,
+		removed.iter().map(AsRef::as_ref),
+		added.iter().map(AsRef::as_ref),
+	)?;
+	write_txn.commit()?;
+	Ok(())
+}
+
+/// [`update_redb_batch_commit`] for a single changed file
+pub fn update_redb_single_insert(
+	db: &redb::Database,
+	path: &FileCachePath,
+	meta: &FileMeta,
+) -> LinkfieldResult<()> {
+	let write_txn = db.begin_write()?;
+	let mut hashes = HashIndexUpdate::new(&write_txn)?;
+	let mut table = write_txn.open_table(FILE_CACHE_TABLE)?;
+	let mut checksum = ChecksumUpdate::default();
+	let key = serialize_path(path);
+	let meta = with_stored_hash(&table, &key, meta)?;
+	let value = meta.serialize()?;
+	let old = table.insert(key.as_ref(), value.as_slice())?;
+	checksum.insert(&key, old.as_ref().map(|v| v.value()), &value);
+	hashes.insert(&key, old.as_ref().map(|v| v.value()), &meta);
+	drop(old);
+	drop(table);
+	checksum.commit(&write_txn)?;
+	hashes.commit(&write_txn)?;
+	update_dir_index(&write_txn, [], [key.as_ref()])?;
+	write_txn.commit()?;
+	Ok(())
+}
+
+/// `meta`, or a copy with the stored content hash if `meta` has none and the file kept its
+/// size and mtime, so that rescans don't throw the hashes away
+fn with_stored_hash<'a>(
+	table: &redb::Table<&str, &[u8]>,
+	key: &str,
+	meta: &'a FileMeta,
+) -> LinkfieldResult<Cow<'a, FileMeta>> {
+	if meta.content_hash.is_some() {
+		return Ok(Cow::Borrowed(meta));
+	}
+	let Some(stored) = table.get(key)? else {
+		return Ok(Cow::Borrowed(meta));
 	};
-	let mut table = match write_txn.open_table(FILE_CACHE_TABLE) {
-		Ok(t) => t,
-		Err(e) => {
-			tracing::error!(error = %e, "Failed to open file_cache table");
-			return;
+	Ok(match FileMeta::deserialize(stored.value()) {
+		Ok(old) if old.size == meta.size && old.modified == meta.modified => {
+			old.content_hash.map_or(Cow::Borrowed(meta), |hash| {
+				Cow::Owned(FileMeta {
+					content_hash: Some(hash),
+					..meta.clone()
+				})
+			})
 		}
+		_ => Cow::Borrowed(meta),
+	})
+}
+
+/// [`update_redb_batch_commit`] for a single removed file
+pub fn update_redb_single_remove(db: &redb::Database, path: &FileCachePath) -> LinkfieldResult<()> {
+	let write_txn = db.begin_write()?;
+	let mut hashes = HashIndexUpdate::new(&write_txn)?;
+	let mut table = write_txn.open_table(FILE_CACHE_TABLE)?;
+	let mut checksum = ChecksumUpdate::default();
+	let key = serialize_path(path);
+	let old = table.remove(key.as_ref())?;
+	checksum.remove(&key, old.as_ref().map(|v| v.value()));
+	hashes.remove(&key, old.as_ref().map(|v| v.value()));
+	drop(old);
+	drop(table);
+	checksum.commit(&write_txn)?;
+	hashes.commit(&write_txn)?;
+	update_dir_index(&write_txn, [key.as_ref()], [])?;
+	write_txn.commit()?;
+	Ok(())
+}
+
+/// The stored rows of those `paths` that have one, read in a single transaction
+pub fn load_stored_metas<'a>(
+	db: &redb::Database,
+	paths: impl IntoIterator<Item = &'a std::path::Path>,
+) -> LinkfieldResult<Vec<FileMeta>> {
+	let read_txn = db.begin_read()?;
+	let table = match read_txn.open_table(FILE_CACHE_TABLE) {
+		Ok(table) => table,
+		Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+		Err(e) => return Err(e.into()),
 	};
-	for path in to_remove {
-		if let Err(e) = table.remove(serialize_path(path).as_ref()) {
-			tracing::error!(error = %e, path = %path.0.display(), "Failed to remove file meta");
+	let mut metas = Vec::new();
+	for path in paths {
+		if let Some(value) = table.get(path.to_string_lossy().as_ref())? {
+			metas.push(FileMeta::deserialize(value.value())?);
 		}
 	}
-	for (path, meta) in to_add_or_update {
-		if let Err(e) = table.insert(serialize_path(path).as_ref(), meta.serialize().as_slice()) {
-			tracing::error!(error = %e, path = %path.0.display(), "Failed to insert/update file meta");
+	Ok(metas)
+}
+
+/// Entries per batch when [`crate::file_cache::FileCache::load_from_redb`] loads the table
+pub const DEFAULT_LOAD_BATCH_SIZE: usize = 1000;
+
+impl crate::file_cache::FileCache {
+	/// Load every entry of the `file_cache` table into the in-memory tree, returning the count
+	#[deprecated(note = "use `load_from_redb_batched`")]
+	pub fn load_from_redb(&self, db: &redb::Database) -> LinkfieldResult<usize> {
+		self.load_from_redb_batched(db, DEFAULT_LOAD_BATCH_SIZE, None)
+	}
+
+	/// Load every entry of the `file_cache` table into the in-memory tree `batch_size` rows
+	/// at a time, calling `on_batch` with the number loaded so far after each batch.
+	/// Returns the count, or [`integrity::IntegrityError::ChecksumMismatch`] once all rows
+	/// are loaded if they don't match the checksum stored with them.
+	pub fn load_from_redb_batched(
+		&self,
+		db: &redb::Database,
+		batch_size: usize,
+		mut on_batch: Option<&mut dyn FnMut(usize)>,
+	) -> LinkfieldResult<usize> {
+		let batch_size = batch_size.max(1);
+		let generation = integrity::stored_generation(&db.begin_read()?)?;
+		let mut batch = Vec::with_capacity(batch_size);
+		let mut count = 0;
+		let mut flush = |batch: &mut Vec<FileMeta>, count: &mut usize| {
+			*count += batch.len();
+			for meta in batch.drain(..) {
+				self.insert_meta(meta);
+			}
+			if let Some(cb) = on_batch.as_mut() {
+				cb(*count);
+			}
+		};
+		Self::stream_rows(db, true, |_, meta| {
+			batch.push(meta);
+			if batch.len() >= batch_size {
+				flush(&mut batch, &mut count);
+			}
+		})?;
+		if !batch.is_empty() {
+			flush(&mut batch, &mut count);
 		}
+		self.synced_generation.store(generation, Ordering::Relaxed);
+		self.restore_last_updated(db)?;
+		debug!("Loaded {count} file metas from redb");
+		Ok(count)
 	}
-	drop(table);
-	if let Err(e) = write_txn.commit() {
-		tracing::error!(error = %e, "Failed to commit batch diff update");
+
+	/// Attach `db` to a cache built in memory, writing every cached file to it in one batch.
+	/// Replaces any database attached before. Returns the number of files written.
+	pub fn set_db(&self, db: redb::Database) -> LinkfieldResult<usize> {
+		ensure_file_cache_table(&db)?;
+		if self.hash_index().is_some() {
+			ensure_hash_index(&db)?;
+		}
+		let batch: Vec<_> = self
+			.tree_files()
+			.into_iter()
+			.map(|meta| (meta.path.clone(), meta))
+			.collect();
+		update_redb_batch_commit(&db, &[], &batch)?;
+		*self
+			.attached_db
+			.lock()
+			.unwrap_or_else(std::sync::PoisonError::into_inner) = Some(Arc::new(db));
+		Ok(batch.len())
+	}
+
+	/// Take back the database attached with [`FileCache::set_db`], keeping the cached files.
+	/// `None` when there is none, or while another thread is still writing to it, in which
+	/// case it stays attached.
+	pub fn detach_db(&self) -> Option<redb::Database> {
+		let mut attached = self
+			.attached_db
+			.lock()
+			.unwrap_or_else(std::sync::PoisonError::into_inner);
+		match Arc::try_unwrap(attached.take()?) {
+			Ok(db) => Some(db),
+			Err(shared) => {
+				*attached = Some(shared);
+				None
+			}
+		}
+	}
+
+	/// Whether a database is attached with [`FileCache::set_db`]
+	pub fn has_db(&self) -> bool {
+		self.attached_db.lock().is_ok_and(|db| db.is_some())
+	}
+
+	/// The database attached with [`FileCache::set_db`], to use alongside the cache. Hold on
+	/// to it only briefly, [`FileCache::detach_db`] fails while it is shared.
+	pub fn database(&self) -> Option<Arc<redb::Database>> {
+		self.attached_db
+			.lock()
+			.unwrap_or_else(std::sync::PoisonError::into_inner)
+			.clone()
+	}
+
+	/// Call `callback` for every entry of the `file_cache` table without keeping them,
+	/// e.g. to compute statistics without loading the cache. Returns the count.
+	pub fn stream_from_redb<F: FnMut(FileCachePath, FileMeta)>(
+		db: &redb::Database,
+		callback: F,
+	) -> LinkfieldResult<usize> {
+		Self::stream_rows(db, false, callback)
+	}
+
+	/// [`FileCache::stream_from_redb`], checking the rows against the stored checksum after
+	/// the last one when `verify` is set
+	fn stream_rows<F: FnMut(FileCachePath, FileMeta)>(
+		db: &redb::Database,
+		verify: bool,
+		mut callback: F,
+	) -> LinkfieldResult<usize> {
+		let read_txn = db.begin_read()?;
+		let table = read_txn.open_table(FILE_CACHE_TABLE)?;
+		let stored = if verify {
+			integrity::stored_checksum(&read_txn)?
+		} else {
+			None
+		};
+		let mut computed = Checksum::default();
+		let mut count = 0;
+		for entry in table.iter()? {
+			let (key, value) = entry?;
+			if stored.is_some() {
+				computed.add(key.value(), value.value());
+			}
+			callback(
+				FileCachePath(key.value().into()),
+				FileMeta::deserialize(value.value())?,
+			);
+			count += 1;
+		}
+		if let Some(stored) = stored {
+			integrity::check(stored, computed)?;
+		}
+		Ok(count)
 	}
 }
 
-pub fn update_redb_single_insert(db: &redb::Database, path: &FileCachePath, meta: &FileMeta) {
-	let write_txn = match db.begin_write() {
-		Ok(txn) => txn,
-		Err(e) => {
-			tracing::error!(error = %e, "Failed to begin write txn");
-			return;
+impl crate::file_cache::FileCache {
+	/// Delete every stored file under `prefix` from `db` in one transaction, whether or not it
+	/// is loaded in memory. Returns the number of rows deleted.
+	pub fn remove_prefix_from_db_only(
+		db: &redb::Database,
+		prefix: &std::path::Path,
+	) -> LinkfieldResult<usize> {
+		let prefix_str = prefix.to_string_lossy();
+		let write_txn = db.begin_write()?;
+		let removed = {
+			let mut hashes = HashIndexUpdate::new(&write_txn)?;
+			let mut table = write_txn.open_table(FILE_CACHE_TABLE)?;
+			// Keys under the prefix sort right after it; the string prefix narrows the range and
+			// the path check drops siblings like `dir2` when removing `dir`
+			let mut keys = Vec::new();
+			let mut checksum = ChecksumUpdate::default();
+			for entry in table.range(prefix_str.as_ref()..)? {
+				let (key, _) = entry?;
+				let key = key.value();
+				if !key.starts_with(prefix_str.as_ref()) {
+					break;
+				}
+				if std::path::Path::new(key).starts_with(prefix) {
+					keys.push(key.to_string());
+				}
+			}
+			for key in &keys {
+				let old = table.remove(key.as_str())?;
+				checksum.remove(key, old.as_ref().map(|v| v.value()));
+				hashes.remove(key, old.as_ref().map(|v| v.value()));
+			}
+			drop(table);
+			checksum.commit(&write_txn)?;
+			hashes.commit(&write_txn)?;
+			keys.len()
+		};
+		remove_dir_index_prefix(&write_txn, prefix)?;
+		write_txn.commit()?;
+		debug!(prefix = %prefix.display(), removed, "Removed stored files under prefix");
+		Ok(removed)
+	}
+}
+
+/// `FileMeta` as stored before the `inode` field was added
+#[derive(Decode)]
+struct FileMetaV1 {
+	path: FileCachePath,
+	size: u64,
+	modified: Option<SystemTime>,
+	created: Option<SystemTime>,
+	extension: Option<String>,
+}
+
+/// `FileMeta` as stored before the `content_hash` field was added
+#[derive(Encode, Decode)]
+struct FileMetaV2 {
+	path: FileCachePath,
+	size: u64,
+	modified: Option<SystemTime>,
+	created: Option<SystemTime>,
+	extension: Option<String>,
+	inode: Option<u64>,
+}
+
+impl From<FileMetaV1> for FileMetaV2 {
+	fn from(old: FileMetaV1) -> Self {
+		Self {
+			path: old.path,
+			size: old.size,
+			modified: old.modified,
+			created: old.created,
+			extension: old.extension,
+			inode: None,
 		}
-	};
-	let mut table = match write_txn.open_table(FILE_CACHE_TABLE) {
-		Ok(t) => t,
-		Err(e) => {
-			tracing::error!(error = %e, "Failed to open file_cache table");
-			return;
+	}
+}
+
+/// `FileMeta` as stored before the `is_virtual` field was added
+#[derive(Encode, Decode)]
+struct FileMetaV3 {
+	path: FileCachePath,
+	size: u64,
+	modified: Option<SystemTime>,
+	created: Option<SystemTime>,
+	extension: Option<String>,
+	inode: Option<u64>,
+	content_hash: Option<u64>,
+}
+
+impl From<FileMetaV2> for FileMetaV3 {
+	fn from(old: FileMetaV2) -> Self {
+		Self {
+			path: old.path,
+			size: old.size,
+			modified: old.modified,
+			created: old.created,
+			extension: old.extension,
+			inode: old.inode,
+			content_hash: None,
 		}
-	};
-	if let Err(e) = table.insert(serialize_path(path).as_ref(), meta.serialize().as_slice()) {
-		tracing::error!(error = %e, path = %path.0.display(), "Failed to insert/update file meta");
 	}
-	drop(table);
-	if let Err(e) = write_txn.commit() {
-		tracing::error!(error = %e, "Failed to commit update");
+}
+
+/// `FileMeta` as stored before the `file_type` field was added
+#[derive(Encode, Decode)]
+struct FileMetaV4 {
+	path: FileCachePath,
+	size: u64,
+	modified: Option<SystemTime>,
+	created: Option<SystemTime>,
+	extension: Option<String>,
+	inode: Option<u64>,
+	content_hash: Option<u64>,
+	is_virtual: bool,
+}
+
+impl From<FileMetaV3> for FileMetaV4 {
+	fn from(old: FileMetaV3) -> Self {
+		Self {
+			path: old.path,
+			size: old.size,
+			modified: old.modified,
+			created: old.created,
+			extension: old.extension,
+			inode: old.inode,
+			content_hash: old.content_hash,
+			is_virtual: false,
+		}
 	}
 }
 
-pub fn update_redb_single_remove(db: &redb::Database, path: &FileCachePath) {
-	let write_txn = match db.begin_write() {
-		Ok(txn) => txn,
-		Err(e) => {
-			tracing::error!(error = %e, "Failed to begin write txn");
-			return;
+/// `FileMeta` as stored before the `device` field was added
+#[derive(Encode, Decode)]
+struct FileMetaV5 {
+	path: FileCachePath,
+	size: u64,
+	modified: Option<SystemTime>,
+	created: Option<SystemTime>,
+	extension: Option<String>,
+	inode: Option<u64>,
+	content_hash: Option<u64>,
+	is_virtual: bool,
+	file_type: FileType,
+}
+
+/// Entries stored before file types were recorded are taken to be regular files
+impl From<FileMetaV4> for FileMetaV5 {
+	fn from(old: FileMetaV4) -> Self {
+		Self {
+			path: old.path,
+			size: old.size,
+			modified: old.modified,
+			created: old.created,
+			extension: old.extension,
+			inode: old.inode,
+			content_hash: old.content_hash,
+			is_virtual: old.is_virtual,
+			file_type: FileType::Regular,
 		}
-	};
-	let mut table = match write_txn.open_table(FILE_CACHE_TABLE) {
-		Ok(t) => t,
-		Err(e) => {
-			tracing::error!(error = %e, "Failed to open file_cache table");
-			return;
+	}
+}
+
+/// The device is unknown until the next scan reads the file again
+impl From<FileMetaV5> for FileMeta {
+	fn from(old: FileMetaV5) -> Self {
+		Self {
+			path: old.path,
+			size: old.size,
+			modified: old.modified,
+			created: old.created,
+			extension: old.extension,
+			inode: old.inode,
+			content_hash: old.content_hash,
+			is_virtual: old.is_virtual,
+			file_type: old.file_type,
+			device: None,
 		}
-	};
-	if let Err(e) = table.remove(serialize_path(path).as_ref()) {
-		tracing::error!(error = %e, path = %path.0.display(), "Failed to remove file meta");
 	}
-	drop(table);
-	if let Err(e) = write_txn.commit() {
-		tracing::error!(error = %e, "Failed to commit remove");
+}
+
+/// Migration adding `inode` to the stored `FileMeta`s
+pub(crate) fn upgrade_file_metas_v1(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	upgrade_file_metas::<FileMetaV1, FileMetaV2>(txn)
+}
+
+/// Migration adding `content_hash` to the stored `FileMeta`s
+pub(crate) fn upgrade_file_metas_v2(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	upgrade_file_metas::<FileMetaV2, FileMetaV3>(txn)
+}
+
+/// Migration adding `is_virtual` to the stored `FileMeta`s
+pub(crate) fn upgrade_file_metas_v3(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	upgrade_file_metas::<FileMetaV3, FileMetaV4>(txn)
+}
+
+/// Migration adding `file_type` to the stored `FileMeta`s
+pub(crate) fn upgrade_file_metas_v4(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	upgrade_file_metas::<FileMetaV4, FileMetaV5>(txn)
+}
+
+/// Migration adding `device` to the stored `FileMeta`s
+pub(crate) fn upgrade_file_metas_v5(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	upgrade_file_metas::<FileMetaV5, FileMeta>(txn)
+}
+
+/// Migration prefixing the stored `FileMeta`s with [`FileMeta::FORMAT_VERSION`]. Only the
+/// `file_cache` table goes through [`FileMeta::serialize`]; checkpoints and the journal
+/// keep the plain encoding.
+pub(crate) fn upgrade_file_metas_v6(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	let config = bincode::config::standard();
+	let mut cache = txn.open_table(FILE_CACHE_TABLE)?;
+	let stored: Vec<(String, Vec<u8>)> = cache
+		.iter()?
+		.map(|entry| entry.map(|(k, v)| (k.value().to_string(), v.value().to_vec())))
+		.collect::<Result<_, _>>()?;
+	for (key, bytes) in stored
+		.into_iter()
+		.filter(|(_, bytes)| !is_current_layout(bytes))
+	{
+		match decode_from_slice::<FileMeta, _>(&bytes, config) {
+			Ok((meta, _)) => {
+				cache.insert(key.as_str(), meta.serialize()?.as_slice())?;
+			}
+			Err(e) => {
+				tracing::warn!(path = %key, error = %e, "Dropping unreadable cache entry");
+				cache.remove(key.as_str())?;
+			}
+		}
+	}
+	drop(cache);
+	integrity::rebuild_checksum(txn)?;
+	rebuild_hash_index_if_kept(txn)
+}
+
+/// Whether `bytes` already is a [`FileMeta::serialize`] value, e.g. one written through a
+/// database whose migrations were never recorded. Migrations leave those rows as they are.
+fn is_current_layout(bytes: &[u8]) -> bool {
+	bytes.split_first().is_some_and(|(&version, encoded)| {
+		version == FileMeta::FORMAT_VERSION
+			&& decode_from_slice::<FileMeta, _>(encoded, bincode::config::standard())
+				.is_ok_and(|(_, read)| read == encoded.len())
+	})
+}
+
+/// Re-encode the `FileMeta`s in the file cache, checkpoint and journal tables from the `Old`
+/// layout to the `New` one. Values that don't decode in the old layout are dropped; the next
+/// scan re-adds the files.
+fn upgrade_file_metas<Old: Decode<()>, New: From<Old> + Encode>(
+	txn: &redb::WriteTransaction,
+) -> LinkfieldResult<()> {
+	let config = bincode::config::standard();
+	let mut cache = txn.open_table(FILE_CACHE_TABLE)?;
+	let stored: Vec<(String, Vec<u8>)> = cache
+		.iter()?
+		.map(|entry| entry.map(|(k, v)| (k.value().to_string(), v.value().to_vec())))
+		.collect::<Result<_, _>>()?;
+	for (key, bytes) in stored
+		.into_iter()
+		.filter(|(_, bytes)| !is_current_layout(bytes))
+	{
+		match decode_from_slice::<Old, _>(&bytes, config) {
+			Ok((old, _)) => {
+				let new = encode_to_vec(New::from(old), config)?;
+				cache.insert(key.as_str(), new.as_slice())?;
+			}
+			Err(e) => {
+				tracing::warn!(path = %key, error = %e, "Dropping unreadable cache entry");
+				cache.remove(key.as_str())?;
+			}
+		}
+	}
+	drop(cache);
+	integrity::rebuild_checksum(txn)?;
+	let mut checkpoints = txn.open_table(crate::file_cache::checkpoint::CHECKPOINTS_TABLE)?;
+	let stored: Vec<(String, Vec<u8>)> = checkpoints
+		.iter()?
+		.map(|entry| entry.map(|(k, v)| (k.value().to_string(), v.value().to_vec())))
+		.collect::<Result<_, _>>()?;
+	for (name, bytes) in stored {
+		match decode_from_slice::<Vec<Old>, _>(&bytes, config) {
+			Ok((old, _)) => {
+				let files: Vec<New> = old.into_iter().map(New::from).collect();
+				checkpoints.insert(name.as_str(), encode_to_vec(&files, config)?.as_slice())?;
+			}
+			Err(e) => {
+				tracing::warn!(checkpoint = %name, error = %e, "Dropping unreadable checkpoint");
+				checkpoints.remove(name.as_str())?;
+			}
+		}
 	}
+	drop(checkpoints);
+	crate::change_journal::upgrade_journal_metas::<Old, New>(txn)
 }
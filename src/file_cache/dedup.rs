@@ -0,0 +1,185 @@
+//! Duplicate file detection by content hash
+
+use crate::file_cache::FileCache;
+use crate::file_cache::meta::{FileCachePath, FileMeta};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use xxhash_rust::xxh3::Xxh3;
+
+/// How much of each file is hashed when looking for duplicates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashGranularity {
+	/// Hash the whole file
+	#[default]
+	Full,
+	/// Hash only the first `n` bytes. Faster on large files, but files that share a prefix
+	/// and size are reported as duplicates even if they differ later on.
+	Prefix(u64),
+}
+
+/// Files that have identical size and content hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+	pub hash: u64,
+	pub size_each: u64,
+	pub paths: Vec<FileCachePath>,
+}
+
+impl DuplicateGroup {
+	/// Bytes that would be freed by keeping only one copy
+	pub fn wasted_bytes(&self) -> u64 {
+		self.size_each * (self.paths.len() as u64 - 1)
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeduplicationReport {
+	/// Groups ordered by wasted bytes, largest first
+	pub duplicate_groups: Vec<DuplicateGroup>,
+	pub total_wasted_bytes: u64,
+}
+
+impl DeduplicationReport {
+	/// Human-readable listing of the groups, one header line per group followed by its paths
+	pub fn format_table(&self) -> String {
+		let mut out = String::new();
+		for group in &self.duplicate_groups {
+			let _ = writeln!(
+				out,
+				"{:>14} wasted  {} x {} bytes  [{:016x}]",
+				group.wasted_bytes(),
+				group.paths.len(),
+				group.size_each,
+				group.hash
+			);
+			for path in &group.paths {
+				let _ = writeln!(out, "    {path}");
+			}
+		}
+		let _ = writeln!(
+			out,
+			"{} duplicate groups, {} bytes wasted",
+			self.duplicate_groups.len(),
+			self.total_wasted_bytes
+		);
+		out
+	}
+}
+
+/// xxh3 hash of the file contents, or of the first `n` bytes for [`HashGranularity::Prefix`]
+pub fn content_hash(path: &Path, granularity: HashGranularity) -> io::Result<u64> {
+	let file = File::open(path)?;
+	let mut reader: Box<dyn Read> = match granularity {
+		HashGranularity::Full => Box::new(file),
+		HashGranularity::Prefix(n) => Box::new(file.take(n)),
+	};
+	let mut hasher = Xxh3::new();
+	let mut buf = [0u8; 64 * 1024];
+	loop {
+		let read = reader.read(&mut buf)?;
+		if read == 0 {
+			break;
+		}
+		hasher.update(&buf[..read]);
+	}
+	Ok(hasher.digest())
+}
+
+/// [`content_hash`], or `None` when `skip_locked_files` is set and another process holds a
+/// write lock on `path`
+pub fn unlocked_content_hash(
+	path: &Path,
+	granularity: HashGranularity,
+	skip_locked_files: bool,
+) -> io::Result<Option<u64>> {
+	if skip_locked_files && crate::platform::is_file_locked(path) {
+		tracing::debug!(path = %path.display(), "Skipping locked file");
+		return Ok(None);
+	}
+	content_hash(path, granularity).map(Some)
+}
+
+impl FileCache {
+	/// Find duplicate files by hashing the full contents of every non-empty cached file
+	pub fn deduplication_report(&self) -> DeduplicationReport {
+		self.deduplication_report_with(HashGranularity::Full, 1)
+	}
+
+	/// Find duplicate files, skipping files smaller than `min_size`.
+	///
+	/// Only files that share their size with another file are read. Files that can no longer
+	/// be read, or that another process holds a write lock on, are left out of the report.
+	pub fn deduplication_report_with(
+		&self,
+		granularity: HashGranularity,
+		min_size: u64,
+	) -> DeduplicationReport {
+		self.deduplication_report_with_options(granularity, min_size, true)
+	}
+
+	/// Like [`FileCache::deduplication_report_with`]; with `skip_locked_files` false, files
+	/// locked for writing are hashed anyway and may get a hash of half-written contents
+	pub fn deduplication_report_with_options(
+		&self,
+		granularity: HashGranularity,
+		min_size: u64,
+		skip_locked_files: bool,
+	) -> DeduplicationReport {
+		let mut by_size: HashMap<u64, Vec<FileMeta>> = HashMap::new();
+		for meta in self.all_files() {
+			if meta.size >= min_size {
+				by_size.entry(meta.size).or_default().push(meta);
+			}
+		}
+		let candidates: Vec<FileMeta> = by_size
+			.into_values()
+			.filter(|metas| metas.len() > 1)
+			.flatten()
+			.collect();
+		let hashed: Vec<(u64, u64, FileCachePath)> = candidates
+			.into_par_iter()
+			.filter_map(|meta| {
+				match unlocked_content_hash(&meta.path.0, granularity, skip_locked_files) {
+					Ok(hash) => Some((meta.size, hash?, meta.path)),
+					Err(e) => {
+						tracing::debug!(path = %meta.path, error = %e, "Skipping unreadable file");
+						None
+					}
+				}
+			})
+			.collect();
+		let mut groups: HashMap<(u64, u64), Vec<FileCachePath>> = HashMap::new();
+		for (size, hash, path) in hashed {
+			groups.entry((size, hash)).or_default().push(path);
+		}
+		let mut duplicate_groups: Vec<DuplicateGroup> = groups
+			.into_iter()
+			.filter(|(_, paths)| paths.len() > 1)
+			.map(|((size_each, hash), mut paths)| {
+				paths.sort_by(|a, b| a.0.cmp(&b.0));
+				DuplicateGroup {
+					hash,
+					size_each,
+					paths,
+				}
+			})
+			.collect();
+		duplicate_groups.sort_by(|a, b| {
+			b.wasted_bytes()
+				.cmp(&a.wasted_bytes())
+				.then_with(|| a.paths[0].0.cmp(&b.paths[0].0))
+		});
+		let total_wasted_bytes = duplicate_groups
+			.iter()
+			.map(DuplicateGroup::wasted_bytes)
+			.sum();
+		DeduplicationReport {
+			duplicate_groups,
+			total_wasted_bytes,
+		}
+	}
+}
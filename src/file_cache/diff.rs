@@ -0,0 +1,199 @@
+//! Diffing a fresh scan against the cached state
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileCache;
+use crate::file_cache::cache::EntryKind;
+use crate::file_cache::db::DEFAULT_LOAD_BATCH_SIZE;
+use crate::file_cache::meta::{FileCachePath, FileMeta};
+use crate::ignore_config::IgnoreConfig;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Changes between two sets of file metadata
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffResult {
+	pub added: Vec<FileMeta>,
+	pub removed: Vec<FileMeta>,
+	/// `(old, new)` pairs for files whose size or modification time changed
+	pub modified: Vec<(FileMeta, FileMeta)>,
+}
+
+impl DiffResult {
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+	}
+
+	/// Total number of changed paths
+	pub fn len(&self) -> usize {
+		self.added.len() + self.removed.len() + self.modified.len()
+	}
+}
+
+/// What a scan would change in a cache, from [`FileCache::dry_run_scan`]. Paths are sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DryRunScanResult {
+	pub would_add: Vec<FileCachePath>,
+	/// Files whose size or modification time changed
+	pub would_update: Vec<FileCachePath>,
+	pub would_remove: Vec<FileCachePath>,
+	pub unchanged: usize,
+}
+
+/// Compare two path-keyed maps. A file counts as modified when its size or mtime differs.
+pub fn diff_file_maps(
+	old: &HashMap<FileCachePath, FileMeta>,
+	new: &HashMap<FileCachePath, FileMeta>,
+) -> DiffResult {
+	let mut diff = DiffResult::default();
+	for (path, new_meta) in new {
+		match old.get(path) {
+			None => diff.added.push(new_meta.clone()),
+			Some(old_meta)
+				if old_meta.size != new_meta.size || old_meta.modified != new_meta.modified =>
+			{
+				diff.modified.push((old_meta.clone(), new_meta.clone()));
+			}
+			Some(_) => {}
+		}
+	}
+	for (path, old_meta) in old {
+		if !new.contains_key(path) {
+			diff.removed.push(old_meta.clone());
+		}
+	}
+	diff
+}
+
+impl FileCache {
+	/// Snapshot of all cached files keyed by path
+	pub fn file_map(&self) -> HashMap<FileCachePath, FileMeta> {
+		self.entries
+			.iter()
+			.filter_map(|entry| match &entry.kind {
+				EntryKind::File(meta) => Some((meta.path.clone(), meta.clone())),
+				EntryKind::Directory => None,
+			})
+			.collect()
+	}
+
+	/// Scan `dir` into a separate cache and diff it against the cached files under `dir`,
+	/// leaving this cache and any database untouched
+	pub fn dry_run_scan(&self, dir: &Path, ignore: &IgnoreConfig) -> DryRunScanResult {
+		let scanned = Self::populate_from_dir(dir, ignore).file_map();
+		let cached: HashMap<_, _> = self
+			.file_map()
+			.into_iter()
+			.filter(|(path, _)| path.0.starts_with(dir))
+			.collect();
+		let diff = diff_file_maps(&cached, &scanned);
+		let sorted_paths = |metas: Vec<&FileMeta>| {
+			let mut paths: Vec<_> = metas.into_iter().map(|meta| meta.path.clone()).collect();
+			paths.sort();
+			paths
+		};
+		DryRunScanResult {
+			unchanged: scanned.len() - diff.added.len() - diff.modified.len(),
+			would_add: sorted_paths(diff.added.iter().collect()),
+			would_update: sorted_paths(diff.modified.iter().map(|(_, new)| new).collect()),
+			would_remove: sorted_paths(diff.removed.iter().collect()),
+		}
+	}
+
+	/// Keys of the file entries whose path is in `paths`
+	fn file_keys_for(&self, paths: &HashSet<&FileCachePath>) -> HashMap<FileCachePath, u64> {
+		self.entries
+			.iter()
+			.filter_map(|entry| match &entry.kind {
+				EntryKind::File(meta) if paths.contains(&meta.path) => {
+					Some((meta.path.clone(), *entry.key()))
+				}
+				_ => None,
+			})
+			.collect()
+	}
+
+	/// Diff `scanned` against the cached files, apply the changes to the tree and, when a
+	/// database is given, commit them in a single batch.
+	pub fn diff_and_update(
+		&self,
+		scanned: &HashMap<FileCachePath, FileMeta>,
+		db: Option<&redb::Database>,
+	) -> DiffResult {
+		let diff = diff_file_maps(&self.file_map(), scanned);
+		self.apply_diff(&diff, db);
+		diff
+	}
+
+	/// Scan `dir` and merge the result into the cache: new files are added and changed ones
+	/// updated, but only files under `dir` can be removed, so the cache can hold several
+	/// roots. With `db` the changes are committed in a single batch.
+	pub fn merge_scan(
+		&self,
+		dir: &Path,
+		ignore: &IgnoreConfig,
+		db: Option<&redb::Database>,
+	) -> DiffResult {
+		let scanned = Self::populate_from_dir(dir, ignore).file_map();
+		let mut diff = diff_file_maps(&self.file_map(), &scanned);
+		diff.removed.retain(|meta| meta.path.0.starts_with(dir));
+		self.apply_diff(&diff, db);
+		diff
+	}
+
+	/// Scan `dir` and merge the result into the files stored in `db`, like
+	/// [`FileCache::merge_scan`], for caches that keep their files in the database instead of
+	/// in memory, e.g. the watch mode's after its initial scan. Counts as an update of this
+	/// cache for [`FileCache::warn_if_stale`].
+	pub fn rescan_into_db(
+		&self,
+		db: &redb::Database,
+		dir: &Path,
+		ignore: &IgnoreConfig,
+	) -> LinkfieldResult<DiffResult> {
+		let stored = Self::new_root(dir.to_string_lossy().as_ref());
+		stored.load_from_redb_batched(db, DEFAULT_LOAD_BATCH_SIZE, None)?;
+		let diff = stored.merge_scan(dir, ignore, Some(db));
+		self.mark_updated();
+		Ok(diff)
+	}
+
+	fn apply_diff(&self, diff: &DiffResult, db: Option<&redb::Database>) {
+		self.mark_updated();
+		let touched: HashSet<&FileCachePath> = diff
+			.removed
+			.iter()
+			.chain(diff.modified.iter().map(|(old, _)| old))
+			.map(|meta| &meta.path)
+			.collect();
+		let keys = self.file_keys_for(&touched);
+		for meta in &diff.removed {
+			if let Some(key) = keys.get(&meta.path) {
+				self.remove_entry(*key);
+			}
+		}
+		for (_, new_meta) in &diff.modified {
+			if let Some(mut entry) = keys
+				.get(&new_meta.path)
+				.and_then(|k| self.entries.get_mut(k))
+			{
+				self.set_entry_kind(&mut entry, EntryKind::File(new_meta.clone()));
+			}
+		}
+		for meta in &diff.added {
+			self.insert_meta(meta.clone());
+		}
+		if let Some(db) = db {
+			let to_remove: Vec<_> = diff.removed.iter().map(|m| m.path.clone()).collect();
+			let to_add: Vec<_> = diff
+				.added
+				.iter()
+				.chain(diff.modified.iter().map(|(_, new)| new))
+				.map(|m| (m.path.clone(), m.clone()))
+				.collect();
+			if let Err(e) = crate::file_cache::db::update_redb_batch_commit(db, &to_remove, &to_add)
+			{
+				tracing::error!(error = %e, "Failed to commit diff");
+			}
+		}
+	}
+}
@@ -0,0 +1,133 @@
+//! Human readable and JSON reports of a [`DiffResult`]
+
+use crate::file_cache::FileCache;
+use crate::file_cache::FileMeta;
+use crate::file_cache::diff::DiffResult;
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Up to `max_items` changes of each kind from a [`DiffResult`], plus the full counts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffReport {
+	pub added_total: usize,
+	pub removed_total: usize,
+	pub modified_total: usize,
+	/// Largest first
+	pub added: Vec<ReportedFile>,
+	/// Largest first
+	pub removed: Vec<ReportedFile>,
+	/// Sorted by path
+	pub modified: Vec<ReportedChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReportedFile {
+	pub path: PathBuf,
+	pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReportedChange {
+	pub path: PathBuf,
+	pub old_size: u64,
+	pub new_size: u64,
+}
+
+impl DiffReport {
+	pub fn new(diff: &DiffResult, max_items: usize) -> Self {
+		let largest_first = |metas: &[FileMeta]| {
+			let mut files: Vec<_> = metas
+				.iter()
+				.map(|meta| ReportedFile {
+					path: meta.path.0.clone(),
+					size: meta.size,
+				})
+				.collect();
+			files.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+			files.truncate(max_items);
+			files
+		};
+		let mut modified: Vec<_> = diff
+			.modified
+			.iter()
+			.map(|(old, new)| ReportedChange {
+				path: new.path.0.clone(),
+				old_size: old.size,
+				new_size: new.size,
+			})
+			.collect();
+		modified.sort_by(|a, b| a.path.cmp(&b.path));
+		modified.truncate(max_items);
+		Self {
+			added_total: diff.added.len(),
+			removed_total: diff.removed.len(),
+			modified_total: diff.modified.len(),
+			added: largest_first(&diff.added),
+			removed: largest_first(&diff.removed),
+			modified,
+		}
+	}
+
+	pub fn to_json(&self) -> String {
+		serde_json::to_string(self).unwrap_or_default()
+	}
+}
+
+/// `and N more <kind>` when the list was cut short
+fn write_remainder(
+	f: &mut fmt::Formatter<'_>,
+	shown: usize,
+	total: usize,
+	kind: &str,
+) -> fmt::Result {
+	if total > shown {
+		writeln!(f, "  ... and {} more {kind}", total - shown)?;
+	}
+	Ok(())
+}
+
+impl fmt::Display for DiffReport {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(
+			f,
+			"{} added, {} removed, {} modified",
+			self.added_total, self.removed_total, self.modified_total
+		)?;
+		if self.added_total > 0 {
+			writeln!(f, "Added:")?;
+			for file in &self.added {
+				writeln!(f, "  + {} ({} bytes)", file.path.display(), file.size)?;
+			}
+			write_remainder(f, self.added.len(), self.added_total, "added")?;
+		}
+		if self.removed_total > 0 {
+			writeln!(f, "Removed:")?;
+			for file in &self.removed {
+				writeln!(f, "  - {} ({} bytes)", file.path.display(), file.size)?;
+			}
+			write_remainder(f, self.removed.len(), self.removed_total, "removed")?;
+		}
+		if self.modified_total > 0 {
+			writeln!(f, "Modified:")?;
+			for change in &self.modified {
+				writeln!(
+					f,
+					"  ~ {} ({} -> {} bytes)",
+					change.path.display(),
+					change.old_size,
+					change.new_size
+				)?;
+			}
+			write_remainder(f, self.modified.len(), self.modified_total, "modified")?;
+		}
+		Ok(())
+	}
+}
+
+impl FileCache {
+	/// Text report of `diff` listing at most `max_items` paths of each kind
+	pub fn diff_report(diff: &DiffResult, max_items: usize) -> String {
+		DiffReport::new(diff, max_items).to_string()
+	}
+}
@@ -0,0 +1,170 @@
+//! Per-directory index of the `file_cache` table, so listing one directory reads only its
+//! own rows instead of scanning the key range
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileCache;
+use crate::file_cache::db::FILE_CACHE_TABLE;
+use crate::file_cache::meta::FileMeta;
+use bincode::{Decode, Encode, decode_from_slice, encode_to_vec};
+use redb::ReadableTable;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::path::Path;
+
+/// Directory path -> bincode-encoded [`DirectoryIndex`] of the files stored directly in it
+pub const DIR_INDEX_TABLE: redb::TableDefinition<&str, &[u8]> =
+	redb::TableDefinition::new("dir_index");
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode)]
+pub struct DirectoryIndex {
+	/// File names, sorted
+	pub children: Vec<String>,
+}
+
+/// Split a `file_cache` key into its directory key and file name
+fn split_key(key: &str) -> (String, String) {
+	let path = Path::new(key);
+	let dir = path
+		.parent()
+		.map(|p| p.to_string_lossy().to_string())
+		.unwrap_or_default();
+	let name = path
+		.file_name()
+		.map_or_else(|| key.to_string(), |n| n.to_string_lossy().to_string());
+	(dir, name)
+}
+
+fn read_index(
+	table: &impl ReadableTable<&'static str, &'static [u8]>,
+	dir: &str,
+) -> LinkfieldResult<DirectoryIndex> {
+	Ok(match table.get(dir)? {
+		Some(bytes) => decode_from_slice(bytes.value(), bincode::config::standard())?.0,
+		None => DirectoryIndex::default(),
+	})
+}
+
+/// The index of `dir` being edited, read from `table` the first time it is touched
+fn touched_index<'m>(
+	touched: &'m mut HashMap<String, DirectoryIndex>,
+	table: &impl ReadableTable<&'static str, &'static [u8]>,
+	dir: String,
+) -> LinkfieldResult<&'m mut DirectoryIndex> {
+	Ok(match touched.entry(dir) {
+		Entry::Occupied(entry) => entry.into_mut(),
+		Entry::Vacant(entry) => {
+			let index = read_index(table, entry.key())?;
+			entry.insert(index)
+		}
+	})
+}
+
+/// Apply removed and added `file_cache` keys to the index inside `txn`, so the index
+/// commits or rolls back together with the main table
+pub(crate) fn update_dir_index<'a>(
+	txn: &redb::WriteTransaction,
+	removed: impl IntoIterator<Item = &'a str>,
+	added: impl IntoIterator<Item = &'a str>,
+) -> LinkfieldResult<()> {
+	let mut table = txn.open_table(DIR_INDEX_TABLE)?;
+	let mut touched: HashMap<String, DirectoryIndex> = HashMap::new();
+	for key in removed {
+		let (dir, name) = split_key(key);
+		let children = &mut touched_index(&mut touched, &table, dir)?.children;
+		if let Ok(pos) = children.binary_search(&name) {
+			children.remove(pos);
+		}
+	}
+	for key in added {
+		let (dir, name) = split_key(key);
+		let children = &mut touched_index(&mut touched, &table, dir)?.children;
+		if let Err(pos) = children.binary_search(&name) {
+			children.insert(pos, name);
+		}
+	}
+	for (dir, index) in touched {
+		if index.children.is_empty() {
+			table.remove(dir.as_str())?;
+		} else {
+			let bytes = encode_to_vec(&index, bincode::config::standard())?;
+			table.insert(dir.as_str(), bytes.as_slice())?;
+		}
+	}
+	Ok(())
+}
+
+/// Drop the index entries of `prefix` and every directory below it
+pub(crate) fn remove_dir_index_prefix(
+	txn: &redb::WriteTransaction,
+	prefix: &Path,
+) -> LinkfieldResult<()> {
+	let prefix_str = prefix.to_string_lossy();
+	let mut table = txn.open_table(DIR_INDEX_TABLE)?;
+	let mut keys = Vec::new();
+	for entry in table.range(prefix_str.as_ref()..)? {
+		let (key, _) = entry?;
+		let key = key.value();
+		if !key.starts_with(prefix_str.as_ref()) {
+			break;
+		}
+		if Path::new(key).starts_with(prefix) {
+			keys.push(key.to_string());
+		}
+	}
+	for key in &keys {
+		table.remove(key.as_str())?;
+	}
+	Ok(())
+}
+
+/// Rebuild the whole index from the `file_cache` table
+pub(crate) fn rebuild_dir_index(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	let keys: Vec<String> = {
+		let cache = txn.open_table(FILE_CACHE_TABLE)?;
+		cache
+			.iter()?
+			.map(|entry| entry.map(|(k, _)| k.value().to_string()))
+			.collect::<Result<_, _>>()?
+	};
+	let mut table = txn.open_table(DIR_INDEX_TABLE)?;
+	table.retain(|_, _| false)?;
+	drop(table);
+	update_dir_index(txn, [], keys.iter().map(String::as_str))
+}
+
+/// The stored index of `dir`, or `None` when no file directly in `dir` is stored
+pub fn read_dir_index(db: &redb::Database, dir: &Path) -> LinkfieldResult<Option<DirectoryIndex>> {
+	let read_txn = db.begin_read()?;
+	let table = match read_txn.open_table(DIR_INDEX_TABLE) {
+		Ok(table) => table,
+		Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+		Err(e) => return Err(e.into()),
+	};
+	let index = read_index(&table, dir.to_string_lossy().as_ref())?;
+	Ok((!index.children.is_empty()).then_some(index))
+}
+
+impl FileCache {
+	/// Stored metadata of the files directly in `dir`, looked up through the directory
+	/// index rather than by scanning the `file_cache` table
+	pub fn files_in_directory_from_db(
+		db: &redb::Database,
+		dir: &Path,
+	) -> LinkfieldResult<Vec<FileMeta>> {
+		let read_txn = db.begin_read()?;
+		let index = match read_txn.open_table(DIR_INDEX_TABLE) {
+			Ok(index_table) => read_index(&index_table, dir.to_string_lossy().as_ref())?,
+			Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+			Err(e) => return Err(e.into()),
+		};
+		let table = read_txn.open_table(FILE_CACHE_TABLE)?;
+		let mut files = Vec::with_capacity(index.children.len());
+		for name in &index.children {
+			let key = dir.join(name);
+			if let Some(bytes) = table.get(key.to_string_lossy().as_ref())? {
+				files.push(FileMeta::deserialize(bytes.value())?);
+			}
+		}
+		Ok(files)
+	}
+}
@@ -0,0 +1,173 @@
+//! Counts and recent rate of the file system events applied to the cache
+
+use crate::file_cache::FileCache;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Length of the sliding window used by [`FileCache::event_rate`]
+pub const EVENT_RATE_WINDOW_SECS: u64 = 60;
+
+/// Kind of file event counted by [`FileCache::record_event`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+	Create,
+	Remove,
+	Modify,
+	/// Renames and moves
+	Rename,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventCounts {
+	pub creates: u64,
+	pub removes: u64,
+	pub modifies: u64,
+	pub renames: u64,
+}
+
+impl EventCounts {
+	pub const fn total(&self) -> u64 {
+		self.creates + self.removes + self.modifies + self.renames
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventRate {
+	pub events_per_second: f64,
+	/// Seconds the rate was averaged over, shorter than the full window right after startup
+	pub window_secs: f64,
+}
+
+/// Per-kind totals plus one-second buckets covering the last [`EVENT_RATE_WINDOW_SECS`]
+pub(crate) struct EventTracker {
+	creates: AtomicU64,
+	removes: AtomicU64,
+	modifies: AtomicU64,
+	renames: AtomicU64,
+	start: Instant,
+	/// `(second since start, events in that second)`, indexed by second modulo the window
+	buckets: Mutex<[(u64, u64); EVENT_RATE_WINDOW_SECS as usize]>,
+}
+
+impl EventTracker {
+	pub(crate) fn new() -> Self {
+		Self {
+			creates: AtomicU64::new(0),
+			removes: AtomicU64::new(0),
+			modifies: AtomicU64::new(0),
+			renames: AtomicU64::new(0),
+			start: Instant::now(),
+			buckets: Mutex::new([(0, 0); EVENT_RATE_WINDOW_SECS as usize]),
+		}
+	}
+
+	pub(crate) fn record_at(&self, kind: EventKind, now: Instant) {
+		let counter = match kind {
+			EventKind::Create => &self.creates,
+			EventKind::Remove => &self.removes,
+			EventKind::Modify => &self.modifies,
+			EventKind::Rename => &self.renames,
+		};
+		counter.fetch_add(1, Ordering::Relaxed);
+		let second = now.saturating_duration_since(self.start).as_secs();
+		let Ok(mut buckets) = self.buckets.lock() else {
+			return;
+		};
+		let bucket = &mut buckets[(second % EVENT_RATE_WINDOW_SECS) as usize];
+		if bucket.0 == second {
+			bucket.1 += 1;
+		} else {
+			// The slot still holds a second that fell out of the window
+			*bucket = (second, 1);
+		}
+	}
+
+	pub(crate) fn counts(&self) -> EventCounts {
+		EventCounts {
+			creates: self.creates.load(Ordering::Relaxed),
+			removes: self.removes.load(Ordering::Relaxed),
+			modifies: self.modifies.load(Ordering::Relaxed),
+			renames: self.renames.load(Ordering::Relaxed),
+		}
+	}
+
+	pub(crate) fn rate_at(&self, now: Instant) -> EventRate {
+		let elapsed = now.saturating_duration_since(self.start);
+		let second = elapsed.as_secs();
+		let events: u64 = self.buckets.lock().map_or(0, |buckets| {
+			buckets
+				.iter()
+				.filter(|(bucket_second, _)| {
+					*bucket_second <= second && second - bucket_second < EVENT_RATE_WINDOW_SECS
+				})
+				.map(|(_, count)| count)
+				.sum()
+		});
+		#[allow(clippy::cast_precision_loss)]
+		let window_secs = elapsed
+			.as_secs_f64()
+			.clamp(1.0, EVENT_RATE_WINDOW_SECS as f64);
+		#[allow(clippy::cast_precision_loss)]
+		let events_per_second = events as f64 / window_secs;
+		EventRate {
+			events_per_second,
+			window_secs,
+		}
+	}
+}
+
+impl FileCache {
+	/// Count one file event applied to the cache. Events that only touch directories are
+	/// not recorded.
+	pub fn record_event(&self, kind: EventKind) {
+		self.events.record_at(kind, Instant::now());
+	}
+
+	/// Events recorded since the cache was created, by kind
+	pub fn event_counts(&self) -> EventCounts {
+		self.events.counts()
+	}
+
+	/// Total of [`FileCache::event_counts`]
+	pub fn watch_event_count(&self) -> u64 {
+		self.events.counts().total()
+	}
+
+	/// Recorded events per second over the last [`EVENT_RATE_WINDOW_SECS`] seconds
+	pub fn event_rate(&self) -> EventRate {
+		self.events.rate_at(Instant::now())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn test_rate_covers_only_the_window() {
+		let tracker = EventTracker::new();
+		let at = |secs: u64| tracker.start + Duration::from_secs(secs);
+		for _ in 0..30 {
+			tracker.record_at(EventKind::Create, at(5));
+		}
+		tracker.record_at(EventKind::Remove, at(9));
+
+		// 10s after start the window is the 10s so far
+		let rate = tracker.rate_at(at(10));
+		assert!((rate.window_secs - 10.0).abs() < f64::EPSILON);
+		assert!((rate.events_per_second - 3.1).abs() < 1e-9);
+
+		// The burst at 5s leaves the window after 60s, the remove at 9s is still in it
+		let rate = tracker.rate_at(at(66));
+		assert!((rate.window_secs - 60.0).abs() < f64::EPSILON);
+		assert!((rate.events_per_second - 1.0 / 60.0).abs() < 1e-9);
+
+		// Reusing the slot of second 5 drops its old count
+		tracker.record_at(EventKind::Modify, at(65));
+		let rate = tracker.rate_at(at(66));
+		assert!((rate.events_per_second - 2.0 / 60.0).abs() < 1e-9);
+		assert_eq!(tracker.counts().total(), 32);
+	}
+}
@@ -0,0 +1,207 @@
+//! Export helpers for handing the cache contents to other tools, and the matching import
+//! of path lists they produce
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileCache;
+use crate::file_cache::db::update_redb_batch_commit;
+use crate::file_cache::meta::{FileMeta, FileType};
+use crate::ignore_config::IgnoreConfig;
+use serde::Serialize;
+use std::io::{BufRead, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/// Which of the metadata that changes without the contents changing
+/// [`FileCache::export_for_backup_with_options`] writes. Everything is left out by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackupExportOptions {
+	pub include_modified: bool,
+	pub include_created: bool,
+	/// The content hash, which is missing until a file was hashed
+	pub include_hash: bool,
+}
+
+/// One file of a backup export; fields that aren't included are left out of the JSON
+#[derive(Serialize)]
+struct BackupRecord<'a> {
+	path: &'a Path,
+	size: u64,
+	extension: Option<&'a str>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	modified: Option<Option<SystemTime>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	created: Option<Option<SystemTime>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	content_hash: Option<Option<u64>>,
+}
+
+impl FileCache {
+	/// Write every cached file path to `writer`, one record per path terminated by `separator`.
+	///
+	/// Use `b'\n'` for line-based tools or `b'\0'` for NUL-separated lists (`xargs -0`,
+	/// `rsync --from0`, `tar --null`), which are safe with file names containing newlines.
+	/// Paths are written in sorted order.
+	pub fn export_path_list<W: Write>(&self, writer: W, separator: u8) -> std::io::Result<()> {
+		self.export_path_list_filtered(writer, separator, |_| true)
+	}
+
+	/// Like [`FileCache::export_path_list`], but only writes files for which `filter` returns true.
+	pub fn export_path_list_filtered<W, F>(
+		&self,
+		mut writer: W,
+		separator: u8,
+		filter: F,
+	) -> std::io::Result<()>
+	where
+		W: Write,
+		F: Fn(&FileMeta) -> bool,
+	{
+		let mut files: Vec<_> = self
+			.all_files()
+			.into_iter()
+			.filter(|meta| filter(meta))
+			.collect();
+		files.sort_by(|a, b| a.path.0.cmp(&b.path.0));
+		for meta in files {
+			writer.write_all(meta.path.0.as_os_str().as_encoded_bytes())?;
+			writer.write_all(&[separator])?;
+		}
+		writer.flush()
+	}
+
+	/// Write every cached file as a JSON array of `FileMeta` records sorted by path, the
+	/// format read back by [`FileCache::from_snapshot_reader`]
+	pub fn export_json<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+		let mut files = self.all_files();
+		files.sort_by(|a, b| a.path.0.cmp(&b.path.0));
+		serde_json::to_writer(&mut writer, &files)?;
+		writer.flush()
+	}
+
+	/// Write every cached file as a JSON array of `path`, `size` and `extension` records
+	/// sorted by path. The output only changes when files are added, removed or resized, so
+	/// it diffs and checksums well for backups.
+	pub fn export_for_backup<W: Write>(&self, writer: W) -> std::io::Result<()> {
+		self.export_for_backup_with_options(writer, BackupExportOptions::default())
+	}
+
+	/// Like [`FileCache::export_for_backup`], adding the fields chosen in `options`
+	pub fn export_for_backup_with_options<W: Write>(
+		&self,
+		mut writer: W,
+		options: BackupExportOptions,
+	) -> std::io::Result<()> {
+		let mut files = self.all_files();
+		files.sort_by(|a, b| a.path.0.cmp(&b.path.0));
+		let records: Vec<_> = files
+			.iter()
+			.map(|meta| BackupRecord {
+				path: &meta.path.0,
+				size: meta.size,
+				extension: meta.extension.as_deref(),
+				modified: options.include_modified.then_some(meta.modified),
+				created: options.include_created.then_some(meta.created),
+				content_hash: options.include_hash.then_some(meta.content_hash),
+			})
+			.collect();
+		serde_json::to_writer(&mut writer, &records)?;
+		writer.flush()
+	}
+
+	/// Read a path list from `reader`, one record per path terminated by `separator`, as
+	/// written by `find`, `fd` or [`FileCache::export_path_list`]. Each file is cached and
+	/// stored in `db`, committing every `batch_size` files; this skips the directory walk of
+	/// a scan. Relative paths are taken from the current directory, and are stored below the
+	/// cache root the way a scan stores them. Paths outside the root, ignored by `ignore`,
+	/// missing or not regular files are skipped. Returns how many files were imported.
+	pub fn import_path_list<R: BufRead>(
+		&self,
+		db: &redb::Database,
+		mut reader: R,
+		separator: u8,
+		batch_size: usize,
+		ignore: &IgnoreConfig,
+	) -> LinkfieldResult<usize> {
+		let root = self
+			.entries
+			.get(&self.root)
+			.map(|root| PathBuf::from(&root.name))
+			.unwrap_or_default();
+		let absolute_root = normalize(&std::path::absolute(&root)?);
+		let mut batch = Vec::with_capacity(batch_size);
+		let mut imported = 0;
+		let mut record = Vec::new();
+		loop {
+			record.clear();
+			if reader.read_until(separator, &mut record)? == 0 {
+				break;
+			}
+			if record.last() == Some(&separator) {
+				record.pop();
+			}
+			// Lists written on Windows end their lines with \r\n
+			if separator == b'\n' && record.last() == Some(&b'\r') {
+				record.pop();
+			}
+			if record.is_empty() {
+				continue;
+			}
+			let listed = normalize(&std::path::absolute(path_from_bytes(&record))?);
+			let Ok(relative) = listed.strip_prefix(&absolute_root) else {
+				tracing::debug!(path = %listed.display(), "Skipping path outside the root in path list");
+				continue;
+			};
+			let path = root.join(relative);
+			if ignore.is_ignored_below(&root, &path, false) {
+				continue;
+			}
+			let Some(meta) = self
+				.read_single_meta(&path)
+				.filter(|meta| meta.file_type == FileType::Regular)
+				.filter(|meta| !ignore.is_ignored_by_size(meta.size))
+			else {
+				tracing::debug!(path = %path.display(), "Skipping missing or ignored file in path list");
+				continue;
+			};
+			self.insert_meta(meta.clone());
+			batch.push((meta.path.clone(), meta));
+			if batch.len() >= batch_size.max(1) {
+				update_redb_batch_commit(db, &[], &batch)?;
+				imported += batch.len();
+				batch.clear();
+			}
+		}
+		if !batch.is_empty() {
+			update_redb_batch_commit(db, &[], &batch)?;
+			imported += batch.len();
+		}
+		Ok(imported)
+	}
+}
+
+/// `path` with `.` and `..` resolved lexically, without following symlinks
+fn normalize(path: &Path) -> PathBuf {
+	let mut normalized = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir => {
+				normalized.pop();
+			}
+			other => normalized.push(other),
+		}
+	}
+	normalized
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+	use std::os::unix::ffi::OsStrExt;
+	PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+/// Lists written on other platforms are expected to be UTF-8
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+	PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
@@ -0,0 +1,170 @@
+//! Rewriting the cached extension of files, e.g. to query `.jpeg` and `.jpg` files together
+
+use crate::file_cache::FileCache;
+use crate::file_cache::cache::EntryKind;
+use crate::file_cache::meta::FileMeta;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError};
+
+/// Variant -> canonical pairs used by [`ExtensionNormalizer::default`]
+const BUILT_IN_MAPPINGS: &[(&str, &str)] = &[
+	("jpeg", "jpg"),
+	("jpe", "jpg"),
+	("htm", "html"),
+	("tif", "tiff"),
+	("yml", "yaml"),
+	("mpeg", "mpg"),
+	("markdown", "md"),
+];
+
+/// Maps the spellings tools use for the same format to one canonical extension, so the
+/// cache stores `jpg` for `.jpeg`, `.jpg` and `.JPG` files alike. Extensions are lowercased
+/// before they are looked up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionNormalizer {
+	mappings: HashMap<String, String>,
+}
+
+impl Default for ExtensionNormalizer {
+	/// Lowercases extensions and maps common variants such as `jpeg -> jpg` and
+	/// `htm -> html`
+	fn default() -> Self {
+		let mut normalizer = Self::lowercase_only();
+		for (from, to) in BUILT_IN_MAPPINGS {
+			normalizer.add_mapping(from, to);
+		}
+		normalizer
+	}
+}
+
+impl ExtensionNormalizer {
+	/// A normalizer without mappings, that only lowercases extensions
+	pub fn lowercase_only() -> Self {
+		Self {
+			mappings: HashMap::new(),
+		}
+	}
+
+	/// Store `to` for files with extension `from`, replacing any earlier mapping for `from`.
+	/// A leading dot on either side is ignored and `from` matches regardless of case.
+	pub fn add_mapping(&mut self, from: &str, to: &str) {
+		self.mappings.insert(
+			from.trim_start_matches('.').to_lowercase(),
+			to.trim_start_matches('.').to_string(),
+		);
+	}
+
+	/// The canonical form of `extension`, given without the leading dot
+	pub fn normalize(&self, extension: &str) -> String {
+		let lowercase = extension.to_lowercase();
+		self.mappings.get(&lowercase).cloned().unwrap_or(lowercase)
+	}
+
+	/// Replace the extension of `meta` with its canonical form
+	pub fn normalize_meta(&self, meta: &mut FileMeta) {
+		if let Some(extension) = &mut meta.extension {
+			*extension = self.normalize(extension);
+		}
+	}
+}
+
+/// One `FROM=TO` extension mapping, as given to `--extension-map`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionMapping {
+	pub from: String,
+	pub to: String,
+}
+
+impl FromStr for ExtensionMapping {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (from, to) = s
+			.split_once('=')
+			.ok_or_else(|| format!("expected FROM=TO, got '{s}'"))?;
+		let (from, to) = (from.trim_start_matches('.'), to.trim_start_matches('.'));
+		if from.is_empty() || to.is_empty() {
+			return Err(format!("expected FROM=TO, got '{s}'"));
+		}
+		Ok(Self {
+			from: from.to_string(),
+			to: to.to_string(),
+		})
+	}
+}
+
+impl FileCache {
+	/// Store extensions normalized by `normalizer` for every file read from disk from now
+	/// on, by scans as well as [`FileCache::update_file`]. `None` stores them as found.
+	pub fn set_extension_normalizer(&self, normalizer: Option<Arc<ExtensionNormalizer>>) {
+		*self
+			.extension_normalizer
+			.write()
+			.unwrap_or_else(PoisonError::into_inner) = normalizer;
+	}
+
+	/// [`FileMeta::from_path`] with the extension normalized as set with
+	/// [`FileCache::set_extension_normalizer`]
+	pub(crate) fn read_meta(&self, path: &Path) -> Option<FileMeta> {
+		let mut meta = FileMeta::from_path(path)?;
+		self.normalize_extension(&mut meta);
+		Some(meta)
+	}
+
+	/// Normalize the extension of `meta` as set with [`FileCache::set_extension_normalizer`]
+	pub(crate) fn normalize_extension(&self, meta: &mut FileMeta) {
+		if let Some(normalizer) = self
+			.extension_normalizer
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.as_ref()
+		{
+			normalizer.normalize_meta(meta);
+		}
+	}
+
+	/// Record every cached file with extension `from_ext` as having `to_ext`, in memory and,
+	/// with `db`, in the database. Extensions are given without the leading dot. Only the
+	/// cached metadata changes: files on disk keep their names. Returns the number of files
+	/// updated.
+	pub fn rename_extension(
+		&self,
+		db: Option<&redb::Database>,
+		from_ext: &str,
+		to_ext: &str,
+	) -> usize {
+		self.normalize_extensions(db, &HashMap::from([(from_ext, to_ext)]))
+	}
+
+	/// [`FileCache::rename_extension`] for every `from -> to` pair of `mapping` in one pass,
+	/// committing all updated files in a single batch
+	pub fn normalize_extensions(
+		&self,
+		db: Option<&redb::Database>,
+		mapping: &HashMap<&str, &str>,
+	) -> usize {
+		let mut updated = Vec::new();
+		for mut entry in self.entries.iter_mut() {
+			let EntryKind::File(meta) = &mut entry.kind else {
+				continue;
+			};
+			let Some(to) = meta.extension.as_deref().and_then(|ext| mapping.get(ext)) else {
+				continue;
+			};
+			if meta.extension.as_deref() == Some(*to) {
+				continue;
+			}
+			meta.extension = Some((*to).to_string());
+			updated.push((meta.path.clone(), meta.clone()));
+		}
+		if let Some(db) = db.filter(|_| !updated.is_empty())
+			&& let Err(e) = crate::file_cache::db::update_redb_batch_commit(db, &[], &updated)
+		{
+			tracing::error!(error = %e, "Failed to commit normalized extensions");
+		}
+		tracing::debug!(updated = updated.len(), "Normalized cached extensions");
+		updated.len()
+	}
+}
@@ -0,0 +1,117 @@
+//! When the cache last changed, to spot a watcher that died or a scan that stalled
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileCache;
+use std::sync::PoisonError;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Small values about the cache as a whole, such as [`LAST_UPDATED_KEY`]
+pub const METADATA_TABLE: redb::TableDefinition<&str, u64> = redb::TableDefinition::new("metadata");
+
+/// Unix milliseconds of the latest write to the `file_cache` table
+pub const LAST_UPDATED_KEY: &str = "last_updated";
+
+/// [`FileCache::cache_age`] is reported once it is more than this many rescan intervals
+const STALE_RESCAN_INTERVALS: u32 = 5;
+
+/// When the cache last changed: the moment it was seen in this process, plus the age it
+/// already had then when it was restored from a database
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LastUpdated {
+	at: Instant,
+	age_before: Duration,
+}
+
+impl LastUpdated {
+	pub(crate) fn now() -> Self {
+		Self {
+			at: Instant::now(),
+			age_before: Duration::ZERO,
+		}
+	}
+}
+
+impl FileCache {
+	/// When a file was last updated or removed, or a scan was applied. A cache loaded from a
+	/// database starts at the time stored with it, which is clamped to the start of the
+	/// monotonic clock when it is older than that.
+	pub fn last_updated_at(&self) -> Instant {
+		let last = self.last_updated();
+		last.at.checked_sub(last.age_before).unwrap_or(last.at)
+	}
+
+	/// Time since [`FileCache::last_updated_at`]
+	pub fn cache_age(&self) -> Duration {
+		let last = self.last_updated();
+		last.at.elapsed() + last.age_before
+	}
+
+	/// Warn and return true when the cache hasn't changed for several `rescan_interval`s,
+	/// which usually means the watcher or the rescans stopped
+	pub fn warn_if_stale(&self, rescan_interval: Duration) -> bool {
+		let age = self.cache_age();
+		let stale = age > rescan_interval * STALE_RESCAN_INTERVALS;
+		if stale {
+			tracing::warn!(
+				age_secs = age.as_secs(),
+				rescan_interval_secs = rescan_interval.as_secs(),
+				"Cache has not been updated for {STALE_RESCAN_INTERVALS} rescan intervals"
+			);
+		}
+		stale
+	}
+
+	pub(crate) fn mark_updated(&self) {
+		*self
+			.last_updated
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = LastUpdated::now();
+	}
+
+	/// Take over the update time stored in `db`, if there is one
+	pub(crate) fn restore_last_updated(&self, db: &redb::Database) -> LinkfieldResult<()> {
+		if let Some(stored) = stored_last_updated(db)? {
+			*self
+				.last_updated
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner) = LastUpdated {
+				at: Instant::now(),
+				age_before: SystemTime::now().duration_since(stored).unwrap_or_default(),
+			};
+		}
+		Ok(())
+	}
+
+	fn last_updated(&self) -> LastUpdated {
+		*self
+			.last_updated
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+	}
+}
+
+/// The time of the latest write to the `file_cache` table of `db`, `None` if it was never
+/// written or only by an older version
+pub fn stored_last_updated(db: &redb::Database) -> LinkfieldResult<Option<SystemTime>> {
+	let read_txn = db.begin_read()?;
+	let table = match read_txn.open_table(METADATA_TABLE) {
+		Ok(table) => table,
+		Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+		Err(e) => return Err(e.into()),
+	};
+	Ok(table
+		.get(LAST_UPDATED_KEY)?
+		.map(|millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis.value())))
+}
+
+/// Store the current time as [`LAST_UPDATED_KEY`] with a write to the `file_cache` table
+pub(crate) fn record_last_updated(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	let millis = SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.map_or(0, |since| {
+			u64::try_from(since.as_millis()).unwrap_or(u64::MAX)
+		});
+	txn.open_table(METADATA_TABLE)?
+		.insert(LAST_UPDATED_KEY, millis)?;
+	Ok(())
+}
@@ -0,0 +1,55 @@
+//! Hard link detection: paths that share an inode on the same device
+
+use crate::file_cache::FileCache;
+use crate::file_cache::meta::FileMeta;
+use std::collections::{HashMap, HashSet};
+
+/// `(device, inode)`, which identifies a file; inode numbers alone repeat across devices
+pub type FileId = (u64, u64);
+
+impl FileCache {
+	/// Cached files grouped by device and inode, keeping only files reached through more
+	/// than one path. Entries live in a `DashMap`, so the groups hold clones rather than
+	/// references.
+	#[cfg(unix)]
+	pub fn find_hard_links(&self) -> HashMap<FileId, Vec<FileMeta>> {
+		let mut groups = self.files_by_id();
+		groups.retain(|_, files| files.len() > 1);
+		groups
+	}
+
+	/// Total size with every file counted once however many paths lead to it, i.e. the
+	/// space the files actually use. Files without a device and inode (non-Unix platforms,
+	/// or stored before the device was recorded) are each counted.
+	pub fn total_size_deduped(&self) -> u64 {
+		let mut seen = HashSet::new();
+		self.all_files()
+			.iter()
+			.filter(|meta| file_id(meta).is_none_or(|id| seen.insert(id)))
+			.map(|meta| meta.size)
+			.sum()
+	}
+
+	/// Number of cached paths that share their file with at least one other cached path
+	pub fn hard_link_count(&self) -> usize {
+		self.files_by_id()
+			.into_values()
+			.filter(|files| files.len() > 1)
+			.map(|files| files.len())
+			.sum()
+	}
+
+	fn files_by_id(&self) -> HashMap<FileId, Vec<FileMeta>> {
+		let mut groups: HashMap<FileId, Vec<FileMeta>> = HashMap::new();
+		for meta in self.all_files() {
+			if let Some(id) = file_id(&meta) {
+				groups.entry(id).or_default().push(meta);
+			}
+		}
+		groups
+	}
+}
+
+fn file_id(meta: &FileMeta) -> Option<FileId> {
+	meta.device.zip(meta.inode)
+}
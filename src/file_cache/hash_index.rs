@@ -0,0 +1,230 @@
+//! Content-addressed lookups: the stored paths of every content hash, kept in the attached
+//! database when [`FileCacheBuilder::enable_hash_index`](crate::file_cache::FileCacheBuilder::enable_hash_index)
+//! is set. Every write to the `file_cache` table moves the changed rows in the index within
+//! the same transaction.
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileCache;
+use crate::file_cache::db::FILE_CACHE_TABLE;
+use crate::file_cache::meta::{FileCachePath, FileMeta};
+use bincode::{decode_from_slice, encode_to_vec};
+use redb::{ReadableTable, ReadableTableMetadata, TableHandle};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+/// Big-endian xxh3 content hash -> bincode-encoded `Vec<FileCachePath>` of the files with
+/// that content
+pub const HASH_INDEX_TABLE: redb::TableDefinition<[u8; 8], &[u8]> =
+	redb::TableDefinition::new("hash_index");
+
+/// Looks up files by content in the hash index of a cache, see [`FileCache::hash_index`].
+/// Without an attached database there is no index and nothing is found.
+pub struct HashIndexedCache<'a> {
+	cache: &'a FileCache,
+}
+
+impl HashIndexedCache<'_> {
+	/// Every stored path whose contents hash to `hash`
+	pub fn lookup_by_hash(&self, hash: u64) -> Vec<FileCachePath> {
+		self.read(|table| {
+			Ok(table
+				.get(hash.to_be_bytes())?
+				.map(|paths| paths.value().to_vec()))
+		})
+		.flatten()
+		.and_then(|bytes| match decode_paths(&bytes) {
+			Ok(paths) => Some(paths),
+			Err(e) => {
+				tracing::error!(hash, error = %e, "Failed to decode hash index entry");
+				None
+			}
+		})
+		.unwrap_or_default()
+	}
+
+	/// Number of distinct contents among the stored files with a hash
+	pub fn unique_file_count(&self) -> u64 {
+		self.read(|table| Ok(table.len()?)).unwrap_or(0)
+	}
+
+	/// Run `read` on the index table of the attached database; `None` without a database,
+	/// table or on errors, which are logged
+	fn read<T>(
+		&self,
+		read: impl FnOnce(&redb::ReadOnlyTable<[u8; 8], &[u8]>) -> LinkfieldResult<T>,
+	) -> Option<T> {
+		let db = self.cache.database()?;
+		let result = db.begin_read().map_err(Into::into).and_then(|txn| {
+			match txn.open_table(HASH_INDEX_TABLE) {
+				Ok(table) => read(&table).map(Some),
+				Err(redb::TableError::TableDoesNotExist(_)) => Ok(None),
+				Err(e) => Err(e.into()),
+			}
+		});
+		result
+			.inspect_err(|e| tracing::error!(error = %e, "Failed to read the hash index"))
+			.ok()
+			.flatten()
+	}
+}
+
+impl FileCache {
+	/// Content lookups for a cache built with the hash index enabled, `None` otherwise
+	pub fn hash_index(&self) -> Option<HashIndexedCache<'_>> {
+		self.hash_index_enabled
+			.load(Ordering::Relaxed)
+			.then_some(HashIndexedCache { cache: self })
+	}
+}
+
+/// Whether the database of `txn` keeps a hash index, i.e. a cache with the index enabled was
+/// attached to it
+fn has_hash_index(txn: &redb::WriteTransaction) -> LinkfieldResult<bool> {
+	Ok(txn
+		.list_tables()?
+		.any(|table| table.name() == HASH_INDEX_TABLE.name()))
+}
+
+/// Create the hash index of `db` from the stored files, unless it already has one
+pub(crate) fn ensure_hash_index(db: &redb::Database) -> LinkfieldResult<()> {
+	let txn = db.begin_write()?;
+	if !has_hash_index(&txn)? {
+		rebuild_hash_index(&txn)?;
+	}
+	txn.commit()?;
+	Ok(())
+}
+
+/// [`rebuild_hash_index`] when the database of `txn` keeps a hash index
+pub(crate) fn rebuild_hash_index_if_kept(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	if has_hash_index(txn)? {
+		rebuild_hash_index(txn)?;
+	}
+	Ok(())
+}
+
+/// Fill the hash index from the stored files that have a content hash, replacing what it
+/// held, e.g. after the `file_cache` table was rewritten
+pub(crate) fn rebuild_hash_index(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	let mut hashes: HashMap<u64, Vec<FileCachePath>> = HashMap::new();
+	for row in txn.open_table(FILE_CACHE_TABLE)?.iter()? {
+		let (key, value) = row?;
+		if let Some(hash) = stored_hash(Some(value.value())) {
+			hashes
+				.entry(hash)
+				.or_default()
+				.push(FileCachePath::from(Path::new(key.value())));
+		}
+	}
+	let mut table = txn.open_table(HASH_INDEX_TABLE)?;
+	table.retain(|_, _| false)?;
+	for (hash, paths) in hashes {
+		store_paths(&mut table, hash, &paths)?;
+	}
+	Ok(())
+}
+
+/// A path whose stored content hash changed, `(path, old hash, new hash)`
+type HashMove = (FileCachePath, Option<u64>, Option<u64>);
+
+/// Content hashes of the rows one write transaction changes in the `file_cache` table, moved
+/// in the hash index on commit when the database keeps one
+pub(crate) struct HashIndexUpdate {
+	/// `None` without a hash index, so no row is decoded
+	moves: Option<Vec<HashMove>>,
+}
+
+impl HashIndexUpdate {
+	pub(crate) fn new(txn: &redb::WriteTransaction) -> LinkfieldResult<Self> {
+		Ok(Self {
+			moves: has_hash_index(txn)?.then(Vec::new),
+		})
+	}
+
+	/// Record that `key` now holds `meta`, replacing the row `old` if it was stored
+	pub(crate) fn insert(&mut self, key: &str, old: Option<&[u8]>, meta: &FileMeta) {
+		if let Some(moves) = &mut self.moves {
+			let path = FileCachePath::from(Path::new(key));
+			moves.push((path, stored_hash(old), meta.content_hash));
+		}
+	}
+
+	/// Record that `key` was removed, if it held `old`
+	pub(crate) fn remove(&mut self, key: &str, old: Option<&[u8]>) {
+		if let Some(moves) = &mut self.moves {
+			let path = FileCachePath::from(Path::new(key));
+			moves.push((path, stored_hash(old), None));
+		}
+	}
+
+	/// Move every recorded path from its old hash to its new one
+	pub(crate) fn commit(self, txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+		let Some(moves) = self.moves.filter(|moves| !moves.is_empty()) else {
+			return Ok(());
+		};
+		let mut table = txn.open_table(HASH_INDEX_TABLE)?;
+		for (path, old, new) in moves {
+			if old != new {
+				move_path(&mut table, &path, old, new)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Content hash of a stored `file_cache` row; `None` for rows that don't decode
+fn stored_hash(row: Option<&[u8]>) -> Option<u64> {
+	FileMeta::deserialize(row?).ok()?.content_hash
+}
+
+/// Take `path` out of the entry of `old` and add it to the entry of `new`, dropping entries
+/// left empty
+fn move_path(
+	table: &mut redb::Table<[u8; 8], &[u8]>,
+	path: &FileCachePath,
+	old: Option<u64>,
+	new: Option<u64>,
+) -> LinkfieldResult<()> {
+	if let Some(old) = old {
+		let mut paths = stored_paths(table, old)?;
+		paths.retain(|stored| stored != path);
+		store_paths(table, old, &paths)?;
+	}
+	if let Some(new) = new {
+		let mut paths = stored_paths(table, new)?;
+		if !paths.contains(path) {
+			paths.push(path.clone());
+			store_paths(table, new, &paths)?;
+		}
+	}
+	Ok(())
+}
+
+fn stored_paths(
+	table: &redb::Table<[u8; 8], &[u8]>,
+	hash: u64,
+) -> LinkfieldResult<Vec<FileCachePath>> {
+	match table.get(hash.to_be_bytes())? {
+		Some(bytes) => decode_paths(bytes.value()),
+		None => Ok(Vec::new()),
+	}
+}
+
+fn store_paths(
+	table: &mut redb::Table<[u8; 8], &[u8]>,
+	hash: u64,
+	paths: &[FileCachePath],
+) -> LinkfieldResult<()> {
+	if paths.is_empty() {
+		table.remove(hash.to_be_bytes())?;
+	} else {
+		let bytes = encode_to_vec(paths, bincode::config::standard())?;
+		table.insert(hash.to_be_bytes(), bytes.as_slice())?;
+	}
+	Ok(())
+}
+
+fn decode_paths(bytes: &[u8]) -> LinkfieldResult<Vec<FileCachePath>> {
+	Ok(decode_from_slice(bytes, bincode::config::standard())?.0)
+}
@@ -0,0 +1,151 @@
+//! Background backfill of the content hashes of cached files
+
+use crate::file_cache::FileCache;
+use crate::file_cache::cache::EntryKind;
+use crate::file_cache::db::update_redb_batch_commit;
+use crate::file_cache::dedup::{HashGranularity, content_hash, unlocked_content_hash};
+use crate::file_cache::meta::{FileCachePath, FileMeta};
+use rayon::prelude::*;
+use std::fs;
+use std::io;
+use std::sync::atomic::Ordering;
+
+/// Threads that hash file contents, kept apart from the global rayon pool so a backfill
+/// doesn't starve scans
+pub struct HashWorkerPool {
+	pool: rayon::ThreadPool,
+}
+
+impl HashWorkerPool {
+	/// Pool of `threads` workers; 0 uses one per CPU
+	pub fn new(threads: usize) -> Result<Self, rayon::ThreadPoolBuildError> {
+		let pool = rayon::ThreadPoolBuilder::new()
+			.num_threads(threads)
+			.thread_name(|i| format!("linkfield-hash-{i}"))
+			.build()?;
+		Ok(Self { pool })
+	}
+
+	/// xxh3 hashes of the full contents of `paths`, in the same order
+	pub fn hash_files(&self, paths: &[FileCachePath]) -> Vec<io::Result<u64>> {
+		self.pool.install(|| {
+			paths
+				.par_iter()
+				.map(|path| content_hash(&path.0, HashGranularity::Full))
+				.collect()
+		})
+	}
+
+	/// Like [`HashWorkerPool::hash_files`], with `None` for files skipped by
+	/// [`unlocked_content_hash`]
+	pub fn hash_unlocked_files(
+		&self,
+		paths: &[FileCachePath],
+		skip_locked_files: bool,
+	) -> Vec<io::Result<Option<u64>>> {
+		self.pool.install(|| {
+			paths
+				.par_iter()
+				.map(|path| {
+					unlocked_content_hash(&path.0, HashGranularity::Full, skip_locked_files)
+				})
+				.collect()
+		})
+	}
+}
+
+impl FileCache {
+	/// Leave files locked for writing unhashed, see [`ScanConfig::skip_locked_files`]. On by
+	/// default; scans set it from their config.
+	///
+	/// [`ScanConfig::skip_locked_files`]: crate::file_cache::ScanConfig::skip_locked_files
+	pub fn set_skip_locked_files(&self, skip: bool) {
+		self.skip_locked_files.store(skip, Ordering::Relaxed);
+	}
+
+	/// See [`FileCache::set_skip_locked_files`]
+	pub fn skip_locked_files(&self) -> bool {
+		self.skip_locked_files.load(Ordering::Relaxed)
+	}
+
+	/// Cached files without a content hash. Collected up front, like
+	/// [`FileCache::all_paths`], so no shard lock is held while the caller iterates.
+	pub fn files_missing_hash(&self) -> impl Iterator<Item = FileMeta> {
+		let files: Vec<_> = self
+			.entries
+			.iter()
+			.filter_map(|entry| match &entry.kind {
+				EntryKind::File(meta) if meta.content_hash.is_none() => Some(meta.clone()),
+				_ => None,
+			})
+			.collect();
+		files.into_iter()
+	}
+
+	/// Hash up to `batch_size` files from [`FileCache::files_missing_hash`] on `pool`,
+	/// storing the hashes in the cache and in `db`. Files that can't be read, that changed
+	/// since they were cached, or that are locked for writing while
+	/// [`FileCache::skip_locked_files`] is set, are skipped by later calls. Returns the number of files tried,
+	/// so 0 means the backfill is done.
+	pub fn populate_missing_hashes(
+		&self,
+		db: Option<&redb::Database>,
+		batch_size: usize,
+		pool: &HashWorkerPool,
+	) -> usize {
+		let batch: Vec<(u64, FileMeta)> = self
+			.entries
+			.iter()
+			.filter_map(|entry| match &entry.kind {
+				EntryKind::File(meta)
+					if meta.content_hash.is_none() && !self.unhashable.contains(&meta.path) =>
+				{
+					Some((*entry.key(), meta.clone()))
+				}
+				_ => None,
+			})
+			.take(batch_size.max(1))
+			.collect();
+		let paths: Vec<_> = batch.iter().map(|(_, meta)| meta.path.clone()).collect();
+		let mut hashed = Vec::new();
+		for ((key, meta), hash) in batch
+			.iter()
+			.zip(pool.hash_unlocked_files(&paths, self.skip_locked_files()))
+		{
+			let unchanged = fs::metadata(&meta.path.0).is_ok_and(|metadata| {
+				metadata.len() == meta.size && metadata.modified().ok() == meta.modified
+			});
+			match hash {
+				Ok(Some(hash)) if unchanged => {
+					if let Some(mut entry) = self.entries.get_mut(key)
+						&& let EntryKind::File(cached) = &mut entry.kind
+					{
+						cached.content_hash = Some(hash);
+					}
+					let meta = FileMeta {
+						content_hash: Some(hash),
+						..meta.clone()
+					};
+					hashed.push((meta.path.clone(), meta));
+				}
+				Ok(None) => {
+					self.unhashable.insert(meta.path.clone());
+				}
+				Ok(Some(_)) => {
+					tracing::debug!(path = %meta.path, "File changed since it was cached, not hashing");
+					self.unhashable.insert(meta.path.clone());
+				}
+				Err(e) => {
+					tracing::debug!(path = %meta.path, error = %e, "Skipping unreadable file");
+					self.unhashable.insert(meta.path.clone());
+				}
+			}
+		}
+		if let Some(db) = db
+			&& let Err(e) = update_redb_batch_commit(db, &[], &hashed)
+		{
+			tracing::error!(error = %e, "Failed to store content hashes");
+		}
+		batch.len()
+	}
+}
@@ -0,0 +1,249 @@
+//! Checksum of the `file_cache` table, to detect changes made to the database behind
+//! linkfield's back
+//!
+//! The checksum is the wrapping sum of the SHA-256 digest of every `(key, value)` row. It does
+//! not depend on the order of the rows, so each write adjusts it with the rows it touches
+//! instead of rereading the whole table.
+//!
+//! Each write also bumps a generation counter, so a cache can tell cheaply whether the table
+//! changed since it last read it.
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileCache;
+use crate::file_cache::db::FILE_CACHE_TABLE;
+use redb::ReadableTable;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+
+pub const CACHE_INTEGRITY_TABLE: redb::TableDefinition<&str, [u8; 32]> =
+	redb::TableDefinition::new("cache_integrity");
+
+/// Key of the `file_cache` checksum in [`CACHE_INTEGRITY_TABLE`]
+const FILE_CACHE_CHECKSUM_KEY: &str = "file_cache";
+
+pub const CACHE_GENERATION_TABLE: redb::TableDefinition<&str, u64> =
+	redb::TableDefinition::new("cache_generation");
+
+/// Key of the `file_cache` write counter in [`CACHE_GENERATION_TABLE`]
+const FILE_CACHE_GENERATION_KEY: &str = "file_cache";
+
+#[derive(Debug)]
+pub enum IntegrityError {
+	/// The rows of the `file_cache` table don't add up to the stored checksum
+	ChecksumMismatch {
+		stored: [u8; 32],
+		computed: [u8; 32],
+	},
+	/// No checksum was stored yet; it is written with the next change to the table
+	MissingChecksum,
+}
+
+impl fmt::Display for IntegrityError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::ChecksumMismatch { stored, computed } => write!(
+				f,
+				"file_cache table does not match its checksum (stored {}, computed {})",
+				hex(stored),
+				hex(computed)
+			),
+			Self::MissingChecksum => write!(f, "no checksum stored for the file_cache table"),
+		}
+	}
+}
+
+impl Error for IntegrityError {}
+
+fn hex(bytes: &[u8; 32]) -> String {
+	bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Order-independent checksum of a set of rows, as a little-endian 256-bit number
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Checksum([u8; 32]);
+
+impl Checksum {
+	fn digest(key: &str, value: &[u8]) -> [u8; 32] {
+		let mut hasher = Sha256::new();
+		// The length keeps `("ab", "c")` and `("a", "bc")` apart
+		hasher.update((key.len() as u64).to_le_bytes());
+		hasher.update(key.as_bytes());
+		hasher.update(value);
+		hasher.finalize().into()
+	}
+
+	pub(crate) fn add(&mut self, key: &str, value: &[u8]) {
+		let digest = Self::digest(key, value);
+		let mut carry = 0u16;
+		for (byte, d) in self.0.iter_mut().zip(digest) {
+			let sum = u16::from(*byte) + u16::from(d) + carry;
+			*byte = sum.to_le_bytes()[0];
+			carry = sum >> 8;
+		}
+	}
+
+	pub(crate) fn subtract(&mut self, key: &str, value: &[u8]) {
+		let digest = Self::digest(key, value);
+		let mut borrow = 0i16;
+		for (byte, d) in self.0.iter_mut().zip(digest) {
+			let diff = i16::from(*byte) - i16::from(d) - borrow;
+			borrow = i16::from(diff < 0);
+			*byte = (diff + (borrow << 8)).to_le_bytes()[0];
+		}
+	}
+
+	fn apply(&mut self, update: &ChecksumUpdate) {
+		for (key, value) in &update.added {
+			self.add(key, value);
+		}
+		for (key, value) in &update.removed {
+			self.subtract(key, value);
+		}
+	}
+
+	fn of_table<T: ReadableTable<&'static str, &'static [u8]>>(
+		table: &T,
+	) -> Result<Self, redb::StorageError> {
+		let mut checksum = Self::default();
+		for entry in table.iter()? {
+			let (key, value) = entry?;
+			checksum.add(key.value(), value.value());
+		}
+		Ok(checksum)
+	}
+}
+
+/// Rows written and replaced by one write transaction on the `file_cache` table
+#[derive(Default)]
+pub(crate) struct ChecksumUpdate {
+	added: Vec<(String, Vec<u8>)>,
+	removed: Vec<(String, Vec<u8>)>,
+}
+
+impl ChecksumUpdate {
+	/// Record that `key` now holds `value`, replacing `old` if it was stored
+	pub(crate) fn insert(&mut self, key: &str, old: Option<&[u8]>, value: &[u8]) {
+		self.remove(key, old);
+		self.added.push((key.to_string(), value.to_vec()));
+	}
+
+	/// Record that `key` was removed, if it held `old`
+	pub(crate) fn remove(&mut self, key: &str, old: Option<&[u8]>) {
+		if let Some(old) = old {
+			self.removed.push((key.to_string(), old.to_vec()));
+		}
+	}
+
+	/// Fold the rows into the stored checksum. Without a stored checksum, e.g. in a database
+	/// written by an older version, it is computed from the whole table instead.
+	pub(crate) fn commit(&self, txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+		let mut integrity = txn.open_table(CACHE_INTEGRITY_TABLE)?;
+		let stored = integrity
+			.get(FILE_CACHE_CHECKSUM_KEY)?
+			.map(|bytes| Checksum(bytes.value()));
+		let checksum = match stored {
+			Some(mut checksum) => {
+				checksum.apply(self);
+				checksum
+			}
+			None => Checksum::of_table(&txn.open_table(FILE_CACHE_TABLE)?)?,
+		};
+		integrity.insert(FILE_CACHE_CHECKSUM_KEY, checksum.0)?;
+		bump_generation(txn)
+	}
+}
+
+/// Recompute the checksum from the whole `file_cache` table, after rewriting it in place
+pub(crate) fn rebuild_checksum(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	let checksum = Checksum::of_table(&txn.open_table(FILE_CACHE_TABLE)?)?;
+	txn.open_table(CACHE_INTEGRITY_TABLE)?
+		.insert(FILE_CACHE_CHECKSUM_KEY, checksum.0)?;
+	bump_generation(txn)
+}
+
+fn bump_generation(txn: &redb::WriteTransaction) -> LinkfieldResult<()> {
+	let mut table = txn.open_table(CACHE_GENERATION_TABLE)?;
+	let generation = table
+		.get(FILE_CACHE_GENERATION_KEY)?
+		.map_or(0, |generation| generation.value());
+	table.insert(FILE_CACHE_GENERATION_KEY, generation.wrapping_add(1))?;
+	crate::file_cache::freshness::record_last_updated(txn)
+}
+
+/// Number of writes to the `file_cache` table seen by `txn`; 0 before the first one
+pub(crate) fn stored_generation(txn: &redb::ReadTransaction) -> LinkfieldResult<u64> {
+	let table = match txn.open_table(CACHE_GENERATION_TABLE) {
+		Ok(table) => table,
+		Err(redb::TableError::TableDoesNotExist(_)) => return Ok(0),
+		Err(e) => return Err(e.into()),
+	};
+	Ok(table
+		.get(FILE_CACHE_GENERATION_KEY)?
+		.map_or(0, |generation| generation.value()))
+}
+
+/// The checksum stored in the database read by `txn`, if any
+pub(crate) fn stored_checksum(txn: &redb::ReadTransaction) -> LinkfieldResult<Option<Checksum>> {
+	let table = match txn.open_table(CACHE_INTEGRITY_TABLE) {
+		Ok(table) => table,
+		Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+		Err(e) => return Err(e.into()),
+	};
+	Ok(table
+		.get(FILE_CACHE_CHECKSUM_KEY)?
+		.map(|bytes| Checksum(bytes.value())))
+}
+
+/// Compare `computed` against `stored`, logging a mismatch
+pub(crate) fn check(stored: Checksum, computed: Checksum) -> Result<(), IntegrityError> {
+	if stored == computed {
+		return Ok(());
+	}
+	let error = IntegrityError::ChecksumMismatch {
+		stored: stored.0,
+		computed: computed.0,
+	};
+	tracing::error!(error = %error, "Database was modified outside linkfield");
+	Err(error)
+}
+
+impl FileCache {
+	/// Recompute the checksum of the `file_cache` table and compare it against the stored
+	/// one. Fails with [`IntegrityError::MissingChecksum`] when none was stored yet.
+	pub fn verify_cache_checksum(db: &redb::Database) -> LinkfieldResult<()> {
+		let read_txn = db.begin_read()?;
+		let stored = stored_checksum(&read_txn)?.ok_or(IntegrityError::MissingChecksum)?;
+		let computed = match read_txn.open_table(FILE_CACHE_TABLE) {
+			Ok(table) => Checksum::of_table(&table)?,
+			Err(redb::TableError::TableDoesNotExist(_)) => Checksum::default(),
+			Err(e) => return Err(e.into()),
+		};
+		check(stored, computed)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_checksum_ignores_order_and_undoes_removals() {
+		let mut forward = Checksum::default();
+		forward.add("a", b"1");
+		forward.add("b", b"2");
+		let mut backward = Checksum::default();
+		backward.add("b", b"2");
+		backward.add("a", b"1");
+		assert_eq!(forward, backward);
+
+		backward.add("c", b"3");
+		assert_ne!(forward, backward);
+		backward.subtract("c", b"3");
+		assert_eq!(forward, backward);
+		backward.subtract("a", b"1");
+		backward.subtract("b", b"2");
+		assert_eq!(backward, Checksum::default());
+	}
+}
@@ -1,12 +1,22 @@
 //! File metadata for the file cache module
 
+use crate::error::LinkfieldResult;
+use crate::file_cache::dedup::{HashGranularity, unlocked_content_hash};
+use bincode::error::DecodeError;
 use bincode::{Decode, Encode, decode_from_slice, encode_to_vec};
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
+/// Upper bound on the encoded size accepted by [`FileMeta::deserialize`], so a corrupt length
+/// prefix is rejected instead of triggering a huge allocation
+const MAX_ENCODED_LEN: usize = 1 << 20;
+
 /// Strongly typed file path wrapper for cache keys
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Encode, Decode)]
+#[derive(
+	Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Encode, Decode, Serialize, Deserialize,
+)]
 pub struct FileCachePath(pub PathBuf);
 
 impl From<&Path> for FileCachePath {
@@ -21,20 +31,201 @@ impl AsRef<Path> for FileCachePath {
 	}
 }
 
+impl std::fmt::Display for FileCachePath {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		self.0.display().fmt(f)
+	}
+}
+
+impl FileCachePath {
+	pub fn is_absolute(&self) -> bool {
+		self.0.is_absolute()
+	}
+	pub fn is_relative(&self) -> bool {
+		self.0.is_relative()
+	}
+	/// Display the path relative to `root`, or in full if it is not under `root`
+	pub fn display_relative(&self, root: &Path) -> std::path::Display<'_> {
+		self.0.strip_prefix(root).unwrap_or(&self.0).display()
+	}
+}
+
+/// A path that hashes and compares regardless of case, for case-insensitive file systems.
+/// It keeps and displays the original case.
+#[derive(Debug, Clone)]
+pub struct CaseInsensitivePath(pub FileCachePath);
+
+impl CaseInsensitivePath {
+	fn folded(&self) -> String {
+		fold_case(&self.0.0.to_string_lossy())
+	}
+}
+
+impl From<&Path> for CaseInsensitivePath {
+	fn from(path: &Path) -> Self {
+		Self(FileCachePath::from(path))
+	}
+}
+
+impl PartialEq for CaseInsensitivePath {
+	fn eq(&self, other: &Self) -> bool {
+		self.folded() == other.folded()
+	}
+}
+
+impl Eq for CaseInsensitivePath {}
+
+impl std::hash::Hash for CaseInsensitivePath {
+	fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+		self.folded().hash(state);
+	}
+}
+
+impl std::fmt::Display for CaseInsensitivePath {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+/// `name` in the case used to compare names on case-insensitive file systems
+pub(crate) fn fold_case(name: &str) -> String {
+	name.to_lowercase()
+}
+
 /// Metadata for a single file in the cache
-#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
 pub struct FileMeta {
 	pub path: FileCachePath,
 	pub size: u64,
 	pub modified: Option<SystemTime>,
 	pub created: Option<SystemTime>,
 	pub extension: Option<String>,
+	/// Inode number on Unix, used to spot hard links; `None` on other platforms
+	pub inode: Option<u64>,
+	/// xxh3 hash of the contents, filled in by [`crate::file_cache::FileCache::populate_missing_hashes`]
+	#[serde(default)]
+	pub content_hash: Option<u64>,
+	/// An entry inside an archive rather than a file on disk, see
+	/// [`crate::file_cache::FileCache::virtual_files`]
+	#[serde(default)]
+	pub is_virtual: bool,
+	/// What kind of entry this is; only [`FileType::Regular`] entries are listed by
+	/// [`crate::file_cache::FileCache::all_files`]
+	#[serde(default)]
+	pub file_type: FileType,
+	/// Device the file is on (`st_dev`) on Unix; together with `inode` it identifies the
+	/// file, so hard links on different file systems aren't confused
+	#[serde(default)]
+	pub device: Option<u64>,
+}
+
+/// Kind of file system entry, from [`std::fs::FileType`]
+#[derive(
+	Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Encode, Decode, Serialize, Deserialize,
+)]
+pub enum FileType {
+	#[default]
+	Regular,
+	Directory,
+	Symlink,
+	/// Block or character device
+	Device,
+	Socket,
+	/// Named pipe (FIFO)
+	Pipe,
+	/// None of the above, e.g. a platform-specific type
+	Unknown,
+}
+
+impl From<fs::FileType> for FileType {
+	fn from(file_type: fs::FileType) -> Self {
+		if file_type.is_file() {
+			Self::Regular
+		} else if file_type.is_dir() {
+			Self::Directory
+		} else if file_type.is_symlink() {
+			Self::Symlink
+		} else {
+			special_file_type(file_type)
+		}
+	}
+}
+
+#[cfg(unix)]
+fn special_file_type(file_type: fs::FileType) -> FileType {
+	use std::os::unix::fs::FileTypeExt;
+	if file_type.is_block_device() || file_type.is_char_device() {
+		FileType::Device
+	} else if file_type.is_socket() {
+		FileType::Socket
+	} else if file_type.is_fifo() {
+		FileType::Pipe
+	} else {
+		FileType::Unknown
+	}
+}
+
+#[cfg(not(unix))]
+const fn special_file_type(_file_type: fs::FileType) -> FileType {
+	FileType::Unknown
+}
+
+#[cfg(unix)]
+fn inode_of(metadata: &fs::Metadata) -> Option<u64> {
+	use std::os::unix::fs::MetadataExt;
+	Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+const fn inode_of(_metadata: &fs::Metadata) -> Option<u64> {
+	None
+}
+
+#[cfg(unix)]
+fn device_of(metadata: &fs::Metadata) -> Option<u64> {
+	use std::os::unix::fs::MetadataExt;
+	Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+const fn device_of(_metadata: &fs::Metadata) -> Option<u64> {
+	None
+}
+
+/// Allocation unit assumed when the file system doesn't report one
+pub const DEFAULT_BLOCK_SIZE: u64 = 4096;
+
+#[cfg(unix)]
+fn block_size_of(metadata: &fs::Metadata) -> u64 {
+	use std::os::unix::fs::MetadataExt;
+	metadata.blksize()
+}
+
+#[cfg(not(unix))]
+const fn block_size_of(_metadata: &fs::Metadata) -> u64 {
+	DEFAULT_BLOCK_SIZE
 }
 
 impl FileMeta {
 	pub fn from_path(path: &Path) -> Option<Self> {
 		let metadata = fs::metadata(path).ok()?;
-		Some(Self {
+		Some(Self::from_metadata(path, &metadata))
+	}
+	/// [`FileMeta::from_path`] with the content hash filled in. Files that can't be read, or
+	/// that are locked for writing while `skip_locked_files` is set, get no hash.
+	pub fn from_path_with_hash(path: &Path, skip_locked_files: bool) -> Option<Self> {
+		let mut meta = Self::from_path(path)?;
+		meta.content_hash = unlocked_content_hash(path, HashGranularity::Full, skip_locked_files)
+			.inspect_err(
+				|e| tracing::warn!(path = %path.display(), error = %e, "Failed to hash file"),
+			)
+			.ok()
+			.flatten();
+		Some(meta)
+	}
+	/// The entry for `path` from metadata read before, e.g. by an async scan
+	pub fn from_metadata(path: &Path, metadata: &fs::Metadata) -> Self {
+		Self {
 			path: FileCachePath::from(path),
 			size: metadata.len(),
 			modified: metadata.modified().ok(),
@@ -43,28 +234,182 @@ impl FileMeta {
 				.extension()
 				.and_then(|e| e.to_str())
 				.map(std::string::ToString::to_string),
-		})
-	}
-	pub fn serialize(&self) -> Vec<u8> {
-		encode_to_vec(self, bincode::config::standard()).unwrap_or_else(|e| {
-			tracing::error!(error = %e, "Serialization failed");
-			Vec::new()
-		})
-	}
-	pub fn deserialize(bytes: &[u8]) -> Self {
-		let (meta, _) = decode_from_slice(bytes, bincode::config::standard()).unwrap_or_else(|e| {
-			tracing::error!(error = %e, "Deserialization failed");
-			(
-				Self {
-					path: FileCachePath(PathBuf::new()),
-					size: 0,
-					modified: None,
-					created: None,
-					extension: None,
-				},
-				0,
-			)
-		});
-		meta
+			inode: inode_of(metadata),
+			content_hash: None,
+			is_virtual: false,
+			file_type: metadata.file_type().into(),
+			device: device_of(metadata),
+		}
+	}
+	/// Space the file takes up: its size rounded up to whole file system blocks. Reads the
+	/// block size from the file, using [`DEFAULT_BLOCK_SIZE`] when that fails.
+	pub fn size_on_disk(&self) -> u64 {
+		let block_size = fs::metadata(&self.path.0)
+			.map(|metadata| block_size_of(&metadata))
+			.ok()
+			.filter(|&block_size| block_size > 0)
+			.unwrap_or(DEFAULT_BLOCK_SIZE);
+		self.size.div_ceil(block_size) * block_size
+	}
+	/// Bytes actually allocated to the file as reported by the file system, which is less
+	/// than [`FileMeta::size_on_disk`] for sparse files. `None` when the file can't be read.
+	#[cfg(unix)]
+	pub fn blocks_used(&self) -> Option<u64> {
+		use std::os::unix::fs::MetadataExt;
+		fs::metadata(&self.path.0)
+			.ok()
+			.map(|metadata| metadata.blocks() * 512)
+	}
+	/// Minimal JSON object for logging with the path, size, timestamps and extension. Unlike
+	/// the serde form, timestamps are plain Unix epoch seconds; missing values are `null`.
+	pub fn to_json_value(&self) -> String {
+		fn epoch_secs(time: Option<SystemTime>) -> String {
+			time.and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+				.map_or_else(|| "null".to_string(), |d| d.as_secs().to_string())
+		}
+		format!(
+			r#"{{"path":"{}","size":{},"modified":{},"created":{},"extension":{}}}"#,
+			json_escape(&self.path.0.to_string_lossy()),
+			self.size,
+			epoch_secs(self.modified),
+			epoch_secs(self.created),
+			self.extension
+				.as_ref()
+				.map_or_else(|| "null".to_string(), |e| format!("\"{}\"", json_escape(e)))
+		)
+	}
+	/// Single-line `path=<p> size=<s> ext=<e>` form for log output; `ext=-` when there is none
+	pub fn to_key_value_string(&self) -> String {
+		format!(
+			"path={} size={} ext={}",
+			self.path,
+			self.size,
+			self.extension.as_deref().unwrap_or("-")
+		)
+	}
+	/// Version of the layout written by [`FileMeta::serialize`]. Bump it, and add a migration
+	/// re-encoding the stored values, whenever a field is added, removed or reordered; the
+	/// golden bytes in `tests/serialization_stability.rs` are tagged with it.
+	pub const FORMAT_VERSION: u8 = 6;
+	/// See [`FileMeta::FORMAT_VERSION`]
+	pub const fn format_version() -> u8 {
+		Self::FORMAT_VERSION
+	}
+	/// Encode for storage in the `file_cache` table, prefixed with [`FileMeta::FORMAT_VERSION`]
+	pub fn serialize(&self) -> LinkfieldResult<Vec<u8>> {
+		let mut bytes = vec![Self::FORMAT_VERSION];
+		bytes.extend(encode_to_vec(self, bincode::config::standard())?);
+		Ok(bytes)
+	}
+	/// Decode a value written by [`FileMeta::serialize`]. Values written in another format
+	/// version are rejected rather than misread.
+	pub fn deserialize(bytes: &[u8]) -> LinkfieldResult<Self> {
+		let encoded = match bytes.split_first() {
+			Some((&Self::FORMAT_VERSION, encoded)) => encoded,
+			Some((version, _)) => {
+				return Err(DecodeError::OtherString(format!(
+					"unsupported FileMeta format version {version}, expected {}",
+					Self::FORMAT_VERSION
+				))
+				.into());
+			}
+			None => return Err(DecodeError::UnexpectedEnd { additional: 1 }.into()),
+		};
+		let config = bincode::config::standard().with_limit::<MAX_ENCODED_LEN>();
+		Ok(decode_from_slice(encoded, config)?.0)
+	}
+}
+
+/// Escape `s` for use inside a JSON string literal
+fn json_escape(s: &str) -> String {
+	use std::fmt::Write as _;
+	let mut out = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			c if u32::from(c) < 0x20 => {
+				let _ = write!(out, "\\u{:04x}", u32::from(c));
+			}
+			c => out.push(c),
+		}
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_display_relative() {
+		let root = std::env::current_dir().unwrap();
+		let path = FileCachePath(root.join("photos").join("a.jpg"));
+		assert!(path.is_absolute());
+		let expected = Path::new("photos").join("a.jpg");
+		assert_eq!(
+			path.display_relative(&root).to_string(),
+			expected.display().to_string()
+		);
+		// Not under the root: shown in full
+		let other = root.join("photos");
+		let outside = FileCachePath(root.join("music").join("b.mp3"));
+		assert_eq!(
+			outside.display_relative(&other).to_string(),
+			outside.to_string()
+		);
+
+		let relative = FileCachePath(expected.clone());
+		assert!(relative.is_relative());
+		assert_eq!(relative.to_string(), expected.display().to_string());
+	}
+
+	#[test]
+	fn test_to_json_value_and_key_value_string() {
+		let meta = FileMeta {
+			path: FileCachePath(PathBuf::from("dir/say \"hi\".rs")),
+			size: 123,
+			modified: Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000)),
+			created: None,
+			extension: Some("rs".to_string()),
+			inode: None,
+			content_hash: None,
+			is_virtual: false,
+			file_type: FileType::Regular,
+			device: None,
+		};
+		let json = meta.to_json_value();
+		assert_eq!(
+			json,
+			r#"{"path":"dir/say \"hi\".rs","size":123,"modified":1700000000,"created":null,"extension":"rs"}"#
+		);
+		let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+		assert_eq!(parsed["path"], "dir/say \"hi\".rs");
+		assert!(parsed["created"].is_null());
+		assert_eq!(
+			meta.to_key_value_string(),
+			"path=dir/say \"hi\".rs size=123 ext=rs"
+		);
+
+		let bare = FileMeta {
+			path: FileCachePath(PathBuf::from("Makefile")),
+			size: 0,
+			modified: None,
+			created: None,
+			extension: None,
+			inode: None,
+			content_hash: None,
+			is_virtual: false,
+			file_type: FileType::Regular,
+			device: None,
+		};
+		assert_eq!(
+			bare.to_json_value(),
+			r#"{"path":"Makefile","size":0,"modified":null,"created":null,"extension":null}"#
+		);
+		assert_eq!(bare.to_key_value_string(), "path=Makefile size=0 ext=-");
 	}
 }
@@ -1,10 +1,59 @@
 //! `file_cache` module root
 
+#[cfg(feature = "archives")]
+pub mod archives;
+#[cfg(feature = "tokio")]
+pub mod async_scan;
+pub mod blob;
+pub mod builder;
 pub mod cache;
+pub mod checkpoint;
 pub mod db;
+pub mod dedup;
+pub mod diff;
+pub mod diff_report;
+pub mod dir_index;
+pub mod event_stats;
+pub mod export;
+pub mod extensions;
+pub mod freshness;
+pub mod hard_links;
+pub mod hash_index;
+pub mod hashes;
+pub mod integrity;
 pub mod meta;
+pub mod query;
+pub mod rebuild;
+pub mod root_migration;
+pub mod scan;
+pub mod signatures;
+pub mod snapshot;
+pub mod staleness;
+pub mod stats;
+pub mod streaming;
+pub mod subset;
+pub mod summary;
+pub mod sync;
+pub mod tree;
 
+#[cfg(feature = "archives")]
+pub use archives::ArchiveWatcher;
+pub use blob::CacheBlobOptions;
+pub use builder::{CaseSensitivity, FileCacheBuilder};
 pub use cache::FileCache;
 pub use db::ensure_file_cache_table;
-pub use meta::FileMeta;
+pub use diff::{DiffResult, DryRunScanResult};
+pub use diff_report::DiffReport;
+pub use export::BackupExportOptions;
+pub use extensions::{ExtensionMapping, ExtensionNormalizer};
+pub use hash_index::HashIndexedCache;
+pub use hashes::HashWorkerPool;
+pub use meta::{FileMeta, FileType};
+pub use query::{FileCacheQuery, FileCategory, SortKey};
+pub use scan::{
+	ExtensionTiming, ExtensionTimingCallback, ExtensionTimings, ProgressCallback, ProgressStyle,
+	ScanConfig, ScanError, ScanProgress,
+};
+pub use staleness::StalenessEstimate;
+pub use tree::{DirectoryNode, DirectoryTree};
 // FileCachePath is not re-exported unless needed externally
@@ -0,0 +1,369 @@
+//! Composable queries over the cached files
+
+use crate::file_cache::FileCache;
+use crate::file_cache::cache::EntryKind;
+use crate::file_cache::meta::FileMeta;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic;
+use std::time::SystemTime;
+
+/// Broad kind of a file, guessed from its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileCategory {
+	Image,
+	Video,
+	Audio,
+	Document,
+	Archive,
+	Code,
+	Other,
+}
+
+impl FileCategory {
+	/// Category of a file with extension `ext` (without the leading dot), ignoring case
+	pub fn from_extension(ext: Option<&str>) -> Self {
+		let Some(ext) = ext.map(str::to_ascii_lowercase) else {
+			return Self::Other;
+		};
+		match ext.as_str() {
+			"jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "tif" | "tiff" | "svg" | "heic"
+			| "raw" => Self::Image,
+			"mp4" | "mkv" | "mov" | "avi" | "webm" | "wmv" | "m4v" => Self::Video,
+			"mp3" | "wav" | "flac" | "ogg" | "m4a" | "aac" | "opus" => Self::Audio,
+			"pdf" | "doc" | "docx" | "odt" | "rtf" | "txt" | "md" | "xls" | "xlsx" | "ods"
+			| "ppt" | "pptx" | "odp" | "csv" => Self::Document,
+			"zip" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "zst" | "7z" | "rar" => Self::Archive,
+			"rs" | "py" | "js" | "ts" | "c" | "h" | "cpp" | "hpp" | "go" | "java" | "kt" | "rb"
+			| "sh" | "toml" | "json" | "yaml" | "yml" | "html" | "css" => Self::Code,
+			_ => Self::Other,
+		}
+	}
+}
+
+/// Order of the results of a [`FileCacheQuery`], ascending; ties are broken by path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+	Path,
+	/// File name without the directory
+	Name,
+	Size,
+	/// Modification time, files without one first
+	Modified,
+}
+
+/// Builder for a query over the cached files, from [`FileCache::query`]. Every filter must
+/// match; the files are checked in a single pass when [`FileCacheQuery::collect`] is called.
+#[derive(Clone)]
+#[must_use]
+pub struct FileCacheQuery<'a> {
+	cache: &'a FileCache,
+	extensions: Vec<String>,
+	size_range: Option<RangeInclusive<u64>>,
+	modified_since: Option<SystemTime>,
+	modified_before: Option<SystemTime>,
+	category: Option<FileCategory>,
+	name_contains: Option<String>,
+	limit: Option<usize>,
+	offset: usize,
+	sort_by: Option<SortKey>,
+}
+
+impl FileCache {
+	/// Start a [`FileCacheQuery`] matching every cached file
+	pub fn query(&self) -> FileCacheQuery<'_> {
+		FileCacheQuery {
+			cache: self,
+			extensions: Vec::new(),
+			size_range: None,
+			modified_since: None,
+			modified_before: None,
+			category: None,
+			name_contains: None,
+			limit: None,
+			offset: 0,
+			sort_by: None,
+		}
+	}
+
+	/// Files created at or after `since`. Files without a creation time never match,
+	/// unless [`FileCache::set_created_fallback_to_modified`] is enabled.
+	pub fn find_recently_created(&self, since: SystemTime) -> impl Iterator<Item = FileMeta> {
+		let fallback = self
+			.created_fallback_to_modified
+			.load(atomic::Ordering::Relaxed);
+		self.files_where(move |meta| {
+			let created = if fallback {
+				meta.created.or(meta.modified)
+			} else {
+				meta.created
+			};
+			created.is_some_and(|created| created >= since)
+		})
+	}
+
+	/// Use the modification time for files without a creation time in
+	/// [`FileCache::find_recently_created`], e.g. on file systems that don't record birth
+	/// times
+	pub fn set_created_fallback_to_modified(&self, enabled: bool) {
+		self.created_fallback_to_modified
+			.store(enabled, atomic::Ordering::Relaxed);
+	}
+
+	/// Files modified at or after `since`
+	pub fn find_recently_modified(&self, since: SystemTime) -> impl Iterator<Item = FileMeta> {
+		self.files_where(move |meta| meta.modified.is_some_and(|modified| modified >= since))
+	}
+
+	/// Files anywhere under `dir` modified after `since`, e.g. the last build. Only the
+	/// cached metadata is read, nothing on disk; see
+	/// [`FileCache::changed_files_since_mtime_from_disk`] for when the cache may be stale.
+	pub fn changed_files_since_mtime(
+		&self,
+		dir: &Path,
+		since: SystemTime,
+	) -> impl Iterator<Item = FileMeta> {
+		let dir = dir.to_path_buf();
+		self.files_where(move |meta| {
+			meta.path.0.starts_with(&dir) && meta.modified.is_some_and(|modified| modified > since)
+		})
+	}
+
+	/// Like [`FileCache::changed_files_since_mtime`], walking `dir` on disk instead of
+	/// reading the cache. Symlinks are not followed and ignore rules don't apply. Paths are
+	/// sorted; unreadable directories are skipped.
+	pub fn changed_files_since_mtime_from_disk(dir: &Path, since: SystemTime) -> Vec<PathBuf> {
+		let mut changed = Vec::new();
+		let mut pending = vec![dir.to_path_buf()];
+		while let Some(current) = pending.pop() {
+			let entries = match std::fs::read_dir(&current) {
+				Ok(entries) => entries,
+				Err(e) => {
+					tracing::warn!(error = %e, dir = %current.display(), "Error reading dir");
+					continue;
+				}
+			};
+			for entry in entries.filter_map(Result::ok) {
+				let Ok(metadata) = entry.metadata() else {
+					continue;
+				};
+				if metadata.is_dir() {
+					pending.push(entry.path());
+				} else if metadata.is_file()
+					&& metadata.modified().is_ok_and(|modified| modified > since)
+				{
+					changed.push(entry.path());
+				}
+			}
+		}
+		changed.sort();
+		changed
+	}
+
+	/// The `n` most recently modified files anywhere under `dir`, newest first. Files without
+	/// a modification time are left out.
+	pub fn most_recently_modified_in_dir(&self, dir: &Path, n: usize) -> Vec<FileMeta> {
+		self.top_n_in_dir(dir, n, |meta| meta.modified)
+	}
+
+	/// The `n` largest files anywhere under `dir`, largest first
+	pub fn largest_files_in_dir(&self, dir: &Path, n: usize) -> Vec<FileMeta> {
+		self.top_n_in_dir(dir, n, |meta| Some(meta.size))
+	}
+
+	/// The `n` files under `dir` with the largest `key`, largest first and ties by path.
+	/// Keeps a heap of at most `n` files, so this is O(K log n) for K files under `dir`.
+	fn top_n_in_dir<K: Ord>(
+		&self,
+		dir: &Path,
+		n: usize,
+		key: impl Fn(&FileMeta) -> Option<K>,
+	) -> Vec<FileMeta> {
+		if n == 0 {
+			return Vec::new();
+		}
+		let mut heap: BinaryHeap<Reverse<Ranked<K>>> = BinaryHeap::with_capacity(n + 1);
+		for entry in &self.entries {
+			let EntryKind::File(meta) = &entry.kind else {
+				continue;
+			};
+			if !meta.path.0.starts_with(dir) {
+				continue;
+			}
+			let Some(key) = key(meta) else {
+				continue;
+			};
+			let beats_worst = heap.peek().is_none_or(|Reverse(worst)| {
+				(&key, Reverse(&meta.path)) > (&worst.key, Reverse(&worst.meta.path))
+			});
+			if heap.len() < n || beats_worst {
+				heap.push(Reverse(Ranked {
+					key,
+					meta: meta.clone(),
+				}));
+				if heap.len() > n {
+					heap.pop();
+				}
+			}
+		}
+		heap.into_sorted_vec()
+			.into_iter()
+			.map(|Reverse(ranked)| ranked.meta)
+			.collect()
+	}
+
+	/// Clones of the files matching `filter`, collected up front so no shard lock is held
+	/// while the caller iterates
+	fn files_where(&self, filter: impl Fn(&FileMeta) -> bool) -> impl Iterator<Item = FileMeta> {
+		let files: Vec<_> = self
+			.entries
+			.iter()
+			.filter_map(|entry| match &entry.kind {
+				EntryKind::File(meta) if filter(meta) => Some(meta.clone()),
+				_ => None,
+			})
+			.collect();
+		files.into_iter()
+	}
+}
+
+impl FileCacheQuery<'_> {
+	/// Only files with extension `ext`, given with or without the leading dot. Calling this
+	/// again allows further extensions.
+	pub fn extension(mut self, ext: &str) -> Self {
+		self.extensions
+			.push(ext.strip_prefix('.').unwrap_or(ext).to_string());
+		self
+	}
+
+	/// Only files of `min..=max` bytes
+	pub const fn size_range(mut self, min: u64, max: u64) -> Self {
+		self.size_range = Some(min..=max);
+		self
+	}
+
+	/// Only files modified at or after `time`; files without a modification time never match
+	pub const fn modified_since(mut self, time: SystemTime) -> Self {
+		self.modified_since = Some(time);
+		self
+	}
+
+	/// Only files modified before `time`; files without a modification time never match
+	pub const fn modified_before(mut self, time: SystemTime) -> Self {
+		self.modified_before = Some(time);
+		self
+	}
+
+	/// Only files whose extension falls in `category`
+	pub const fn category(mut self, category: FileCategory) -> Self {
+		self.category = Some(category);
+		self
+	}
+
+	/// Only files whose name, without the directory, contains `needle`
+	pub fn name_contains(mut self, needle: &str) -> Self {
+		self.name_contains = Some(needle.to_string());
+		self
+	}
+
+	/// Return at most `n` files
+	pub const fn limit(mut self, n: usize) -> Self {
+		self.limit = Some(n);
+		self
+	}
+
+	/// Skip the first `n` matching files
+	pub const fn offset(mut self, n: usize) -> Self {
+		self.offset = n;
+		self
+	}
+
+	/// Sort the matching files before `offset` and `limit` apply. Unsorted results come in
+	/// no particular order.
+	pub const fn sort_by(mut self, key: SortKey) -> Self {
+		self.sort_by = Some(key);
+		self
+	}
+
+	/// Run the query. The entries live in a `DashMap`, so the matching files are cloned
+	/// rather than borrowed.
+	pub fn collect(&self) -> Vec<FileMeta> {
+		let matching = self
+			.cache
+			.entries
+			.iter()
+			.filter_map(|entry| match &entry.kind {
+				EntryKind::File(meta) if self.matches(meta) => Some(meta.clone()),
+				_ => None,
+			});
+		let limit = self.limit.unwrap_or(usize::MAX);
+		let Some(key) = self.sort_by else {
+			return matching.skip(self.offset).take(limit).collect();
+		};
+		let mut files: Vec<_> = matching.collect();
+		match key {
+			SortKey::Path => files.sort_by(|a, b| a.path.cmp(&b.path)),
+			SortKey::Name => files.sort_by(|a, b| {
+				(a.path.0.file_name(), &a.path).cmp(&(b.path.0.file_name(), &b.path))
+			}),
+			SortKey::Size => files.sort_by(|a, b| (a.size, &a.path).cmp(&(b.size, &b.path))),
+			SortKey::Modified => {
+				files.sort_by(|a, b| (a.modified, &a.path).cmp(&(b.modified, &b.path)));
+			}
+		}
+		files.into_iter().skip(self.offset).take(limit).collect()
+	}
+
+	fn matches(&self, meta: &FileMeta) -> bool {
+		let ext = meta.extension.as_deref();
+		(self.extensions.is_empty() || self.extensions.iter().any(|e| Some(e.as_str()) == ext))
+			&& self
+				.size_range
+				.as_ref()
+				.is_none_or(|range| range.contains(&meta.size))
+			&& self
+				.modified_since
+				.is_none_or(|since| meta.modified.is_some_and(|m| m >= since))
+			&& self
+				.modified_before
+				.is_none_or(|before| meta.modified.is_some_and(|m| m < before))
+			&& self
+				.category
+				.is_none_or(|category| FileCategory::from_extension(ext) == category)
+			&& self.name_contains.as_deref().is_none_or(|needle| {
+				meta.path
+					.0
+					.file_name()
+					.is_some_and(|name| name.to_string_lossy().contains(needle))
+			})
+	}
+}
+
+/// A file in the heap of [`FileCache::top_n_in_dir`], ordered by key and then by reverse path,
+/// so that of two files with the same key the one with the smaller path ranks higher
+struct Ranked<K> {
+	key: K,
+	meta: FileMeta,
+}
+
+impl<K: Ord> Ord for Ranked<K> {
+	fn cmp(&self, other: &Self) -> Ordering {
+		(&self.key, Reverse(&self.meta.path)).cmp(&(&other.key, Reverse(&other.meta.path)))
+	}
+}
+
+impl<K: Ord> PartialOrd for Ranked<K> {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl<K: Ord> PartialEq for Ranked<K> {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl<K: Ord> Eq for Ranked<K> {}
@@ -0,0 +1,82 @@
+//! Last-resort recovery: wipe the stored files and rebuild them from a fresh scan
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileCache;
+use crate::file_cache::db::{FILE_CACHE_TABLE, serialize_path};
+use crate::file_cache::diff::{DiffResult, diff_file_maps};
+use crate::file_cache::dir_index::rebuild_dir_index;
+use crate::file_cache::hash_index::rebuild_hash_index_if_kept;
+use crate::file_cache::integrity::{rebuild_checksum, stored_generation};
+use crate::file_cache::meta::{FileCachePath, FileMeta};
+use crate::ignore_config::IgnoreConfig;
+use redb::ReadableTable;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+/// The stored files that still decode. A table of the wrong type counts as empty.
+fn readable_stored_files(db: &redb::Database) -> LinkfieldResult<HashMap<FileCachePath, FileMeta>> {
+	let read_txn = db.begin_read()?;
+	let mut files = HashMap::new();
+	let table = match read_txn.open_table(FILE_CACHE_TABLE) {
+		Ok(table) => table,
+		Err(
+			redb::TableError::TableDoesNotExist(_) | redb::TableError::TableTypeMismatch { .. },
+		) => return Ok(files),
+		Err(e) => return Err(e.into()),
+	};
+	let mut unreadable = 0;
+	for entry in table.iter()? {
+		let (_, value) = entry?;
+		match FileMeta::deserialize(value.value()) {
+			Ok(meta) => {
+				files.insert(meta.path.clone(), meta);
+			}
+			Err(_) => unreadable += 1,
+		}
+	}
+	if unreadable > 0 {
+		tracing::warn!(
+			unreadable,
+			"Dropping stored files that could not be decoded"
+		);
+	}
+	Ok(files)
+}
+
+impl FileCache {
+	/// Throw away every stored and cached file and rebuild both from a fresh scan of `dir`,
+	/// for a database that is beyond repair. The new files are written in one transaction
+	/// together with a new checksum, directory index and hash index, so a failed rebuild leaves the
+	/// database as it was.
+	///
+	/// Returns the changes relative to the stored files that could still be decoded.
+	pub fn rebuild_from_scratch(
+		&self,
+		db: &redb::Database,
+		dir: &Path,
+		ignore: &IgnoreConfig,
+	) -> LinkfieldResult<DiffResult> {
+		let old = readable_stored_files(db)?;
+		self.clear();
+		self.scan_dir_collect_with_ignore(dir, ignore, None);
+		let new = self.file_map();
+		let write_txn = db.begin_write()?;
+		// Deleting the table also gets rid of one that was created with the wrong type
+		write_txn.delete_table(FILE_CACHE_TABLE)?;
+		{
+			let mut table = write_txn.open_table(FILE_CACHE_TABLE)?;
+			for (path, meta) in &new {
+				table.insert(serialize_path(path).as_ref(), meta.serialize()?.as_slice())?;
+			}
+		}
+		rebuild_checksum(&write_txn)?;
+		rebuild_dir_index(&write_txn)?;
+		rebuild_hash_index_if_kept(&write_txn)?;
+		write_txn.commit()?;
+		let generation = stored_generation(&db.begin_read()?)?;
+		self.synced_generation.store(generation, Ordering::Relaxed);
+		tracing::info!(files = new.len(), "Rebuilt the file cache from scratch");
+		Ok(diff_file_maps(&old, &new))
+	}
+}
@@ -0,0 +1,135 @@
+//! Moving every cached path to a new watch root, after the watched directory itself moved
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileCache;
+use crate::file_cache::db::{FILE_CACHE_TABLE, update_redb_batch_commit};
+use crate::file_cache::meta::{FileCachePath, FileMeta};
+use crate::persisted_config::PersistedConfig;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+impl FileCache {
+	/// Rewrite every cached path under `old_root` to the same path under `new_root`, in
+	/// memory and in `db`, and point the stored watch paths at `new_root`. Rows stored in
+	/// `db` move too when they aren't loaded. Returns the number of files migrated.
+	///
+	/// Fails without changing anything if a migrated path is already taken by a file
+	/// outside `old_root`.
+	pub fn migrate_root(
+		&self,
+		db: &redb::Database,
+		old_root: &Path,
+		new_root: &Path,
+	) -> LinkfieldResult<usize> {
+		let moved_path = |path: &Path| {
+			path.strip_prefix(old_root)
+				.ok()
+				.map(|relative| FileCachePath(new_root.join(relative)))
+		};
+		let mut files: HashMap<FileCachePath, FileMeta> = stored_under(db, old_root)?;
+		let mut taken = HashSet::new();
+		for meta in self.tree_files() {
+			if meta.path.0.starts_with(old_root) {
+				files.insert(meta.path.clone(), meta);
+			} else {
+				taken.insert(meta.path);
+			}
+		}
+		let moved: Vec<_> = files
+			.into_iter()
+			.filter_map(|(old_path, mut meta)| {
+				meta.path = moved_path(&old_path.0)?;
+				Some((old_path, meta))
+			})
+			.collect();
+		check_conflicts(db, old_root, &taken, &moved)?;
+
+		self.apply_rename(None, old_root, new_root);
+		if let Some(mut root) = self.entries.get_mut(&self.root)
+			&& Path::new(&root.name) == old_root
+		{
+			root.name = new_root.to_string_lossy().into_owned();
+		}
+		let (old_paths, new_entries): (Vec<_>, Vec<_>) = moved
+			.into_iter()
+			.map(|(old_path, meta)| (old_path, (meta.path.clone(), meta)))
+			.unzip();
+		update_redb_batch_commit(db, &old_paths, &new_entries)?;
+		if let Some(mut config) = PersistedConfig::load(db)? {
+			for path in &mut config.watch_paths {
+				if let Some(moved) = moved_path(path) {
+					*path = moved.0;
+				}
+			}
+			config.save(db)?;
+		}
+		tracing::info!(
+			old_root = %old_root.display(),
+			new_root = %new_root.display(),
+			migrated = new_entries.len(),
+			"Migrated watch root"
+		);
+		Ok(new_entries.len())
+	}
+}
+
+/// The rows of `db` under `prefix`, found by iterating the key range starting at it
+fn stored_under(
+	db: &redb::Database,
+	prefix: &Path,
+) -> LinkfieldResult<HashMap<FileCachePath, FileMeta>> {
+	let prefix_str = prefix.to_string_lossy();
+	let read_txn = db.begin_read()?;
+	let table = match read_txn.open_table(FILE_CACHE_TABLE) {
+		Ok(table) => table,
+		Err(redb::TableError::TableDoesNotExist(_)) => return Ok(HashMap::new()),
+		Err(e) => return Err(e.into()),
+	};
+	let mut files = HashMap::new();
+	for row in table.range(prefix_str.as_ref()..)? {
+		let (key, value) = row?;
+		if !key.value().starts_with(prefix_str.as_ref()) {
+			break;
+		}
+		// Skips siblings such as `dir2` when migrating `dir`
+		if Path::new(key.value()).starts_with(prefix) {
+			let meta = FileMeta::deserialize(value.value())?;
+			files.insert(FileCachePath::from(Path::new(key.value())), meta);
+		}
+	}
+	Ok(files)
+}
+
+/// Fail if a migrated path is cached in `taken` or stored in `db` outside `old_root`
+fn check_conflicts(
+	db: &redb::Database,
+	old_root: &Path,
+	taken: &HashSet<FileCachePath>,
+	moved: &[(FileCachePath, FileMeta)],
+) -> LinkfieldResult<()> {
+	let read_txn = db.begin_read()?;
+	let table = match read_txn.open_table(FILE_CACHE_TABLE) {
+		Ok(table) => Some(table),
+		Err(redb::TableError::TableDoesNotExist(_)) => None,
+		Err(e) => return Err(e.into()),
+	};
+	for (_, meta) in moved {
+		let new_path = &meta.path;
+		// Entries under the old root move away themselves
+		if new_path.0.starts_with(old_root) {
+			continue;
+		}
+		let stored = match &table {
+			Some(table) => table.get(new_path.0.to_string_lossy().as_ref())?.is_some(),
+			None => false,
+		};
+		if stored || taken.contains(new_path) {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::AlreadyExists,
+				format!("{new_path} is already cached"),
+			)
+			.into());
+		}
+	}
+	Ok(())
+}
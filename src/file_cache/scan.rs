@@ -0,0 +1,639 @@
+//! Scan configuration and retrying directories that could not be read
+
+use crate::file_cache::FileCache;
+use crate::file_cache::extensions::ExtensionNormalizer;
+use crate::file_cache::meta::{FileMeta, FileType};
+use crate::file_cache::summary::{OutputFormat, ScanSummary};
+use crate::ignore_config::IgnoreConfig;
+use dashmap::{DashMap, DashSet};
+use indicatif::{ProgressBar, ProgressDrawTarget};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// A directory the scan could not read
+#[derive(Debug)]
+pub struct ScanError {
+	pub path: PathBuf,
+	/// Tree key of the directory, used to rescan it in place
+	pub entry: u64,
+	pub error: std::io::Error,
+}
+
+/// Snapshot of a running scan, passed to [`ScanConfig::on_progress`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanProgress {
+	pub files_scanned: usize,
+	pub dirs_scanned: usize,
+	pub elapsed: Duration,
+}
+
+pub type ProgressCallback = Arc<dyn Fn(ScanProgress) + Send + Sync>;
+
+/// Time spent reading file metadata for one extension during a scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExtensionTiming {
+	pub count: u64,
+	pub total: Duration,
+}
+
+impl ExtensionTiming {
+	/// Mean time per file, zero when no file was read
+	pub fn average(&self) -> Duration {
+		if self.count == 0 {
+			return Duration::ZERO;
+		}
+		let nanos = self.total.as_nanos() / u128::from(self.count);
+		Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+	}
+}
+
+/// Metadata read timings by extension, `None` for files without one
+pub type ExtensionTimings = HashMap<Option<String>, ExtensionTiming>;
+
+pub type ExtensionTimingCallback = Arc<dyn Fn(&ExtensionTimings) + Send + Sync>;
+
+/// Extensions listed in the debug log of the slowest metadata reads after a scan
+const SLOWEST_EXTENSIONS_LOGGED: usize = 5;
+
+/// Default [`ScanConfig::max_concurrent_dirs`]
+#[cfg(feature = "tokio")]
+pub const DEFAULT_MAX_CONCURRENT_DIRS: usize = 64;
+
+/// How scan progress is drawn on stderr when there is no [`ScanConfig::on_progress`] callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProgressStyle {
+	#[default]
+	Spinner,
+	/// A bar filling up to [`ScanConfig::progress_total`]; a spinner when that is unknown
+	Bar,
+	/// Draw nothing, e.g. where escape codes would end up in logs
+	None,
+}
+
+impl ProgressStyle {
+	/// [`ProgressStyle::detect_from`] for this process
+	pub fn detect() -> Self {
+		Self::detect_from(
+			std::env::var("CI").ok().as_deref(),
+			std::io::stdout().is_terminal(),
+		)
+	}
+
+	/// `None` when running in CI, i.e. `ci` (the `CI` environment variable) is set to
+	/// anything but `false` or `0`, or when stdout is not a terminal; `Spinner` otherwise
+	pub fn detect_from(ci: Option<&str>, stdout_is_terminal: bool) -> Self {
+		let in_ci = ci.is_some_and(|ci| !matches!(ci, "" | "0" | "false"));
+		if in_ci || !stdout_is_terminal {
+			Self::None
+		} else {
+			Self::Spinner
+		}
+	}
+}
+
+#[derive(Clone)]
+pub struct ScanConfig {
+	/// How many times unreadable directories are rescanned before giving up
+	pub max_error_retries: u8,
+	/// Delay before the first retry, doubled for every further attempt
+	pub retry_base_delay: Duration,
+	/// Receives progress updates; without it a terminal progress spinner is shown instead
+	pub on_progress: Option<ProgressCallback>,
+	/// Report progress every this many files, and once more when the scan ends
+	pub progress_callback_interval: usize,
+	/// How the summary is reported when the scan ends
+	pub output_format: OutputFormat,
+	/// Descend into symlinked directories and record symlinked files. Otherwise symlinks
+	/// are skipped.
+	pub follow_symlinks: bool,
+	/// Progress display used without an `on_progress` callback
+	pub progress_style: ProgressStyle,
+	/// Files the scan is expected to find, e.g. the count stored by the previous scan
+	pub progress_total: Option<u64>,
+	/// Receives the time spent reading metadata per extension when the scan ends, to find
+	/// the file types that slow it down
+	pub on_extension_timing: Option<ExtensionTimingCallback>,
+	/// Store canonical extensions, e.g. `jpg` for `.jpeg` files. The cache keeps using it
+	/// for files the watcher updates after the scan.
+	pub extension_normalizer: Option<Arc<ExtensionNormalizer>>,
+	/// Cache devices, sockets and pipes too. Otherwise only regular files are cached; either
+	/// way [`FileCache::all_files`] lists only regular files.
+	pub include_special_files: bool,
+	/// Leave files another process holds a write lock on unhashed, since their contents may
+	/// be mid-write. The cache keeps using it when hashing files after the scan.
+	pub skip_locked_files: bool,
+	/// Directories [`FileCache::scan_dir_async`] reads at the same time
+	#[cfg(feature = "tokio")]
+	pub max_concurrent_dirs: usize,
+}
+
+impl Default for ScanConfig {
+	fn default() -> Self {
+		Self {
+			max_error_retries: 3,
+			retry_base_delay: Duration::from_secs(2),
+			on_progress: None,
+			progress_callback_interval: 500,
+			output_format: OutputFormat::None,
+			follow_symlinks: false,
+			progress_style: ProgressStyle::Spinner,
+			progress_total: None,
+			on_extension_timing: None,
+			extension_normalizer: None,
+			include_special_files: false,
+			skip_locked_files: true,
+			#[cfg(feature = "tokio")]
+			max_concurrent_dirs: DEFAULT_MAX_CONCURRENT_DIRS,
+		}
+	}
+}
+
+impl ScanConfig {
+	/// The defaults with the progress style picked by [`ProgressStyle::detect`]
+	pub fn with_auto_progress_style() -> Self {
+		Self {
+			progress_style: ProgressStyle::detect(),
+			..Self::default()
+		}
+	}
+}
+
+impl std::fmt::Debug for ScanConfig {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let mut debug = f.debug_struct("ScanConfig");
+		debug
+			.field("max_error_retries", &self.max_error_retries)
+			.field("retry_base_delay", &self.retry_base_delay)
+			.field("on_progress", &self.on_progress.is_some())
+			.field(
+				"progress_callback_interval",
+				&self.progress_callback_interval,
+			)
+			.field("output_format", &self.output_format)
+			.field("follow_symlinks", &self.follow_symlinks)
+			.field("progress_style", &self.progress_style)
+			.field("progress_total", &self.progress_total)
+			.field("on_extension_timing", &self.on_extension_timing.is_some())
+			.field("extension_normalizer", &self.extension_normalizer)
+			.field("include_special_files", &self.include_special_files)
+			.field("skip_locked_files", &self.skip_locked_files);
+		#[cfg(feature = "tokio")]
+		debug.field("max_concurrent_dirs", &self.max_concurrent_dirs);
+		debug.finish()
+	}
+}
+
+enum ProgressSink {
+	Callback(ProgressCallback),
+	Bar(ProgressBar),
+	Hidden,
+}
+
+/// Counts files and directories across the parallel scan and forwards progress every
+/// `progress_callback_interval` files
+pub(crate) struct ScanProgressReporter {
+	files: AtomicUsize,
+	dirs: AtomicUsize,
+	bytes: AtomicU64,
+	extensions: DashMap<String, usize>,
+	extension_timings: DashMap<Option<String>, ExtensionTiming>,
+	on_extension_timing: Option<ExtensionTimingCallback>,
+	start: Instant,
+	interval: usize,
+	sink: ProgressSink,
+	output_format: OutputFormat,
+}
+
+impl ScanProgressReporter {
+	pub(crate) fn new(config: &ScanConfig) -> Self {
+		// Draws to stderr only when it is a terminal
+		Self::with_draw_target(config, ProgressDrawTarget::stderr())
+	}
+
+	fn with_draw_target(config: &ScanConfig, target: ProgressDrawTarget) -> Self {
+		let bar = |len: Option<u64>, template: &str| {
+			let bar = ProgressBar::with_draw_target(len, target);
+			if let Ok(style) = indicatif::ProgressStyle::with_template(template) {
+				bar.set_style(style);
+			}
+			ProgressSink::Bar(bar)
+		};
+		let sink = match (&config.on_progress, config.progress_style) {
+			(Some(callback), _) => ProgressSink::Callback(callback.clone()),
+			(None, ProgressStyle::None) => ProgressSink::Hidden,
+			(None, ProgressStyle::Bar) if config.progress_total.is_some() => bar(
+				config.progress_total,
+				"[{elapsed}] {wide_bar} {pos}/{len} {msg}",
+			),
+			(None, ProgressStyle::Spinner | ProgressStyle::Bar) => {
+				bar(None, "{spinner} [{elapsed}] {msg}")
+			}
+		};
+		Self {
+			files: AtomicUsize::new(0),
+			dirs: AtomicUsize::new(0),
+			bytes: AtomicU64::new(0),
+			extensions: DashMap::new(),
+			extension_timings: DashMap::new(),
+			on_extension_timing: config.on_extension_timing.clone(),
+			start: Instant::now(),
+			interval: config.progress_callback_interval.max(1),
+			sink,
+			output_format: config.output_format,
+		}
+	}
+
+	pub(crate) fn dir_scanned(&self) {
+		self.dirs.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub(crate) fn file_scanned(&self, meta: &FileMeta) {
+		self.bytes.fetch_add(meta.size, Ordering::Relaxed);
+		if let Some(extension) = &meta.extension {
+			*self.extensions.entry(extension.clone()).or_default() += 1;
+		}
+		let files = self.files.fetch_add(1, Ordering::Relaxed) + 1;
+		if files.is_multiple_of(self.interval) {
+			self.report(files);
+		}
+	}
+
+	/// Record that reading the metadata of a file with `extension` took `elapsed`
+	pub(crate) fn metadata_read(&self, extension: Option<String>, elapsed: Duration) {
+		let mut timing = self.extension_timings.entry(extension).or_default();
+		timing.count += 1;
+		timing.total += elapsed;
+	}
+
+	/// Send the final progress update and report the summary
+	pub(crate) fn finish(self, ignored_count: usize) -> ScanSummary {
+		self.report(self.files.load(Ordering::Relaxed));
+		if let ProgressSink::Bar(bar) = &self.sink {
+			bar.finish_and_clear();
+		}
+		let timings: ExtensionTimings = self.extension_timings.into_iter().collect();
+		log_slowest_extensions(&timings);
+		if let Some(callback) = &self.on_extension_timing {
+			callback(&timings);
+		}
+		let summary = ScanSummary::new(
+			self.files.load(Ordering::Relaxed),
+			self.dirs.load(Ordering::Relaxed),
+			self.bytes.load(Ordering::Relaxed),
+			self.start.elapsed(),
+			self.extensions,
+		)
+		.with_ignored_count(ignored_count);
+		summary.report(self.output_format);
+		summary
+	}
+
+	fn report(&self, files_scanned: usize) {
+		let progress = ScanProgress {
+			files_scanned,
+			dirs_scanned: self.dirs.load(Ordering::Relaxed),
+			elapsed: self.start.elapsed(),
+		};
+		match &self.sink {
+			ProgressSink::Callback(callback) => callback(progress),
+			ProgressSink::Bar(bar) => {
+				bar.set_position(progress.files_scanned as u64);
+				bar.set_message(format!(
+					"Scanned {} files in {} directories",
+					progress.files_scanned, progress.dirs_scanned
+				));
+			}
+			ProgressSink::Hidden => {}
+		}
+	}
+}
+
+/// The `n` extensions with the highest average metadata read time, slowest first
+fn slowest_extensions(timings: &ExtensionTimings, n: usize) -> Vec<(&Option<String>, Duration)> {
+	let mut averages: Vec<_> = timings
+		.iter()
+		.map(|(extension, timing)| (extension, timing.average()))
+		.collect();
+	averages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+	averages.truncate(n);
+	averages
+}
+
+fn log_slowest_extensions(timings: &ExtensionTimings) {
+	for (extension, average) in slowest_extensions(timings, SLOWEST_EXTENSIONS_LOGGED) {
+		tracing::debug!(
+			extension = extension.as_deref().unwrap_or("-"),
+			count = timings[extension].count,
+			?average,
+			"Slow metadata reads by extension"
+		);
+	}
+}
+
+/// Identity of a directory independent of the path used to reach it
+#[cfg(unix)]
+type DirIdentity = (u64, u64);
+#[cfg(not(unix))]
+type DirIdentity = PathBuf;
+
+#[cfg(unix)]
+fn dir_identity(dir: &std::path::Path) -> Option<DirIdentity> {
+	use std::os::unix::fs::MetadataExt;
+	// Follows symlinks, so a link back to an ancestor resolves to the ancestor itself
+	let metadata = std::fs::metadata(dir).ok()?;
+	Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_identity(dir: &std::path::Path) -> Option<DirIdentity> {
+	std::fs::canonicalize(dir).ok()
+}
+
+/// Per-scan state shared by the parallel directory walk
+pub(crate) struct ScanState<'a> {
+	visited: DashSet<DirIdentity>,
+	/// Files and directories skipped by the ignore rules
+	ignored: AtomicUsize,
+	pub(crate) progress: Option<&'a ScanProgressReporter>,
+	follow_symlinks: bool,
+	include_special_files: bool,
+}
+
+impl<'a> ScanState<'a> {
+	pub(crate) fn new(progress: Option<&'a ScanProgressReporter>) -> Self {
+		Self {
+			visited: DashSet::new(),
+			ignored: AtomicUsize::new(0),
+			progress,
+			follow_symlinks: false,
+			include_special_files: false,
+		}
+	}
+
+	/// See [`ScanConfig::follow_symlinks`]
+	pub(crate) const fn with_follow_symlinks(mut self, follow: bool) -> Self {
+		self.follow_symlinks = follow;
+		self
+	}
+
+	/// See [`ScanConfig::include_special_files`]
+	pub(crate) const fn with_include_special_files(mut self, include: bool) -> Self {
+		self.include_special_files = include;
+		self
+	}
+
+	/// True when `meta` is not a regular file and special files are not included
+	pub(crate) fn skip_special(&self, meta: &FileMeta) -> bool {
+		let skip = !self.include_special_files && meta.file_type != FileType::Regular;
+		if skip {
+			tracing::debug!(path = %meta.path, file_type = ?meta.file_type, "Skipping special file");
+		}
+		skip
+	}
+
+	/// The path of `entry` and whether it is a directory, or `None` when it is a symlink that
+	/// is not followed or the ignore patterns skip it. The type comes from `read_dir`, so
+	/// only followed symlinks are stat'd here.
+	pub(crate) fn walk_entry(
+		&self,
+		entry: &std::fs::DirEntry,
+		ignore: &IgnoreConfig,
+	) -> Option<(PathBuf, bool)> {
+		let path = entry.path();
+		let file_type = entry.file_type().ok()?;
+		let is_dir = if file_type.is_symlink() {
+			if !self.follow_symlinks {
+				tracing::debug!(path = %path.display(), "Skipping symlink");
+				return None;
+			}
+			path.is_dir()
+		} else {
+			file_type.is_dir()
+		};
+		if ignore.is_ignored_by_pattern(&path, is_dir) {
+			if is_dir {
+				tracing::info!(ignore_match = %path.display(), "ignoring directory due to ignore config");
+			}
+			self.entry_ignored();
+			return None;
+		}
+		Some((path, is_dir))
+	}
+
+	pub(crate) fn entry_ignored(&self) {
+		self.ignored.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub(crate) fn ignored_count(&self) -> usize {
+		self.ignored.load(Ordering::Relaxed)
+	}
+
+	/// False when `dir` was already scanned, e.g. because a symlink leads back into the tree.
+	/// Directories whose identity can't be read are always scanned.
+	pub(crate) fn first_visit(&self, dir: &std::path::Path) -> bool {
+		dir_identity(dir).is_none_or(|identity| self.visited.insert(identity))
+	}
+}
+
+impl FileCache {
+	/// Rescan the directories in `errors` with exponential backoff (2s, 4s, 8s by default).
+	/// With `db` the rescans commit like the initial scan, otherwise they only update memory.
+	/// Stops early when the scan is cancelled. Returns the directories that stayed unreadable.
+	pub fn retry_scan_errors(
+		self: &Arc<Self>,
+		db: Option<&redb::Database>,
+		ignore: &IgnoreConfig,
+		mut errors: Vec<ScanError>,
+		config: &ScanConfig,
+	) -> Vec<ScanError> {
+		let cancel = self.scan_cancellation_token();
+		let state = ScanState::new(None)
+			.with_follow_symlinks(config.follow_symlinks)
+			.with_include_special_files(config.include_special_files);
+		for attempt in 1..=config.max_error_retries {
+			if errors.is_empty() {
+				break;
+			}
+			let delay = config.retry_base_delay * 2u32.pow(u32::from(attempt - 1));
+			warn!(
+				attempt,
+				max_attempts = config.max_error_retries,
+				failed = errors.len(),
+				?delay,
+				"Retrying unreadable directories"
+			);
+			if !cancel.sleep(delay) {
+				break;
+			}
+			errors = errors
+				.into_iter()
+				.flat_map(|failed| {
+					warn!(path = %failed.path.display(), error = %failed.error, attempt, "Retrying scan");
+					match db {
+						Some(db) => self.scan_commit(
+							db,
+							&failed.path,
+							ignore,
+							Some(failed.entry),
+							1000,
+							None,
+							&state,
+						),
+						None => self.scan_collect(&failed.path, ignore, Some(failed.entry), &state),
+					}
+				})
+				.collect();
+		}
+		// Retried directories were never reached by the scan that failed on them
+		self.add_ignored_count(state.ignored_count());
+		for failed in &errors {
+			warn!(path = %failed.path.display(), error = %failed.error, "Giving up on unreachable directory");
+		}
+		errors
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use indicatif::TermLike;
+	use std::io;
+	use std::sync::Mutex;
+
+	/// Terminal that records everything drawn to it
+	#[derive(Debug, Clone, Default)]
+	struct RecordingTerm(Arc<Mutex<String>>);
+
+	impl TermLike for RecordingTerm {
+		fn width(&self) -> u16 {
+			80
+		}
+		fn move_cursor_up(&self, _: usize) -> io::Result<()> {
+			self.write_str("\x1b[A")
+		}
+		fn move_cursor_down(&self, _: usize) -> io::Result<()> {
+			self.write_str("\x1b[B")
+		}
+		fn move_cursor_right(&self, _: usize) -> io::Result<()> {
+			self.write_str("\x1b[C")
+		}
+		fn move_cursor_left(&self, _: usize) -> io::Result<()> {
+			self.write_str("\x1b[D")
+		}
+		fn write_line(&self, s: &str) -> io::Result<()> {
+			self.write_str(&format!("{s}\n"))
+		}
+		fn write_str(&self, s: &str) -> io::Result<()> {
+			self.0.lock().unwrap().push_str(s);
+			Ok(())
+		}
+		fn clear_line(&self) -> io::Result<()> {
+			self.write_str("\r\x1b[2K")
+		}
+		fn flush(&self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	fn drawn(config: &ScanConfig) -> String {
+		let term = RecordingTerm::default();
+		let reporter = ScanProgressReporter::with_draw_target(
+			config,
+			ProgressDrawTarget::term_like(Box::new(term.clone())),
+		);
+		reporter.report(42);
+		reporter.finish(0);
+		term.0.lock().unwrap().clone()
+	}
+
+	#[test]
+	fn test_progress_style_none_draws_nothing() {
+		let spinner = drawn(&ScanConfig::default());
+		assert!(spinner.contains("Scanned 42 files"));
+		assert!(spinner.contains('\x1b'));
+
+		let bar = drawn(&ScanConfig {
+			progress_style: ProgressStyle::Bar,
+			progress_total: Some(100),
+			..ScanConfig::default()
+		});
+		assert!(bar.contains("42/100"));
+
+		let none = drawn(&ScanConfig {
+			progress_style: ProgressStyle::None,
+			..ScanConfig::default()
+		});
+		assert_eq!(none, "");
+	}
+
+	#[test]
+	fn test_progress_style_detection() {
+		assert_eq!(
+			ProgressStyle::detect_from(None, true),
+			ProgressStyle::Spinner
+		);
+		assert_eq!(
+			ProgressStyle::detect_from(Some("true"), true),
+			ProgressStyle::None
+		);
+		assert_eq!(
+			ProgressStyle::detect_from(Some("1"), true),
+			ProgressStyle::None
+		);
+		assert_eq!(
+			ProgressStyle::detect_from(Some("false"), true),
+			ProgressStyle::Spinner
+		);
+		assert_eq!(ProgressStyle::detect_from(None, false), ProgressStyle::None);
+	}
+
+	#[test]
+	fn test_extension_timings_reach_the_callback() {
+		let received = Arc::new(Mutex::new(ExtensionTimings::new()));
+		let sink = received.clone();
+		let config = ScanConfig {
+			progress_style: ProgressStyle::None,
+			on_extension_timing: Some(Arc::new(move |timings: &ExtensionTimings| {
+				*sink.lock().unwrap() = timings.clone();
+			})),
+			..ScanConfig::default()
+		};
+		let reporter = ScanProgressReporter::new(&config);
+		// Mail on a slow network drive next to local source files
+		let eml = Some("eml".to_string());
+		reporter.metadata_read(eml.clone(), Duration::from_millis(40));
+		reporter.metadata_read(eml.clone(), Duration::from_millis(60));
+		for _ in 0..4 {
+			reporter.metadata_read(Some("rs".to_string()), Duration::from_micros(10));
+		}
+		reporter.metadata_read(None, Duration::from_millis(1));
+		reporter.finish(0);
+
+		let timings = received.lock().unwrap().clone();
+		assert_eq!(timings.len(), 3);
+		assert_eq!(
+			timings[&eml],
+			ExtensionTiming {
+				count: 2,
+				total: Duration::from_millis(100)
+			}
+		);
+		assert_eq!(timings[&eml].average(), Duration::from_millis(50));
+		assert_eq!(timings[&Some("rs".to_string())].count, 4);
+		let slowest: Vec<_> = slowest_extensions(&timings, 2)
+			.into_iter()
+			.map(|(extension, _)| extension.clone())
+			.collect();
+		assert_eq!(slowest, [eml, None]);
+	}
+
+	#[test]
+	fn test_extension_timing_average_of_nothing() {
+		assert_eq!(ExtensionTiming::default().average(), Duration::ZERO);
+	}
+}
@@ -0,0 +1,53 @@
+//! MinHash signatures of the cached files, kept for the move heuristics
+
+use crate::file_cache::FileCache;
+use crate::file_cache::meta::FileCachePath;
+use crate::minhash::{self, MinHash};
+use std::path::Path;
+
+impl FileCache {
+	/// Read the [`MinHash`] signature of the file at `path` and keep it until the file is
+	/// removed, so a Remove event can still be paired by contents. Files locked by another
+	/// process are skipped as [`FileCache::skip_locked_files`] says. Returns the signature,
+	/// or `None` after dropping any older one when it can't be read.
+	pub fn update_signature(&self, path: &Path) -> Option<MinHash> {
+		let key = FileCachePath::from(path);
+		if self.skip_locked_files() && crate::platform::is_file_locked(path) {
+			tracing::debug!(path = %path.display(), "Skipping locked file");
+			self.signatures.remove(&key);
+			return None;
+		}
+		match minhash::file_signature(path) {
+			Ok(signature) => {
+				self.signatures.insert(key, signature);
+				Some(signature)
+			}
+			Err(e) => {
+				tracing::debug!(path = %path.display(), error = %e, "Failed to read MinHash signature");
+				self.signatures.remove(&key);
+				None
+			}
+		}
+	}
+
+	/// The signature kept for `path` by [`FileCache::update_signature`]
+	pub fn signature(&self, path: &Path) -> Option<MinHash> {
+		self.signatures
+			.get(&FileCachePath::from(path))
+			.map(|signature| *signature)
+	}
+
+	/// Forget the signature of a removed file, returning it
+	pub fn take_signature(&self, path: &Path) -> Option<MinHash> {
+		self.signatures
+			.remove(&FileCachePath::from(path))
+			.map(|(_, signature)| signature)
+	}
+
+	/// Keep the signature of a file renamed from `from` under `to`
+	pub fn move_signature(&self, from: &Path, to: &Path) {
+		if let Some(signature) = self.take_signature(from) {
+			self.signatures.insert(FileCachePath::from(to), signature);
+		}
+	}
+}
@@ -0,0 +1,82 @@
+//! Bootstrap a cache from a JSON export instead of scanning
+
+use crate::error::LinkfieldError;
+use crate::file_cache::FileCache;
+use crate::file_cache::db::update_redb_batch_commit;
+use crate::file_cache::meta::FileMeta;
+use serde::de::{Deserializer, SeqAccess, Visitor};
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Entries committed to the database per transaction while importing
+pub const SNAPSHOT_BATCH_SIZE: usize = 1000;
+
+impl FileCache {
+	/// Load a JSON array of `FileMeta` records written by [`FileCache::export_json`] and
+	/// commit it to `db`.
+	///
+	/// The entries are taken as-is, not checked against the disk; run a scan afterwards to
+	/// pick up anything that changed since the export.
+	pub fn from_snapshot_file(
+		root_name: &str,
+		path: &Path,
+		db: &redb::Database,
+	) -> Result<Arc<Self>, Box<dyn Error>> {
+		let file = std::fs::File::open(path)?;
+		Self::from_snapshot_reader(root_name, std::io::BufReader::new(file), db)
+	}
+
+	/// Like [`FileCache::from_snapshot_file`], reading from any stream such as stdin. Records
+	/// are committed in batches of [`SNAPSHOT_BATCH_SIZE`] as they are parsed.
+	pub fn from_snapshot_reader<R: Read>(
+		root_name: &str,
+		reader: R,
+		db: &redb::Database,
+	) -> Result<Arc<Self>, Box<dyn Error>> {
+		let cache = Self::new_root(root_name);
+		let mut batch = Vec::with_capacity(SNAPSHOT_BATCH_SIZE);
+		let mut imported = 0;
+		let mut commit = |batch: &mut Vec<(_, FileMeta)>| {
+			update_redb_batch_commit(db, &[], batch)?;
+			imported += batch.len();
+			batch.clear();
+			tracing::info!(imported, "Imported snapshot entries");
+			Ok::<_, LinkfieldError>(())
+		};
+		let mut deserializer = serde_json::Deserializer::from_reader(reader);
+		deserializer.deserialize_seq(SnapshotVisitor(|meta: FileMeta| {
+			cache.insert_meta(meta.clone());
+			batch.push((meta.path.clone(), meta));
+			if batch.len() >= SNAPSHOT_BATCH_SIZE {
+				commit(&mut batch)?;
+			}
+			Ok(())
+		}))?;
+		deserializer.end()?;
+		if !batch.is_empty() {
+			commit(&mut batch)?;
+		}
+		Ok(cache)
+	}
+}
+
+/// Hands each element of a JSON array to the closure without collecting the array first
+struct SnapshotVisitor<F>(F);
+
+impl<'de, F: FnMut(FileMeta) -> Result<(), LinkfieldError>> Visitor<'de> for SnapshotVisitor<F> {
+	type Value = ();
+
+	fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("a list of file metadata records")
+	}
+
+	fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+		while let Some(meta) = seq.next_element()? {
+			(self.0)(meta).map_err(serde::de::Error::custom)?;
+		}
+		Ok(())
+	}
+}
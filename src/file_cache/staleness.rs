@@ -0,0 +1,65 @@
+//! Estimating whether a cache loaded from the database still matches the disk
+
+use crate::file_cache::FileCache;
+use crate::file_cache::cache::EntryKind;
+use rand::seq::IteratorRandom;
+use std::fs;
+
+/// Result of [`FileCache::estimate_staleness`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StalenessEstimate {
+	/// Files actually checked, at most the requested sample size
+	pub sample_size: usize,
+	/// Sampled files that are gone or whose size or mtime changed
+	pub mismatches: usize,
+	/// `mismatches / sample_size`, or 1.0 for an empty cache, which is never fresh
+	pub staleness_ratio: f64,
+}
+
+impl FileCache {
+	/// Check a random sample of up to `sample_size` cached files against the disk. Only
+	/// changes to cached files are seen; files created since the cache was written are not.
+	pub fn estimate_staleness(&self, sample_size: usize) -> StalenessEstimate {
+		let sample: Vec<_> = self
+			.entries
+			.iter()
+			.filter(|entry| matches!(entry.kind, EntryKind::File(_)))
+			.choose_multiple(&mut rand::rng(), sample_size)
+			.into_iter()
+			.filter_map(|entry| match &entry.kind {
+				EntryKind::File(meta) => Some((meta.path.clone(), meta.size, meta.modified)),
+				EntryKind::Directory => None,
+			})
+			.collect();
+		let mismatches = sample
+			.iter()
+			.filter(|(path, size, modified)| {
+				fs::metadata(path).map_or(true, |metadata| {
+					metadata.len() != *size || metadata.modified().ok() != *modified
+				})
+			})
+			.count();
+		#[allow(clippy::cast_precision_loss)]
+		let staleness_ratio = if sample.is_empty() {
+			1.0
+		} else {
+			mismatches as f64 / sample.len() as f64
+		};
+		StalenessEstimate {
+			sample_size: sample.len(),
+			mismatches,
+			staleness_ratio,
+		}
+	}
+	/// Fraction of a random sample of up to `sample_size` cached files that changed on disk,
+	/// the [`StalenessEstimate::staleness_ratio`] of [`FileCache::estimate_staleness`]
+	pub fn stale_ratio(&self, sample_size: usize) -> f64 {
+		self.estimate_staleness(sample_size).staleness_ratio
+	}
+
+	/// Whether at least `threshold` of a sample of cached files changed on disk, so a
+	/// rescan is due. A threshold of 0 always asks for one, as does an empty cache.
+	pub fn needs_rescan(&self, threshold: f64, sample_size: usize) -> bool {
+		self.stale_ratio(sample_size) >= threshold
+	}
+}
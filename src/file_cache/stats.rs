@@ -0,0 +1,268 @@
+//! Aggregate queries over the cached files for storage analysis
+
+use crate::file_cache::FileCache;
+use crate::file_cache::cache::EntryKind;
+use crate::file_cache::meta::FileMeta;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Size totals for one directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirectorySize {
+	/// Files anywhere in the subtree
+	pub file_count: usize,
+	/// Bytes of all files in the subtree
+	pub total_bytes: u64,
+	/// Bytes of the files directly inside the directory
+	pub direct_bytes: u64,
+}
+
+/// `(extension, file_count, total_bytes)`; `None` groups files without an extension
+pub type ExtensionRow = (Option<String>, usize, u64);
+
+/// Fixed-width table of `rows` with a header line
+pub fn format_extension_table(rows: &[ExtensionRow]) -> String {
+	let mut out = format!("{:<16}  {:>8}  {:>14}\n", "extension", "files", "bytes");
+	for (extension, count, bytes) in rows {
+		let _ = writeln!(
+			out,
+			"{:<16}  {count:>8}  {bytes:>14}",
+			extension.as_deref().unwrap_or("(none)")
+		);
+	}
+	out
+}
+
+/// Lower bounds of the buckets of [`FileCache::size_distribution`] listed by `linkfield stats`:
+/// 0, 1 KB, 10 KB, 100 KB, 1 MB, 10 MB and 100 MB, the last one open-ended
+pub const DEFAULT_SIZE_BUCKETS: [u64; 7] = [
+	0,
+	1_000,
+	10_000,
+	100_000,
+	1_000_000,
+	10_000_000,
+	100_000_000,
+];
+
+/// Fixed-width table of the rows of [`FileCache::size_distribution`], with a header line
+pub fn format_size_distribution(rows: &[(u64, usize)]) -> String {
+	let mut out = format!("{:>14}  {:>8}\n", "from bytes", "files");
+	for (bound, count) in rows {
+		let _ = writeln!(out, "{bound:>14}  {count:>8}");
+	}
+	out
+}
+
+/// Alert when a directory holds more than `max_files` files, parsed from `<dir>:<n>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirCountThreshold {
+	pub dir: PathBuf,
+	pub max_files: usize,
+}
+
+impl FromStr for DirCountThreshold {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		// Split on the last colon so Windows drive letters stay in the path
+		let (dir, count) = s
+			.rsplit_once(':')
+			.ok_or_else(|| format!("expected <dir>:<n>, got '{s}'"))?;
+		let max_files = count
+			.parse()
+			.map_err(|e| format!("invalid file count '{count}': {e}"))?;
+		Ok(Self {
+			dir: PathBuf::from(dir),
+			max_files,
+		})
+	}
+}
+
+impl FileCache {
+	/// Sizes of `root` and every directory below it that contains cached files.
+	/// Files outside `root` are ignored.
+	pub fn directory_sizes(&self, root: &Path) -> HashMap<PathBuf, DirectorySize> {
+		let mut sizes: HashMap<PathBuf, DirectorySize> = HashMap::new();
+		for meta in self.all_files() {
+			let path = &meta.path.0;
+			if !path.starts_with(root) {
+				continue;
+			}
+			let Some(parent) = path.parent() else {
+				continue;
+			};
+			sizes.entry(parent.to_path_buf()).or_default().direct_bytes += meta.size;
+			for dir in parent.ancestors() {
+				if !dir.starts_with(root) {
+					break;
+				}
+				let entry = sizes.entry(dir.to_path_buf()).or_default();
+				entry.file_count += 1;
+				entry.total_bytes += meta.size;
+			}
+		}
+		sizes
+	}
+
+	/// The `n` directories under `root` with the largest subtree size, largest first
+	pub fn top_n_directories(&self, root: &Path, n: usize) -> Vec<(PathBuf, DirectorySize)> {
+		let mut dirs: Vec<_> = self.directory_sizes(root).into_iter().collect();
+		dirs.sort_by(|a, b| {
+			b.1.total_bytes
+				.cmp(&a.1.total_bytes)
+				.then_with(|| a.0.cmp(&b.0))
+		});
+		dirs.truncate(n);
+		dirs
+	}
+
+	fn file_sizes(&self) -> impl Iterator<Item = u64> + '_ {
+		self.entries.iter().filter_map(|entry| match &entry.kind {
+			EntryKind::File(meta) => Some(meta.size),
+			EntryKind::Directory => None,
+		})
+	}
+
+	/// Bytes of all cached files
+	pub fn total_size_bytes(&self) -> u64 {
+		self.file_sizes().sum()
+	}
+
+	/// Disk space taken up by all cached files, see [`FileMeta::size_on_disk`]. The
+	/// difference to [`FileCache::total_size_bytes`] is lost to partly filled blocks.
+	pub fn total_size_on_disk(&self) -> u64 {
+		self.all_files().iter().map(FileMeta::size_on_disk).sum()
+	}
+
+	/// Mean size of the cached files, 0.0 when there are none
+	#[allow(clippy::cast_precision_loss)]
+	pub fn average_file_size(&self) -> f64 {
+		let (count, total) = self
+			.file_sizes()
+			.fold((0u64, 0u64), |(count, total), size| {
+				(count + 1, total + size)
+			});
+		if count == 0 {
+			0.0
+		} else {
+			total as f64 / count as f64
+		}
+	}
+
+	/// Median size of the cached files, the mean of the middle two for an even count and 0
+	/// when there are none
+	pub fn median_file_size(&self) -> u64 {
+		let mut sizes: Vec<u64> = self.file_sizes().collect();
+		sizes.sort_unstable();
+		let mid = sizes.len() / 2;
+		match sizes.len() {
+			0 => 0,
+			len if len % 2 == 1 => sizes[mid],
+			_ => sizes[mid - 1] + (sizes[mid] - sizes[mid - 1]) / 2,
+		}
+	}
+
+	/// Number of files per size bucket. `buckets` holds ascending lower bounds, e.g.
+	/// [`DEFAULT_SIZE_BUCKETS`]; each bucket runs up to the next bound and the last one has
+	/// no upper bound. Files smaller than the first bound are not counted.
+	pub fn size_distribution(&self, buckets: &[u64]) -> Vec<(u64, usize)> {
+		let mut counts = vec![0; buckets.len()];
+		for size in self.file_sizes() {
+			let bucket = buckets.partition_point(|&bound| bound <= size);
+			if let Some(count) = bucket.checked_sub(1).map(|i| &mut counts[i]) {
+				*count += 1;
+			}
+		}
+		buckets.iter().copied().zip(counts).collect()
+	}
+
+	/// File count and total size per extension, largest total size first
+	pub fn path_count_by_extension_sorted(&self) -> Vec<ExtensionRow> {
+		let mut groups: HashMap<Option<String>, (usize, u64)> = HashMap::new();
+		for meta in self.all_files() {
+			let group = groups.entry(meta.extension).or_default();
+			group.0 += 1;
+			group.1 += meta.size;
+		}
+		let mut rows: Vec<_> = groups
+			.into_iter()
+			.map(|(extension, (count, bytes))| (extension, count, bytes))
+			.collect();
+		rows.sort_by(|a, b| {
+			b.2.cmp(&a.2)
+				.then_with(|| b.1.cmp(&a.1))
+				.then_with(|| a.0.cmp(&b.0))
+		});
+		rows
+	}
+
+	/// The first `n` rows of [`FileCache::path_count_by_extension_sorted`]
+	pub fn top_extensions(&self, n: usize) -> Vec<ExtensionRow> {
+		let mut rows = self.path_count_by_extension_sorted();
+		rows.truncate(n);
+		rows
+	}
+
+	/// Number of files directly inside each directory at most `max_depth` levels below `root`
+	/// (`root` itself is depth 0). Known directories without files are reported with 0.
+	pub fn entry_count_by_dir(&self, root: &Path, max_depth: usize) -> HashMap<PathBuf, usize> {
+		let depth = |dir: &Path| {
+			dir.strip_prefix(root)
+				.ok()
+				.map(|relative| relative.components().count())
+		};
+		let mut counts: HashMap<PathBuf, usize> = self
+			.subdirectories_of(root)
+			.map(|dir| dir.0)
+			.chain(std::iter::once(root.to_path_buf()))
+			.filter(|dir| depth(dir).is_some_and(|d| d <= max_depth))
+			.map(|dir| (dir, 0))
+			.collect();
+		for meta in self.all_files() {
+			let Some(parent) = meta.path.0.parent() else {
+				continue;
+			};
+			if depth(parent).is_some_and(|d| d <= max_depth) {
+				*counts.entry(parent.to_path_buf()).or_default() += 1;
+			}
+		}
+		counts
+	}
+
+	/// Number of files anywhere below each directory under `root` that contains files
+	pub fn entry_count_recursive_by_dir(&self, root: &Path) -> HashMap<PathBuf, usize> {
+		self.directory_sizes(root)
+			.into_iter()
+			.map(|(dir, size)| (dir, size.file_count))
+			.collect()
+	}
+
+	/// Warn about every threshold whose directory holds more files than allowed, counting the
+	/// whole subtree. Returns the exceeded thresholds with the actual counts.
+	pub fn check_dir_count_thresholds(
+		&self,
+		thresholds: &[DirCountThreshold],
+	) -> Vec<(DirCountThreshold, usize)> {
+		let mut exceeded = Vec::new();
+		for threshold in thresholds {
+			let count = self
+				.entry_count_recursive_by_dir(&threshold.dir)
+				.get(&threshold.dir)
+				.copied()
+				.unwrap_or(0);
+			if count > threshold.max_files {
+				tracing::warn!(
+					dir = %threshold.dir.display(),
+					count,
+					threshold = threshold.max_files,
+					"Directory file count above alert threshold"
+				);
+				exceeded.push((threshold.clone(), count));
+			}
+		}
+		exceeded
+	}
+}
@@ -0,0 +1,196 @@
+//! Scanning straight into the database in fixed-size batches, for trees too large to hold
+//! in memory
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileCache;
+use crate::file_cache::db::{FILE_CACHE_TABLE, serialize_path, update_redb_batch_commit};
+use crate::file_cache::meta::{FileCachePath, FileMeta};
+use crate::file_cache::scan::{ScanError, ScanState};
+use crate::ignore_config::IgnoreConfig;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// What [`FileCache::scan_dir_with_redb_streaming`] changed in the database
+#[derive(Debug, Default)]
+pub struct StreamingScanSummary {
+	pub added: usize,
+	pub modified: usize,
+	pub unchanged: usize,
+	pub removed: usize,
+	/// Directories that could not be read; stored files below them are kept
+	pub errors: Vec<ScanError>,
+}
+
+impl FileCache {
+	/// Scan `dir` and bring the stored files under it up to date without building the tree
+	/// in memory. Every `batch_size` files are diffed against the database and the changes
+	/// committed; only the paths seen are kept, to remove the stored files that are gone
+	/// once the walk ends. Nothing is removed when the scan is cancelled.
+	pub fn scan_dir_with_redb_streaming(
+		&self,
+		db: &redb::Database,
+		dir: &Path,
+		ignore: &IgnoreConfig,
+		batch_size: usize,
+	) -> LinkfieldResult<StreamingScanSummary> {
+		let batch_size = batch_size.max(1);
+		let cancel = self.scan_cancellation_token();
+		let state = ScanState::new(None);
+		let mut summary = StreamingScanSummary::default();
+		let mut seen: HashSet<FileCachePath> = HashSet::new();
+		let mut batch: Vec<FileMeta> = Vec::with_capacity(batch_size);
+		// Entries below the root are checked against the ignore rules before they are queued
+		let mut pending = Vec::new();
+		if ignore.is_ignored(dir) {
+			state.entry_ignored();
+		} else {
+			pending.push(dir.to_path_buf());
+		}
+		while let Some(current) = pending.pop() {
+			if cancel.is_cancelled() {
+				break;
+			}
+			if !state.first_visit(&current) {
+				tracing::warn!(path = %current.display(), "Circular symlink detected, skipping");
+				continue;
+			}
+			let entries = match std::fs::read_dir(&current) {
+				Ok(entries) => entries,
+				Err(e) => {
+					tracing::warn!(error = %e, dir = %current.display(), "Error reading dir");
+					summary.errors.push(ScanError {
+						path: current,
+						entry: self.root,
+						error: e,
+					});
+					continue;
+				}
+			};
+			for entry in entries.filter_map(Result::ok) {
+				let Some((path, is_dir)) = state.walk_entry(&entry, ignore) else {
+					continue;
+				};
+				if is_dir {
+					pending.push(path);
+					continue;
+				}
+				let Some(meta) = self
+					.read_meta(&path)
+					.filter(|meta| !state.skip_special(meta))
+				else {
+					continue;
+				};
+				if ignore.is_ignored_by_size(meta.size) {
+					state.entry_ignored();
+					continue;
+				}
+				batch.push(meta);
+				if batch.len() >= batch_size {
+					commit_changed(db, &mut batch, &mut seen, &mut summary)?;
+				}
+			}
+		}
+		commit_changed(db, &mut batch, &mut seen, &mut summary)?;
+		self.set_ignored_count(state.ignored_count());
+		if cancel.is_cancelled() {
+			tracing::info!("Scan cancelled, keeping stored files that were not reached");
+			return Ok(summary);
+		}
+		let failed: Vec<PathBuf> = summary.errors.iter().map(|e| e.path.clone()).collect();
+		summary.removed = remove_unseen(db, dir, &seen, &failed, batch_size)?;
+		tracing::info!(
+			added = summary.added,
+			modified = summary.modified,
+			unchanged = summary.unchanged,
+			removed = summary.removed,
+			"Streaming scan finished"
+		);
+		Ok(summary)
+	}
+}
+
+/// Diff `batch` against the stored files, commit the new and changed ones and empty it
+fn commit_changed(
+	db: &redb::Database,
+	batch: &mut Vec<FileMeta>,
+	seen: &mut HashSet<FileCachePath>,
+	summary: &mut StreamingScanSummary,
+) -> LinkfieldResult<()> {
+	if batch.is_empty() {
+		return Ok(());
+	}
+	let mut changed = Vec::new();
+	{
+		let read_txn = db.begin_read()?;
+		let table = match read_txn.open_table(FILE_CACHE_TABLE) {
+			Ok(table) => Some(table),
+			Err(redb::TableError::TableDoesNotExist(_)) => None,
+			Err(e) => return Err(e.into()),
+		};
+		for meta in batch.drain(..) {
+			let stored = match &table {
+				Some(table) => table
+					.get(serialize_path(&meta.path).as_ref())?
+					.map(|bytes| FileMeta::deserialize(bytes.value()))
+					.transpose()?,
+				None => None,
+			};
+			seen.insert(meta.path.clone());
+			match stored {
+				None => summary.added += 1,
+				Some(old) if old.size != meta.size || old.modified != meta.modified => {
+					summary.modified += 1;
+				}
+				Some(_) => {
+					summary.unchanged += 1;
+					continue;
+				}
+			}
+			changed.push((meta.path.clone(), meta));
+		}
+	}
+	if !changed.is_empty() {
+		update_redb_batch_commit(db, &[], &changed)?;
+	}
+	Ok(())
+}
+
+/// Remove the stored files under `dir` that were not seen, leaving those below directories
+/// that failed to read. Returns how many were removed.
+fn remove_unseen(
+	db: &redb::Database,
+	dir: &Path,
+	seen: &HashSet<FileCachePath>,
+	failed: &[PathBuf],
+	batch_size: usize,
+) -> LinkfieldResult<usize> {
+	let prefix = dir.to_string_lossy();
+	let stale: Vec<FileCachePath> = {
+		let read_txn = db.begin_read()?;
+		let table = match read_txn.open_table(FILE_CACHE_TABLE) {
+			Ok(table) => table,
+			Err(redb::TableError::TableDoesNotExist(_)) => return Ok(0),
+			Err(e) => return Err(e.into()),
+		};
+		let mut stale = Vec::new();
+		for entry in table.range(prefix.as_ref()..)? {
+			let (key, _) = entry?;
+			let key = key.value();
+			if !key.starts_with(prefix.as_ref()) {
+				break;
+			}
+			let path = FileCachePath(PathBuf::from(key));
+			if path.0.starts_with(dir)
+				&& !seen.contains(&path)
+				&& !failed.iter().any(|f| path.0.starts_with(f))
+			{
+				stale.push(path);
+			}
+		}
+		stale
+	};
+	for chunk in stale.chunks(batch_size) {
+		update_redb_batch_commit(db, chunk, &[])?;
+	}
+	Ok(stale.len())
+}
@@ -0,0 +1,44 @@
+//! Derived caches holding a filtered subset of entries
+
+use crate::file_cache::FileCache;
+use crate::file_cache::meta::{FileCachePath, FileMeta};
+use std::sync::Arc;
+
+impl FileCache {
+	/// New in-memory cache with the same root containing only the files for which `filter`
+	/// returns true. Nothing is written to a database.
+	pub fn clone_subset<F>(&self, filter: F) -> Arc<Self>
+	where
+		F: Fn(&FileCachePath, &FileMeta) -> bool,
+	{
+		let root_name = self
+			.entries
+			.get(&self.root)
+			.map(|root| root.name.clone())
+			.unwrap_or_default();
+		let subset = Self::with_case_sensitivity(&root_name, self.case_sensitivity());
+		for meta in self.tree_files() {
+			if filter(&meta.path, &meta) {
+				subset.insert_meta(meta);
+			}
+		}
+		subset
+	}
+
+	/// Like [`FileCache::clone_subset`], and also writes the subset to `db` in one batch commit
+	pub fn clone_subset_to_db<F>(&self, filter: F, db: &redb::Database) -> Arc<Self>
+	where
+		F: Fn(&FileCachePath, &FileMeta) -> bool,
+	{
+		let subset = self.clone_subset(filter);
+		let batch: Vec<_> = subset
+			.tree_files()
+			.into_iter()
+			.map(|meta| (meta.path.clone(), meta))
+			.collect();
+		if let Err(e) = crate::file_cache::db::update_redb_batch_commit(db, &[], &batch) {
+			tracing::error!(error = %e, "Failed to commit subset");
+		}
+		subset
+	}
+}
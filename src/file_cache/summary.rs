@@ -0,0 +1,152 @@
+//! End-of-scan summary in the format chosen with `--output-format`
+
+use crate::file_cache::FileCache;
+use crate::file_cache::cache::EntryKind;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How the scan summary is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+	/// Log line with counts, top extensions and duration
+	#[default]
+	HumanReadable,
+	/// One JSON object on stdout
+	Json,
+	/// Header row plus one data row on stdout
+	Csv,
+	/// No summary, e.g. when running as a daemon
+	None,
+}
+
+/// Number of extensions listed in [`ScanSummary::top_extensions`]
+pub const TOP_EXTENSIONS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScanSummary {
+	pub files_scanned: usize,
+	pub dirs_scanned: usize,
+	pub total_bytes: u64,
+	pub duration_secs: f64,
+	/// Most common extensions by file count, most common first
+	pub top_extensions: Vec<ExtensionCount>,
+	/// Files and directories skipped by the ignore rules
+	pub ignored_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExtensionCount {
+	pub extension: String,
+	pub count: usize,
+}
+
+impl ScanSummary {
+	pub fn new(
+		files_scanned: usize,
+		dirs_scanned: usize,
+		total_bytes: u64,
+		duration: Duration,
+		extension_counts: impl IntoIterator<Item = (String, usize)>,
+	) -> Self {
+		let mut top_extensions: Vec<_> = extension_counts
+			.into_iter()
+			.map(|(extension, count)| ExtensionCount { extension, count })
+			.collect();
+		top_extensions.sort_by(|a, b| b.count.cmp(&a.count).then(a.extension.cmp(&b.extension)));
+		top_extensions.truncate(TOP_EXTENSIONS);
+		Self {
+			files_scanned,
+			dirs_scanned,
+			total_bytes,
+			duration_secs: duration.as_secs_f64(),
+			top_extensions,
+			ignored_count: 0,
+		}
+	}
+
+	#[must_use]
+	pub const fn with_ignored_count(mut self, ignored_count: usize) -> Self {
+		self.ignored_count = ignored_count;
+		self
+	}
+
+	/// The summary as text in `format`, or `None` for [`OutputFormat::None`]
+	pub fn render(&self, format: OutputFormat) -> Option<String> {
+		match format {
+			OutputFormat::HumanReadable => Some(self.human_readable()),
+			OutputFormat::Json => serde_json::to_string(self).ok(),
+			OutputFormat::Csv => Some(self.csv()),
+			OutputFormat::None => None,
+		}
+	}
+
+	/// Log human readable summaries, print the others to stdout
+	pub fn report(&self, format: OutputFormat) {
+		match (format, self.render(format)) {
+			(OutputFormat::HumanReadable, Some(text)) => tracing::info!("{text}"),
+			(_, Some(text)) => println!("{text}"),
+			(_, None) => {}
+		}
+	}
+
+	fn human_readable(&self) -> String {
+		let extensions: Vec<_> = self
+			.top_extensions
+			.iter()
+			.map(|e| format!("{} ({})", e.extension, e.count))
+			.collect();
+		format!(
+			"After scan_dir: {} files in {} directories (total size: {} bytes) in {:.2}s, {} ignored, top extensions: {}",
+			self.files_scanned,
+			self.dirs_scanned,
+			self.total_bytes,
+			self.duration_secs,
+			self.ignored_count,
+			if extensions.is_empty() {
+				"-".to_string()
+			} else {
+				extensions.join(", ")
+			}
+		)
+	}
+
+	/// Extensions go in one field as `ext:count` pairs separated by `;`
+	fn csv(&self) -> String {
+		let extensions: Vec<_> = self
+			.top_extensions
+			.iter()
+			.map(|e| format!("{}:{}", e.extension, e.count))
+			.collect();
+		format!(
+			"files_scanned,dirs_scanned,total_bytes,duration_secs,top_extensions\n{},{},{},{:.3},\"{}\"",
+			self.files_scanned,
+			self.dirs_scanned,
+			self.total_bytes,
+			self.duration_secs,
+			extensions.join(";").replace('"', "\"\"")
+		)
+	}
+}
+
+impl FileCache {
+	/// Summary of the files currently in the cache, for a scan that took `duration`
+	pub fn summary(&self, duration: Duration) -> ScanSummary {
+		let (mut files, mut dirs, mut bytes) = (0, 0, 0);
+		let mut extensions: HashMap<String, usize> = HashMap::new();
+		for entry in &self.entries {
+			match &entry.kind {
+				EntryKind::File(meta) => {
+					files += 1;
+					bytes += meta.size;
+					if let Some(extension) = &meta.extension {
+						*extensions.entry(extension.clone()).or_default() += 1;
+					}
+				}
+				EntryKind::Directory => dirs += 1,
+			}
+		}
+		ScanSummary::new(files, dirs, bytes, duration, extensions)
+			.with_ignored_count(self.last_scan_ignored_count())
+	}
+}
@@ -0,0 +1,71 @@
+//! Picking up changes other writers made to a database shared between caches
+
+use crate::error::LinkfieldResult;
+use crate::file_cache::FileCache;
+use crate::file_cache::cache::EntryKind;
+use crate::file_cache::db::FILE_CACHE_TABLE;
+use crate::file_cache::integrity::stored_generation;
+use crate::file_cache::meta::{FileCachePath, FileMeta};
+use redb::ReadableTable;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+
+/// Generation of a cache that was never loaded from or synced with a database
+pub(crate) const NEVER_SYNCED: u64 = u64::MAX;
+
+impl FileCache {
+	/// Whether `db` was written since this cache was loaded from it or last synced with
+	/// [`FileCache::apply_external_changes`]. Only reads a counter, so it is cheap to poll.
+	/// Writes made through this cache count too.
+	pub fn is_stale_with_db(&self, db: &redb::Database) -> LinkfieldResult<bool> {
+		let generation = stored_generation(&db.begin_read()?)?;
+		Ok(generation != self.synced_generation.load(Ordering::Relaxed))
+	}
+
+	/// Make the cached files match `db`, trusting the database: files stored there are added
+	/// or updated, and cached files missing from it are dropped. Returns the number of files
+	/// changed.
+	pub fn apply_external_changes(&self, db: &redb::Database) -> LinkfieldResult<usize> {
+		let read_txn = db.begin_read()?;
+		let generation = stored_generation(&read_txn)?;
+		let mut stored = HashMap::new();
+		match read_txn.open_table(FILE_CACHE_TABLE) {
+			Ok(table) => {
+				for entry in table.iter()? {
+					let (_, value) = entry?;
+					let meta = FileMeta::deserialize(value.value())?;
+					stored.insert(meta.path.clone(), meta);
+				}
+			}
+			Err(redb::TableError::TableDoesNotExist(_)) => {}
+			Err(e) => return Err(e.into()),
+		}
+		drop(read_txn);
+		let mut changed = 0;
+		let mut cached: HashSet<FileCachePath> = HashSet::new();
+		self.retain_entries(|_, entry| match &mut entry.kind {
+			EntryKind::File(meta) => {
+				let Some(new) = stored.get(&meta.path) else {
+					changed += 1;
+					return false;
+				};
+				if new != meta {
+					*meta = new.clone();
+					changed += 1;
+				}
+				cached.insert(meta.path.clone());
+				true
+			}
+			EntryKind::Directory => true,
+		});
+		for (path, meta) in stored {
+			if !cached.contains(&path) {
+				self.insert_meta(meta);
+				changed += 1;
+			}
+		}
+		self.synced_generation.store(generation, Ordering::Relaxed);
+		tracing::debug!(changed, generation, "Applied external database changes");
+		Ok(changed)
+	}
+}
@@ -0,0 +1,105 @@
+//! Nested view of the cached files, for tools that walk a directory hierarchy
+
+use crate::file_cache::FileCache;
+use crate::file_cache::meta::FileMeta;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Children of a directory by name, sorted so iteration and JSON output are stable
+pub type DirectoryChildren = BTreeMap<String, DirectoryNode>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectoryNode {
+	File(FileMeta),
+	Directory(DirectoryChildren),
+}
+
+/// The cached files nested by directory below the cache root, from [`FileCache::to_tree`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryTree {
+	/// Path the tree is rooted at; files outside it are nested by their full path
+	pub root: PathBuf,
+	pub children: DirectoryChildren,
+}
+
+impl DirectoryTree {
+	/// Every node with its full path, each directory before its children. Siblings come in
+	/// name order.
+	pub fn iter_depth_first(&self) -> impl Iterator<Item = (PathBuf, &DirectoryNode)> {
+		let mut stack: Vec<_> = self
+			.children
+			.iter()
+			.rev()
+			.map(|(name, node)| (self.root.join(name), node))
+			.collect();
+		std::iter::from_fn(move || {
+			let (path, node) = stack.pop()?;
+			if let DirectoryNode::Directory(children) = node {
+				stack.extend(
+					children
+						.iter()
+						.rev()
+						.map(|(name, child)| (path.join(name), child)),
+				);
+			}
+			Some((path, node))
+		})
+	}
+
+	/// The node at `path`, given either in full or relative to the root
+	pub fn at_path(&self, path: &Path) -> Option<&DirectoryNode> {
+		let relative = path.strip_prefix(&self.root).unwrap_or(path);
+		let mut components = relative.iter().map(|c| c.to_string_lossy());
+		let mut node = self.children.get(components.next()?.as_ref())?;
+		for component in components {
+			match node {
+				DirectoryNode::Directory(children) => node = children.get(component.as_ref())?,
+				DirectoryNode::File(_) => return None,
+			}
+		}
+		Some(node)
+	}
+}
+
+impl FileCache {
+	/// The cached files nested by directory. Directories without cached files are left out.
+	pub fn to_tree(&self) -> DirectoryTree {
+		let root = self
+			.entries
+			.get(&self.root)
+			.map(|root| PathBuf::from(&root.name))
+			.unwrap_or_default();
+		let mut children = DirectoryChildren::new();
+		// A stale entry can leave a file where another needs a directory. The directory always
+		// wins, so the result doesn't depend on the order the files are visited in.
+		for meta in self.all_files() {
+			let relative = meta.path.0.strip_prefix(&root).unwrap_or(&meta.path.0);
+			let names: Vec<_> = relative
+				.iter()
+				.map(|c| c.to_string_lossy().to_string())
+				.collect();
+			let Some((file_name, dirs)) = names.split_last() else {
+				continue;
+			};
+			let mut current = &mut children;
+			for dir in dirs {
+				let node = current
+					.entry(dir.clone())
+					.or_insert_with(|| DirectoryNode::Directory(DirectoryChildren::new()));
+				if let DirectoryNode::File(_) = node {
+					*node = DirectoryNode::Directory(DirectoryChildren::new());
+				}
+				let DirectoryNode::Directory(next) = node else {
+					unreachable!("replaced above");
+				};
+				current = next;
+			}
+			current
+				.entry(file_name.clone())
+				.or_insert(DirectoryNode::File(meta));
+		}
+		DirectoryTree { root, children }
+	}
+}
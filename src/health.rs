@@ -0,0 +1,183 @@
+// Application lifecycle state, shared with the `health` subcommand through a state file
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// How often the watcher's event loop refreshes the heartbeat in the state file
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A heartbeat older than this means the watcher hung or died without cleaning up
+pub const HEARTBEAT_STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Lifecycle state of a running watcher
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state")]
+pub enum AppState {
+	Starting,
+	/// Initial scan in progress; `progress` is the completed fraction when known, else 0.0
+	Scanning {
+		progress: f64,
+	},
+	/// Initial scan complete and watcher active
+	Ready,
+	Degraded {
+		reason: String,
+	},
+	ShuttingDown,
+}
+
+impl AppState {
+	/// Exit code reported by `linkfield health`: 0 ready, 2 degraded, 1 anything else
+	pub const fn exit_code(&self) -> i32 {
+		match self {
+			Self::Ready => 0,
+			Self::Degraded { .. } => 2,
+			Self::Starting | Self::Scanning { .. } | Self::ShuttingDown => 1,
+		}
+	}
+}
+
+/// State file written next to the database, e.g. `linkfield.redb` -> `linkfield.state`
+pub fn state_file_path(db_path: &Path) -> PathBuf {
+	db_path.with_extension("state")
+}
+
+/// Contents of the state file: the state, and which process wrote it when
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateFile {
+	#[serde(flatten)]
+	pub state: AppState,
+	pub pid: u32,
+	/// Seconds since the Unix epoch at the last write
+	pub heartbeat: u64,
+}
+
+impl StateFile {
+	/// `state` as written by this process now
+	pub fn current(state: AppState) -> Self {
+		Self {
+			state,
+			pid: std::process::id(),
+			heartbeat: unix_now(),
+		}
+	}
+
+	/// Whether the heartbeat is older than [`HEARTBEAT_STALE_AFTER`] at `now`, in seconds
+	/// since the Unix epoch
+	pub const fn is_stale(&self, now: u64) -> bool {
+		now.saturating_sub(self.heartbeat) > HEARTBEAT_STALE_AFTER.as_secs()
+	}
+
+	/// Exit code for `linkfield health`: the state's, or 1 when the writing process is gone
+	/// or its heartbeat is stale
+	pub fn exit_code(&self, process_running: bool) -> i32 {
+		if !process_running || self.is_stale(unix_now()) {
+			return AppState::Starting.exit_code();
+		}
+		self.state.exit_code()
+	}
+}
+
+fn unix_now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |elapsed| elapsed.as_secs())
+}
+
+pub fn read_state_file(path: &Path) -> Result<StateFile, Box<dyn std::error::Error>> {
+	let contents = std::fs::read(path)?;
+	Ok(serde_json::from_slice(&contents)?)
+}
+
+/// Shared handle to the current `AppState`. Every change is logged and, when a state file is
+/// configured, mirrored to it so other processes can read it.
+#[derive(Debug, Clone)]
+pub struct AppStateTracker {
+	state: Arc<RwLock<AppState>>,
+	state_file: Option<PathBuf>,
+}
+
+impl AppStateTracker {
+	pub fn new(state_file: Option<PathBuf>) -> Self {
+		let tracker = Self {
+			state: Arc::new(RwLock::new(AppState::Starting)),
+			state_file,
+		};
+		tracker.publish(&AppState::Starting);
+		tracker
+	}
+
+	pub fn get(&self) -> AppState {
+		self.state
+			.read()
+			.map_or_else(|e| e.into_inner().clone(), |state| state.clone())
+	}
+
+	pub fn set(&self, new_state: AppState) {
+		self.update(|_| Some(new_state));
+	}
+
+	/// Move to `Ready` unless something already marked the app as degraded
+	pub fn mark_ready(&self) {
+		self.update(|current| match current {
+			AppState::Degraded { .. } => None,
+			_ => Some(AppState::Ready),
+		});
+	}
+
+	/// Update the progress of the initial scan. Ignored once the app has moved past
+	/// `Scanning`, e.g. by later rescans reporting through the same callback.
+	pub fn set_scan_progress(&self, progress: f64) {
+		self.update(|current| {
+			matches!(current, AppState::Scanning { .. }).then_some(AppState::Scanning { progress })
+		});
+	}
+
+	/// Rewrite the state file with a fresh heartbeat
+	pub fn heartbeat(&self) {
+		// Held while writing so a concurrent change can't be overwritten with the old state
+		let state = match self.state.read() {
+			Ok(state) => state,
+			Err(e) => e.into_inner(),
+		};
+		self.write(&state);
+	}
+
+	fn update(&self, f: impl FnOnce(&AppState) -> Option<AppState>) {
+		let mut state = match self.state.write() {
+			Ok(state) => state,
+			Err(e) => e.into_inner(),
+		};
+		if let Some(new_state) = f(&state)
+			&& new_state != *state
+		{
+			*state = new_state;
+			self.publish(&state);
+		}
+	}
+
+	fn publish(&self, state: &AppState) {
+		info!(state = ?state, "App state changed");
+		self.write(state);
+	}
+
+	fn write(&self, state: &AppState) {
+		let Some(path) = &self.state_file else {
+			return;
+		};
+		if let Err(e) = write_state_file(path, &StateFile::current(state.clone())) {
+			tracing::warn!(path = %path.display(), error = %e, "Failed to write state file");
+		}
+	}
+}
+
+/// Write through a temporary file and rename, so readers never see a partial file
+pub fn write_state_file(path: &Path, file: &StateFile) -> Result<(), Box<dyn std::error::Error>> {
+	let tmp = path.with_extension("state.tmp");
+	std::fs::write(&tmp, serde_json::to_vec(file)?)?;
+	std::fs::rename(&tmp, path)?;
+	Ok(())
+}
@@ -0,0 +1,190 @@
+//! Shell commands run when the watcher sees a file change, for handing events to other tools
+
+use crate::watcher::WatchEvent;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How long a hook may run before it is killed, see [`crate::watcher::WatchConfig::hook_timeout`]
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a running hook is checked for having exited
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Hooks that may run at the same time
+pub const HOOK_WORKERS: usize = 4;
+
+/// Hooks that may wait for a free worker; further ones are dropped with a warning
+pub const HOOK_QUEUE_CAPACITY: usize = 256;
+
+/// Shell command templates run through `sh -c` (`cmd /C` on Windows). `{path}` is replaced
+/// with the created or removed path, `{from}` and `{to}` with both sides of a move; the
+/// paths are quoted for the shell.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventHooks {
+	pub on_create: Option<String>,
+	/// Run for renames within a directory as well as moves between directories
+	pub on_move: Option<String>,
+	pub on_remove: Option<String>,
+}
+
+impl EventHooks {
+	pub const fn is_empty(&self) -> bool {
+		self.on_create.is_none() && self.on_move.is_none() && self.on_remove.is_none()
+	}
+
+	/// Workers running these hooks, killing each after `timeout`; `None` when there are no
+	/// hooks to run
+	pub fn runner(&self, timeout: Duration) -> Option<HookRunner> {
+		(!self.is_empty()).then(|| HookRunner::new(self.clone(), timeout))
+	}
+
+	/// The shell command for `event`, if it has a hook
+	fn command_for(&self, event: &WatchEvent) -> Option<String> {
+		match event {
+			WatchEvent::Create(path) => self
+				.on_create
+				.as_deref()
+				.map(|template| expand(template, &[("{path}", path)])),
+			WatchEvent::Remove(path) => self
+				.on_remove
+				.as_deref()
+				.map(|template| expand(template, &[("{path}", path)])),
+			WatchEvent::Rename { from, to } | WatchEvent::Move { from, to, .. } => self
+				.on_move
+				.as_deref()
+				.map(|template| expand(template, &[("{from}", from), ("{to}", to)])),
+		}
+	}
+}
+
+/// Runs [`EventHooks`] on [`HOOK_WORKERS`] threads fed by a queue of [`HOOK_QUEUE_CAPACITY`],
+/// so a burst of events can't start a shell per event. The workers exit once the runner
+/// is dropped and the queue is drained.
+pub struct HookRunner {
+	hooks: EventHooks,
+	queue: SyncSender<String>,
+}
+
+impl HookRunner {
+	fn new(hooks: EventHooks, timeout: Duration) -> Self {
+		let (queue, commands) = std::sync::mpsc::sync_channel::<String>(HOOK_QUEUE_CAPACITY);
+		let commands = Arc::new(Mutex::new(commands));
+		for i in 0..HOOK_WORKERS {
+			let commands = commands.clone();
+			let spawned = std::thread::Builder::new()
+				.name(format!("linkfield-hook-{i}"))
+				.spawn(move || {
+					// The lock is only held while waiting for the next command
+					while let Ok(command) = commands
+						.lock()
+						.unwrap_or_else(PoisonError::into_inner)
+						.recv()
+					{
+						run_command(&command, timeout);
+					}
+				});
+			if let Err(e) = spawned {
+				tracing::warn!(error = %e, "Failed to start hook worker");
+			}
+		}
+		Self { hooks, queue }
+	}
+
+	/// Queue the hook for `event`, if it has one
+	pub fn run(&self, event: &WatchEvent) {
+		let Some(command) = self.hooks.command_for(event) else {
+			return;
+		};
+		match self.queue.try_send(command) {
+			Ok(()) => {}
+			Err(TrySendError::Full(command)) => {
+				tracing::warn!(command, "Too many hooks waiting to run, dropping this one");
+			}
+			Err(TrySendError::Disconnected(command)) => {
+				tracing::warn!(command, "No hook workers running, dropping hook");
+			}
+		}
+	}
+}
+
+/// Replace each placeholder in `template` with its quoted path, in one pass from left to
+/// right so that text taken from a path is never matched as a placeholder itself
+fn expand(template: &str, placeholders: &[(&str, &Path)]) -> String {
+	let mut command = String::with_capacity(template.len());
+	let mut rest = template;
+	'next: while !rest.is_empty() {
+		for (placeholder, path) in placeholders {
+			if let Some(after) = rest.strip_prefix(placeholder) {
+				command.push_str(&shell_quote(path));
+				rest = after;
+				continue 'next;
+			}
+		}
+		let mut chars = rest.chars();
+		command.extend(chars.next());
+		rest = chars.as_str();
+	}
+	command
+}
+
+#[cfg(unix)]
+fn shell_quote(path: &Path) -> String {
+	format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+/// `cmd` expands `%VAR%` even inside quotes, so each `%` is left outside them and escaped
+#[cfg(not(unix))]
+fn shell_quote(path: &Path) -> String {
+	format!("\"{}\"", path.to_string_lossy().replace('%', "\"^%\""))
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+	let mut shell = Command::new("sh");
+	shell.arg("-c").arg(command);
+	shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+	let mut shell = Command::new("cmd");
+	shell.arg("/C").arg(command);
+	shell
+}
+
+fn run_command(command: &str, timeout: Duration) {
+	tracing::debug!(command, "Running hook");
+	let child = shell(command)
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.stderr(Stdio::null())
+		.spawn();
+	match child.and_then(|child| wait_with_timeout(child, timeout)) {
+		Ok(Some(status)) if status.success() => {}
+		Ok(Some(status)) => tracing::warn!(command, %status, "Hook failed"),
+		Ok(None) => tracing::warn!(command, ?timeout, "Hook timed out and was killed"),
+		Err(e) => tracing::warn!(command, error = %e, "Failed to run hook"),
+	}
+}
+
+/// The exit status, or `None` after killing a child still running at `timeout`
+fn wait_with_timeout(
+	mut child: Child,
+	timeout: Duration,
+) -> std::io::Result<Option<std::process::ExitStatus>> {
+	let deadline = Instant::now() + timeout;
+	loop {
+		if let Some(status) = child.try_wait()? {
+			return Ok(Some(status));
+		}
+		if Instant::now() >= deadline {
+			child.kill()?;
+			child.wait()?;
+			return Ok(None);
+		}
+		std::thread::sleep(HOOK_POLL_INTERVAL);
+	}
+}
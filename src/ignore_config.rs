@@ -1,31 +1,81 @@
 // Provides configuration for directories/files to ignore during filesystem scanning/watching
 
+use dashmap::DashMap;
+use ignore::Match;
 use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub type IgnoreConfigResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
+/// Ignore files outside a size range. Directories are never matched by a size rule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeIgnoreRule {
+	/// Files smaller than this are ignored
+	pub min_bytes: Option<u64>,
+	/// Files larger than this are ignored
+	pub max_bytes: Option<u64>,
+}
+
+impl SizeIgnoreRule {
+	pub fn matches(&self, size: u64) -> bool {
+		self.min_bytes.is_some_and(|min| size < min) || self.max_bytes.is_some_and(|max| size > max)
+	}
+}
+
+/// Name of the ignore file read by [`IgnoreConfig::from_dir_hierarchy`]
+pub const IGNORE_FILE_NAME: &str = ".linkfieldignore";
+
+/// Key under which [`IgnoreConfig::pattern_statistics`] counts files skipped by the size rule
+pub const SIZE_RULE_KEY: &str = "<size rule>";
+
 /// Holds the set of ignore patterns for the scanner.
 pub struct IgnoreConfig {
+	/// Matcher for the patterns after the inherited ones
 	gitignore: Gitignore,
+	/// Directory that patterns anchored with a slash are relative to: the start directory of
+	/// [`IgnoreConfig::from_dir_hierarchy`], otherwise empty
+	base: PathBuf,
+	/// Matchers of the ignore files in parent directories, innermost first, each anchored at
+	/// its own directory
+	inherited: Vec<Gitignore>,
 	patterns: Vec<String>,
+	/// How many of the leading `patterns` were read from ignore files in parent directories
+	inherited_patterns: usize,
+	/// How many `patterns` after the inherited ones were read from the ignore file
+	file_patterns: usize,
+	size_rule: Option<SizeIgnoreRule>,
+	/// Pattern -> number of paths it has ignored
+	hits: DashMap<String, usize>,
+}
+
+/// Patterns added and removed by [`IgnoreConfig::reload_from_file`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PatternChanges {
+	pub added: Vec<String>,
+	pub removed: Vec<String>,
+}
+
+impl PatternChanges {
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty()
+	}
 }
 
 impl IgnoreConfig {
 	/// Create a new ignoreConfig from a list of glob pattern strings.
 	pub fn new(patterns: &[&str]) -> IgnoreConfigResult<Self> {
-		let mut builder = GitignoreBuilder::new("");
-		for pat in patterns {
-			builder.add_line(None, pat)?;
-		}
-		let gitignore = builder
-			.build()
-			.map_err(|e| format!("Gitignore build error: {e}"))?;
+		let patterns: Vec<String> = patterns.iter().map(|s| s.to_string()).collect();
 		Ok(IgnoreConfig {
-			gitignore,
-			patterns: patterns.iter().map(|s| s.to_string()).collect(),
+			gitignore: build_gitignore(Path::new(""), &patterns)?,
+			base: PathBuf::new(),
+			inherited: Vec::new(),
+			patterns,
+			inherited_patterns: 0,
+			file_patterns: 0,
+			size_rule: None,
+			hits: DashMap::new(),
 		})
 	}
 
@@ -34,43 +84,218 @@ impl IgnoreConfig {
 	pub fn from_file_with_patterns<P: AsRef<Path>>(
 		path: P,
 	) -> IgnoreConfigResult<(Self, Vec<String>)> {
-		match File::open(path.as_ref()) {
-			Ok(file) => {
-				let reader = BufReader::new(file);
-				let mut builder = GitignoreBuilder::new("");
-				let mut patterns = Vec::new();
-				for line in reader.lines() {
-					let line = line?;
-					let trimmed = line.trim();
-					if trimmed.is_empty() || trimmed.starts_with('#') {
-						continue;
-					}
-					builder.add_line(None, trimmed)?;
-					patterns.push(trimmed.to_string());
+		// File not found: treat as empty ignore config, no warning
+		let patterns = read_pattern_file(path.as_ref())?;
+		Ok((
+			IgnoreConfig {
+				gitignore: build_gitignore(Path::new(""), &patterns)?,
+				base: PathBuf::new(),
+				inherited: Vec::new(),
+				patterns: patterns.clone(),
+				inherited_patterns: 0,
+				file_patterns: patterns.len(),
+				size_rule: None,
+				hits: DashMap::new(),
+			},
+			patterns,
+		))
+	}
+
+	/// Patterns from a `.gitignore` file that mean the same to linkfield, and the skipped
+	/// ones as `pattern: reason`. Negations are skipped so an import never re-includes what
+	/// other ignore files exclude, and so are patterns anchored to the repository root, since
+	/// linkfield matches patterns below the watch root.
+	pub fn from_gitignore_file(path: &Path) -> IgnoreConfigResult<(Self, Vec<String>)> {
+		// Unlike ignore files, a missing .gitignore is an error
+		std::fs::metadata(path)?;
+		let mut patterns = Vec::new();
+		let mut skipped = Vec::new();
+		for pattern in read_pattern_file(path)? {
+			let reason = if pattern.starts_with('!') {
+				Some("negation patterns are not imported".to_string())
+			} else if is_anchored(&pattern) {
+				Some("anchored to the repository root".to_string())
+			} else {
+				build_gitignore(Path::new(""), std::slice::from_ref(&pattern))
+					.err()
+					.map(|e| e.to_string())
+			};
+			match reason {
+				Some(reason) => {
+					tracing::warn!(pattern, reason, path = %path.display(), "Skipping .gitignore pattern");
+					skipped.push(format!("{pattern}: {reason}"));
 				}
-				let gitignore = builder
-					.build()
-					.map_err(|e| format!("Gitignore build error: {e}"))?;
-				Ok((
-					IgnoreConfig {
-						gitignore,
-						patterns: patterns.clone(),
-					},
-					patterns,
-				))
+				None => patterns.push(pattern),
 			}
-			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-				// File not found: treat as empty ignore config, no warning
-				Ok((IgnoreConfig::empty(), vec![]))
+		}
+		Ok((
+			IgnoreConfig {
+				gitignore: build_gitignore(Path::new(""), &patterns)?,
+				base: PathBuf::new(),
+				inherited: Vec::new(),
+				patterns,
+				inherited_patterns: 0,
+				file_patterns: 0,
+				size_rule: None,
+				hits: DashMap::new(),
+			},
+			skipped,
+		))
+	}
+
+	/// Patterns from the [`IGNORE_FILE_NAME`] files in `start` and every directory above it.
+	/// Each file's patterns are relative to its own directory, and deeper files take
+	/// precedence: `!pattern` in `start` re-includes what a parent directory ignores. Parent
+	/// files that can't be read or parsed are skipped with a warning. The file in `start` is
+	/// the one [`IgnoreConfig::reload_from_file`] replaces.
+	pub fn from_dir_hierarchy(start: &Path) -> IgnoreConfigResult<Self> {
+		let start = std::path::absolute(start)?;
+		let parents: Vec<_> = start.ancestors().skip(1).collect();
+		let mut patterns = Vec::new();
+		let mut inherited = Vec::new();
+		for dir in parents.into_iter().rev() {
+			let file = dir.join(IGNORE_FILE_NAME);
+			let parsed = read_pattern_file(&file).and_then(|from_file| {
+				let gitignore = build_gitignore(dir, &from_file)?;
+				Ok((from_file, gitignore))
+			});
+			match parsed {
+				Ok((from_file, _)) if from_file.is_empty() => {}
+				Ok((from_file, gitignore)) => {
+					patterns.extend(from_file);
+					inherited.push(gitignore);
+				}
+				Err(e) => {
+					tracing::warn!(path = %file.display(), error = %e, "Skipping unreadable ignore file");
+				}
 			}
-			Err(e) => Err(Box::new(e)),
 		}
+		inherited.reverse();
+		let inherited_patterns = patterns.len();
+		let from_file = read_pattern_file(&start.join(IGNORE_FILE_NAME))?;
+		Ok(IgnoreConfig {
+			gitignore: build_gitignore(&start, &from_file)?,
+			base: start,
+			inherited,
+			file_patterns: from_file.len(),
+			inherited_patterns,
+			patterns: patterns.into_iter().chain(from_file).collect(),
+			size_rule: None,
+			hits: DashMap::new(),
+		})
+	}
+
+	/// Replace the patterns read from the ignore file with the current contents of `path`,
+	/// keeping the ones inherited from parent directories, the ones given to
+	/// [`IgnoreConfig::new`] or [`IgnoreConfig::add_pattern`] and the size rule. A missing
+	/// file has no patterns. On error the old patterns stay in effect.
+	pub fn reload_from_file<P: AsRef<Path>>(
+		&mut self,
+		path: P,
+	) -> IgnoreConfigResult<PatternChanges> {
+		let from_file = read_pattern_file(path.as_ref())?;
+		let file_end = self.inherited_patterns + self.file_patterns;
+		let mut patterns = self.patterns[..self.inherited_patterns].to_vec();
+		patterns.extend_from_slice(&from_file);
+		patterns.extend_from_slice(&self.patterns[file_end..]);
+		self.gitignore = build_gitignore(&self.base, &patterns[self.inherited_patterns..])?;
+		let changes = PatternChanges {
+			added: from_file
+				.iter()
+				.filter(|p| !self.patterns.contains(p))
+				.cloned()
+				.collect(),
+			removed: self.patterns[self.inherited_patterns..file_end]
+				.iter()
+				.filter(|p| !patterns.contains(p))
+				.cloned()
+				.collect(),
+		};
+		self.patterns = patterns;
+		self.file_patterns = from_file.len();
+		Ok(changes)
+	}
+
+	/// Add a single pattern on top of the existing ones, rebuilding the matcher.
+	pub fn add_pattern(&mut self, pattern: &str) -> IgnoreConfigResult<()> {
+		let mut patterns = self.patterns.clone();
+		patterns.push(pattern.to_string());
+		self.gitignore = build_gitignore(&self.base, &patterns[self.inherited_patterns..])?;
+		self.patterns = patterns;
+		Ok(())
+	}
+
+	/// Also ignore files whose size falls outside `rule`.
+	pub fn with_size_rule(mut self, rule: SizeIgnoreRule) -> Self {
+		self.size_rule = Some(rule);
+		self
+	}
+
+	pub fn size_rule(&self) -> Option<SizeIgnoreRule> {
+		self.size_rule
 	}
 
 	/// Returns true if the given path should be ignoreped.
+	///
+	/// With a size rule set this stats the file; use [`IgnoreConfig::is_ignored_with_size`]
+	/// when the size is already known.
 	pub fn is_ignored<P: AsRef<Path>>(&self, path: P) -> bool {
 		let path = path.as_ref();
-		self.gitignore.matched(path, path.is_dir()).is_ignore()
+		let Some(rule) = self.size_rule else {
+			return self.matches_pattern(path, path.is_dir());
+		};
+		match std::fs::metadata(path) {
+			Ok(m) if m.is_file() => {
+				self.matches_pattern(path, false) || self.matches_size_rule(rule, m.len())
+			}
+			Ok(m) => self.matches_pattern(path, m.is_dir()),
+			Err(_) => self.matches_pattern(path, false),
+		}
+	}
+
+	/// Like [`IgnoreConfig::is_ignored`], but `known_size` (the size of a regular file, e.g.
+	/// from `FileMeta`) avoids touching the file system. With `None` this falls back to
+	/// [`IgnoreConfig::is_ignored`].
+	pub fn is_ignored_with_size<P: AsRef<Path>>(&self, path: P, known_size: Option<u64>) -> bool {
+		let path = path.as_ref();
+		let Some(size) = known_size else {
+			return self.is_ignored(path);
+		};
+		self.matches_pattern(path, false)
+			|| self
+				.size_rule
+				.is_some_and(|rule| self.matches_size_rule(rule, size))
+	}
+
+	/// Whether the patterns ignore `path`, given whether it is a directory. Nothing is read
+	/// from disk, so scans check this before stat'ing an entry.
+	pub fn is_ignored_by_pattern(&self, path: &Path, is_dir: bool) -> bool {
+		self.matches_pattern(path, is_dir)
+	}
+
+	/// Whether the patterns ignore `path` or one of its directories below `root`, given
+	/// whether `path` is a directory. Nothing is read from disk, e.g. for paths stored in a
+	/// database.
+	pub fn is_ignored_below(&self, root: &Path, path: &Path, is_dir: bool) -> bool {
+		let Ok(relative) = path.strip_prefix(root) else {
+			return false;
+		};
+		let mut current = root.to_path_buf();
+		let mut components = relative.components().peekable();
+		while let Some(component) = components.next() {
+			current.push(component);
+			let last = components.peek().is_none();
+			if self.matches_pattern(&current, !last || is_dir) {
+				return true;
+			}
+		}
+		false
+	}
+
+	/// Whether the size rule ignores a regular file of `size` bytes
+	pub fn is_ignored_by_size(&self, size: u64) -> bool {
+		self.size_rule
+			.is_some_and(|rule| self.matches_size_rule(rule, size))
 	}
 
 	/// Returns the patterns for logging/debugging.
@@ -82,9 +307,92 @@ impl IgnoreConfig {
 	pub fn empty() -> Self {
 		IgnoreConfig {
 			gitignore: ignore::gitignore::Gitignore::empty(),
+			base: PathBuf::new(),
+			inherited: Vec::new(),
 			patterns: Vec::new(),
+			inherited_patterns: 0,
+			file_patterns: 0,
+			size_rule: None,
+			hits: DashMap::new(),
 		}
 	}
+
+	/// How many paths each pattern has ignored so far, most hits first. Paths skipped
+	/// because of their size are counted under [`SIZE_RULE_KEY`].
+	pub fn pattern_statistics(&self) -> Vec<(String, usize)> {
+		let mut stats: Vec<_> = self
+			.hits
+			.iter()
+			.map(|entry| (entry.key().clone(), *entry.value()))
+			.collect();
+		stats.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+		stats
+	}
+
+	fn record_hit(&self, pattern: &str) {
+		*self.hits.entry(pattern.to_string()).or_default() += 1;
+	}
+
+	/// Match against the patterns, counting the hit
+	/// The innermost matcher with an opinion on `path` decides
+	fn matches_pattern(&self, path: &Path, is_dir: bool) -> bool {
+		let matched = std::iter::once(&self.gitignore)
+			.chain(&self.inherited)
+			.map(|gitignore| gitignore.matched(path, is_dir))
+			.find(|matched| !matched.is_none());
+		match matched.unwrap_or(Match::None) {
+			Match::Ignore(glob) => {
+				self.record_hit(glob.original());
+				true
+			}
+			Match::None | Match::Whitelist(_) => false,
+		}
+	}
+
+	fn matches_size_rule(&self, rule: SizeIgnoreRule, size: u64) -> bool {
+		let matched = rule.matches(size);
+		if matched {
+			self.record_hit(SIZE_RULE_KEY);
+		}
+		matched
+	}
+}
+
+/// Non-empty, non-comment lines of an ignore file; none when it doesn't exist
+fn read_pattern_file(path: &Path) -> IgnoreConfigResult<Vec<String>> {
+	let file = match File::open(path) {
+		Ok(file) => file,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(Box::new(e)),
+	};
+	let mut patterns = Vec::new();
+	for line in BufReader::new(file).lines() {
+		let line = line?;
+		let trimmed = line.trim();
+		if trimmed.is_empty() || trimmed.starts_with('#') {
+			continue;
+		}
+		patterns.push(trimmed.to_string());
+	}
+	Ok(patterns)
+}
+
+/// Whether gitignore semantics tie `pattern` to the directory of its file: a slash at the
+/// start or in the middle, as in `/build` or `docs/*.pdf`
+fn is_anchored(pattern: &str) -> bool {
+	let pattern = pattern.strip_suffix('/').unwrap_or(pattern);
+	!pattern.starts_with("**/") && pattern.contains('/')
+}
+
+/// Matcher for `patterns`, with anchored ones relative to `base`
+fn build_gitignore(base: &Path, patterns: &[String]) -> IgnoreConfigResult<Gitignore> {
+	let mut builder = GitignoreBuilder::new(base);
+	for pat in patterns {
+		builder.add_line(None, pat)?;
+	}
+	Ok(builder
+		.build()
+		.map_err(|e| format!("Gitignore build error: {e}"))?)
 }
 
 #[cfg(test)]
@@ -98,4 +406,138 @@ mod tests {
 		assert!(config.is_ignored("src/node_modules/bar.js"));
 		assert!(!config.is_ignored("src/main.rs"));
 	}
+
+	#[test]
+	fn test_size_rule() {
+		let dir = tempfile::tempdir().unwrap();
+		let small = dir.path().join("thumb.dat");
+		let medium = dir.path().join("photo.jpg");
+		let large = dir.path().join("disk.img");
+		std::fs::write(&small, [0u8; 10]).unwrap();
+		std::fs::write(&medium, [0u8; 2000]).unwrap();
+		std::fs::write(&large, [0u8; 5000]).unwrap();
+		let config = IgnoreConfig::new(&["*.tmp"])
+			.unwrap()
+			.with_size_rule(SizeIgnoreRule {
+				min_bytes: Some(1024),
+				max_bytes: Some(4096),
+			});
+		assert!(config.is_ignored(&small));
+		assert!(!config.is_ignored(&medium));
+		assert!(config.is_ignored(&large));
+		assert!(
+			!config.is_ignored(dir.path()),
+			"directories are never size-ignored"
+		);
+
+		// Known sizes take precedence over the file on disk
+		assert!(!config.is_ignored_with_size(&small, Some(2000)));
+		assert!(config.is_ignored_with_size(&medium, Some(1)));
+		assert!(config.is_ignored_with_size("never_created.tmp", Some(2000)));
+	}
+
+	#[test]
+	fn test_reload_keeps_added_patterns() {
+		let dir = tempfile::tempdir().unwrap();
+		let file = dir.path().join(".linkfieldignore");
+		std::fs::write(&file, "*.log\n# comment\nbuild/\n").unwrap();
+		let (mut config, _) = IgnoreConfig::from_file_with_patterns(&file).unwrap();
+		config.add_pattern("*.tmp").unwrap();
+
+		std::fs::write(&file, "build/\n*.bak\n").unwrap();
+		let changes = config.reload_from_file(&file).unwrap();
+		assert_eq!(changes.added, ["*.bak"]);
+		assert_eq!(changes.removed, ["*.log"]);
+		assert_eq!(config.patterns(), ["build/", "*.bak", "*.tmp"]);
+		assert!(!config.is_ignored("a.log"));
+		assert!(config.is_ignored("a.bak"));
+		assert!(config.is_ignored("a.tmp"));
+
+		std::fs::remove_file(&file).unwrap();
+		let changes = config.reload_from_file(&file).unwrap();
+		assert_eq!(changes.removed, ["build/", "*.bak"]);
+		assert_eq!(config.patterns(), ["*.tmp"]);
+	}
+
+	#[test]
+	fn test_deeper_ignore_files_take_precedence() {
+		let home = tempfile::tempdir().unwrap();
+		let project = home.path().join("project");
+		std::fs::create_dir(&project).unwrap();
+		std::fs::write(home.path().join(IGNORE_FILE_NAME), "*.log\n*.bak\n").unwrap();
+		std::fs::write(project.join(IGNORE_FILE_NAME), "!keep.log\n*.tmp\n").unwrap();
+
+		let config = IgnoreConfig::from_dir_hierarchy(&project).unwrap();
+		assert!(config.is_ignored("debug.log"));
+		assert!(config.is_ignored("old.bak"));
+		assert!(config.is_ignored("scratch.tmp"));
+		// The project re-includes a file the home directory ignores
+		assert!(!config.is_ignored("keep.log"));
+		assert!(!config.is_ignored("main.rs"));
+
+		// Outside the project only the home directory file applies
+		let outer = IgnoreConfig::from_dir_hierarchy(home.path()).unwrap();
+		assert!(outer.is_ignored("keep.log"));
+		assert!(!outer.is_ignored("scratch.tmp"));
+	}
+
+	#[test]
+	fn test_reload_keeps_inherited_patterns() {
+		let home = tempfile::tempdir().unwrap();
+		let project = home.path().join("project");
+		std::fs::create_dir(&project).unwrap();
+		std::fs::write(home.path().join(IGNORE_FILE_NAME), "*.log\n").unwrap();
+		let file = project.join(IGNORE_FILE_NAME);
+		std::fs::write(&file, "*.tmp\n").unwrap();
+		let mut config = IgnoreConfig::from_dir_hierarchy(&project).unwrap();
+		config.add_pattern("*.bak").unwrap();
+
+		std::fs::write(&file, "!keep.log\n").unwrap();
+		let changes = config.reload_from_file(&file).unwrap();
+		assert_eq!(changes.added, ["!keep.log"]);
+		assert_eq!(changes.removed, ["*.tmp"]);
+		assert_eq!(config.patterns()[1..], ["!keep.log", "*.bak"]);
+		assert_eq!(config.patterns()[0], "*.log");
+		assert!(config.is_ignored("a.log"));
+		assert!(!config.is_ignored("keep.log"));
+		assert!(!config.is_ignored("a.tmp"));
+	}
+
+	#[test]
+	fn test_parent_ignore_files_anchor_at_their_own_directory() {
+		let home = tempfile::tempdir().unwrap();
+		let project = home.path().join("project");
+		for dir in ["build", "project/build/out", "project/out"] {
+			std::fs::create_dir_all(home.path().join(dir)).unwrap();
+		}
+		// Anchored to the home directory, so the project's own build/ is kept
+		std::fs::write(
+			home.path().join(IGNORE_FILE_NAME),
+			"/build/\nproject/*.tmp\n",
+		)
+		.unwrap();
+		std::fs::write(project.join(IGNORE_FILE_NAME), "/out/\n").unwrap();
+
+		let config = IgnoreConfig::from_dir_hierarchy(&project).unwrap();
+		assert!(config.is_ignored(home.path().join("build")));
+		assert!(!config.is_ignored(project.join("build")));
+		assert!(config.is_ignored(project.join("scratch.tmp")));
+		assert!(!config.is_ignored(project.join("build/scratch.tmp")));
+		assert!(config.is_ignored(project.join("out")));
+		assert!(!config.is_ignored(project.join("build/out")));
+	}
+
+	#[test]
+	fn test_unreadable_parent_ignore_files_are_skipped() {
+		let home = tempfile::tempdir().unwrap();
+		let project = home.path().join("project");
+		std::fs::create_dir_all(&project).unwrap();
+		// A directory where the ignore file should be can't be read as one
+		std::fs::create_dir(home.path().join(IGNORE_FILE_NAME)).unwrap();
+		std::fs::write(project.join(IGNORE_FILE_NAME), "*.tmp\n").unwrap();
+
+		let config = IgnoreConfig::from_dir_hierarchy(&project).unwrap();
+		assert_eq!(config.patterns(), ["*.tmp"]);
+		assert!(config.is_ignored(project.join("a.tmp")));
+	}
 }
@@ -1,9 +1,21 @@
 pub mod args;
+pub mod change_journal;
 pub mod db;
+pub mod error;
+pub mod event_recorder;
+pub mod events;
 pub mod file_cache;
+pub mod health;
+pub mod hooks;
 pub mod ignore_config;
+pub mod metrics;
+pub mod minhash;
 pub mod move_heuristics;
+pub mod persisted_config;
 pub mod platform;
+pub mod shutdown;
+#[cfg(feature = "opentelemetry")]
+pub mod telemetry;
 pub mod watcher;
 pub mod windows_registry;
 
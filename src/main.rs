@@ -4,32 +4,119 @@
 #![warn(clippy::expect_used)]
 
 mod app;
+mod commands;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+struct AutoFlushStdout;
+impl std::io::Write for AutoFlushStdout {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		let n = std::io::stdout().write(buf)?;
+		std::io::stdout().flush()?;
+		Ok(n)
+	}
+	fn flush(&mut self) -> std::io::Result<()> {
+		std::io::stdout().flush()
+	}
+}
+
+fn main() -> std::process::ExitCode {
+	let cli = linkfield::args::parse_cli();
+	let telemetry = init_tracing(&cli);
+	let result = match &cli.command {
+		Some(linkfield::args::Command::Watch {
+			once: true,
+			output,
+			persist,
+			..
+		}) => app::run_once(&cli, output.as_deref(), *persist),
+		None | Some(linkfield::args::Command::Watch { .. }) => app::run(&cli),
+		Some(command) => commands::run(&cli, command),
+	};
+	telemetry.shutdown();
+	match result {
+		Ok(()) => std::process::ExitCode::SUCCESS,
+		Err(e) if e.is::<app::InvalidArgs>() => {
+			eprintln!("{e}");
+			std::process::ExitCode::from(2)
+		}
+		// What returning the error from `main` would print
+		Err(e) => {
+			eprintln!("Error: {e:?}");
+			std::process::ExitCode::FAILURE
+		}
+	}
+}
+
+/// Flushes exported spans on [`TelemetryGuard::shutdown`]
+#[cfg(feature = "opentelemetry")]
+struct TelemetryGuard(Option<linkfield::telemetry::OtlpGuard>);
+#[cfg(not(feature = "opentelemetry"))]
+struct TelemetryGuard;
+
+impl TelemetryGuard {
+	#[cfg(feature = "opentelemetry")]
+	fn shutdown(self) {
+		if let Some(guard) = self.0 {
+			guard.shutdown();
+		}
+	}
+	#[cfg(not(feature = "opentelemetry"))]
+	#[allow(clippy::unused_self)]
+	const fn shutdown(self) {}
+}
+
+/// Log to the terminal, and to the `--otlp-endpoint` collector if one is given
+fn init_tracing(cli: &linkfield::args::Cli) -> TelemetryGuard {
+	use linkfield::args::LogFormat;
+	use tracing_subscriber::filter::LevelFilter;
 	use tracing_subscriber::fmt::format::FmtSpan;
-	tracing_subscriber::fmt()
-		.with_ansi(true)
+	use tracing_subscriber::fmt::writer::BoxMakeWriter;
+	use tracing_subscriber::prelude::*;
+	// Subcommands write their results to stdout, so their logs go to stderr
+	let writer = if cli.is_watch() {
+		BoxMakeWriter::new(|| AutoFlushStdout)
+	} else {
+		BoxMakeWriter::new(std::io::stderr)
+	};
+	let fmt_layer = tracing_subscriber::fmt::layer()
 		.with_level(true)
 		.with_target(false)
 		.with_thread_ids(false)
 		.with_thread_names(false)
 		.without_time()
 		.with_span_events(FmtSpan::NONE)
-		.compact()
-		.with_writer(|| {
-			struct AutoFlushStdout;
-			impl std::io::Write for AutoFlushStdout {
-				fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-					let n = std::io::stdout().write(buf)?;
-					std::io::stdout().flush()?;
-					Ok(n)
-				}
-				fn flush(&mut self) -> std::io::Result<()> {
-					std::io::stdout().flush()
-				}
+		.with_writer(writer);
+	let fmt_layer = match cli.log_format {
+		LogFormat::Compact => fmt_layer.with_ansi(true).compact().boxed(),
+		LogFormat::Json => fmt_layer.with_ansi(false).json().boxed(),
+	};
+	let registry = tracing_subscriber::registry()
+		.with(LevelFilter::INFO)
+		.with(fmt_layer);
+	#[cfg(feature = "opentelemetry")]
+	{
+		let (otlp_layer, guard) = match cli
+			.otlp_endpoint
+			.as_deref()
+			.map(linkfield::telemetry::otlp_layer)
+		{
+			Some(Ok((layer, guard))) => (Some(layer), Some(guard)),
+			Some(Err(e)) => {
+				eprintln!("Failed to set up OpenTelemetry export: {e}");
+				(None, None)
 			}
-			AutoFlushStdout
-		})
-		.init();
-	app::run()
+			None => (None, None),
+		};
+		registry.with(otlp_layer).init();
+		TelemetryGuard(guard)
+	}
+	#[cfg(not(feature = "opentelemetry"))]
+	{
+		registry.init();
+		if cli.otlp_endpoint.is_some() {
+			tracing::warn!(
+				"--otlp-endpoint is ignored, linkfield was built without the opentelemetry feature"
+			);
+		}
+		TelemetryGuard
+	}
 }
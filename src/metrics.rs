@@ -0,0 +1,83 @@
+//! Minimal HTTP listener behind `--metrics-port`: the app state at `GET /health` and the
+//! Prometheus gauges at `GET /metrics`
+
+use crate::health::AppStateTracker;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+use tracing::info;
+
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Source of the `linkfield_cache_age_seconds` gauge, e.g.
+/// [`WatcherHandle::cache_age`](crate::watcher::WatcherHandle::cache_age)
+pub type CacheAge = Box<dyn Fn() -> Duration + Send>;
+
+/// Serve `/health` and `/metrics` on `127.0.0.1:port` from a background thread until the
+/// process exits; port 0 picks a free one. Returns the bound address.
+pub fn serve(
+	port: u16,
+	app_state: AppStateTracker,
+	cache_age: CacheAge,
+) -> std::io::Result<SocketAddr> {
+	let listener = TcpListener::bind(("127.0.0.1", port))?;
+	let addr = listener.local_addr()?;
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			let result = stream.and_then(|stream| respond(&stream, &app_state, &cache_age));
+			if let Err(e) = result {
+				tracing::debug!(error = %e, "Failed to answer a metrics request");
+			}
+		}
+	});
+	info!(%addr, "Serving metrics");
+	Ok(addr)
+}
+
+/// Answer one request and close the connection. `/health` is 200 when the app is ready
+/// and 503 otherwise, with the state as the body.
+fn respond(
+	stream: &TcpStream,
+	app_state: &AppStateTracker,
+	cache_age: &CacheAge,
+) -> std::io::Result<()> {
+	stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+	let mut reader = BufReader::new(stream);
+	let mut request_line = String::new();
+	reader.read_line(&mut request_line)?;
+	// Read the headers too, closing with unread data would reset the connection
+	let mut header = String::new();
+	while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+		header.clear();
+	}
+	let mut parts = request_line.split_whitespace();
+	let (status, body) = match (parts.next(), parts.next()) {
+		(Some("GET"), Some("/health")) => {
+			let state = app_state.get();
+			let status = if state.exit_code() == 0 {
+				"200 OK"
+			} else {
+				"503 Service Unavailable"
+			};
+			(status, format!("{state:?}\n"))
+		}
+		(Some("GET"), Some("/metrics")) => (
+			"200 OK",
+			format!(
+				"# HELP linkfield_cache_age_seconds Seconds since the cache last changed\n\
+				# TYPE linkfield_cache_age_seconds gauge\n\
+				linkfield_cache_age_seconds {}\n",
+				cache_age().as_secs_f64()
+			),
+		),
+		_ => ("404 Not Found", String::new()),
+	};
+	let mut stream = stream;
+	write!(
+		stream,
+		"HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+		Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+		body.len()
+	)
+}
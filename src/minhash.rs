@@ -0,0 +1,73 @@
+//! MinHash signatures of file contents, for pairing moves of files whose contents changed
+//! a little on the way, e.g. by a partial write or a re-encode
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Number of hash functions, i.e. values in a signature
+pub const SIGNATURE_LEN: usize = 8;
+/// A MinHash signature: for each hash function, the smallest hash of any shingle
+pub type MinHash = [u64; SIGNATURE_LEN];
+/// Bytes sampled from each end of a file
+pub const SAMPLE_BYTES: u64 = 64 * 1024;
+/// Length of the overlapping byte windows hashed as shingles
+const SHINGLE_LEN: usize = 8;
+
+/// Signature of the first and last [`SAMPLE_BYTES`] of the file at `path`, or of all of
+/// it when it is smaller than both samples together
+pub fn file_signature(path: &Path) -> io::Result<MinHash> {
+	let mut file = File::open(path)?;
+	let len = file.metadata()?.len();
+	if len <= 2 * SAMPLE_BYTES {
+		let mut contents = Vec::new();
+		file.read_to_end(&mut contents)?;
+		return Ok(signature(&[&contents]));
+	}
+	let mut head = vec![0; SAMPLE_BYTES as usize];
+	file.read_exact(&mut head)?;
+	let mut tail = vec![0; SAMPLE_BYTES as usize];
+	file.seek(SeekFrom::End(-(SAMPLE_BYTES as i64)))?;
+	file.read_exact(&mut tail)?;
+	Ok(signature(&[&head, &tail]))
+}
+
+/// Signature of the shingles of `parts`; shingles never span two parts
+pub fn signature(parts: &[&[u8]]) -> MinHash {
+	let mut signature = [u64::MAX; SIGNATURE_LEN];
+	let mut add = |shingle: u64| {
+		for (seed, min) in (1u64..).zip(signature.iter_mut()) {
+			*min = (*min).min(mix(shingle ^ seed.wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+		}
+	};
+	for part in parts {
+		if part.len() < SHINGLE_LEN {
+			if !part.is_empty() {
+				let mut padded = [0; SHINGLE_LEN];
+				padded[..part.len()].copy_from_slice(part);
+				add(u64::from_le_bytes(padded));
+			}
+			continue;
+		}
+		for window in part.windows(SHINGLE_LEN) {
+			let mut shingle = [0; SHINGLE_LEN];
+			shingle.copy_from_slice(window);
+			add(u64::from_le_bytes(shingle));
+		}
+	}
+	signature
+}
+
+/// Number of positions at which `a` and `b` differ. Each equal position is a hash function
+/// agreeing on the minimum, so the fraction of equal positions estimates the share of
+/// shingles the contents have in common.
+pub fn distance(a: &MinHash, b: &MinHash) -> u32 {
+	a.iter().zip(b).map(|(a, b)| u32::from(a != b)).sum()
+}
+
+/// The SplitMix64 finalizer
+const fn mix(mut x: u64) -> u64 {
+	x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+	x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+	x ^ (x >> 31)
+}
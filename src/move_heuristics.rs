@@ -1,58 +1,542 @@
-use std::collections::VecDeque;
+use bincode::{Decode, Encode};
+use redb::ReadableTable;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
 use std::path::PathBuf;
-use std::time::{Duration, Instant};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
 
+use crate::error::LinkfieldResult;
 use crate::file_cache::FileMeta;
+use crate::minhash::{self, MinHash};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileEvent {
 	pub path: PathBuf,
 	#[allow(dead_code)]
 	pub kind: FileEventKind,
 	pub meta: Option<FileMeta>,
+	#[serde(with = "instant_offset")]
 	pub time: Instant,
+	/// Signature of the start and end of the contents, see [`MoveHeuristicsConfig::use_minhash`]
+	#[serde(default)]
+	pub minhash: Option<MinHash>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
 pub enum FileEventKind {
 	Remove,
 	Create,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct MoveCandidate {
 	pub from: FileEvent,
 	pub to: FileEvent,
 	pub score: f64,
 }
 
+/// Tunable parameters for move detection
+#[derive(Debug, Clone)]
+pub struct MoveHeuristicsConfig {
+	/// How long a Remove event waits for a matching Create
+	pub max_age: Duration,
+	/// Pairs must score strictly above this to count as a move
+	pub min_score: f64,
+	/// Remove events for smaller files are never kept as move candidates, e.g. `1` to
+	/// ignore the empty lock and PID files some tools churn through
+	pub min_size_for_matching: u64,
+	/// Remove events for larger files are never kept, since equal sizes say little about
+	/// very large files
+	pub max_size_for_matching: Option<u64>,
+	/// Have the watcher read a [`MinHash`] signature of each created or modified file, so
+	/// files whose contents are similar but not equal still pair up. Costs up to 128 KB of
+	/// reads per event. The signatures are kept with the cache, see
+	/// [`FileCache::update_signature`](crate::file_cache::FileCache::update_signature), for
+	/// the Remove events of those files. Pairs are only scored by signature when both
+	/// events carry one.
+	pub use_minhash: bool,
+}
+
+impl Default for MoveHeuristicsConfig {
+	fn default() -> Self {
+		Self {
+			max_age: Duration::from_secs(5),
+			min_score: 0.5,
+			min_size_for_matching: 0,
+			max_size_for_matching: None,
+			use_minhash: false,
+		}
+	}
+}
+
+impl MoveHeuristicsConfig {
+	/// Whether a file of `size` bytes may take part in move detection
+	pub fn size_matchable(&self, size: u64) -> bool {
+		size >= self.min_size_for_matching
+			&& self.max_size_for_matching.is_none_or(|max| size <= max)
+	}
+}
+
+/// Outcome of replaying an event sequence with [`MoveHeuristics::dry_run_from_events`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DryRunResult {
+	pub moves_detected: Vec<MoveCandidate>,
+	pub unmatched_removes: Vec<FileEvent>,
+	pub unmatched_creates: Vec<FileEvent>,
+}
+
+/// Table holding the pending Remove events saved at shutdown
+pub const PENDING_MOVES_TABLE: redb::TableDefinition<&str, &[u8]> =
+	redb::TableDefinition::new("pending_moves");
+const PENDING_MOVES_KEY: &str = "state";
+
+/// Running totals of the [`MoveHeuristics`] counters across restarts, saved alongside the
+/// pending Remove events
+pub const MOVE_STATS_TABLE: redb::TableDefinition<&str, u64> =
+	redb::TableDefinition::new("move_stats");
+/// Keys of [`MOVE_STATS_TABLE`], in the order `linkfield stats moves` lists them
+pub const MOVE_STAT_KEYS: [&str; 5] = [
+	"moves_detected",
+	"removes_received",
+	"creates_received",
+	"removes_expired",
+	"false_positive_suspected",
+];
+
+/// Add `deltas`, in the order of [`MOVE_STAT_KEYS`], to the totals in `table`
+fn add_stats(
+	table: &mut redb::Table<&str, u64>,
+	deltas: [u64; 5],
+) -> Result<(), redb::StorageError> {
+	for (key, delta) in MOVE_STAT_KEYS.into_iter().zip(deltas) {
+		let total = table.get(key)?.map_or(0, |v| v.value());
+		table.insert(key, total + delta)?;
+	}
+	Ok(())
+}
+
+/// On-disk form of the heuristic state; event times are wall-clock offsets from `UNIX_EPOCH`
+#[derive(Encode, Decode)]
+struct PersistedState {
+	max_age: Duration,
+	min_score: f64,
+	remove_events: Vec<PersistedEvent>,
+}
+
+#[derive(Encode, Decode)]
+struct PersistedEvent {
+	path: PathBuf,
+	kind: FileEventKind,
+	meta: Option<FileMeta>,
+	since_unix_epoch: Duration,
+}
+
+/// Counters describing what a [`MoveHeuristics`] has seen, including the totals restored by
+/// [`MoveHeuristics::load_from_redb`]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct MoveHeuristicsStats {
+	pub total_removes_received: u64,
+	pub total_creates_received: u64,
+	pub moves_detected: u64,
+	/// Remove events dropped unpaired after `max_age`
+	pub removes_expired: u64,
+	/// Creates at the old path of a move detected within `max_age`, as when an editor saves
+	/// by deleting and recreating a file
+	pub false_positives_suspected: u64,
+	pub pending_removes: usize,
+	/// Mean score of detected moves; the score fields are 0.0 until the first move
+	pub avg_score: f64,
+	pub min_score_seen: f64,
+	pub max_score_seen: f64,
+}
+
+/// Running mean, minimum and maximum of the scores of detected moves
+#[derive(Debug, Default)]
+struct ScoreStats {
+	count: u64,
+	mean: f64,
+	min: f64,
+	max: f64,
+}
+
+impl ScoreStats {
+	fn record(&mut self, score: f64) {
+		self.count += 1;
+		// Welford's update keeps the mean stable over long runs
+		#[allow(clippy::cast_precision_loss)]
+		let n = self.count as f64;
+		self.mean += (score - self.mean) / n;
+		if self.count == 1 {
+			self.min = score;
+			self.max = score;
+		} else {
+			self.min = self.min.min(score);
+			self.max = self.max.max(score);
+		}
+	}
+}
+
 /// Heuristic for pairing Remove/Create events as moves.
 pub struct MoveHeuristics {
 	pub remove_events: VecDeque<FileEvent>,
-	pub max_age: Duration,
+	pub config: MoveHeuristicsConfig,
+	removes_received: u64,
+	creates_received: u64,
+	moves_detected: u64,
+	removes_expired: u64,
+	false_positives_suspected: u64,
+	/// The counters as last added to the `move_stats` table, see
+	/// [`MoveHeuristics::take_stat_deltas`]
+	persisted_counters: [u64; 5],
+	scores: ScoreStats,
+	/// Old paths of recently detected moves, for [`MoveHeuristicsStats::false_positives_suspected`]
+	recent_move_sources: VecDeque<(PathBuf, Instant)>,
 }
 
 impl MoveHeuristics {
-	pub const fn new(max_age: Duration) -> Self {
+	pub fn new(max_age: Duration) -> Self {
+		Self::with_config(MoveHeuristicsConfig {
+			max_age,
+			..MoveHeuristicsConfig::default()
+		})
+	}
+
+	pub fn with_config(config: MoveHeuristicsConfig) -> Self {
+		init_reference_epoch();
 		Self {
 			remove_events: VecDeque::new(),
-			max_age,
+			config,
+			removes_received: 0,
+			creates_received: 0,
+			moves_detected: 0,
+			removes_expired: 0,
+			false_positives_suspected: 0,
+			persisted_counters: [0; 5],
+			scores: ScoreStats::default(),
+			recent_move_sources: VecDeque::new(),
+		}
+	}
+
+	/// Snapshot of the event counters and the scores of detected moves
+	pub fn statistics(&self) -> MoveHeuristicsStats {
+		MoveHeuristicsStats {
+			total_removes_received: self.removes_received,
+			total_creates_received: self.creates_received,
+			moves_detected: self.moves_detected,
+			removes_expired: self.removes_expired,
+			false_positives_suspected: self.false_positives_suspected,
+			pending_removes: self.remove_events.len(),
+			avg_score: self.scores.mean,
+			min_score_seen: self.scores.min,
+			max_score_seen: self.scores.max,
+		}
+	}
+
+	/// Forget all pending Remove events
+	pub fn reset(&mut self) {
+		self.remove_events.clear();
+	}
+
+	/// Encode the pending Remove events so they can outlive a restart
+	pub fn serialize_state(&self) -> Vec<u8> {
+		let now = Instant::now();
+		let wall_now = SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.unwrap_or_default();
+		let state = PersistedState {
+			max_age: self.config.max_age,
+			min_score: self.config.min_score,
+			remove_events: self
+				.remove_events
+				.iter()
+				.map(|e| PersistedEvent {
+					path: e.path.clone(),
+					kind: e.kind.clone(),
+					meta: e.meta.clone(),
+					since_unix_epoch: wall_now
+						.saturating_sub(now.saturating_duration_since(e.time)),
+				})
+				.collect(),
+		};
+		bincode::encode_to_vec(&state, bincode::config::standard()).unwrap_or_else(|e| {
+			tracing::error!(error = %e, "Heuristics state serialization failed");
+			Vec::new()
+		})
+	}
+
+	/// Rebuild a heuristic from [`MoveHeuristics::serialize_state`] output.
+	///
+	/// `started_at` is the `Instant` that corresponds to the current wall-clock time; event ages
+	/// (including the time the process was down) are measured back from it, and anything older
+	/// than `max_age` is pruned straight away.
+	pub fn restore_state(
+		bytes: &[u8],
+		started_at: Instant,
+	) -> Result<Self, bincode::error::DecodeError> {
+		let (state, _): (PersistedState, usize) =
+			bincode::decode_from_slice(bytes, bincode::config::standard())?;
+		let wall_now = SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.unwrap_or_default();
+		let mut heuristics = Self::with_config(MoveHeuristicsConfig {
+			max_age: state.max_age,
+			min_score: state.min_score,
+			..MoveHeuristicsConfig::default()
+		});
+		for event in state.remove_events {
+			let age = wall_now.saturating_sub(event.since_unix_epoch);
+			// Events older than the monotonic clock itself are long expired
+			if let Some(time) = started_at.checked_sub(age) {
+				heuristics.remove_events.push_back(FileEvent {
+					path: event.path,
+					kind: event.kind,
+					meta: event.meta,
+					time,
+					minhash: None,
+				});
+			}
+		}
+		heuristics.prune_old();
+		Ok(heuristics)
+	}
+
+	/// Save the pending Remove events to the `pending_moves` table and add the counters not
+	/// yet persisted to the `move_stats` table
+	pub fn save_to_redb(&mut self, db: &redb::Database) -> LinkfieldResult<()> {
+		let deltas = self.take_stat_deltas();
+		let result = self.write_to_redb(db, deltas);
+		if result.is_err() {
+			self.restore_stat_deltas(deltas);
+		}
+		result
+	}
+
+	fn write_to_redb(&self, db: &redb::Database, deltas: [u64; 5]) -> LinkfieldResult<()> {
+		let write_txn = db.begin_write()?;
+		{
+			let mut table = write_txn.open_table(PENDING_MOVES_TABLE)?;
+			table.insert(PENDING_MOVES_KEY, self.serialize_state().as_slice())?;
+			add_stats(&mut write_txn.open_table(MOVE_STATS_TABLE)?, deltas)?;
+		}
+		write_txn.commit()?;
+		Ok(())
+	}
+
+	/// Add counter deltas from [`MoveHeuristics::take_stat_deltas`] to the `move_stats` table
+	pub fn add_stats_to_redb(db: &redb::Database, deltas: [u64; 5]) -> LinkfieldResult<()> {
+		let write_txn = db.begin_write()?;
+		add_stats(&mut write_txn.open_table(MOVE_STATS_TABLE)?, deltas)?;
+		write_txn.commit()?;
+		Ok(())
+	}
+
+	/// How much each counter grew since the last call, in the order of [`MOVE_STAT_KEYS`].
+	/// The counters count as persisted from here on.
+	pub fn take_stat_deltas(&mut self) -> [u64; 5] {
+		let counters = self.counters();
+		let mut deltas = [0; 5];
+		for ((delta, counter), persisted) in deltas
+			.iter_mut()
+			.zip(counters)
+			.zip(&self.persisted_counters)
+		{
+			*delta = counter.saturating_sub(*persisted);
+		}
+		self.persisted_counters = counters;
+		deltas
+	}
+
+	/// Hand back deltas whose write failed, so the next [`MoveHeuristics::take_stat_deltas`]
+	/// includes them again
+	pub fn restore_stat_deltas(&mut self, deltas: [u64; 5]) {
+		for (persisted, delta) in self.persisted_counters.iter_mut().zip(deltas) {
+			*persisted = persisted.saturating_sub(delta);
+		}
+	}
+
+	/// Restore a heuristic saved by [`MoveHeuristics::save_to_redb`], if the table holds one.
+	/// Its counters continue from the saved totals.
+	pub fn load_from_redb(db: &redb::Database) -> LinkfieldResult<Option<Self>> {
+		let read_txn = db.begin_read()?;
+		let table = match read_txn.open_table(PENDING_MOVES_TABLE) {
+			Ok(table) => table,
+			Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+			Err(e) => return Err(e.into()),
+		};
+		let Some(bytes) = table.get(PENDING_MOVES_KEY)? else {
+			return Ok(None);
+		};
+		let mut heuristics = Self::restore_state(bytes.value(), Instant::now())?;
+		for (counter, total) in heuristics
+			.counters_mut()
+			.into_iter()
+			.zip(Self::stored_stats(db)?)
+		{
+			*counter += total.1;
+		}
+		// The saved totals are already in the table
+		heuristics.persisted_counters = heuristics.counters();
+		Ok(Some(heuristics))
+	}
+
+	/// The counters saved by [`MoveHeuristics::save_to_redb`], keyed as in [`MOVE_STAT_KEYS`];
+	/// zero when none were saved
+	pub fn stored_stats(db: &redb::Database) -> LinkfieldResult<[(&'static str, u64); 5]> {
+		let mut stats = MOVE_STAT_KEYS.map(|key| (key, 0));
+		let read_txn = db.begin_read()?;
+		let table = match read_txn.open_table(MOVE_STATS_TABLE) {
+			Ok(table) => table,
+			Err(redb::TableError::TableDoesNotExist(_)) => return Ok(stats),
+			Err(e) => return Err(e.into()),
+		};
+		for (key, value) in &mut stats {
+			*value = table.get(*key)?.map_or(0, |v| v.value());
+		}
+		Ok(stats)
+	}
+
+	/// The persisted counters, in the order of [`MOVE_STAT_KEYS`]
+	const fn counters(&self) -> [u64; 5] {
+		[
+			self.moves_detected,
+			self.removes_received,
+			self.creates_received,
+			self.removes_expired,
+			self.false_positives_suspected,
+		]
+	}
+
+	/// Like [`MoveHeuristics::counters`], for adding the saved totals
+	const fn counters_mut(&mut self) -> [&mut u64; 5] {
+		[
+			&mut self.moves_detected,
+			&mut self.removes_received,
+			&mut self.creates_received,
+			&mut self.removes_expired,
+			&mut self.false_positives_suspected,
+		]
+	}
+
+	/// Count a create at the old path of a recently detected move
+	fn check_false_positive(&mut self, create: &FileEvent) {
+		if let Some(pos) = self
+			.recent_move_sources
+			.iter()
+			.position(|(path, _)| *path == create.path)
+		{
+			self.recent_move_sources.remove(pos);
+			self.false_positives_suspected += 1;
 		}
 	}
 
-	/// Add a Remove event to the cache
+	fn move_detected(&mut self, candidate: &MoveCandidate) {
+		self.moves_detected += 1;
+		self.scores.record(candidate.score);
+		self.recent_move_sources
+			.push_back((candidate.from.path.clone(), candidate.to.time));
+	}
+
+	/// Add a Remove event to the cache, unless its size is outside the configured
+	/// matching range. Events without metadata are always kept. Only kept events count as
+	/// received.
 	pub fn add_remove(&mut self, event: FileEvent) {
+		let matchable = event
+			.meta
+			.as_ref()
+			.is_none_or(|meta| self.config.size_matchable(meta.size));
+		if !matchable {
+			return;
+		}
+		self.removes_received += 1;
 		self.remove_events.push_back(event);
 		self.prune_old();
 	}
 
 	/// Try to pair a Create event with a cached Remove event
 	pub fn pair_create(&mut self, create: &FileEvent) -> Option<MoveCandidate> {
+		self.creates_received += 1;
+		self.prune_old();
+		self.check_false_positive(create);
+		self.take_best_match(create)
+	}
+
+	/// Pair several Create events at once. Unlike calling [`MoveHeuristics::pair_create`] for
+	/// each, the highest scoring pairs across all creates are assigned first, so an early
+	/// create can't take the remove a later one matches better. The result is aligned with
+	/// `creates`.
+	pub fn pair_batch(&mut self, creates: &[FileEvent]) -> Vec<Option<MoveCandidate>> {
+		self.creates_received += creates.len() as u64;
 		self.prune_old();
+		for create in creates {
+			self.check_false_positive(create);
+		}
+		let mut pairs: Vec<(usize, usize, f64)> = Vec::new();
+		for (r, remove) in self.remove_events.iter().enumerate() {
+			for (c, create) in creates.iter().enumerate() {
+				let score = score_pair(remove, create);
+				if score > self.config.min_score {
+					pairs.push((r, c, score));
+				}
+			}
+		}
+		pairs.sort_by(|a, b| b.2.total_cmp(&a.2).then(a.0.cmp(&b.0)).then(a.1.cmp(&b.1)));
+		let mut matched: Vec<Option<MoveCandidate>> = vec![None; creates.len()];
+		let mut used_removes = vec![false; self.remove_events.len()];
+		for (r, c, score) in pairs {
+			if used_removes[r] || matched[c].is_some() {
+				continue;
+			}
+			used_removes[r] = true;
+			let candidate = MoveCandidate {
+				from: self.remove_events[r].clone(),
+				to: creates[c].clone(),
+				score,
+			};
+			self.move_detected(&candidate);
+			matched[c] = Some(candidate);
+		}
+		let mut used = used_removes.into_iter();
+		self.remove_events.retain(|_| !used.next().unwrap_or(false));
+		matched
+	}
+
+	/// Replay `events` in order through a fresh heuristic without touching the file cache or
+	/// database. Event ages are measured against each event's own timestamp, so recorded
+	/// sequences behave the same no matter when they are replayed.
+	pub fn dry_run_from_events(events: &[FileEvent], config: MoveHeuristicsConfig) -> DryRunResult {
+		let mut heuristics = Self::with_config(config);
+		let mut result = DryRunResult::default();
+		for event in events {
+			let expired = heuristics.prune_at(event.time);
+			result.unmatched_removes.extend(expired);
+			match event.kind {
+				FileEventKind::Remove => {
+					let matchable = event
+						.meta
+						.as_ref()
+						.is_none_or(|meta| heuristics.config.size_matchable(meta.size));
+					if matchable {
+						heuristics.remove_events.push_back(event.clone());
+					} else {
+						result.unmatched_removes.push(event.clone());
+					}
+				}
+				FileEventKind::Create => match heuristics.take_best_match(event) {
+					Some(candidate) => result.moves_detected.push(candidate),
+					None => result.unmatched_creates.push(event.clone()),
+				},
+			}
+		}
+		result.unmatched_removes.extend(heuristics.remove_events);
+		result
+	}
+
+	fn take_best_match(&mut self, create: &FileEvent) -> Option<MoveCandidate> {
 		let mut best: Option<MoveCandidate> = None;
 		for remove in &self.remove_events {
 			let score = score_pair(remove, create);
-			if score > 0.5 {
+			if score > self.config.min_score {
 				// Good enough match
 				let candidate = MoveCandidate {
 					from: remove.clone(),
@@ -65,6 +549,7 @@ impl MoveHeuristics {
 			}
 		}
 		if let Some(ref best_candidate) = best {
+			self.move_detected(best_candidate);
 			// Remove the paired Remove event
 			if let Some(pos) = self
 				.remove_events
@@ -78,9 +563,53 @@ impl MoveHeuristics {
 	}
 
 	fn prune_old(&mut self) {
-		let now = Instant::now();
-		self.remove_events
-			.retain(|e| now.duration_since(e.time) < self.max_age);
+		self.prune_at(Instant::now());
+	}
+
+	/// Drop Remove events older than `max_age` as of `now`, returning them
+	fn prune_at(&mut self, now: Instant) -> Vec<FileEvent> {
+		let max_age = self.config.max_age;
+		let (kept, expired): (Vec<_>, Vec<_>) = self
+			.remove_events
+			.drain(..)
+			.partition(|e| now.saturating_duration_since(e.time) < max_age);
+		self.remove_events = kept.into();
+		self.removes_expired += expired.len() as u64;
+		self.recent_move_sources
+			.retain(|(_, time)| now.saturating_duration_since(*time) < max_age);
+		expired
+	}
+}
+
+/// Process-wide reference point used to express `Instant`s as serializable offsets
+static REFERENCE_EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Fix the reference epoch before any event time is taken, so no serialized `Instant`
+/// predates it and saturates to zero
+fn init_reference_epoch() {
+	REFERENCE_EPOCH.get_or_init(Instant::now);
+}
+
+fn reference_epoch() -> Instant {
+	*REFERENCE_EPOCH.get_or_init(Instant::now)
+}
+
+/// Serializes an `Instant` as milliseconds since [`reference_epoch`]
+mod instant_offset {
+	use super::reference_epoch;
+	use serde::{Deserialize, Deserializer, Serializer};
+	use std::time::{Duration, Instant};
+
+	pub fn serialize<S: Serializer>(time: &Instant, serializer: S) -> Result<S::Ok, S::Error> {
+		let millis = time
+			.saturating_duration_since(reference_epoch())
+			.as_millis();
+		serializer.serialize_u64(u64::try_from(millis).unwrap_or(u64::MAX))
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Instant, D::Error> {
+		let millis = u64::deserialize(deserializer)?;
+		Ok(reference_epoch() + Duration::from_millis(millis))
 	}
 }
 
@@ -99,15 +628,9 @@ pub fn score_pair(remove: &FileEvent, create: &FileEvent) -> f64 {
 	if remove.path.extension() == create.path.extension() {
 		score += 0.2;
 	}
-	// File name similarity (Levenshtein or prefix match)
+	// File name similarity (exact, prefix or trigram match)
 	if let (Some(rn), Some(cn)) = (remove.path.file_name(), create.path.file_name()) {
-		let rn = rn.to_string_lossy();
-		let cn = cn.to_string_lossy();
-		if rn == cn {
-			score += 0.2;
-		} else if rn.as_ref().starts_with(cn.as_ref()) || cn.as_ref().starts_with(rn.as_ref()) {
-			score += 0.1;
-		}
+		score += name_similarity_score(&rn.to_string_lossy(), &cn.to_string_lossy());
 	}
 	// Timestamps (if available)
 	if let (Some(rm), Some(cm)) = (remove.meta.as_ref(), create.meta.as_ref()) {
@@ -119,15 +642,310 @@ pub fn score_pair(remove: &FileEvent, create: &FileEvent) -> f64 {
 			}
 		}
 	}
+	// Mostly the same contents (at least 7 of the 8 signature values agree)
+	if remove
+		.minhash
+		.zip(create.minhash)
+		.is_some_and(|(rm, cm)| minhash::distance(&rm, &cm) < 2)
+	{
+		score += 0.3;
+	}
 	score.min(1.0f64)
 }
 
+/// Score contribution of two file names: exact matches beat prefix matches, which beat
+/// names that merely share most of their trigrams
+fn name_similarity_score(a: &str, b: &str) -> f64 {
+	if a == b {
+		0.2
+	} else if a.starts_with(b) || b.starts_with(a) {
+		0.1
+	} else {
+		let similarity = trigram_similarity(a, b);
+		if similarity > 0.8 {
+			0.15
+		} else if similarity > 0.5 {
+			0.1
+		} else {
+			0.0
+		}
+	}
+}
+
+/// Jaccard coefficient of the character trigram sets of `a` and `b`.
+///
+/// Catches edits in the middle of a name (`document_draft.docx` → `document_final.docx`)
+/// that a prefix check misses. Building the sets is O(n) in the name length, which is
+/// cheap for file names. Strings too short to have a trigram only match themselves.
+pub fn trigram_similarity(a: &str, b: &str) -> f64 {
+	fn trigrams(s: &str) -> HashSet<[char; 3]> {
+		let chars: Vec<char> = s.chars().collect();
+		chars.windows(3).map(|w| [w[0], w[1], w[2]]).collect()
+	}
+	let (a_set, b_set) = (trigrams(a), trigrams(b));
+	let union = a_set.union(&b_set).count();
+	if union == 0 {
+		return if a == b { 1.0 } else { 0.0 };
+	}
+	let intersection = a_set.intersection(&b_set).count();
+	#[allow(clippy::cast_precision_loss)]
+	let similarity = intersection as f64 / union as f64;
+	similarity
+}
+
 /// Helper to create a `FileEvent` from a path and kind
 pub fn make_file_event(path: PathBuf, kind: FileEventKind, meta: Option<FileMeta>) -> FileEvent {
+	init_reference_epoch();
 	FileEvent {
 		path,
 		kind,
 		meta,
 		time: Instant::now(),
+		minhash: None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::file_cache::meta::{FileCachePath, FileType};
+
+	fn event(path: &str, kind: FileEventKind, size: u64, offset_ms: u64) -> FileEvent {
+		let path = PathBuf::from(path);
+		FileEvent {
+			meta: Some(FileMeta {
+				path: FileCachePath(path.clone()),
+				size,
+				modified: None,
+				created: None,
+				extension: path.extension().map(|e| e.to_string_lossy().to_string()),
+				inode: None,
+				content_hash: None,
+				is_virtual: false,
+				file_type: FileType::Regular,
+				device: None,
+			}),
+			path,
+			kind,
+			time: reference_epoch() + Duration::from_millis(offset_ms),
+			minhash: None,
+		}
+	}
+
+	#[test]
+	fn test_dry_run_from_events() {
+		let events = vec![
+			event("a/report.pdf", FileEventKind::Remove, 4096, 0),
+			event("b/report.pdf", FileEventKind::Create, 4096, 100),
+			event("a/stale.txt", FileEventKind::Remove, 10, 200),
+			event("c/new.bin", FileEventKind::Create, 99, 10_000),
+		];
+		let json = serde_json::to_string(&events).unwrap();
+		let events: Vec<FileEvent> = serde_json::from_str(&json).unwrap();
+		let result = MoveHeuristics::dry_run_from_events(&events, MoveHeuristicsConfig::default());
+		assert_eq!(result.moves_detected.len(), 1);
+		assert_eq!(
+			result.moves_detected[0].to.path,
+			PathBuf::from("b/report.pdf")
+		);
+		assert_eq!(result.unmatched_removes.len(), 1);
+		assert_eq!(
+			result.unmatched_removes[0].path,
+			PathBuf::from("a/stale.txt")
+		);
+		assert_eq!(result.unmatched_creates.len(), 1);
+	}
+
+	#[test]
+	fn test_remove_event_survives_serialize_restore() {
+		let mut heuristics = MoveHeuristics::new(Duration::from_secs(60));
+		let mut removed = event("old/photo.jpg", FileEventKind::Remove, 2048, 0);
+		removed.time = Instant::now();
+		heuristics.add_remove(removed);
+		let bytes = heuristics.serialize_state();
+		heuristics.reset();
+		assert!(heuristics.remove_events.is_empty());
+
+		let mut restored = MoveHeuristics::restore_state(&bytes, Instant::now()).unwrap();
+		assert_eq!(restored.remove_events.len(), 1);
+		assert_eq!(restored.config.max_age, Duration::from_secs(60));
+		let mut created = event("new/photo.jpg", FileEventKind::Create, 2048, 0);
+		created.time = Instant::now();
+		let pair = restored.pair_create(&created).unwrap();
+		assert_eq!(pair.from.path, PathBuf::from("old/photo.jpg"));
+	}
+
+	fn now_event(path: &str, kind: FileEventKind, size: u64) -> FileEvent {
+		let mut e = event(path, kind, size, 0);
+		e.time = Instant::now();
+		e
+	}
+
+	#[test]
+	fn test_pair_batch_assigns_best_pairs_globally() {
+		// new/b.txt matches old/b.txt best, but new/x.txt comes first and takes old/b.txt
+		// when paired one by one
+		let removes = [
+			now_event("old/b.txt", FileEventKind::Remove, 10),
+			now_event("old/q.txt", FileEventKind::Remove, 20),
+		];
+		let creates = [
+			now_event("new/x.txt", FileEventKind::Create, 10),
+			now_event("new/b.txt", FileEventKind::Create, 10),
+		];
+		let with_removes = || {
+			let mut heuristics = MoveHeuristics::new(Duration::from_secs(60));
+			for remove in &removes {
+				heuristics.add_remove(remove.clone());
+			}
+			heuristics
+		};
+
+		let mut one_by_one = with_removes();
+		let sequential: Vec<_> = creates
+			.iter()
+			.map(|create| one_by_one.pair_create(create))
+			.collect();
+		let mut batched = with_removes();
+		let batch = batched.pair_batch(&creates);
+
+		let from = |pairs: &[Option<MoveCandidate>]| -> Vec<Option<PathBuf>> {
+			pairs
+				.iter()
+				.map(|p| p.as_ref().map(|p| p.from.path.clone()))
+				.collect()
+		};
+		assert_eq!(
+			from(&sequential),
+			vec![
+				Some(PathBuf::from("old/b.txt")),
+				Some(PathBuf::from("old/q.txt"))
+			]
+		);
+		assert_eq!(
+			from(&batch),
+			vec![
+				Some(PathBuf::from("old/q.txt")),
+				Some(PathBuf::from("old/b.txt"))
+			]
+		);
+		let total = |pairs: &[Option<MoveCandidate>]| -> f64 {
+			pairs.iter().flatten().map(|p| p.score).sum()
+		};
+		assert!(total(&batch) > total(&sequential));
+		assert!(batched.remove_events.is_empty());
+		assert_eq!(batched.statistics().moves_detected, 2);
+		assert_eq!(batched.statistics().total_creates_received, 2);
+	}
+
+	#[test]
+	fn test_pair_batch_matches_pair_create_for_single_create() {
+		let create = now_event("new/report.pdf", FileEventKind::Create, 4096);
+		let mut one = MoveHeuristics::new(Duration::from_secs(60));
+		let mut batched = MoveHeuristics::new(Duration::from_secs(60));
+		for heuristics in [&mut one, &mut batched] {
+			heuristics.add_remove(now_event("old/report.pdf", FileEventKind::Remove, 4096));
+			heuristics.add_remove(now_event("old/other.bin", FileEventKind::Remove, 7));
+		}
+
+		let single = one.pair_create(&create).unwrap();
+		let batch = batched.pair_batch(std::slice::from_ref(&create));
+
+		assert_eq!(batch.len(), 1);
+		let paired = batch[0].as_ref().unwrap();
+		assert_eq!(paired.from.path, single.from.path);
+		assert!((paired.score - single.score).abs() < f64::EPSILON);
+		assert_eq!(batched.remove_events.len(), one.remove_events.len());
+	}
+
+	#[test]
+	fn test_statistics_track_events_and_scores() {
+		let mut heuristics = MoveHeuristics::new(Duration::from_secs(60));
+		assert_eq!(heuristics.statistics(), MoveHeuristicsStats::default());
+
+		heuristics.add_remove(now_event("a/report.pdf", FileEventKind::Remove, 4096));
+		heuristics.add_remove(now_event("a/notes.txt", FileEventKind::Remove, 10));
+		heuristics.add_remove(now_event("x/data.csv", FileEventKind::Remove, 100));
+		let exact = heuristics.pair_create(&now_event("b/report.pdf", FileEventKind::Create, 4096));
+		assert!(exact.is_some());
+		let unrelated =
+			heuristics.pair_create(&now_event("c/other.bin", FileEventKind::Create, 99));
+		assert!(unrelated.is_none());
+		let close = heuristics.pair_create(&now_event("y/data.csv", FileEventKind::Create, 110));
+		assert!(close.is_some());
+
+		let stats = heuristics.statistics();
+		assert_eq!(stats.total_removes_received, 3);
+		assert_eq!(stats.total_creates_received, 3);
+		assert_eq!(stats.moves_detected, 2);
+		assert_eq!(stats.removes_expired, 0);
+		assert_eq!(stats.pending_removes, 1);
+		assert!((stats.max_score_seen - 1.0).abs() < 1e-9);
+		assert!((stats.min_score_seen - 0.8).abs() < 1e-9);
+		assert!((stats.avg_score - 0.9).abs() < 1e-9);
+
+		heuristics.prune_at(Instant::now() + Duration::from_secs(61));
+		let stats = heuristics.statistics();
+		assert_eq!(stats.removes_expired, 1);
+		assert_eq!(stats.pending_removes, 0);
+	}
+
+	#[test]
+	fn test_trigram_similarity_contribution_is_monotonic() {
+		assert!((trigram_similarity("report.pdf", "report.pdf") - 1.0).abs() < 1e-9);
+		assert!(trigram_similarity("holiday.jpg", "invoice.pdf").abs() < 1e-9);
+		assert!(trigram_similarity("ab", "ab") > trigram_similarity("ab", "cd"));
+
+		let pairs = [
+			("report.pdf", "report.pdf"),
+			(
+				"project_roadmap_final_version.md",
+				"project_roadmap_final_version2.md",
+			),
+			("quarterly_report_2023.xlsx", "quarterly_report_2024.xlsx"),
+			("holiday.jpg", "invoice.pdf"),
+		];
+		let similarities: Vec<f64> = pairs
+			.iter()
+			.map(|(a, b)| trigram_similarity(a, b))
+			.collect();
+		let scores: Vec<f64> = pairs
+			.iter()
+			.map(|(a, b)| name_similarity_score(a, b))
+			.collect();
+		assert!(similarities.windows(2).all(|w| w[0] >= w[1]));
+		assert!(scores.windows(2).all(|w| w[0] >= w[1]));
+		assert!((scores[1] - 0.15).abs() < 1e-9);
+		assert!((scores[2] - 0.1).abs() < 1e-9);
+		assert!(scores[3].abs() < 1e-9);
+	}
+
+	#[test]
+	fn test_size_filters_exclude_remove_events() {
+		let mut heuristics = MoveHeuristics::with_config(MoveHeuristicsConfig {
+			max_age: Duration::from_secs(60),
+			min_size_for_matching: 1,
+			max_size_for_matching: Some(1024),
+			..MoveHeuristicsConfig::default()
+		});
+		heuristics.add_remove(now_event("run/app.pid", FileEventKind::Remove, 0));
+		heuristics.add_remove(now_event("data/huge.img", FileEventKind::Remove, 4096));
+		heuristics.add_remove(now_event("docs/a.txt", FileEventKind::Remove, 10));
+		assert_eq!(heuristics.remove_events.len(), 1);
+		assert_eq!(
+			heuristics.remove_events[0].path,
+			PathBuf::from("docs/a.txt")
+		);
+		// Filtered removes are not counted as received
+		assert_eq!(heuristics.statistics().total_removes_received, 1);
+		assert!(
+			heuristics
+				.pair_create(&now_event("other/app.pid", FileEventKind::Create, 0))
+				.is_none()
+		);
+
+		let mut defaults = MoveHeuristics::new(Duration::from_secs(60));
+		defaults.add_remove(now_event("run/app.pid", FileEventKind::Remove, 0));
+		assert_eq!(defaults.remove_events.len(), 1);
 	}
 }
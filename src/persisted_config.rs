@@ -0,0 +1,101 @@
+// Watcher settings stored in the database so they survive restarts
+
+use crate::error::LinkfieldResult;
+use crate::move_heuristics::MoveHeuristicsConfig;
+use bincode::{Decode, Encode, decode_from_slice, encode_to_vec};
+use redb::{Database, TableDefinition};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Holds a single bincode-encoded [`PersistedConfig`] under [`CONFIG_KEY`]
+pub const WATCHER_CONFIG_TABLE: TableDefinition<&str, &[u8]> =
+	TableDefinition::new("watcher_config");
+pub const CONFIG_KEY: &str = "config";
+
+#[derive(Debug, Clone, PartialEq, Encode, Decode, Serialize, Deserialize)]
+pub struct PersistedConfig {
+	pub watch_paths: Vec<PathBuf>,
+	pub ignore_patterns: Vec<String>,
+	/// Minimum score for a Remove/Create pair to count as a move
+	pub move_threshold: f64,
+	/// How long a Remove waits for a matching Create
+	pub move_max_age_secs: u64,
+	/// Size of the scan thread pool; rayon's default when `None`
+	pub scan_threads: Option<usize>,
+}
+
+impl Default for PersistedConfig {
+	fn default() -> Self {
+		let heuristics = MoveHeuristicsConfig::default();
+		Self {
+			watch_paths: Vec::new(),
+			ignore_patterns: Vec::new(),
+			move_threshold: heuristics.min_score,
+			move_max_age_secs: heuristics.max_age.as_secs(),
+			scan_threads: None,
+		}
+	}
+}
+
+impl PersistedConfig {
+	/// The stored config, or `None` if nothing was saved yet
+	pub fn load(db: &Database) -> LinkfieldResult<Option<Self>> {
+		let read_txn = db.begin_read()?;
+		let table = match read_txn.open_table(WATCHER_CONFIG_TABLE) {
+			Ok(table) => table,
+			Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+			Err(e) => return Err(e.into()),
+		};
+		let Some(bytes) = table.get(CONFIG_KEY)? else {
+			return Ok(None);
+		};
+		let (config, _) = decode_from_slice(bytes.value(), bincode::config::standard())?;
+		Ok(Some(config))
+	}
+
+	pub fn save(&self, db: &Database) -> LinkfieldResult<()> {
+		let bytes = encode_to_vec(self, bincode::config::standard())?;
+		let write_txn = db.begin_write()?;
+		{
+			let mut table = write_txn.open_table(WATCHER_CONFIG_TABLE)?;
+			table.insert(CONFIG_KEY, bytes.as_slice())?;
+		}
+		write_txn.commit()?;
+		Ok(())
+	}
+
+	/// Delete the stored config, returning whether there was one
+	pub fn reset(db: &Database) -> LinkfieldResult<bool> {
+		let write_txn = db.begin_write()?;
+		let removed = {
+			let mut table = write_txn.open_table(WATCHER_CONFIG_TABLE)?;
+			table.remove(CONFIG_KEY)?.is_some()
+		};
+		write_txn.commit()?;
+		Ok(removed)
+	}
+
+	/// Apply command line values on top of the stored ones; anything given on the command
+	/// line wins, the rest is kept
+	#[must_use]
+	pub fn merged_with_cli(mut self, watch_path: PathBuf, cli_ignore: &[String]) -> Self {
+		self.watch_paths = vec![watch_path];
+		if !cli_ignore.is_empty() {
+			self.ignore_patterns = cli_ignore.to_vec();
+		}
+		self
+	}
+
+	pub fn heuristics_config(&self) -> MoveHeuristicsConfig {
+		MoveHeuristicsConfig {
+			max_age: Duration::from_secs(self.move_max_age_secs),
+			min_score: self.move_threshold,
+			..MoveHeuristicsConfig::default()
+		}
+	}
+
+	pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+		toml::to_string_pretty(self)
+	}
+}
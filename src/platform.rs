@@ -13,6 +13,29 @@ pub fn handle_platform_startup() {
 #[cfg(not(windows))]
 pub fn handle_platform_startup() {}
 
+/// Remove the `.redb` file association, checking that it is gone afterwards. Returns
+/// whether there was one to remove.
+#[cfg(windows)]
+pub fn unregister_file_association() -> std::io::Result<bool> {
+	use crate::windows_registry::{is_redb_registered, unregister_redb_extension};
+	let was_registered = is_redb_registered();
+	unregister_redb_extension()?;
+	if is_redb_registered() {
+		return Err(std::io::Error::other(
+			".redb is still registered after unregistering",
+		));
+	}
+	Ok(was_registered)
+}
+
+#[cfg(not(windows))]
+pub fn unregister_file_association() -> std::io::Result<bool> {
+	Err(std::io::Error::new(
+		std::io::ErrorKind::Unsupported,
+		"file associations are only registered on Windows",
+	))
+}
+
 pub fn wait_for_exit() {
 	use std::io::{self, Read};
 	tracing::info!("Press Enter to exit...");
@@ -34,3 +57,225 @@ pub fn wait_for_exit() {
 		std::thread::sleep(std::time::Duration::from_millis(100));
 	}
 }
+
+/// System information for bug reports, from [`startup_diagnostics`]. Values that could not
+/// be read, or need the `diagnostics` feature, are `None`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct StartupDiagnostics {
+	pub os: String,
+	pub os_version: Option<String>,
+	pub cpu_cores: usize,
+	pub available_ram_bytes: Option<u64>,
+	pub process_memory_bytes: Option<u64>,
+	/// Free space on the volume holding the database
+	pub available_disk_bytes: Option<u64>,
+	/// `fs.inotify.max_user_watches`, Linux only
+	pub inotify_max_user_watches: Option<u64>,
+	pub open_file_descriptors: Option<usize>,
+	pub db_file_size_bytes: Option<u64>,
+}
+
+/// Collect [`StartupDiagnostics`] for the database at `db_path`
+pub fn startup_diagnostics(db_path: &std::path::Path) -> StartupDiagnostics {
+	let (os_version, available_ram_bytes, process_memory_bytes) = system_memory();
+	StartupDiagnostics {
+		os: std::env::consts::OS.to_string(),
+		os_version,
+		cpu_cores: std::thread::available_parallelism().map_or(1, std::num::NonZero::get),
+		available_ram_bytes,
+		process_memory_bytes,
+		available_disk_bytes: available_disk(db_path),
+		inotify_max_user_watches: inotify_max_user_watches(),
+		open_file_descriptors: open_file_descriptors(),
+		db_file_size_bytes: std::fs::metadata(db_path).ok().map(|m| m.len()),
+	}
+}
+
+impl StartupDiagnostics {
+	/// One `name  value` line per field, `-` for unknown values
+	pub fn format_table(&self) -> String {
+		fn or_dash<T: ToString>(value: Option<T>) -> String {
+			value.map_or_else(|| "-".to_string(), |v| v.to_string())
+		}
+		let rows = [
+			("os", self.os.clone()),
+			("os_version", or_dash(self.os_version.as_ref())),
+			("cpu_cores", self.cpu_cores.to_string()),
+			("available_ram_bytes", or_dash(self.available_ram_bytes)),
+			("process_memory_bytes", or_dash(self.process_memory_bytes)),
+			("available_disk_bytes", or_dash(self.available_disk_bytes)),
+			(
+				"inotify_max_user_watches",
+				or_dash(self.inotify_max_user_watches),
+			),
+			("open_file_descriptors", or_dash(self.open_file_descriptors)),
+			("db_file_size_bytes", or_dash(self.db_file_size_bytes)),
+		];
+		rows.iter()
+			.map(|(name, value)| format!("{name:<26}{value}\n"))
+			.collect()
+	}
+}
+
+/// OS version, available RAM and this process's resident memory
+#[cfg(feature = "diagnostics")]
+fn system_memory() -> (Option<String>, Option<u64>, Option<u64>) {
+	use sysinfo::{ProcessesToUpdate, System};
+	let mut system = System::new();
+	system.refresh_memory();
+	let process_memory = sysinfo::get_current_pid().ok().and_then(|pid| {
+		system.refresh_processes(ProcessesToUpdate::Some(&[pid]), false);
+		system.process(pid).map(sysinfo::Process::memory)
+	});
+	(
+		System::long_os_version(),
+		Some(system.available_memory()),
+		process_memory,
+	)
+}
+
+#[cfg(not(feature = "diagnostics"))]
+const fn system_memory() -> (Option<String>, Option<u64>, Option<u64>) {
+	(None, None, None)
+}
+
+/// Free space on the volume of `db_path`, which need not exist yet
+#[cfg(feature = "diagnostics")]
+fn available_disk(db_path: &std::path::Path) -> Option<u64> {
+	db_path
+		.ancestors()
+		.find(|dir| dir.exists())
+		.and_then(|dir| fs2::available_space(dir).ok())
+}
+
+#[cfg(not(feature = "diagnostics"))]
+const fn available_disk(_db_path: &std::path::Path) -> Option<u64> {
+	None
+}
+
+#[cfg(target_os = "linux")]
+fn inotify_max_user_watches() -> Option<u64> {
+	std::fs::read_to_string("/proc/sys/fs/inotify/max_user_watches")
+		.ok()?
+		.trim()
+		.parse()
+		.ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+const fn inotify_max_user_watches() -> Option<u64> {
+	None
+}
+
+/// Inotify watches held by this process, counted from `/proc/self/fdinfo`
+#[cfg(target_os = "linux")]
+pub fn inotify_watch_count() -> Option<usize> {
+	let mut count = 0;
+	for entry in std::fs::read_dir("/proc/self/fdinfo").ok()? {
+		// Descriptors closed since the listing are skipped
+		let Ok(info) = std::fs::read_to_string(entry.ok()?.path()) else {
+			continue;
+		};
+		count += info
+			.lines()
+			.filter(|line| line.starts_with("inotify wd:"))
+			.count();
+	}
+	Some(count)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub const fn inotify_watch_count() -> Option<usize> {
+	None
+}
+
+#[cfg(target_os = "linux")]
+fn open_file_descriptors() -> Option<usize> {
+	Some(std::fs::read_dir("/proc/self/fd").ok()?.count())
+}
+
+#[cfg(not(target_os = "linux"))]
+const fn open_file_descriptors() -> Option<usize> {
+	None
+}
+
+/// Whether another handle holds a write lock on `path`, so its contents may be mid-write.
+///
+/// Linux checks `/proc/locks` for a `WRITE` lock on the file's device and inode; Windows
+/// tries to open the file without sharing. Elsewhere locks are not detected and this
+/// returns false.
+#[cfg(target_os = "linux")]
+pub fn is_file_locked(path: &std::path::Path) -> bool {
+	use std::os::unix::fs::MetadataExt;
+	let Ok(metadata) = std::fs::metadata(path) else {
+		return false;
+	};
+	let Ok(locks) = std::fs::read_to_string("/proc/locks") else {
+		return false;
+	};
+	let dev = metadata.dev();
+	let (major, minor) = (
+		((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff),
+		(dev & 0xff) | ((dev >> 12) & !0xff),
+	);
+	// e.g. `1: FLOCK  ADVISORY  WRITE 1234 fe:00:5678 0 EOF`, major and minor in hex
+	locks.lines().any(|line| {
+		let fields: Vec<&str> = line.split_whitespace().collect();
+		let Some(id_field) = fields.iter().position(|f| f.matches(':').count() == 2) else {
+			return false;
+		};
+		let mut id = fields[id_field].split(':');
+		let parsed = (
+			id.next().and_then(|s| u64::from_str_radix(s, 16).ok()),
+			id.next().and_then(|s| u64::from_str_radix(s, 16).ok()),
+			id.next().and_then(|s| s.parse::<u64>().ok()),
+		);
+		fields.contains(&"WRITE") && parsed == (Some(major), Some(minor), Some(metadata.ino()))
+	})
+}
+
+#[cfg(windows)]
+pub fn is_file_locked(path: &std::path::Path) -> bool {
+	use std::os::windows::fs::OpenOptionsExt;
+	const ERROR_SHARING_VIOLATION: i32 = 32;
+	const ERROR_LOCK_VIOLATION: i32 = 33;
+	match std::fs::OpenOptions::new()
+		.read(true)
+		.share_mode(0)
+		.open(path)
+	{
+		Ok(_) => false,
+		Err(e) => matches!(
+			e.raw_os_error(),
+			Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+		),
+	}
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub const fn is_file_locked(_path: &std::path::Path) -> bool {
+	false
+}
+
+/// Whether a process with `pid` exists, e.g. the watcher that wrote a state file.
+///
+/// Linux checks `/proc`; elsewhere the process list comes from sysinfo with the
+/// `diagnostics` feature, and without it every process is assumed to be running.
+#[cfg(target_os = "linux")]
+pub fn is_process_running(pid: u32) -> bool {
+	std::path::Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(all(not(target_os = "linux"), feature = "diagnostics"))]
+pub fn is_process_running(pid: u32) -> bool {
+	use sysinfo::{Pid, ProcessesToUpdate, System};
+	let pid = Pid::from_u32(pid);
+	let mut system = System::new();
+	system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+	system.process(pid).is_some()
+}
+
+#[cfg(all(not(target_os = "linux"), not(feature = "diagnostics")))]
+pub const fn is_process_running(_pid: u32) -> bool {
+	true
+}
@@ -0,0 +1,88 @@
+// Cooperative cancellation and the bounded shutdown sequence for the watch mode
+
+use crate::watcher::WatcherHandle;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Shared flag that long-running work polls to find out it should stop early.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	pub fn cancel(&self) {
+		self.0.store(true, Ordering::Relaxed);
+	}
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(Ordering::Relaxed)
+	}
+	/// Sleep for `duration`, waking early if cancelled. Returns false when cancelled.
+	pub fn sleep(&self, duration: Duration) -> bool {
+		let deadline = Instant::now() + duration;
+		while !self.is_cancelled() {
+			let now = Instant::now();
+			if now >= deadline {
+				return true;
+			}
+			std::thread::sleep((deadline - now).min(Duration::from_millis(100)));
+		}
+		false
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownResult {
+	Clean,
+	TimedOut { threads_still_running: usize },
+}
+
+/// Stop the scan and the watcher, then wait at most `timeout` in total for both threads.
+///
+/// The scan commits its pending batch before returning, so nothing is lost when it is
+/// cancelled. Returns the scan thread's result when it finished in time; a thread that
+/// is still running after the timeout is detached.
+pub fn shutdown<T>(
+	watcher: WatcherHandle,
+	scan_handle: JoinHandle<T>,
+	scan_cancel: &CancellationToken,
+	timeout: Duration,
+) -> (ShutdownResult, Option<T>) {
+	let deadline = Instant::now() + timeout;
+	scan_cancel.cancel();
+	watcher.stop();
+	let mut threads_still_running = 0;
+	let scan_result = match join_timeout(scan_handle, deadline) {
+		Ok(result) => result,
+		Err(_) => {
+			threads_still_running += 1;
+			None
+		}
+	};
+	if join_timeout(watcher.into_join_handle(), deadline).is_err() {
+		threads_still_running += 1;
+	}
+	let result = if threads_still_running == 0 {
+		ShutdownResult::Clean
+	} else {
+		ShutdownResult::TimedOut {
+			threads_still_running,
+		}
+	};
+	(result, scan_result)
+}
+
+/// Join `handle` if it finishes before `deadline`, otherwise give the handle back.
+/// A thread that panicked counts as finished and yields `None`.
+fn join_timeout<T>(handle: JoinHandle<T>, deadline: Instant) -> Result<Option<T>, JoinHandle<T>> {
+	while !handle.is_finished() {
+		if Instant::now() >= deadline {
+			return Err(handle);
+		}
+		std::thread::sleep(Duration::from_millis(10));
+	}
+	Ok(handle.join().ok())
+}
@@ -0,0 +1,40 @@
+// Export tracing spans to an OpenTelemetry collector over OTLP/HTTP
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use std::error::Error;
+use tracing_opentelemetry::OpenTelemetryLayer;
+
+/// Keeps the exporter alive; call [`OtlpGuard::shutdown`] before exiting to flush
+/// the spans still buffered
+pub struct OtlpGuard(SdkTracerProvider);
+
+impl OtlpGuard {
+	pub fn shutdown(self) {
+		if let Err(e) = self.0.shutdown() {
+			eprintln!("Failed to flush OpenTelemetry spans: {e}");
+		}
+	}
+}
+
+/// A `tracing` layer sending spans in batches to the OTLP/HTTP `endpoint`,
+/// e.g. `http://localhost:4318/v1/traces`
+pub fn otlp_layer<S>(
+	endpoint: &str,
+) -> Result<(OpenTelemetryLayer<S, SdkTracer>, OtlpGuard), Box<dyn Error>>
+where
+	S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+	let exporter = SpanExporter::builder()
+		.with_http()
+		.with_endpoint(endpoint)
+		.build()?;
+	let provider = SdkTracerProvider::builder()
+		.with_batch_exporter(exporter)
+		.with_resource(Resource::builder().with_service_name("linkfield").build())
+		.build();
+	let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("linkfield"));
+	Ok((layer, OtlpGuard(provider)))
+}
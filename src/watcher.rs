@@ -1,48 +1,346 @@
 // File system watcher and event handling logic will be moved here
 
-use crate::file_cache::FileCache;
-use crate::ignore_config::IgnoreConfig;
-use crate::move_heuristics::{FileEventKind, MoveHeuristics, make_file_event};
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use crate::change_journal::{ChangeJournal, JournalEntry};
+use crate::error::{LinkfieldError, LinkfieldResult};
+use crate::event_recorder::EventRecorder;
+use crate::events::{EventBroadcaster, EventReceiver};
+use crate::file_cache::event_stats::EventKind;
+use crate::file_cache::{FileCache, FileMeta};
+use crate::health::{AppStateTracker, HEARTBEAT_INTERVAL};
+use crate::hooks::{DEFAULT_HOOK_TIMEOUT, EventHooks};
+use crate::ignore_config::{IgnoreConfig, PatternChanges};
+use crate::move_heuristics::{
+	FileEvent, FileEventKind, MoveCandidate, MoveHeuristics, MoveHeuristicsStats, make_file_event,
+};
+use crate::platform;
+use crate::shutdown::CancellationToken;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread::JoinHandle;
 use std::time::Duration;
 use tracing::info;
 
+/// How often the event loop checks whether it has been asked to stop
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the event loop logs [`MoveHeuristicsStats`]
+const STATS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+type PathSet = Arc<RwLock<HashSet<PathBuf>>>;
+type NotifyDebouncer = notify_debouncer_full::Debouncer<
+	notify_debouncer_full::notify::RecommendedWatcher,
+	notify_debouncer_full::RecommendedCache,
+>;
+
+/// Owns the watcher thread started by [`start_watcher`]
+pub struct WatcherHandle {
+	stop: CancellationToken,
+	thread: JoinHandle<()>,
+	watched: PathSet,
+	failed: PathSet,
+	broadcaster: Arc<EventBroadcaster>,
+	heuristics: Arc<Mutex<MoveHeuristics>>,
+	file_cache: Arc<Mutex<Arc<FileCache>>>,
+}
+
+impl WatcherHandle {
+	/// Ask the event loop to exit. The underlying watcher is dropped when the thread ends.
+	pub fn stop(&self) {
+		self.stop.cancel();
+	}
+	pub fn is_finished(&self) -> bool {
+		self.thread.is_finished()
+	}
+	pub fn into_join_handle(self) -> JoinHandle<()> {
+		self.thread
+	}
+	/// Receive every [`WatchEvent`] from now on, after the built-in cache and move handling
+	/// and the [`WatchConfig`] callbacks have run
+	pub fn subscribe(&self) -> EventReceiver {
+		self.broadcaster.subscribe()
+	}
+	/// Time since the watched cache last changed, the `linkfield_cache_age_seconds` gauge.
+	/// See [`FileCache::cache_age`].
+	pub fn cache_age(&self) -> Duration {
+		current_cache(&self.file_cache).cache_age()
+	}
+	/// Current counters of the move heuristics used by the event loop
+	pub fn heuristics_stats(&self) -> MoveHeuristicsStats {
+		self.heuristics
+			.lock()
+			.map_or_else(|e| e.into_inner().statistics(), |h| h.statistics())
+	}
+	/// Paths currently registered with the underlying watcher
+	pub fn watched_paths(&self) -> HashSet<PathBuf> {
+		read_path_set(&self.watched)
+	}
+	/// Paths the watcher was asked to watch but could not
+	pub fn failed_paths(&self) -> HashSet<PathBuf> {
+		read_path_set(&self.failed)
+	}
+}
+
+fn read_path_set(set: &PathSet) -> HashSet<PathBuf> {
+	set.read()
+		.map_or_else(|e| e.into_inner().clone(), |set| set.clone())
+}
+
+/// Move `path` into the watched or failed set depending on whether watching it succeeded
+fn record_watch_result(watched: &PathSet, failed: &PathSet, path: &Path, ok: bool) {
+	let (add, remove) = if ok {
+		(watched, failed)
+	} else {
+		(failed, watched)
+	};
+	if let Ok(mut set) = remove.write() {
+		set.remove(path);
+	}
+	if let Ok(mut set) = add.write() {
+		set.insert(path.to_path_buf());
+	}
+}
+
+/// A file system change after the built-in cache and move handling has processed it
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchEvent {
+	Create(PathBuf),
+	Remove(PathBuf),
+	/// Renamed within the same directory
+	Rename {
+		from: PathBuf,
+		to: PathBuf,
+	},
+	/// Moved to another directory. OS-reported moves have a score of 1.0, moves inferred by
+	/// `MoveHeuristics` from a remove/create pair carry the heuristic score.
+	Move {
+		from: PathBuf,
+		to: PathBuf,
+		score: f64,
+	},
+}
+
+pub type PathCallback = Arc<dyn Fn(&Path) + Send + Sync>;
+pub type RenameCallback = Arc<dyn Fn(&Path, &Path) + Send + Sync>;
+pub type MoveCallback = Arc<dyn Fn(&Path, &Path, f64) + Send + Sync>;
+pub type EventCallback = Arc<dyn Fn(&WatchEvent) + Send + Sync>;
+
+/// User callbacks invoked on the watcher thread after the built-in handlers complete.
+///
+/// Callbacks run inline on the event loop, so they should be fast (1 ms or less) and must
+/// not block; hand longer work off to another thread. A create that `MoveHeuristics` pairs
+/// with an earlier remove is reported only as a move.
+#[derive(Clone)]
+pub struct WatchConfig {
+	pub on_create: Option<PathCallback>,
+	pub on_remove: Option<PathCallback>,
+	pub on_rename: Option<RenameCallback>,
+	pub on_move: Option<MoveCallback>,
+	/// Called for every event, after the specific callback
+	pub on_event: Option<EventCallback>,
+	/// Append every raw event, including ignored ones, to this file as JSON lines for
+	/// replaying with [`EventReplayer`](crate::event_recorder::EventReplayer)
+	pub record_events: Option<PathBuf>,
+	/// Watch each directory separately, shallowest first, and stop adding watches once this
+	/// many are in use instead of running into the inotify limit. `None` watches the whole
+	/// tree recursively.
+	pub max_inotify_watches: Option<usize>,
+	/// Reload the ignore patterns from this file whenever it changes, dropping the cached
+	/// files they now ignore and rescanning in the background for the ones they no longer
+	/// ignore
+	pub ignore_file: Option<PathBuf>,
+	/// Watch symlinked directories below the root when watching recursively, and watch a
+	/// symlinked root through its canonical path. Event paths are mapped back to the root as
+	/// given, so they match the cached paths.
+	pub follow_symlinks: bool,
+	/// Warn when the cache hasn't changed for five of these, see
+	/// [`FileCache::warn_if_stale`]. Checked as often as the heuristics stats are logged.
+	pub rescan_interval: Option<Duration>,
+	/// Shell commands run for creates, moves and removes by a small pool of worker threads,
+	/// see [`HookRunner`](crate::hooks::HookRunner)
+	pub hooks: EventHooks,
+	/// How long a hook may run before it is killed
+	pub hook_timeout: Duration,
+	/// Record each batch of events as one transaction, with the cached metadata from before
+	/// it, so it can be rolled back later. Each batch is written to the cache's attached
+	/// database right away; until one is attached the transactions stay queued.
+	pub journal: Option<Arc<ChangeJournal>>,
+	/// Refreshed every [`HEARTBEAT_INTERVAL`] from the event loop, so `linkfield health` can
+	/// tell a hung or dead watcher from a running one
+	pub app_state: Option<AppStateTracker>,
+	/// Reindex archives as their events arrive. The archives already there are indexed
+	/// with [`ArchiveWatcher::index_cached`](crate::file_cache::ArchiveWatcher::index_cached)
+	/// once the scan has found them.
+	#[cfg(feature = "archives")]
+	pub archives: Option<crate::file_cache::ArchiveWatcher>,
+}
+
+impl Default for WatchConfig {
+	fn default() -> Self {
+		Self {
+			on_create: None,
+			on_remove: None,
+			on_rename: None,
+			on_move: None,
+			on_event: None,
+			record_events: None,
+			max_inotify_watches: None,
+			ignore_file: None,
+			follow_symlinks: false,
+			rescan_interval: None,
+			hooks: EventHooks::default(),
+			hook_timeout: DEFAULT_HOOK_TIMEOUT,
+			journal: None,
+			app_state: None,
+			#[cfg(feature = "archives")]
+			archives: None,
+		}
+	}
+}
+
+impl WatchConfig {
+	fn dispatch(&self, event: &WatchEvent) {
+		match event {
+			WatchEvent::Create(path) => {
+				if let Some(cb) = &self.on_create {
+					cb(path);
+				}
+			}
+			WatchEvent::Remove(path) => {
+				if let Some(cb) = &self.on_remove {
+					cb(path);
+				}
+			}
+			WatchEvent::Rename { from, to } => {
+				if let Some(cb) = &self.on_rename {
+					cb(from, to);
+				}
+			}
+			WatchEvent::Move { from, to, score } => {
+				if let Some(cb) = &self.on_move {
+					cb(from, to, *score);
+				}
+			}
+		}
+		if let Some(cb) = &self.on_event {
+			cb(event);
+		}
+	}
+}
+
 pub fn start_watcher<P: AsRef<Path>>(
 	watch_path: P,
 	file_cache: Arc<Mutex<Arc<FileCache>>>,
 	heuristics: Arc<Mutex<MoveHeuristics>>,
-	ignore_config: Arc<IgnoreConfig>,
-) {
+	ignore_config: Arc<RwLock<IgnoreConfig>>,
+) -> WatcherHandle {
+	start_watcher_with_config(
+		watch_path,
+		file_cache,
+		heuristics,
+		ignore_config,
+		WatchConfig::default(),
+	)
+}
+
+/// Like [`start_watcher`], additionally invoking the callbacks in `config`
+pub fn start_watcher_with_config<P: AsRef<Path>>(
+	watch_path: P,
+	file_cache: Arc<Mutex<Arc<FileCache>>>,
+	heuristics: Arc<Mutex<MoveHeuristics>>,
+	ignore_config: Arc<RwLock<IgnoreConfig>>,
+	config: WatchConfig,
+) -> WatcherHandle {
 	let watch_path = watch_path.as_ref().to_path_buf();
 	info!("Watching directory: {}", watch_path.display());
 	info!("Initializing watcher...");
 	let (ready_tx, ready_rx) = std::sync::mpsc::channel();
 	let (tx, rx) = std::sync::mpsc::channel();
-	let heuristics_thread = heuristics;
-	let file_cache_thread = file_cache;
+	let heuristics_thread = heuristics.clone();
+	let file_cache_thread = file_cache.clone();
 	let watcher_setup_start = std::time::Instant::now();
-	std::thread::spawn(move || {
-		use std::collections::HashSet;
+	let stop = CancellationToken::new();
+	let stop_thread = stop.clone();
+	let watched = PathSet::default();
+	let failed = PathSet::default();
+	let watched_thread = watched.clone();
+	let failed_thread = failed.clone();
+	let broadcaster = EventBroadcaster::new();
+	let broadcaster_thread = broadcaster.clone();
+	let config = Arc::new(config);
+	// The built-in handling is the first subscriber, so it sees every event before the others
+	let config_handler = config.clone();
+	let hooks = config.hooks.runner(config.hook_timeout);
+	broadcaster.add_handler(Arc::new(move |event| {
+		config_handler.dispatch(event);
+		if let Some(hooks) = &hooks {
+			hooks.run(event);
+		}
+	}));
+	let thread = std::thread::spawn(move || {
+		// Subscribers see a disconnect however the thread exits
+		let _close_subscribers = CloseOnDrop(broadcaster_thread.clone());
 		let mut recently_moved: HashSet<std::path::PathBuf> = HashSet::new();
-		let mut debouncer =
-			match notify_debouncer_full::new_debouncer(Duration::from_millis(500), None, tx) {
-				Ok(d) => d,
-				Err(e) => {
-					tracing::error!("Failed to create debouncer: {e}");
-					return;
-				}
-			};
-		if let Err(e) = debouncer
-			.watch(
+		let mut recorder = config.record_events.as_deref().and_then(|path| {
+			EventRecorder::create(path)
+				.inspect_err(
+					|e| tracing::error!(path = %path.display(), error = %e, "Failed to open event recording"),
+				)
+				.ok()
+		});
+		let notify_config = notify_debouncer_full::notify::Config::default()
+			.with_follow_symlinks(config.follow_symlinks);
+		let mut debouncer = match notify_debouncer_full::new_debouncer_opt(
+			Duration::from_millis(500),
+			None,
+			tx,
+			notify_debouncer_full::RecommendedCache::new(),
+			notify_config,
+		) {
+			Ok(d) => d,
+			Err(e) => {
+				tracing::error!("Failed to create debouncer: {e}");
+				return;
+			}
+		};
+		let remap = config
+			.follow_symlinks
+			.then(|| RootRemap::new(&watch_path))
+			.flatten();
+		let watch_target = remap.as_ref().map_or(&watch_path, |remap| &remap.canonical);
+		let mut budget = config.max_inotify_watches.map(WatchBudget::new);
+		let watch_result = if let Some(budget) = &mut budget {
+			budget.watch_tree(
+				&mut debouncer,
 				&watch_path,
-				notify_debouncer_full::notify::RecursiveMode::Recursive,
+				&read_ignore(&ignore_config),
+				&watched_thread,
+				&failed_thread,
 			)
-			.map_err(std::io::Error::other)
-		{
+		} else {
+			let result = debouncer
+				.watch(
+					watch_target,
+					notify_debouncer_full::notify::RecursiveMode::Recursive,
+				)
+				.map_err(LinkfieldError::from);
+			record_watch_result(
+				&watched_thread,
+				&failed_thread,
+				watch_target,
+				result.is_ok(),
+			);
+			result
+		};
+		if let Err(e) = watch_result {
 			tracing::error!("Failed to start watcher: {e}");
 			return;
 		}
+		let ignore_file = config.ignore_file.as_deref().map(IgnoreFile::new);
+		if let Some(ignore_file) = &ignore_file {
+			ignore_file.watch(&mut debouncer, &watch_path, &watched_thread, &failed_thread);
+		}
+		tracing::debug!(watched_paths = ?read_path_set(&watched_thread), "Watch set");
 		// Signal ready after watcher is set up
 		if ready_tx.send(()).is_err() {
 			tracing::error!("Failed to signal ready");
@@ -53,64 +351,517 @@ pub fn start_watcher<P: AsRef<Path>>(
 			"[WatcherThread] Event loop started (setup took {:.2?})",
 			setup_elapsed
 		);
-		for result in rx {
+		let mut last_stats_log = std::time::Instant::now();
+		let mut last_heartbeat = std::time::Instant::now();
+		let mut stats_write = None;
+		loop {
+			if stop_thread.is_cancelled() {
+				info!("[WatcherThread] Stopping");
+				break;
+			}
+			if let Some(app_state) = &config.app_state
+				&& last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL
+			{
+				last_heartbeat = std::time::Instant::now();
+				app_state.heartbeat();
+			}
+			if last_stats_log.elapsed() >= STATS_LOG_INTERVAL {
+				last_stats_log = std::time::Instant::now();
+				log_heuristics_stats(&heuristics_thread);
+				persist_move_stats(&heuristics_thread, &file_cache_thread, &mut stats_write);
+				if let Some(interval) = config.rescan_interval {
+					current_cache(&file_cache_thread).warn_if_stale(interval);
+				}
+			}
+			let result = match rx.recv_timeout(STOP_POLL_INTERVAL) {
+				Ok(result) => result,
+				Err(RecvTimeoutError::Timeout) => continue,
+				Err(RecvTimeoutError::Disconnected) => break,
+			};
 			match result {
 				Ok(events) => {
-					for event in events {
-						// Skip events for paths matching ignore_config
-						if event
-							.event
-							.paths
-							.iter()
-							.any(|p| ignore_config.is_ignored(p))
+					let mut handled = Vec::with_capacity(events.len());
+					let mut reload = false;
+					let ignore = read_ignore(&ignore_config);
+					for mut event in events {
+						if let Some(recorder) = &mut recorder
+							&& let Err(e) = recorder.record(&event)
+						{
+							tracing::warn!(error = %e, "Failed to record event");
+						}
+						if let Some(remap) = &remap {
+							remap.apply(&mut event.event.paths);
+						}
+						if let Some(ignore_file) = &ignore_file
+							&& !event.kind.is_access()
+							&& event.paths.iter().any(|p| ignore_file.matches(p))
 						{
+							reload = true;
+						}
+						// Skip events for paths matching ignore_config
+						if event.event.paths.iter().any(|p| ignore.is_ignored(p)) {
 							continue;
 						}
-						handle_event(
-							&event,
+						if let Some(budget) = &mut budget
+							&& event.kind.is_create()
+						{
+							for dir in event.paths.iter().filter(|p| p.is_dir()) {
+								if let Err(e) = budget.watch_tree(
+									&mut debouncer,
+									dir,
+									&ignore,
+									&watched_thread,
+									&failed_thread,
+								) {
+									tracing::warn!(path = %dir.display(), error = %e, "Failed to watch new directory");
+								}
+							}
+						}
+						handled.push(event);
+					}
+					drop(ignore);
+					let before = config
+						.journal
+						.as_ref()
+						.map(|_| cached_before(&handled, &file_cache_thread));
+					let mut transaction = Vec::new();
+					for watch_event in handle_batch(
+						&handled,
+						&file_cache_thread,
+						&heuristics_thread,
+						&mut recently_moved,
+					) {
+						if let Some(before) = &before {
+							transaction.push(JournalEntry::from_event(&watch_event, before));
+						}
+						broadcaster_thread.publish(watch_event);
+					}
+					if let Some(journal) = &config.journal {
+						journal.record(transaction);
+						if let Some(db) = current_cache(&file_cache_thread).database()
+							&& let Err(e) = journal.flush(&db)
+						{
+							tracing::warn!(error = %e, "Failed to write change journal");
+						}
+					}
+					#[cfg(feature = "archives")]
+					if let Some(archives) = &config.archives {
+						archives.handle_events(&current_cache(&file_cache_thread), &handled);
+					}
+					if let Some(ignore_file) = &ignore_file
+						&& reload && let Some(changes) = ignore_file.reload(&ignore_config)
+					{
+						apply_ignore_changes(
+							&changes,
+							&watch_path,
 							&file_cache_thread,
-							&heuristics_thread,
-							&mut recently_moved,
+							&ignore_config,
+							&stop_thread,
 						);
+						if let Some(budget) = &mut budget
+							&& !changes.is_empty()
+						{
+							// Directories that are no longer ignored need watches
+							let result = budget.watch_tree(
+								&mut debouncer,
+								&watch_path,
+								&read_ignore(&ignore_config),
+								&watched_thread,
+								&failed_thread,
+							);
+							if let Err(e) = result {
+								tracing::warn!(error = %e, "Failed to watch directories");
+							}
+						}
 					}
 				}
 				Err(e) => tracing::warn!("Watcher error: {e:?}"),
 			}
 		}
+		// Counters since the last tick, written before the database is released on shutdown
+		persist_move_stats(&heuristics_thread, &file_cache_thread, &mut stats_write);
+		if let Some(write) = stats_write
+			&& write.join().is_err()
+		{
+			tracing::error!("Move stats writer panicked");
+		}
 	});
 	if let Err(e) = ready_rx.recv() {
 		tracing::error!("Watcher thread failed to initialize: {e}");
+	} else {
+		info!("Watcher ready. Try renaming, creating, or deleting files in this directory.");
+	}
+	WatcherHandle {
+		stop,
+		thread,
+		watched,
+		failed,
+		broadcaster,
+		heuristics,
+		file_cache,
+	}
+}
+
+/// Non-recursive watches added one directory at a time within
+/// [`WatchConfig::max_inotify_watches`]
+struct WatchBudget {
+	max: usize,
+	added: usize,
+}
+
+impl WatchBudget {
+	const fn new(max: usize) -> Self {
+		Self { max, added: 0 }
+	}
+
+	/// Watches in use: the kernel's count on Linux, otherwise the ones added here
+	fn in_use(&self) -> usize {
+		platform::inotify_watch_count().unwrap_or(self.added)
+	}
+
+	/// Watch `root` and the directories below it, shallowest first, until the budget is
+	/// used up. Fails only when `root` itself can't be watched.
+	fn watch_tree(
+		&mut self,
+		debouncer: &mut NotifyDebouncer,
+		root: &Path,
+		ignore: &IgnoreConfig,
+		watched: &PathSet,
+		failed: &PathSet,
+	) -> LinkfieldResult<()> {
+		let mut unwatched = Vec::new();
+		for dir in directories_by_depth(root, ignore) {
+			if watched.read().is_ok_and(|set| set.contains(&dir)) {
+				continue;
+			}
+			if self.in_use() >= self.max {
+				unwatched.push(dir);
+				continue;
+			}
+			let result = debouncer.watch(
+				&dir,
+				notify_debouncer_full::notify::RecursiveMode::NonRecursive,
+			);
+			record_watch_result(watched, failed, &dir, result.is_ok());
+			match result {
+				Ok(()) => self.added += 1,
+				Err(e) if dir == root => return Err(e.into()),
+				Err(e) => {
+					tracing::warn!(path = %dir.display(), error = %e, "Failed to watch directory")
+				}
+			}
+		}
+		if !unwatched.is_empty() {
+			tracing::warn!(
+				max_watches = self.max,
+				unwatched = unwatched.len(),
+				directories = ?unwatched,
+				"Watch budget reached, changes in these directories will be missed"
+			);
+		}
+		Ok(())
+	}
+}
+
+fn read_ignore(
+	ignore_config: &RwLock<IgnoreConfig>,
+) -> std::sync::RwLockReadGuard<'_, IgnoreConfig> {
+	ignore_config.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The file [`WatchConfig::ignore_file`] reloads the ignore patterns from
+struct IgnoreFile {
+	path: PathBuf,
+	/// Canonical parent directory, to recognise the file however event paths spell it
+	dir: Option<PathBuf>,
+}
+
+impl IgnoreFile {
+	fn new(path: &Path) -> Self {
+		Self {
+			path: path.to_path_buf(),
+			dir: path.parent().and_then(|dir| dir.canonicalize().ok()),
+		}
+	}
+
+	fn matches(&self, path: &Path) -> bool {
+		path == self.path
+			|| (path.file_name() == self.path.file_name()
+				&& self.dir.is_some()
+				&& path.parent().and_then(|dir| dir.canonicalize().ok()) == self.dir)
+	}
+
+	/// Watch the directory holding the file, which replacing it on save keeps intact, unless
+	/// the watch on `watch_root` already covers it
+	fn watch(
+		&self,
+		debouncer: &mut NotifyDebouncer,
+		watch_root: &Path,
+		watched: &PathSet,
+		failed: &PathSet,
+	) {
+		let Some(dir) = &self.dir else {
+			tracing::warn!(path = %self.path.display(), "Ignore file directory not found, changes will be missed");
+			return;
+		};
+		if watch_root
+			.canonicalize()
+			.is_ok_and(|root| dir.starts_with(root))
+		{
+			return;
+		}
+		let result = debouncer.watch(
+			dir,
+			notify_debouncer_full::notify::RecursiveMode::NonRecursive,
+		);
+		record_watch_result(watched, failed, dir, result.is_ok());
+		if let Err(e) = result {
+			tracing::warn!(path = %dir.display(), error = %e, "Failed to watch ignore file directory");
+		}
+	}
+
+	fn reload(&self, ignore_config: &RwLock<IgnoreConfig>) -> Option<PatternChanges> {
+		let mut config = ignore_config
+			.write()
+			.unwrap_or_else(PoisonError::into_inner);
+		match config.reload_from_file(&self.path) {
+			Ok(changes) => {
+				info!(
+					path = %self.path.display(),
+					patterns = ?config.patterns(),
+					added = ?changes.added,
+					removed = ?changes.removed,
+					"Reloaded ignore patterns"
+				);
+				Some(changes)
+			}
+			Err(e) => {
+				tracing::warn!(path = %self.path.display(), error = %e, "Failed to reload ignore patterns, keeping the previous ones");
+				None
+			}
+		}
+	}
+}
+
+/// Drop the cached files that reloaded patterns now ignore, and rescan for the ones they no
+/// longer ignore on a background thread. Negated patterns work the other way round.
+fn apply_ignore_changes(
+	changes: &PatternChanges,
+	watch_root: &Path,
+	file_cache: &Arc<Mutex<Arc<FileCache>>>,
+	ignore_config: &Arc<RwLock<IgnoreConfig>>,
+	stop: &CancellationToken,
+) {
+	let negated = |patterns: &[String]| patterns.iter().any(|p| p.starts_with('!'));
+	let Ok(cache) = file_cache.lock().map(|cache| cache.clone()) else {
+		tracing::error!("Failed to lock file_cache for ignore reload");
+		return;
+	};
+	if !changes.added.is_empty() || negated(&changes.removed) {
+		let removed = cache.remove_ignored(watch_root, &read_ignore(ignore_config));
+		info!(removed, "Removed newly ignored files from cache");
+	}
+	// What the old patterns ignored that the new ones may not
+	let unignored: Vec<&str> = changes
+		.removed
+		.iter()
+		.filter(|p| !p.starts_with('!'))
+		.map(String::as_str)
+		.chain(changes.added.iter().filter_map(|p| p.strip_prefix('!')))
+		.collect();
+	if unignored.is_empty() {
+		return;
+	}
+	let previously_ignored = match IgnoreConfig::new(&unignored) {
+		Ok(config) => config,
+		Err(e) => {
+			tracing::warn!(error = %e, "Failed to match the removed ignore patterns");
+			return;
+		}
+	};
+	let (root, ignore_config, stop) = (
+		watch_root.to_path_buf(),
+		ignore_config.clone(),
+		stop.clone(),
+	);
+	std::thread::spawn(move || {
+		add_unignored_files(&cache, &root, &previously_ignored, &ignore_config, &stop);
+	});
+}
+
+/// Add the files below `root` that `previously_ignored` matches, themselves or through one
+/// of their directories, and the current patterns don't. Only their directories are read
+/// in full; elsewhere just the matching files are looked at.
+fn add_unignored_files(
+	cache: &FileCache,
+	root: &Path,
+	previously_ignored: &IgnoreConfig,
+	ignore_config: &RwLock<IgnoreConfig>,
+	stop: &CancellationToken,
+) {
+	let ignore = read_ignore(ignore_config);
+	let mut unignored = Vec::new();
+	for dir in directories_by_depth(root, &ignore) {
+		if stop.is_cancelled() {
+			return;
+		}
+		let whole_dir = dir != root && previously_ignored.is_ignored_below(root, &dir, true);
+		let Ok(entries) = std::fs::read_dir(&dir) else {
+			continue;
+		};
+		for entry in entries.filter_map(Result::ok) {
+			let path = entry.path();
+			if entry.file_type().is_ok_and(|t| t.is_file())
+				&& (whole_dir || previously_ignored.is_ignored_by_pattern(&path, false))
+				&& !ignore.is_ignored(&path)
+			{
+				unignored.push(path);
+			}
+		}
+	}
+	let added = cache.update_files(&unignored);
+	info!(added, "Added files that are no longer ignored to cache");
+}
+
+/// Maps event paths under the canonical path of a symlinked watch root back to the root as
+/// given
+struct RootRemap {
+	canonical: PathBuf,
+	given: PathBuf,
+}
+
+impl RootRemap {
+	/// `None` when `given` already is its canonical path
+	fn new(given: &Path) -> Option<Self> {
+		let canonical = given.canonicalize().ok()?;
+		(canonical != given).then(|| Self {
+			canonical,
+			given: given.to_path_buf(),
+		})
+	}
+
+	fn apply(&self, paths: &mut [PathBuf]) {
+		for path in paths {
+			if let Ok(rest) = path.strip_prefix(&self.canonical) {
+				*path = if rest.as_os_str().is_empty() {
+					self.given.clone()
+				} else {
+					self.given.join(rest)
+				};
+			}
+		}
+	}
+}
+
+/// `root` and every directory below it that is not ignored, sorted by depth and then by
+/// path. Symlinks are not followed.
+fn directories_by_depth(root: &Path, ignore: &IgnoreConfig) -> Vec<PathBuf> {
+	let mut dirs = vec![root.to_path_buf()];
+	let mut next = 0;
+	while next < dirs.len() {
+		let Ok(entries) = std::fs::read_dir(&dirs[next]) else {
+			next += 1;
+			continue;
+		};
+		let mut children: Vec<_> = entries
+			.filter_map(Result::ok)
+			.filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+			.map(|entry| entry.path())
+			.filter(|path| !ignore.is_ignored(path))
+			.collect();
+		children.sort();
+		dirs.extend(children);
+		next += 1;
+	}
+	dirs
+}
+
+fn log_heuristics_stats(heuristics: &Arc<Mutex<MoveHeuristics>>) {
+	let Ok(heuristics) = heuristics.lock() else {
+		return;
+	};
+	let stats = heuristics.statistics();
+	info!(
+		removes = stats.total_removes_received,
+		creates = stats.total_creates_received,
+		moves = stats.moves_detected,
+		expired = stats.removes_expired,
+		pending = stats.pending_removes,
+		avg_score = stats.avg_score,
+		min_score = stats.min_score_seen,
+		max_score = stats.max_score_seen,
+		"Move heuristics statistics"
+	);
+}
+
+/// Add the move counters gathered since the last call to the `move_stats` table of the
+/// attached database, on a background thread so the event loop doesn't wait for the write.
+/// Skipped while the previous write is still running; failed deltas go into the next one.
+fn persist_move_stats(
+	heuristics: &Arc<Mutex<MoveHeuristics>>,
+	file_cache: &Mutex<Arc<FileCache>>,
+	pending: &mut Option<JoinHandle<()>>,
+) {
+	if pending.as_ref().is_some_and(|write| !write.is_finished()) {
+		return;
+	}
+	let Some(db) = current_cache(file_cache).database() else {
+		return;
+	};
+	let deltas = heuristics
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.take_stat_deltas();
+	if deltas == [0; 5] {
 		return;
 	}
-	info!("Watcher ready. Try renaming, creating, or deleting files in this directory.");
+	let heuristics = Arc::clone(heuristics);
+	*pending = Some(std::thread::spawn(move || {
+		if let Err(e) = MoveHeuristics::add_stats_to_redb(&db, deltas) {
+			tracing::warn!(error = %e, "Failed to save move stats");
+			heuristics
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner)
+				.restore_stat_deltas(deltas);
+		}
+	}));
+}
+
+struct CloseOnDrop(Arc<EventBroadcaster>);
+
+impl Drop for CloseOnDrop {
+	fn drop(&mut self) {
+		self.0.close();
+	}
 }
 
 fn handle_remove_event(
 	event: &notify_debouncer_full::DebouncedEvent,
 	file_cache_thread: &Arc<Mutex<Arc<FileCache>>>,
 	heuristics_thread: &Arc<Mutex<MoveHeuristics>>,
-) {
-	let path = event.event.paths.first().cloned();
-	if let Some(path) = path {
-		let meta = match file_cache_thread.lock() {
-			Ok(guard) => guard.get(&path).map(|m| m.clone()),
-			Err(e) => {
-				tracing::error!(error = %e, "Failed to lock file_cache");
-				None
-			}
-		};
-		let file_event = make_file_event(path.clone(), FileEventKind::Remove, meta);
-		if let Ok(mut heuristics) = heuristics_thread.lock() {
-			heuristics.add_remove(file_event);
+) -> Option<WatchEvent> {
+	let path = event.event.paths.first().cloned()?;
+	let meta = cached_meta(file_cache_thread, &path);
+	let mut file_event = make_file_event(path.clone(), FileEventKind::Remove, meta);
+	// The file can't be read anymore, only a signature kept from before helps pairing it
+	file_event.minhash = current_cache(file_cache_thread).take_signature(&path);
+	if let Ok(mut heuristics) = heuristics_thread.lock() {
+		heuristics.add_remove(file_event);
+	} else {
+		tracing::error!("Failed to lock heuristics for remove");
+	}
+	if let Ok(cache) = file_cache_thread.lock() {
+		if cache.contains_directory(&path) {
+			let removed = cache.remove_prefix(None, &path);
+			tracing::debug!(path = %path.display(), removed, "Removed directory from cache");
 		} else {
-			tracing::error!("Failed to lock heuristics for remove");
-		}
-		if let Ok(cache) = file_cache_thread.lock() {
 			cache.remove_file(&path);
-		} else {
-			tracing::error!("Failed to lock file_cache for remove_file");
+			cache.record_event(EventKind::Remove);
 		}
+	} else {
+		tracing::error!("Failed to lock file_cache for remove_file");
 	}
+	Some(WatchEvent::Remove(path))
 }
 
 fn handle_create_event(
@@ -118,43 +869,105 @@ fn handle_create_event(
 	file_cache_thread: &Arc<Mutex<Arc<FileCache>>>,
 	heuristics_thread: &Arc<Mutex<MoveHeuristics>>,
 	recently_moved: &mut std::collections::HashSet<std::path::PathBuf>,
-) {
-	let path = event.event.paths.first().cloned();
-	if let Some(path) = path {
-		if let Ok(cache) = file_cache_thread.lock() {
-			cache.update_file(&path);
-		} else {
-			tracing::error!("Failed to lock file_cache for update_file");
+) -> Option<WatchEvent> {
+	let file_event = record_create(event, file_cache_thread, use_minhash(heuristics_thread))?;
+	let pair = match heuristics_thread.lock() {
+		Ok(mut heuristics) => heuristics.pair_create(&file_event),
+		Err(e) => {
+			tracing::error!(error = %e, "Failed to lock heuristics for pair_create");
+			None
 		}
-		let meta = match file_cache_thread.lock() {
-			Ok(guard) => guard.get(&path).map(|m| m.clone()),
-			Err(e) => {
-				tracing::error!(error = %e, "Failed to lock file_cache");
-				None
-			}
-		};
-		let file_event = make_file_event(path.clone(), FileEventKind::Create, meta);
-		let pair = match heuristics_thread.lock() {
-			Ok(mut heuristics) => heuristics.pair_create(&file_event),
-			Err(e) => {
-				tracing::error!(error = %e, "Failed to lock heuristics for pair_create");
-				None
+	};
+	Some(create_outcome(file_event.path, pair, recently_moved))
+}
+
+/// Like [`handle_create_event`] for consecutive creates from one debounce batch, pairing
+/// them with pending removes together
+fn handle_create_batch(
+	events: &[notify_debouncer_full::DebouncedEvent],
+	file_cache_thread: &Arc<Mutex<Arc<FileCache>>>,
+	heuristics_thread: &Arc<Mutex<MoveHeuristics>>,
+	recently_moved: &mut std::collections::HashSet<std::path::PathBuf>,
+) -> Vec<WatchEvent> {
+	let use_minhash = use_minhash(heuristics_thread);
+	let file_events: Vec<_> = events
+		.iter()
+		.filter_map(|event| record_create(event, file_cache_thread, use_minhash))
+		.collect();
+	let pairs = match heuristics_thread.lock() {
+		Ok(mut heuristics) => heuristics.pair_batch(&file_events),
+		Err(e) => {
+			tracing::error!(error = %e, "Failed to lock heuristics for pair_batch");
+			vec![None; file_events.len()]
+		}
+	};
+	file_events
+		.into_iter()
+		.zip(pairs)
+		.map(|(file_event, pair)| create_outcome(file_event.path, pair, recently_moved))
+		.collect()
+}
+
+/// Add a created path to the cache and build the Create event for move pairing, with its
+/// MinHash signature when `use_minhash` is set
+fn record_create(
+	event: &notify_debouncer_full::DebouncedEvent,
+	file_cache_thread: &Arc<Mutex<Arc<FileCache>>>,
+	use_minhash: bool,
+) -> Option<FileEvent> {
+	let path = event.event.paths.first().cloned()?;
+	let mut signature = None;
+	if let Ok(cache) = file_cache_thread.lock() {
+		if path.is_dir() {
+			cache.track_directory(&path);
+		} else {
+			cache.update_file(&path);
+			cache.record_event(EventKind::Create);
+			if use_minhash {
+				signature = cache.update_signature(&path);
 			}
-		};
-		if let Some(pair) = pair {
-			tracing::info!(from = %pair.from.path.display(), to = %pair.to.path.display(), score = pair.score, "Move detected");
-			recently_moved.insert(pair.to.path);
-			return;
 		}
-		tracing::info!(path = %path.display(), "Create");
+	} else {
+		tracing::error!("Failed to lock file_cache for update_file");
 	}
+	let meta = cached_meta(file_cache_thread, &path);
+	let mut file_event = make_file_event(path, FileEventKind::Create, meta);
+	file_event.minhash = signature;
+	Some(file_event)
+}
+
+/// Whether the move heuristics pair by [`MinHash`](crate::minhash::MinHash) signatures,
+/// see [`MoveHeuristicsConfig::use_minhash`](crate::move_heuristics::MoveHeuristicsConfig::use_minhash)
+fn use_minhash(heuristics: &Mutex<MoveHeuristics>) -> bool {
+	heuristics
+		.lock()
+		.is_ok_and(|heuristics| heuristics.config.use_minhash)
+}
+
+/// A move when the create was paired with a remove, otherwise a plain create
+fn create_outcome(
+	path: PathBuf,
+	pair: Option<MoveCandidate>,
+	recently_moved: &mut std::collections::HashSet<std::path::PathBuf>,
+) -> WatchEvent {
+	if let Some(pair) = pair {
+		tracing::info!(from = %pair.from.path.display(), to = %pair.to.path.display(), score = pair.score, "Move detected");
+		recently_moved.insert(pair.to.path.clone());
+		return WatchEvent::Move {
+			from: pair.from.path,
+			to: pair.to.path,
+			score: pair.score,
+		};
+	}
+	tracing::info!(path = %path.display(), "Create");
+	WatchEvent::Create(path)
 }
 
 fn handle_modify_name_event(
 	event: &notify_debouncer_full::DebouncedEvent,
 	file_cache_thread: &Arc<Mutex<Arc<FileCache>>>,
 	recently_moved: &mut std::collections::HashSet<std::path::PathBuf>,
-) {
+) -> Option<WatchEvent> {
 	let paths = &event.event.paths;
 	match paths.len() {
 		2 => {
@@ -162,45 +975,194 @@ fn handle_modify_name_event(
 			let to = &paths[1];
 			let old_parent = from.parent();
 			let new_parent = to.parent();
-			if old_parent == new_parent {
+			let watch_event = if old_parent == new_parent {
 				tracing::info!(from = %from.display(), to = %to.display(), "Rename");
+				WatchEvent::Rename {
+					from: from.clone(),
+					to: to.clone(),
+				}
 			} else {
 				tracing::info!(from = %from.display(), to = %to.display(), "Move");
-			}
+				WatchEvent::Move {
+					from: from.clone(),
+					to: to.clone(),
+					score: 1.0,
+				}
+			};
 			if let Ok(cache) = file_cache_thread.lock() {
-				cache.remove_file(from);
-				cache.update_file(to);
+				// Directories and cross-directory moves carry their cached subtree along
+				if cache.contains_directory(from) || old_parent != new_parent {
+					let moved = cache.apply_rename(None, from, to);
+					tracing::debug!(moved, "Moved cached entries");
+				} else {
+					cache.remove_file(from);
+				}
+				cache.move_signature(from, to);
+				if to.is_dir() {
+					cache.track_directory(to);
+				} else {
+					cache.update_file(to);
+					cache.record_event(EventKind::Rename);
+				}
 			} else {
 				tracing::error!("Failed to lock file_cache for rename/move");
 			}
 			recently_moved.insert(to.clone());
+			Some(watch_event)
 		}
 		1 => {
 			tracing::info!(path = %paths[0].display(), "Rename/Move event (single path)");
+			None
 		}
 		_ => {
 			tracing::info!(?paths, "Rename/Move event with unexpected paths");
+			None
 		}
 	}
 }
 
+/// Refresh the cached metadata of modified files, and their signatures when `use_minhash`
+/// is set
+fn handle_modify_data_event(
+	event: &notify_debouncer_full::DebouncedEvent,
+	file_cache_thread: &Arc<Mutex<Arc<FileCache>>>,
+	use_minhash: bool,
+) {
+	let Ok(cache) = file_cache_thread.lock() else {
+		tracing::error!("Failed to lock file_cache for update_file");
+		return;
+	};
+	for path in &event.event.paths {
+		if path.ends_with("linkfield.redb") || !path.is_file() {
+			continue;
+		}
+		tracing::debug!(path = %path.display(), "Modify");
+		cache.update_file(path);
+		cache.record_event(EventKind::Modify);
+		if use_minhash {
+			cache.update_signature(path);
+		}
+	}
+}
+
+/// Run `events` through the same cache and move handling as the live watcher, e.g. events
+/// from an [`EventReplayer`](crate::event_recorder::EventReplayer). Ignore patterns are not
+/// applied.
+pub fn replay_events(
+	events: impl IntoIterator<Item = notify_debouncer_full::DebouncedEvent>,
+	file_cache: &Arc<Mutex<Arc<FileCache>>>,
+	heuristics: &Arc<Mutex<MoveHeuristics>>,
+) -> Vec<WatchEvent> {
+	let mut recently_moved = HashSet::new();
+	events
+		.into_iter()
+		.filter_map(|event| handle_event(&event, file_cache, heuristics, &mut recently_moved))
+		.collect()
+}
+
+/// The cache the watcher currently updates
+fn current_cache(file_cache: &Mutex<Arc<FileCache>>) -> Arc<FileCache> {
+	file_cache
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.clone()
+}
+
+/// The cached metadata of `path`, `None` when it isn't cached or the cache can't be locked
+fn cached_meta(file_cache: &Mutex<Arc<FileCache>>, path: &Path) -> Option<FileMeta> {
+	match file_cache.lock() {
+		Ok(guard) => guard.get(path),
+		Err(e) => {
+			tracing::error!(error = %e, "Failed to lock file_cache");
+			None
+		}
+	}
+}
+
+/// The cached metadata of every path in `events`, taken before they are applied. Paths
+/// missing from memory are looked up in the attached database, which is where watch mode
+/// keeps the scanned files.
+fn cached_before(
+	events: &[notify_debouncer_full::DebouncedEvent],
+	file_cache: &Mutex<Arc<FileCache>>,
+) -> HashMap<PathBuf, FileMeta> {
+	let cache = current_cache(file_cache);
+	let mut before = HashMap::new();
+	let mut missing = Vec::new();
+	for path in events.iter().flat_map(|event| &event.paths) {
+		match cache.get(path) {
+			Some(meta) => {
+				before.insert(path.clone(), meta);
+			}
+			None => missing.push(path.as_path()),
+		}
+	}
+	if let Some(db) = cache.database().filter(|_| !missing.is_empty()) {
+		match crate::file_cache::db::load_stored_metas(&db, missing) {
+			Ok(stored) => before.extend(stored.into_iter().map(|meta| (meta.path.0.clone(), meta))),
+			Err(e) => tracing::warn!(error = %e, "Failed to read stored metadata for the journal"),
+		}
+	}
+	before
+}
+
+/// Handle one debounce batch in order. Runs of two or more creates are paired with pending
+/// removes together through [`MoveHeuristics::pair_batch`].
+fn handle_batch(
+	events: &[notify_debouncer_full::DebouncedEvent],
+	file_cache_thread: &Arc<Mutex<Arc<FileCache>>>,
+	heuristics_thread: &Arc<Mutex<MoveHeuristics>>,
+	recently_moved: &mut std::collections::HashSet<std::path::PathBuf>,
+) -> Vec<WatchEvent> {
+	let mut watch_events = Vec::new();
+	let mut start = 0;
+	while start < events.len() {
+		let creates = events[start..]
+			.iter()
+			.take_while(|event| event.event.kind.is_create())
+			.count();
+		if creates > 1 {
+			watch_events.extend(handle_create_batch(
+				&events[start..start + creates],
+				file_cache_thread,
+				heuristics_thread,
+				recently_moved,
+			));
+			start += creates;
+		} else {
+			watch_events.extend(handle_event(
+				&events[start],
+				file_cache_thread,
+				heuristics_thread,
+				recently_moved,
+			));
+			start += 1;
+		}
+	}
+	watch_events
+}
+
 fn handle_event(
 	event: &notify_debouncer_full::DebouncedEvent,
 	file_cache_thread: &Arc<Mutex<Arc<FileCache>>>,
 	heuristics_thread: &Arc<Mutex<MoveHeuristics>>,
 	recently_moved: &mut std::collections::HashSet<std::path::PathBuf>,
-) {
+) -> Option<WatchEvent> {
 	match &event.event.kind {
 		notify_debouncer_full::notify::event::EventKind::Remove(_) => {
-			handle_remove_event(event, file_cache_thread, heuristics_thread);
+			handle_remove_event(event, file_cache_thread, heuristics_thread)
 		}
 		notify_debouncer_full::notify::event::EventKind::Create(_) => {
-			handle_create_event(event, file_cache_thread, heuristics_thread, recently_moved);
+			handle_create_event(event, file_cache_thread, heuristics_thread, recently_moved)
 		}
 		notify_debouncer_full::notify::event::EventKind::Modify(
 			notify_debouncer_full::notify::event::ModifyKind::Name(_),
+		) => handle_modify_name_event(event, file_cache_thread, recently_moved),
+		notify_debouncer_full::notify::event::EventKind::Modify(
+			notify_debouncer_full::notify::event::ModifyKind::Data(_),
 		) => {
-			handle_modify_name_event(event, file_cache_thread, recently_moved);
+			handle_modify_data_event(event, file_cache_thread, use_minhash(heuristics_thread));
+			None
 		}
 		_ => {
 			let paths = &event.event.paths;
@@ -216,9 +1178,10 @@ fn handle_event(
 				)
 			) && is_dir_event
 			{
-				return;
+				return None;
 			}
 			tracing::info!(?event, "Event");
+			None
 		}
 	}
 }
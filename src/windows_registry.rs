@@ -1,9 +1,10 @@
 use std::ffi::OsStr;
 use std::os::windows::ffi::OsStrExt;
 use tracing::{info, info_span};
+use windows::Win32::Foundation::ERROR_FILE_NOT_FOUND;
 use windows::Win32::System::Registry::{
 	HKEY, HKEY_CURRENT_USER, KEY_QUERY_VALUE, KEY_SET_VALUE, REG_OPTION_NON_VOLATILE, REG_SZ,
-	RegCloseKey, RegCreateKeyExW, RegOpenKeyExW, RegQueryValueExW, RegSetValueExW,
+	RegCloseKey, RegCreateKeyExW, RegDeleteKeyExW, RegOpenKeyExW, RegQueryValueExW, RegSetValueExW,
 };
 use windows::Win32::UI::Shell::{SHCNE_ASSOCCHANGED, SHCNF_IDLIST, SHChangeNotify};
 use windows::core::PCWSTR;
@@ -47,6 +48,43 @@ pub fn register_redb_extension(_all_users: bool) -> std::io::Result<()> {
 	Ok(())
 }
 
+/// Remove the keys written by [`register_redb_extension`]. Keys that are already gone are
+/// skipped.
+#[cfg(windows)]
+pub fn unregister_redb_extension() -> std::io::Result<()> {
+	let span = info_span!("unregister_redb_extension");
+	let _enter = span.enter();
+
+	let prog_id = "Linkfield.redb";
+	let hkcu = HKEY_CURRENT_USER;
+	// Leaf first, a key with subkeys can't be deleted
+	for path in [
+		format!(r"Software\Classes\{prog_id}\shell\open\command"),
+		format!(r"Software\Classes\{prog_id}\shell\open"),
+		format!(r"Software\Classes\{prog_id}\shell"),
+		format!(r"Software\Classes\{prog_id}\DefaultIcon"),
+		format!(r"Software\Classes\{prog_id}"),
+		r"Software\Classes\.redb".to_string(),
+	] {
+		delete_registry_key(hkcu, &path)?;
+	}
+	notify_shell_assoc_changed();
+	info!("Unregistered .redb extension");
+	Ok(())
+}
+
+fn delete_registry_key(hkey: HKEY, path: &str) -> std::io::Result<()> {
+	let span = info_span!("delete_registry_key", path = path);
+	let _enter = span.enter();
+	let key_path = to_wide(path);
+	let result = unsafe { RegDeleteKeyExW(hkey, PCWSTR(key_path.as_ptr()), 0, None) };
+	if result.is_ok() || result == ERROR_FILE_NOT_FOUND {
+		Ok(())
+	} else {
+		Err(std::io::Error::from_raw_os_error(result.0 as i32))
+	}
+}
+
 fn set_registry_value(hkey: windows::Win32::System::Registry::HKEY, path: &str, value: &str) {
 	let span = info_span!("set_registry_value", path = path, value = value);
 	let _enter = span.enter();
@@ -139,3 +177,18 @@ pub fn is_redb_registered() -> bool {
 	}
 	false
 }
+
+#[cfg(all(test, windows))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_unregister_removes_registration() {
+		register_redb_extension(false).unwrap();
+		assert!(is_redb_registered());
+		unregister_redb_extension().unwrap();
+		assert!(!is_redb_registered());
+		// Nothing left to delete is not an error
+		unregister_redb_extension().unwrap();
+	}
+}
@@ -0,0 +1,178 @@
+//! Integration tests: zip contents as virtual files. Run with `--features archives`.
+#![cfg(feature = "archives")]
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::{ArchiveWatcher, FileCache};
+use linkfield::ignore_config::IgnoreConfig;
+use linkfield::move_heuristics::MoveHeuristics;
+use linkfield::watcher::{WatchConfig, start_watcher_with_config};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use zip::write::SimpleFileOptions;
+
+/// Write a zip at `path` holding `files` as `(name, contents)`, plus an empty directory
+fn write_zip(path: &Path, files: &[(&str, &str)]) {
+	let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+	let options = SimpleFileOptions::default()
+		.last_modified_time(zip::DateTime::from_date_and_time(2024, 2, 29, 12, 30, 0).unwrap());
+	zip.add_directory("empty/", options).unwrap();
+	for (name, contents) in files {
+		zip.start_file(*name, options).unwrap();
+		zip.write_all(contents.as_bytes()).unwrap();
+	}
+	zip.finish().unwrap();
+}
+
+fn virtual_sizes(cache: &FileCache) -> BTreeMap<PathBuf, u64> {
+	cache
+		.virtual_files()
+		.into_iter()
+		.map(|meta| (meta.path.0, meta.size))
+		.collect()
+}
+
+#[test]
+fn test_index_archive_diffs_virtual_entries() {
+	let vfs = VirtualFs::new();
+	let archive = vfs.path("bundle.zip");
+	write_zip(&archive, &[("a.txt", "aaa"), ("inner/b.txt", "bb")]);
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	// Scans only see the archive itself
+	assert_eq!(cache.all_files().len(), 1);
+
+	let diff = cache.index_archive(&archive).unwrap();
+	assert_eq!(diff.added.len(), 2);
+	assert_eq!(
+		virtual_sizes(&cache),
+		BTreeMap::from([(archive.join("a.txt"), 3), (archive.join("inner/b.txt"), 2)])
+	);
+	let entry = cache
+		.virtual_files()
+		.into_iter()
+		.find(|meta| meta.path.0 == archive.join("inner/b.txt"))
+		.unwrap();
+	assert!(entry.is_virtual);
+	assert_eq!(entry.extension.as_deref(), Some("txt"));
+	assert_eq!(
+		entry.modified,
+		Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_209_800))
+	);
+	assert_eq!(cache.all_files().len(), 3);
+
+	write_zip(&archive, &[("a.txt", "aaaaa"), ("c.txt", "c")]);
+	let diff = cache.index_archive(&archive).unwrap();
+	assert_eq!(diff.added.len(), 1);
+	assert_eq!(diff.modified.len(), 1);
+	assert_eq!(diff.removed[0].path.0, archive.join("inner/b.txt"));
+	assert_eq!(
+		virtual_sizes(&cache),
+		BTreeMap::from([(archive.join("a.txt"), 5), (archive.join("c.txt"), 1)])
+	);
+
+	// A broken archive keeps the entries read before
+	std::fs::write(&archive, "not a zip").unwrap();
+	assert!(cache.index_archive(&archive).is_err());
+	assert_eq!(cache.virtual_files().len(), 2);
+	assert_eq!(cache.remove_archive(&archive).len(), 2);
+	assert!(cache.virtual_files().is_empty());
+}
+
+#[test]
+fn test_virtual_files_are_never_stored_or_copied() {
+	let vfs = VirtualFs::new();
+	let archive = vfs.path("bundle.zip");
+	write_zip(&archive, &[("a.txt", "aaa"), ("b.txt", "b")]);
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	cache.index_archive(&archive).unwrap();
+	assert_eq!(cache.all_files().len(), 3);
+
+	let subset = cache.clone_subset(|_, _| true);
+	assert_eq!(subset.all_files().len(), 1);
+	let scratch = VirtualFs::new();
+	let open = |name: &str| linkfield::db::open_or_create_db(&scratch.path(name)).unwrap();
+	assert_eq!(
+		cache.record_checkpoint(&open("a.redb"), "before").unwrap(),
+		1
+	);
+	assert_eq!(cache.set_db(open("b.redb")).unwrap(), 1);
+}
+
+fn wait_for(cache: &FileCache, expected: &BTreeMap<PathBuf, u64>) -> BTreeMap<PathBuf, u64> {
+	let deadline = Instant::now() + Duration::from_secs(10);
+	loop {
+		let current = virtual_sizes(cache);
+		if &current == expected || Instant::now() >= deadline {
+			return current;
+		}
+		std::thread::sleep(Duration::from_millis(50));
+	}
+}
+
+#[test]
+fn test_watcher_reindexes_modified_archives() {
+	let vfs = VirtualFs::new();
+	let archive = vfs.path("bundle.zip");
+	write_zip(&archive, &[("a.txt", "aaa")]);
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	let watcher = start_watcher_with_config(
+		vfs.root(),
+		Arc::new(Mutex::new(cache.clone())),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::empty())),
+		WatchConfig {
+			archives: Some(ArchiveWatcher::default()),
+			..WatchConfig::default()
+		},
+	);
+
+	// The archive found by the scan is indexed once it is done
+	assert_eq!(ArchiveWatcher::default().index_cached(&cache), 1);
+	let expected = BTreeMap::from([(archive.join("a.txt"), 3)]);
+	assert_eq!(virtual_sizes(&cache), expected);
+
+	write_zip(&archive, &[("a.txt", "aaaa"), ("b/c.txt", "cc")]);
+	let expected = BTreeMap::from([(archive.join("a.txt"), 4), (archive.join("b/c.txt"), 2)]);
+	assert_eq!(wait_for(&cache, &expected), expected);
+
+	vfs.delete_file("bundle.zip");
+	assert_eq!(wait_for(&cache, &BTreeMap::new()), BTreeMap::new());
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+}
+
+#[test]
+fn test_index_cached_reads_archives_from_the_database() {
+	let vfs = VirtualFs::new();
+	let archive = vfs.path("bundle.zip");
+	write_zip(&archive, &[("a.txt", "aaa")]);
+	let scratch = VirtualFs::new();
+	let db = linkfield::db::open_or_create_db(&scratch.path("linkfield.redb")).unwrap();
+	let scanned = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	scanned.set_db(db).unwrap();
+	// Like after a watch mode scan, the archive is stored but not in memory
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	cache.set_db(scanned.detach_db().unwrap()).unwrap();
+	assert!(cache.all_files().is_empty());
+
+	assert_eq!(ArchiveWatcher::default().index_cached(&cache), 1);
+	assert_eq!(
+		virtual_sizes(&cache),
+		BTreeMap::from([(archive.join("a.txt"), 3)])
+	);
+}
+
+#[test]
+fn test_archive_watcher_extensions() {
+	let watcher = ArchiveWatcher::default();
+	assert!(watcher.is_archive(Path::new("a/b.ZIP")));
+	assert!(!watcher.is_archive(Path::new("a/b.jar")));
+	let watcher = ArchiveWatcher {
+		extensions: vec!["zip".to_string(), "jar".to_string()],
+	};
+	assert!(watcher.is_archive(Path::new("a/b.jar")));
+}
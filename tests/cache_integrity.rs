@@ -0,0 +1,144 @@
+//! Integration tests: detecting changes made to the database outside linkfield
+
+mod common;
+
+use assert_cmd::Command;
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::error::{LinkfieldError, LinkfieldResult};
+use linkfield::file_cache::db::{
+	DEFAULT_LOAD_BATCH_SIZE, FILE_CACHE_TABLE, update_redb_single_insert, update_redb_single_remove,
+};
+use linkfield::file_cache::integrity::IntegrityError;
+use linkfield::file_cache::meta::FileCachePath;
+use linkfield::file_cache::{FileCache, FileMeta};
+use linkfield::ignore_config::IgnoreConfig;
+use std::path::Path;
+
+fn is_mismatch(result: LinkfieldResult<()>) -> bool {
+	matches!(
+		result,
+		Err(LinkfieldError::Integrity(
+			IntegrityError::ChecksumMismatch { .. }
+		))
+	)
+}
+
+fn stored_db(vfs: &VirtualFs, db_path: &Path) -> redb::Database {
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	cache
+		.set_db(db::open_or_create_db(db_path).unwrap())
+		.unwrap();
+	cache.detach_db().unwrap()
+}
+
+#[test]
+fn test_checksum_follows_linkfield_writes() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 1\nCREATE sub/b.txt 2\nCREATE sub/c.txt 3")
+		.unwrap();
+	let db_dir = VirtualFs::new();
+	let database = stored_db(&vfs, &db_dir.path("cache.redb"));
+	FileCache::verify_cache_checksum(&database).unwrap();
+
+	let meta = FileMeta::from_path(&vfs.create_file("d.txt", 4)).unwrap();
+	update_redb_single_insert(&database, &meta.path, &meta).unwrap();
+	update_redb_single_remove(&database, &FileCachePath(vfs.path("a.txt"))).unwrap();
+	FileCache::remove_prefix_from_db_only(&database, &vfs.path("sub")).unwrap();
+	FileCache::verify_cache_checksum(&database).unwrap();
+
+	let cache = FileCache::new_root("root");
+	let loaded = cache
+		.load_from_redb_batched(&database, DEFAULT_LOAD_BATCH_SIZE, None)
+		.unwrap();
+	assert_eq!(loaded, 1);
+}
+
+#[test]
+fn test_external_write_is_detected() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 1\nCREATE b.txt 2").unwrap();
+	let db_dir = VirtualFs::new();
+	let database = stored_db(&vfs, &db_dir.path("cache.redb"));
+
+	let write_txn = database.begin_write().unwrap();
+	{
+		let mut table = write_txn.open_table(FILE_CACHE_TABLE).unwrap();
+		let key = vfs.path("a.txt").to_string_lossy().to_string();
+		let mut meta = FileMeta::from_path(&vfs.path("a.txt")).unwrap();
+		meta.size = 1_000_000;
+		table
+			.insert(key.as_str(), meta.serialize().unwrap().as_slice())
+			.unwrap();
+	}
+	write_txn.commit().unwrap();
+
+	assert!(is_mismatch(FileCache::verify_cache_checksum(&database)));
+	let cache = FileCache::new_root("root");
+	let loaded = cache
+		.load_from_redb_batched(&database, DEFAULT_LOAD_BATCH_SIZE, None)
+		.map(|_| ());
+	assert!(is_mismatch(loaded));
+}
+
+#[test]
+fn test_raw_byte_edit_is_detected() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE marker_aaaa.txt 1\nCREATE other.txt 2")
+		.unwrap();
+	let db_dir = VirtualFs::new();
+	let db_path = db_dir.path("cache.redb");
+	drop(stored_db(&vfs, &db_path));
+
+	// Rename the stored key in place, as an editor working on the raw file would
+	let mut bytes = std::fs::read(&db_path).unwrap();
+	let (from, to) = (b"marker_aaaa", b"marker_zzzz");
+	let mut edits = 0;
+	for i in 0..bytes.len().saturating_sub(from.len()) {
+		if &bytes[i..i + from.len()] == from {
+			bytes[i..i + to.len()].copy_from_slice(to);
+			edits += 1;
+		}
+	}
+	assert!(edits > 0);
+	std::fs::write(&db_path, bytes).unwrap();
+
+	let database = db::open_or_create_db(&db_path).unwrap();
+	assert!(is_mismatch(FileCache::verify_cache_checksum(&database)));
+}
+
+#[test]
+fn test_verify_cli() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 1").unwrap();
+	Command::cargo_bin("linkfield")
+		.unwrap()
+		.arg("scan")
+		.arg(vfs.root())
+		.assert()
+		.success();
+	Command::cargo_bin("linkfield")
+		.unwrap()
+		.args(["verify", "--checksum"])
+		.arg(vfs.root())
+		.assert()
+		.success()
+		.stdout("checksum: ok\n");
+
+	{
+		let database = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+		let write_txn = database.begin_write().unwrap();
+		write_txn
+			.open_table(FILE_CACHE_TABLE)
+			.unwrap()
+			.remove(vfs.path("a.txt").to_string_lossy().as_ref())
+			.unwrap();
+		write_txn.commit().unwrap();
+	}
+	Command::cargo_bin("linkfield")
+		.unwrap()
+		.args(["verify", "--checksum"])
+		.arg(vfs.root())
+		.assert()
+		.failure();
+}
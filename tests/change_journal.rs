@@ -0,0 +1,323 @@
+//! Integration tests: recording detected changes and rolling them back
+
+mod common;
+
+use assert_cmd::Command;
+use common::{VirtualFs, test_meta};
+use linkfield::change_journal::{ChangeJournal, InverseOp, JournalEntry, JournalEntryKind};
+use linkfield::db;
+use linkfield::file_cache::db::update_redb_single_insert;
+use linkfield::file_cache::{FileCache, FileMeta};
+use linkfield::ignore_config::IgnoreConfig;
+use linkfield::move_heuristics::MoveHeuristics;
+use linkfield::watcher::{WatchConfig, start_watcher_with_config};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+fn entry(path: &Path, kind: JournalEntryKind, before: Option<FileMeta>) -> JournalEntry {
+	JournalEntry {
+		path: path.to_path_buf(),
+		kind,
+		metadata_before: before,
+	}
+}
+
+#[test]
+fn test_append_and_list_transactions() {
+	let vfs = VirtualFs::new();
+	let db = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	assert!(ChangeJournal::transactions(&db).unwrap().is_empty());
+
+	// Enough entries that the index prefix has to order more than its last byte
+	let first: Vec<_> = (0..300)
+		.map(|i| entry(&vfs.path(&format!("f{i}")), JournalEntryKind::Create, None))
+		.collect();
+	let second = vec![entry(&vfs.path("b"), JournalEntryKind::Remove, None)];
+	assert_eq!(ChangeJournal::append(&db, &first).unwrap(), 1);
+	assert_eq!(ChangeJournal::append(&db, &second).unwrap(), 2);
+
+	let transactions = ChangeJournal::transactions(&db).unwrap();
+	assert_eq!(transactions.keys().copied().collect::<Vec<_>>(), [1, 2]);
+	assert_eq!(transactions[&1], first);
+	assert_eq!(ChangeJournal::entries(&db, 2).unwrap(), second);
+	assert!(ChangeJournal::entries(&db, 3).unwrap().is_empty());
+}
+
+#[test]
+fn test_flush_writes_pending_transactions() {
+	let vfs = VirtualFs::new();
+	let db = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	let journal = ChangeJournal::new();
+	journal.record(Vec::new());
+	journal.record(vec![entry(&vfs.path("a"), JournalEntryKind::Create, None)]);
+	journal.record(vec![entry(&vfs.path("b"), JournalEntryKind::Remove, None)]);
+	assert_eq!(journal.pending_len(), 2);
+
+	assert_eq!(journal.flush(&db).unwrap(), [1, 2]);
+	assert_eq!(journal.pending_len(), 0);
+	assert!(journal.flush(&db).unwrap().is_empty());
+	assert_eq!(ChangeJournal::transactions(&db).unwrap().len(), 2);
+}
+
+#[test]
+fn test_rollback_plan_inverts_changes_latest_first() {
+	let vfs = VirtualFs::new();
+	let db = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	let (created, moved_from, moved_to, removed) = (
+		vfs.path("new.txt"),
+		vfs.path("old/name.txt"),
+		vfs.path("new/name.txt"),
+		vfs.path("gone.txt"),
+	);
+	let id = ChangeJournal::append(
+		&db,
+		&[
+			entry(&created, JournalEntryKind::Create, None),
+			entry(
+				&moved_to,
+				JournalEntryKind::Move {
+					from: moved_from.clone(),
+				},
+				None,
+			),
+			entry(
+				&removed,
+				JournalEntryKind::Remove,
+				Some(FileMeta {
+					modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000)),
+					..test_meta(&removed, 42)
+				}),
+			),
+		],
+	)
+	.unwrap();
+
+	let plan = ChangeJournal::rollback(&db, id).unwrap();
+	assert_eq!(plan.transaction_id, id);
+	assert_eq!(
+		plan.ops,
+		[
+			InverseOp::Restore {
+				path: removed,
+				size: 42,
+			},
+			InverseOp::Move {
+				from: moved_to,
+				to: moved_from,
+			},
+			InverseOp::Delete { path: created },
+		]
+	);
+	assert!(ChangeJournal::rollback(&db, id + 1).unwrap().ops.is_empty());
+}
+
+#[test]
+fn test_execute_rollback_restores_the_directory() {
+	let vfs = VirtualFs::new();
+	let db = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	let removed = vfs.path("docs/gone.txt");
+	vfs.create_file("new.txt", 5);
+	vfs.create_file("renamed.txt", 7);
+	let id = ChangeJournal::append(
+		&db,
+		&[
+			entry(&vfs.path("new.txt"), JournalEntryKind::Create, None),
+			entry(
+				&vfs.path("renamed.txt"),
+				JournalEntryKind::Move {
+					from: vfs.path("dir/original.txt"),
+				},
+				None,
+			),
+			entry(
+				&removed,
+				JournalEntryKind::Remove,
+				Some(FileMeta {
+					modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000)),
+					..test_meta(&removed, 12)
+				}),
+			),
+		],
+	)
+	.unwrap();
+
+	ChangeJournal::execute_rollback(ChangeJournal::rollback(&db, id).unwrap()).unwrap();
+	assert!(!vfs.path("new.txt").exists());
+	assert!(!vfs.path("renamed.txt").exists());
+	assert_eq!(
+		std::fs::metadata(vfs.path("dir/original.txt"))
+			.unwrap()
+			.len(),
+		7
+	);
+	let restored = std::fs::metadata(&removed).unwrap();
+	assert_eq!(restored.len(), 12);
+	// The placeholder must not look like the removed file to the next scan
+	assert_ne!(
+		restored.modified().unwrap(),
+		SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000)
+	);
+
+	// Restoring never overwrites a file that is back in place
+	let again = ChangeJournal::rollback(&db, id).unwrap();
+	assert!(ChangeJournal::execute_rollback(again).is_err());
+}
+
+#[test]
+fn test_execute_rollback_never_moves_over_a_file() {
+	let vfs = VirtualFs::new();
+	let db = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	vfs.create_file("renamed.txt", 7);
+	vfs.create_file("original.txt", 3);
+	let id = ChangeJournal::append(
+		&db,
+		&[entry(
+			&vfs.path("renamed.txt"),
+			JournalEntryKind::Move {
+				from: vfs.path("original.txt"),
+			},
+			None,
+		)],
+	)
+	.unwrap();
+
+	assert!(ChangeJournal::execute_rollback(ChangeJournal::rollback(&db, id).unwrap()).is_err());
+	assert_eq!(
+		std::fs::metadata(vfs.path("original.txt")).unwrap().len(),
+		3
+	);
+	assert!(vfs.path("renamed.txt").exists());
+}
+
+fn wait_for_pending(journal: &ChangeJournal, count: usize) {
+	let deadline = Instant::now() + Duration::from_secs(10);
+	while journal.pending_len() < count && Instant::now() < deadline {
+		std::thread::sleep(Duration::from_millis(50));
+	}
+}
+
+#[test]
+fn test_watcher_records_changes() {
+	let vfs = VirtualFs::new();
+	let journal = Arc::new(ChangeJournal::new());
+	let watcher = start_watcher_with_config(
+		vfs.root(),
+		Arc::new(Mutex::new(FileCache::new_root(
+			vfs.root().to_string_lossy().as_ref(),
+		))),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::empty())),
+		WatchConfig {
+			journal: Some(journal.clone()),
+			..WatchConfig::default()
+		},
+	);
+
+	let file = vfs.create_file("note.txt", 9);
+	wait_for_pending(&journal, 1);
+	vfs.delete_file("note.txt");
+	wait_for_pending(&journal, 2);
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+
+	let scratch = VirtualFs::new();
+	let db = db::open_or_create_db(&scratch.path("linkfield.redb")).unwrap();
+	journal.flush(&db).unwrap();
+	let entries: Vec<_> = ChangeJournal::transactions(&db)
+		.unwrap()
+		.into_values()
+		.flatten()
+		.collect();
+	assert_eq!(entries.len(), 2, "{entries:?}");
+	assert_eq!(entries[0].kind, JournalEntryKind::Create);
+	assert_eq!(entries[1].kind, JournalEntryKind::Remove);
+	assert!(entries.iter().all(|entry| entry.path == file));
+	// The removal carries the metadata the cache had for the file
+	assert_eq!(
+		entries[1].metadata_before.as_ref().map(|meta| meta.size),
+		Some(9)
+	);
+}
+
+#[test]
+fn test_journal_cli_lists_and_rolls_back() {
+	let vfs = VirtualFs::new();
+	let created = vfs.create_file("new.txt", 1);
+	{
+		let db = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+		ChangeJournal::append(&db, &[entry(&created, JournalEntryKind::Create, None)]).unwrap();
+	}
+	let run = |args: &[&str]| {
+		let output = Command::cargo_bin("linkfield")
+			.unwrap()
+			.args(args)
+			.arg(vfs.root())
+			.output()
+			.unwrap();
+		assert!(output.status.success(), "{output:?}");
+		String::from_utf8(output.stdout).unwrap()
+	};
+
+	let listed = run(&["journal", "list"]);
+	assert!(
+		listed.contains(&format!("1:\n  A {}", created.display())),
+		"{listed}"
+	);
+	let planned = run(&["journal", "rollback", "1", "--dry-run"]);
+	assert!(
+		planned.contains(&format!("delete {}", created.display())),
+		"{planned}"
+	);
+	assert!(created.exists());
+	run(&["journal", "rollback", "1"]);
+	assert!(!created.exists());
+}
+
+#[test]
+fn test_watcher_flushes_each_batch_with_stored_metadata() {
+	let vfs = VirtualFs::new();
+	let scratch = VirtualFs::new();
+	let file = vfs.create_file("stored.txt", 11);
+	// The file is only in the database, like after a watch mode scan
+	let db = db::open_or_create_db(&scratch.path("linkfield.redb")).unwrap();
+	let stored = FileMeta::from_path(&file).unwrap();
+	update_redb_single_insert(&db, &stored.path, &stored).unwrap();
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	cache.set_db(db).unwrap();
+	let journal = Arc::new(ChangeJournal::new());
+	let watcher = start_watcher_with_config(
+		vfs.root(),
+		Arc::new(Mutex::new(cache.clone())),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::empty())),
+		WatchConfig {
+			journal: Some(journal.clone()),
+			..WatchConfig::default()
+		},
+	);
+
+	vfs.delete_file("stored.txt");
+	let db = cache.database().unwrap();
+	let deadline = Instant::now() + Duration::from_secs(10);
+	let mut entries = Vec::new();
+	while entries.is_empty() && Instant::now() < deadline {
+		std::thread::sleep(Duration::from_millis(50));
+		entries = ChangeJournal::transactions(&db)
+			.unwrap()
+			.into_values()
+			.flatten()
+			.collect();
+	}
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+
+	// Written while the watcher runs, not at shutdown
+	assert_eq!(journal.pending_len(), 0);
+	assert_eq!(entries.len(), 1, "{entries:?}");
+	assert_eq!(entries[0].kind, JournalEntryKind::Remove);
+	assert_eq!(
+		entries[0].metadata_before.as_ref().map(|meta| meta.size),
+		Some(11)
+	);
+}
@@ -0,0 +1,27 @@
+//! Integration test: `--ignore` patterns given on the command line are applied during the scan
+
+mod common;
+
+use common::{VirtualFs, run_watch_until_ready};
+use linkfield::file_cache::db::FILE_CACHE_TABLE;
+use redb::{Database, ReadableTable};
+
+#[test]
+fn test_cli_ignore_pattern_excludes_files() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE keep.txt 5\nCREATE drop.log 5\nCREATE other.log 5")
+		.unwrap();
+
+	run_watch_until_ready(&vfs, &["--ignore", "*.log"]);
+
+	let db = Database::open(vfs.path("linkfield.redb")).unwrap();
+	let txn = db.begin_read().unwrap();
+	let table = txn.open_table(FILE_CACHE_TABLE).unwrap();
+	let keys: Vec<String> = table
+		.iter()
+		.unwrap()
+		.map(|entry| entry.unwrap().0.value().to_string())
+		.collect();
+	assert!(keys.iter().any(|k| k.ends_with("keep.txt")));
+	assert!(!keys.iter().any(|k| k.ends_with(".log")), "{keys:?}");
+}
@@ -0,0 +1,178 @@
+//! Shared test utilities.
+//!
+//! `VirtualFs` wraps a temporary directory behind a small file-manipulation API so tests can
+//! describe file system activity by name rather than juggling absolute paths, and replay
+//! scripted event sequences deterministically.
+#![allow(dead_code)]
+
+use linkfield::file_cache::FileMeta;
+use linkfield::file_cache::meta::{FileCachePath, FileType};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tempfile::TempDir;
+
+/// Metadata of a regular file at `path` holding `size` bytes, with the extension taken from
+/// the path and nothing else known. Set other fields with `..test_meta(path, size)`.
+pub fn test_meta(path: impl AsRef<Path>, size: u64) -> FileMeta {
+	let path = path.as_ref();
+	FileMeta {
+		path: FileCachePath(path.to_path_buf()),
+		size,
+		modified: None,
+		created: None,
+		extension: path
+			.extension()
+			.map(|extension| extension.to_string_lossy().into_owned()),
+		inode: None,
+		content_hash: None,
+		is_virtual: false,
+		file_type: FileType::Regular,
+		device: None,
+	}
+}
+
+pub struct VirtualFs {
+	dir: TempDir,
+}
+
+impl VirtualFs {
+	pub fn new() -> Self {
+		Self {
+			dir: tempfile::tempdir().expect("failed to create temp dir"),
+		}
+	}
+
+	/// Root directory of the virtual file system
+	pub fn root(&self) -> &Path {
+		self.dir.path()
+	}
+
+	/// Absolute path for a name relative to the root
+	pub fn path(&self, name: &str) -> PathBuf {
+		self.dir.path().join(name)
+	}
+
+	/// Create (or truncate) `name` with exactly `size` bytes, creating parent directories
+	pub fn create_file(&self, name: &str, size: u64) -> PathBuf {
+		let path = self.path(name);
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent).expect("failed to create parent dirs");
+		}
+		File::create(&path)
+			.and_then(|f| f.set_len(size))
+			.expect("failed to create file");
+		path
+	}
+
+	pub fn delete_file(&self, name: &str) {
+		fs::remove_file(self.path(name)).expect("failed to delete file");
+	}
+
+	pub fn rename_file(&self, from: &str, to: &str) {
+		let to_path = self.path(to);
+		if let Some(parent) = to_path.parent() {
+			fs::create_dir_all(parent).expect("failed to create parent dirs");
+		}
+		fs::rename(self.path(from), to_path).expect("failed to rename file");
+	}
+
+	pub fn set_mtime(&self, name: &str, time: SystemTime) {
+		File::options()
+			.write(true)
+			.open(self.path(name))
+			.and_then(|f| f.set_modified(time))
+			.expect("failed to set mtime");
+	}
+
+	/// All regular files below the root, sorted
+	pub fn list_files(&self) -> Vec<PathBuf> {
+		let mut files = Vec::new();
+		let mut pending = vec![self.root().to_path_buf()];
+		while let Some(dir) = pending.pop() {
+			for entry in fs::read_dir(&dir).expect("failed to read dir") {
+				let path = entry.expect("failed to read dir entry").path();
+				if path.is_dir() {
+					pending.push(path);
+				} else {
+					files.push(path);
+				}
+			}
+		}
+		files.sort();
+		files
+	}
+
+	/// Replay a script of file operations, one per line:
+	///
+	/// ```text
+	/// CREATE foo.txt 1024
+	/// DELETE bar.txt
+	/// RENAME a.txt b.txt
+	/// ```
+	///
+	/// Blank lines and lines starting with `#` are skipped.
+	pub fn replay_script(&self, script: &str) -> io::Result<()> {
+		for (lineno, line) in script.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let parts: Vec<&str> = line.split_whitespace().collect();
+			let bad_line =
+				|| io::Error::other(format!("line {}: invalid command `{line}`", lineno + 1));
+			match parts.as_slice() {
+				["CREATE", name, size] => {
+					let size = size.parse().map_err(|_| bad_line())?;
+					self.create_file(name, size);
+				}
+				["DELETE", name] => self.delete_file(name),
+				["RENAME", from, to] => self.rename_file(from, to),
+				_ => return Err(bad_line()),
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Run the watcher on `vfs` with extra `args`, wait until it reports `Ready`, then press Enter
+/// and return its stdout. Exiting earlier would cancel the initial scan.
+pub fn run_watch_until_ready(vfs: &VirtualFs, args: &[&str]) -> String {
+	use assert_cmd::cargo::CommandCargoExt;
+	use linkfield::health::{self, AppState};
+	use std::io::{Read, Write};
+	use std::process::{Command, Stdio};
+	use std::time::{Duration, Instant};
+
+	let mut child = Command::cargo_bin("linkfield")
+		.expect("binary not built")
+		.arg(vfs.root())
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.spawn()
+		.expect("failed to start linkfield");
+	let state_file = health::state_file_path(&vfs.path("linkfield.redb"));
+	let deadline = Instant::now() + Duration::from_secs(30);
+	while health::read_state_file(&state_file)
+		.ok()
+		.map(|file| file.state)
+		!= Some(AppState::Ready)
+	{
+		assert!(Instant::now() < deadline, "watcher never became ready");
+		std::thread::sleep(Duration::from_millis(20));
+	}
+	let mut stdin = child.stdin.take().expect("stdin not piped");
+	stdin.write_all(b"\n").expect("failed to write to stdin");
+	drop(stdin);
+	let mut stdout = String::new();
+	child
+		.stdout
+		.take()
+		.expect("stdout not piped")
+		.read_to_string(&mut stdout)
+		.expect("failed to read stdout");
+	assert!(child.wait().expect("failed to wait").success());
+	stdout
+}
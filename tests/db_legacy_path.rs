@@ -0,0 +1,45 @@
+//! Integration tests: moving a database out of its legacy location
+
+mod common;
+
+use common::VirtualFs;
+
+use linkfield::db;
+
+#[test]
+fn test_legacy_database_is_copied_and_kept_as_backup() {
+	let dir = VirtualFs::new();
+	let legacy_path = dir.path("test.redb");
+	{
+		let legacy = db::open_or_create_db(&legacy_path).unwrap();
+		db::migrate(&legacy).unwrap();
+	}
+	let new_path = dir.path("data").join("linkfield.redb");
+
+	assert!(db::migrate_legacy_database_from(dir.root(), &new_path).unwrap());
+	assert!(!legacy_path.exists());
+	assert!(dir.path("test.redb.migrated").is_file());
+	let migrated = db::open_or_create_db(&new_path).unwrap();
+	assert_eq!(db::migration_status(&migrated).len(), db::MIGRATIONS.len());
+	drop(migrated);
+
+	// Nothing left to migrate, and an existing database is never overwritten
+	assert!(!db::migrate_legacy_database_from(dir.root(), &new_path).unwrap());
+	std::fs::write(&legacy_path, b"stale").unwrap();
+	assert!(!db::migrate_legacy_database_from(dir.root(), &new_path).unwrap());
+	assert!(legacy_path.exists());
+}
+
+#[test]
+fn test_newer_database_name_is_not_migrated_back() {
+	let dir = VirtualFs::new();
+	drop(db::open_or_create_db(&dir.path("linkfield.redb")).unwrap());
+
+	assert!(!db::migrate_legacy_database_from(dir.root(), &dir.path("test.redb")).unwrap());
+	assert!(dir.path("linkfield.redb").exists());
+
+	// test.redb predates linkfield.redb, so it is migrated to it
+	let other = VirtualFs::new();
+	drop(db::open_or_create_db(&other.path("test.redb")).unwrap());
+	assert!(db::migrate_legacy_database_from(other.root(), &other.path("linkfield.redb")).unwrap());
+}
@@ -0,0 +1,339 @@
+//! Integration tests: migration history bookkeeping
+
+mod common;
+
+use common::VirtualFs;
+
+use assert_cmd::Command;
+use linkfield::db;
+
+#[test]
+fn test_migrate_records_history_and_is_idempotent() {
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("m.redb")).unwrap();
+	assert!(db::migration_status(&database).is_empty());
+
+	let all: Vec<u32> = db::MIGRATIONS.iter().map(|m| m.version).collect();
+	assert_eq!(db::migrate(&database).unwrap(), all);
+	let status = db::migration_status(&database);
+	assert_eq!(status.iter().map(|(v, _)| *v).collect::<Vec<_>>(), all);
+	for (_, applied_at) in &status {
+		assert_eq!(applied_at.len(), "2000-01-01T00:00:00Z".len());
+		assert!(applied_at.ends_with('Z'));
+	}
+
+	assert!(db::migrate(&database).unwrap().is_empty());
+	assert_eq!(db::migration_status(&database), status);
+}
+
+#[test]
+fn test_migrate_fails_when_the_history_cannot_be_read() {
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("m.redb")).unwrap();
+	// A table of the wrong type under the history's name fails to open
+	let wrong: redb::TableDefinition<&str, u64> = redb::TableDefinition::new("migration_history");
+	let write_txn = database.begin_write().unwrap();
+	write_txn.open_table(wrong).unwrap();
+	write_txn.commit().unwrap();
+
+	assert!(db::migration_history(&database).is_err());
+	assert!(
+		db::migrate(&database).is_err(),
+		"migrations must not be re-applied over an unreadable history"
+	);
+	assert!(db::migration_status(&database).is_empty());
+}
+
+#[test]
+fn test_migrations_cli_lists_pending_then_applied() {
+	let dir = VirtualFs::new();
+	let db_path = dir.path("cli.redb");
+	let run = || {
+		let output = Command::cargo_bin("linkfield")
+			.unwrap()
+			.arg("migrations")
+			.arg(&db_path)
+			.output()
+			.unwrap();
+		assert!(output.status.success());
+		String::from_utf8(output.stdout).unwrap()
+	};
+	// Create the file so the path resolves as a database
+	drop(db::open_or_create_db(&db_path).unwrap());
+	assert!(run().lines().all(|line| line.contains(" pending ")));
+
+	db::migrate(&db::open_or_create_db(&db_path).unwrap()).unwrap();
+	assert!(run().lines().all(|line| line.contains(" applied ")));
+}
+
+#[test]
+fn test_scan_cli_records_migrations_of_the_database_it_creates() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 1").unwrap();
+	Command::cargo_bin("linkfield")
+		.unwrap()
+		.arg("scan")
+		.arg(vfs.root())
+		.assert()
+		.success();
+
+	// A later watch run must not upgrade rows that were written in the current layout
+	let database = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	let all: Vec<u32> = db::MIGRATIONS.iter().map(|m| m.version).collect();
+	let applied: Vec<u32> = db::migration_status(&database)
+		.into_iter()
+		.map(|(v, _)| v)
+		.collect();
+	assert_eq!(applied, all);
+	assert!(db::migrate(&database).unwrap().is_empty());
+}
+
+#[test]
+fn test_migration_adds_inode_to_stored_file_metas() {
+	use linkfield::file_cache::FileCache;
+	use linkfield::file_cache::db::{DEFAULT_LOAD_BATCH_SIZE, FILE_CACHE_TABLE};
+	use linkfield::file_cache::meta::FileCachePath;
+	use std::path::PathBuf;
+	use std::time::SystemTime;
+
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("old.redb")).unwrap();
+	// Same encoding as the FileMeta layout from before the inode field
+	let path = PathBuf::from("root/old.txt");
+	let old = (
+		FileCachePath(path.clone()),
+		7u64,
+		None::<SystemTime>,
+		None::<SystemTime>,
+		Some("txt".to_string()),
+	);
+	let bytes = bincode::encode_to_vec(&old, bincode::config::standard()).unwrap();
+	let write_txn = database.begin_write().unwrap();
+	write_txn
+		.open_table(FILE_CACHE_TABLE)
+		.unwrap()
+		.insert("root/old.txt", bytes.as_slice())
+		.unwrap();
+	write_txn.commit().unwrap();
+
+	db::migrate(&database).unwrap();
+	let cache = FileCache::new_root("root");
+	assert_eq!(
+		cache
+			.load_from_redb_batched(&database, DEFAULT_LOAD_BATCH_SIZE, None)
+			.unwrap(),
+		1
+	);
+	let meta = cache.get(&path).unwrap();
+	assert_eq!(meta.size, 7);
+	assert_eq!(meta.extension.as_deref(), Some("txt"));
+	assert_eq!(meta.inode, None);
+}
+
+#[test]
+fn test_migration_adds_content_hash_to_stored_file_metas() {
+	use linkfield::file_cache::FileCache;
+	use linkfield::file_cache::db::{DEFAULT_LOAD_BATCH_SIZE, FILE_CACHE_TABLE};
+	use linkfield::file_cache::meta::FileCachePath;
+	use std::path::PathBuf;
+	use std::time::SystemTime;
+
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("old.redb")).unwrap();
+	// Same encoding as the FileMeta layout from before the content_hash field
+	let path = PathBuf::from("root/old.txt");
+	let old = (
+		FileCachePath(path.clone()),
+		7u64,
+		None::<SystemTime>,
+		None::<SystemTime>,
+		Some("txt".to_string()),
+		Some(42u64),
+	);
+	let bytes = bincode::encode_to_vec(&old, bincode::config::standard()).unwrap();
+	let write_txn = database.begin_write().unwrap();
+	write_txn
+		.open_table(FILE_CACHE_TABLE)
+		.unwrap()
+		.insert("root/old.txt", bytes.as_slice())
+		.unwrap();
+	write_txn.commit().unwrap();
+	// Pretend the database was created by a version that had applied every earlier migration
+	let write_txn = database.begin_write().unwrap();
+	let mut history = write_txn.open_table(db::MIGRATION_HISTORY_TABLE).unwrap();
+	for version in 1..5 {
+		history.insert(version, "2025-01-01T00:00:00Z").unwrap();
+	}
+	drop(history);
+	write_txn.commit().unwrap();
+
+	db::migrate(&database).unwrap();
+	let cache = FileCache::new_root("root");
+	assert_eq!(
+		cache
+			.load_from_redb_batched(&database, DEFAULT_LOAD_BATCH_SIZE, None)
+			.unwrap(),
+		1
+	);
+	let meta = cache.get(&path).unwrap();
+	assert_eq!(meta.size, 7);
+	assert_eq!(meta.inode, Some(42));
+	assert_eq!(meta.content_hash, None);
+}
+
+#[test]
+fn test_migration_adds_is_virtual_to_stored_file_metas_and_journal() {
+	use linkfield::change_journal::{ChangeJournal, JOURNAL_TABLE, JournalEntryKind};
+	use linkfield::file_cache::FileCache;
+	use linkfield::file_cache::db::{DEFAULT_LOAD_BATCH_SIZE, FILE_CACHE_TABLE};
+	use linkfield::file_cache::meta::FileCachePath;
+	use std::path::PathBuf;
+	use std::time::SystemTime;
+
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("old.redb")).unwrap();
+	// Same encoding as the FileMeta layout from before the is_virtual field
+	let path = PathBuf::from("root/old.txt");
+	let old = (
+		FileCachePath(path.clone()),
+		7u64,
+		None::<SystemTime>,
+		None::<SystemTime>,
+		Some("txt".to_string()),
+		Some(42u64),
+		Some(99u64),
+	);
+	let config = bincode::config::standard();
+	let bytes = bincode::encode_to_vec(&old, config).unwrap();
+	let mut journal_value = 0u32.to_be_bytes().to_vec();
+	journal_value.extend(
+		bincode::encode_to_vec((path.clone(), JournalEntryKind::Remove, Some(&old)), config)
+			.unwrap(),
+	);
+	let write_txn = database.begin_write().unwrap();
+	write_txn
+		.open_table(FILE_CACHE_TABLE)
+		.unwrap()
+		.insert("root/old.txt", bytes.as_slice())
+		.unwrap();
+	write_txn
+		.open_multimap_table(JOURNAL_TABLE)
+		.unwrap()
+		.insert(1, journal_value.as_slice())
+		.unwrap();
+	let mut history = write_txn.open_table(db::MIGRATION_HISTORY_TABLE).unwrap();
+	for version in 1..7 {
+		history.insert(version, "2025-01-01T00:00:00Z").unwrap();
+	}
+	drop(history);
+	write_txn.commit().unwrap();
+
+	assert_eq!(db::migrate(&database).unwrap(), [7, 8, 9, 10]);
+	let cache = FileCache::new_root("root");
+	cache
+		.load_from_redb_batched(&database, DEFAULT_LOAD_BATCH_SIZE, None)
+		.unwrap();
+	let meta = cache.get(&path).unwrap();
+	assert_eq!(meta.content_hash, Some(99));
+	assert!(!meta.is_virtual);
+
+	let entries = ChangeJournal::entries(&database, 1).unwrap();
+	assert_eq!(entries.len(), 1);
+	assert_eq!(entries[0].kind, JournalEntryKind::Remove);
+	assert_eq!(entries[0].metadata_before, Some(meta));
+}
+
+#[test]
+fn test_migration_adds_file_type_to_stored_file_metas() {
+	use linkfield::file_cache::FileCache;
+	use linkfield::file_cache::db::{DEFAULT_LOAD_BATCH_SIZE, FILE_CACHE_TABLE};
+	use linkfield::file_cache::meta::{FileCachePath, FileType};
+	use std::path::PathBuf;
+	use std::time::SystemTime;
+
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("old.redb")).unwrap();
+	// Same encoding as the FileMeta layout from before the file_type field
+	let path = PathBuf::from("root/old.txt");
+	let old = (
+		FileCachePath(path.clone()),
+		7u64,
+		None::<SystemTime>,
+		None::<SystemTime>,
+		Some("txt".to_string()),
+		Some(42u64),
+		Some(99u64),
+		true,
+	);
+	let bytes = bincode::encode_to_vec(&old, bincode::config::standard()).unwrap();
+	let write_txn = database.begin_write().unwrap();
+	write_txn
+		.open_table(FILE_CACHE_TABLE)
+		.unwrap()
+		.insert("root/old.txt", bytes.as_slice())
+		.unwrap();
+	let mut history = write_txn.open_table(db::MIGRATION_HISTORY_TABLE).unwrap();
+	for version in 1..8 {
+		history.insert(version, "2025-01-01T00:00:00Z").unwrap();
+	}
+	drop(history);
+	write_txn.commit().unwrap();
+
+	assert_eq!(db::migrate(&database).unwrap(), [8, 9, 10]);
+	let cache = FileCache::new_root("root");
+	cache
+		.load_from_redb_batched(&database, DEFAULT_LOAD_BATCH_SIZE, None)
+		.unwrap();
+	let meta = cache.get(&path).unwrap();
+	assert_eq!(meta.file_type, FileType::Regular);
+	assert_eq!(meta.content_hash, Some(99));
+	assert!(meta.is_virtual);
+}
+
+#[test]
+fn test_migration_adds_device_to_stored_file_metas() {
+	use linkfield::file_cache::FileCache;
+	use linkfield::file_cache::db::{DEFAULT_LOAD_BATCH_SIZE, FILE_CACHE_TABLE};
+	use linkfield::file_cache::meta::{FileCachePath, FileType};
+	use std::path::PathBuf;
+	use std::time::SystemTime;
+
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("old.redb")).unwrap();
+	// Same encoding as the FileMeta layout from before the device field
+	let path = PathBuf::from("root/old.txt");
+	let old = (
+		FileCachePath(path.clone()),
+		7u64,
+		None::<SystemTime>,
+		None::<SystemTime>,
+		Some("txt".to_string()),
+		Some(42u64),
+		Some(99u64),
+		false,
+		FileType::Pipe,
+	);
+	let bytes = bincode::encode_to_vec(&old, bincode::config::standard()).unwrap();
+	let write_txn = database.begin_write().unwrap();
+	write_txn
+		.open_table(FILE_CACHE_TABLE)
+		.unwrap()
+		.insert("root/old.txt", bytes.as_slice())
+		.unwrap();
+	let mut history = write_txn.open_table(db::MIGRATION_HISTORY_TABLE).unwrap();
+	for version in 1..9 {
+		history.insert(version, "2025-01-01T00:00:00Z").unwrap();
+	}
+	drop(history);
+	write_txn.commit().unwrap();
+
+	assert_eq!(db::migrate(&database).unwrap(), [9, 10]);
+	let cache = FileCache::new_root("root");
+	cache
+		.load_from_redb_batched(&database, DEFAULT_LOAD_BATCH_SIZE, None)
+		.unwrap();
+	let meta = cache.get(&path).unwrap();
+	assert_eq!(meta.file_type, FileType::Pipe);
+	assert_eq!(meta.inode, Some(42));
+	assert_eq!(meta.device, None);
+}
@@ -0,0 +1,47 @@
+//! Integration tests: opening the database with `DbOptions`
+
+mod common;
+
+use common::VirtualFs;
+
+use linkfield::db::{self, DbOptions};
+use linkfield::file_cache::FileCache;
+use linkfield::file_cache::db::{DEFAULT_LOAD_BATCH_SIZE, update_redb_batch_commit};
+use linkfield::file_cache::meta::{FileCachePath, FileMeta};
+
+#[test]
+fn test_read_only_never_creates_database() {
+	let dir = VirtualFs::new();
+	let db_path = dir.path("missing.redb");
+	assert!(db::open_or_create_db_with_options(&db_path, &DbOptions::read_only()).is_err());
+	assert!(!db_path.exists());
+}
+
+#[test]
+fn test_read_only_reads_existing_database() {
+	let dir = VirtualFs::new();
+	let db_path = dir.path("existing.redb");
+	let file = dir.path("a.txt");
+	std::fs::write(&file, b"abc").unwrap();
+	{
+		let database = db::open_or_create_db(&db_path).unwrap();
+		let meta = FileMeta::from_path(&file).unwrap();
+		update_redb_batch_commit(
+			&database,
+			&[],
+			&[(FileCachePath::from(file.as_path()), meta)],
+		)
+		.unwrap();
+	}
+
+	let opts = DbOptions::read_only().with_cache_size(1024 * 1024);
+	let database = db::open_or_create_db_with_options(&db_path, &opts).unwrap();
+	let cache = FileCache::new_root("root");
+	assert_eq!(
+		cache
+			.load_from_redb_batched(&database, DEFAULT_LOAD_BATCH_SIZE, None)
+			.unwrap(),
+		1
+	);
+	assert!(cache.get(&file).is_some());
+}
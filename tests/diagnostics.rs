@@ -0,0 +1,29 @@
+mod common;
+
+use common::VirtualFs;
+
+use linkfield::platform::startup_diagnostics;
+
+#[test]
+fn test_startup_diagnostics_reports_db_and_system() {
+	let dir = VirtualFs::new();
+	let db_path = dir.path("d.redb");
+	std::fs::write(&db_path, [0u8; 1234]).unwrap();
+	let diagnostics = startup_diagnostics(&db_path);
+	assert_eq!(diagnostics.os, std::env::consts::OS);
+	assert!(diagnostics.cpu_cores >= 1);
+	assert_eq!(diagnostics.db_file_size_bytes, Some(1234));
+	#[cfg(target_os = "linux")]
+	assert!(diagnostics.open_file_descriptors.is_some_and(|n| n > 0));
+
+	let table = diagnostics.format_table();
+	assert_eq!(table.lines().count(), 9);
+	assert!(table.contains("db_file_size_bytes"));
+	let json: serde_json::Value = serde_json::to_value(&diagnostics).unwrap();
+	assert_eq!(json["db_file_size_bytes"], 1234);
+
+	// A database that doesn't exist yet has no size but still reports the rest
+	let missing = startup_diagnostics(&dir.path("missing.redb"));
+	assert_eq!(missing.db_file_size_bytes, None);
+	assert_eq!(missing.cpu_cores, diagnostics.cpu_cores);
+}
@@ -0,0 +1,39 @@
+//! Integration tests: failures surface as `LinkfieldError` instead of being logged
+
+mod common;
+
+use common::VirtualFs;
+
+use linkfield::db;
+use linkfield::error::LinkfieldError;
+use linkfield::file_cache::FileMeta;
+use linkfield::file_cache::db::update_redb_batch_commit;
+use linkfield::file_cache::meta::FileCachePath;
+use std::error::Error;
+
+#[test]
+fn test_corrupt_file_meta_is_a_deserialization_error() {
+	let err = FileMeta::deserialize(&[0xff; 4]).unwrap_err();
+	assert!(matches!(err, LinkfieldError::Deserialization(_)));
+	assert!(err.to_string().starts_with("deserialization failed"));
+	assert!(err.source().is_some());
+}
+
+#[test]
+fn test_failed_commit_is_a_database_error() {
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("e.redb")).unwrap();
+	// A `file_cache` table of the wrong type can't be opened for the commit
+	let txn = database.begin_write().unwrap();
+	txn.open_table(redb::TableDefinition::<u64, u64>::new("file_cache"))
+		.unwrap();
+	txn.commit().unwrap();
+
+	let path = dir.path("a.txt");
+	std::fs::write(&path, b"a").unwrap();
+	let meta = FileMeta::from_path(&path).unwrap();
+	let err = update_redb_batch_commit(&database, &[], &[(FileCachePath(path), meta)]).unwrap_err();
+	assert!(matches!(err, LinkfieldError::Database(_)));
+	let source = err.source().expect("the redb error is the source");
+	assert!(source.downcast_ref::<redb::Error>().is_some());
+}
@@ -0,0 +1,78 @@
+//! Integration test: record watcher events and replay them offline
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::event_recorder::{EventRecorder, EventReplayer};
+use linkfield::file_cache::FileCache;
+use linkfield::move_heuristics::MoveHeuristics;
+use linkfield::watcher::{WatchEvent, replay_events};
+use notify_debouncer_full::DebouncedEvent;
+use notify_debouncer_full::notify::Event;
+use notify_debouncer_full::notify::event::{CreateKind, EventKind, RemoveKind};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn watcher_state(file: &Path) -> (Arc<Mutex<Arc<FileCache>>>, Arc<Mutex<MoveHeuristics>>) {
+	let cache = FileCache::new_root("root");
+	cache.update_file(file);
+	(
+		Arc::new(Mutex::new(cache)),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+	)
+}
+
+#[test]
+fn test_recorded_events_replay_to_the_same_moves() {
+	let vfs = VirtualFs::new();
+	let old = vfs.create_file("a/report.pdf", 4096);
+	std::fs::create_dir_all(vfs.path("b")).unwrap();
+	let new = vfs.path("b/report.pdf");
+	let start = Instant::now();
+	let events = vec![
+		DebouncedEvent::new(
+			Event::new(EventKind::Remove(RemoveKind::File)).add_path(old.clone()),
+			start,
+		),
+		DebouncedEvent::new(
+			Event::new(EventKind::Create(CreateKind::File)).add_path(new.clone()),
+			start + Duration::from_millis(40),
+		),
+	];
+	let recording = vfs.path("events.jsonl");
+	let mut recorder = EventRecorder::create(&recording).unwrap();
+	for event in &events {
+		recorder.record(event).unwrap();
+	}
+	drop(recorder);
+
+	let (live_cache, live_heuristics) = watcher_state(&old);
+	let (replay_cache, replay_heuristics) = watcher_state(&old);
+	std::fs::rename(&old, &new).unwrap();
+
+	let live = replay_events(events, &live_cache, &live_heuristics);
+	let replayer = EventReplayer::from_file(&recording).unwrap();
+	let replay_start = Instant::now();
+	let replayed: Vec<DebouncedEvent> = replayer.collect();
+	// The second event is held back by its recorded offset
+	assert!(replay_start.elapsed() >= Duration::from_millis(40));
+	assert_eq!(replayed.len(), 2);
+	assert_eq!(
+		replayed[1].time.duration_since(replayed[0].time),
+		Duration::from_millis(40)
+	);
+	let replay = replay_events(replayed, &replay_cache, &replay_heuristics);
+
+	assert_eq!(replay, live);
+	assert!(matches!(
+		replay.last(),
+		Some(WatchEvent::Move { from, to, .. }) if *from == old && *to == new
+	));
+	assert!(
+		EventReplayer::from_file(&recording)
+			.unwrap()
+			.without_delays()
+			.count() == 2
+	);
+}
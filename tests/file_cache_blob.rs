@@ -0,0 +1,86 @@
+//! Integration tests: moving the cache between machines as a single blob
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::error::LinkfieldError;
+use linkfield::file_cache::blob::BLOB_MAGIC;
+use linkfield::file_cache::{CacheBlobOptions, FileCache, FileMeta};
+use linkfield::ignore_config::IgnoreConfig;
+
+fn sorted_files(cache: &FileCache) -> Vec<FileMeta> {
+	let mut files = cache.all_files();
+	files.sort_by(|a, b| a.path.cmp(&b.path));
+	files
+}
+
+fn scanned_cache() -> (VirtualFs, std::sync::Arc<FileCache>) {
+	let vfs = VirtualFs::new();
+	for (name, size) in [("a.txt", 10), ("sub/b.rs", 20), ("sub/deep/c", 30)] {
+		vfs.create_file(name, size);
+	}
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	(vfs, cache)
+}
+
+fn assert_invalid_data(result: Result<std::sync::Arc<FileCache>, LinkfieldError>) {
+	match result {
+		Err(LinkfieldError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+		Err(e) => panic!("expected an invalid data error, got {e}"),
+		Ok(_) => panic!("damaged blob was accepted"),
+	}
+}
+
+#[test]
+fn test_blob_round_trip() {
+	let (vfs, cache) = scanned_cache();
+	let blob = cache.to_blob().unwrap();
+	assert!(blob.starts_with(BLOB_MAGIC));
+
+	let restored = FileCache::from_blob(&blob).unwrap();
+	assert_eq!(sorted_files(&restored), sorted_files(&cache));
+	assert_eq!(restored.len(), 3);
+	assert!(restored.contains_path(&vfs.path("sub/deep/c")));
+	// The same files always give the same bytes
+	assert_eq!(restored.to_blob().unwrap(), blob);
+}
+
+#[test]
+fn test_empty_cache_round_trip() {
+	let blob = FileCache::new_root("root").to_blob().unwrap();
+	assert!(FileCache::from_blob(&blob).unwrap().is_empty());
+}
+
+#[test]
+fn test_rejects_foreign_and_damaged_blobs() {
+	let (_vfs, cache) = scanned_cache();
+	let blob = cache.to_blob().unwrap();
+
+	assert_invalid_data(FileCache::from_blob(b"not a cache blob at all"));
+	assert_invalid_data(FileCache::from_blob(&blob[..blob.len() - 1]));
+	let mut future = blob.clone();
+	future[BLOB_MAGIC.len() - 1] = 2;
+	assert_invalid_data(FileCache::from_blob(&future));
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_compressed_blob_round_trip() {
+	let (_vfs, cache) = scanned_cache();
+	let options = CacheBlobOptions { compress: true };
+	let blob = cache.to_blob_with_options(options).unwrap();
+	assert_ne!(blob, cache.to_blob().unwrap());
+	let restored = FileCache::from_blob(&blob).unwrap();
+	assert_eq!(sorted_files(&restored), sorted_files(&cache));
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn test_compression_needs_the_zstd_feature() {
+	let (_vfs, cache) = scanned_cache();
+	let options = CacheBlobOptions { compress: true };
+	match cache.to_blob_with_options(options) {
+		Err(LinkfieldError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::Unsupported),
+		other => panic!("expected an unsupported error, got {other:?}"),
+	}
+}
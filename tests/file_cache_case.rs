@@ -0,0 +1,142 @@
+//! Integration tests: case-sensitive and case-insensitive path comparison
+
+mod common;
+
+use common::{VirtualFs, test_meta};
+
+use linkfield::db;
+use linkfield::file_cache::meta::{CaseInsensitivePath, FileCachePath};
+use linkfield::file_cache::{CaseSensitivity, FileCache};
+use std::collections::HashSet;
+use std::path::Path;
+
+fn cache(case_sensitivity: CaseSensitivity) -> std::sync::Arc<FileCache> {
+	FileCache::builder("root")
+		.case_sensitivity(case_sensitivity)
+		.build()
+}
+
+#[test]
+fn test_insensitive_paths_resolve_to_the_same_entry() {
+	let cache = cache(CaseSensitivity::Insensitive);
+	assert_eq!(cache.case_sensitivity(), CaseSensitivity::Insensitive);
+	cache.insert_meta(test_meta("root/Docs/Foo.txt", 1));
+	cache.insert_meta(test_meta("root/docs/foo.txt", 2));
+	assert_eq!(cache.len(), 1);
+	assert_eq!(cache.get(Path::new("root/DOCS/FOO.TXT")).unwrap().size, 2);
+	assert!(cache.contains_path(Path::new("root/Docs/Foo.txt")));
+	assert!(cache.contains_directory(Path::new("ROOT/DOCS")));
+
+	// The entry takes the name of the latest update, e.g. after a case-only rename
+	let key = cache.find_entry_by_path("root/docs/foo.txt").unwrap();
+	assert_eq!(cache.entries.get(&key).unwrap().name, "foo.txt");
+
+	// Subsets compare paths the same way
+	let subset = cache.clone_subset(|_, _| true);
+	assert!(subset.contains_path(Path::new("root/docs/FOO.txt")));
+}
+
+#[test]
+fn test_sensitive_paths_stay_apart() {
+	let cache = cache(CaseSensitivity::Sensitive);
+	cache.insert_meta(test_meta("root/Foo.txt", 1));
+	cache.insert_meta(test_meta("root/foo.txt", 2));
+	assert_eq!(cache.len(), 2);
+	assert_eq!(cache.get(Path::new("root/Foo.txt")).unwrap().size, 1);
+	assert!(cache.get(Path::new("root/FOO.txt")).is_none());
+	assert!(!cache.contains_directory(Path::new("ROOT")));
+}
+
+#[test]
+fn test_insensitive_prefixes_and_stored_rows_ignore_case() {
+	let dir = VirtualFs::new();
+	let cache = cache(CaseSensitivity::Insensitive);
+	cache.insert_meta(test_meta("root/Docs/Foo.txt", 1));
+	cache.insert_meta(test_meta("root/Docs/Sub/bar.txt", 2));
+	cache
+		.set_db(db::open_or_create_db(&dir.path("case.redb")).unwrap())
+		.unwrap();
+	let database = cache.database().unwrap();
+	let stored = || {
+		let mut paths = Vec::new();
+		FileCache::stream_from_redb(&database, |path, _| paths.push(path.to_string())).unwrap();
+		paths
+	};
+
+	// A case-only rename drops the row stored under the old case
+	cache.insert_meta(test_meta("root/docs/foo.txt", 1));
+	assert_eq!(stored(), ["root/Docs/Sub/bar.txt"]);
+
+	let subdirs: Vec<_> = cache.subdirectories_of(Path::new("ROOT/docs")).collect();
+	assert_eq!(subdirs, [FileCachePath::from(Path::new("root/Docs/Sub"))]);
+	assert_eq!(
+		cache.remove_prefix(Some(&database), Path::new("ROOT/DOCS")),
+		2
+	);
+	assert!(cache.is_empty());
+	assert!(!cache.contains_directory(Path::new("root/Docs/Sub")));
+	assert!(stored().is_empty());
+}
+
+#[test]
+fn test_case_insensitive_path_keeps_its_case() {
+	let upper = CaseInsensitivePath::from(Path::new("/a/Foo.txt"));
+	let lower = CaseInsensitivePath::from(Path::new("/a/foo.txt"));
+	assert_eq!(upper, lower);
+	assert_eq!(HashSet::from([upper.clone(), lower]).len(), 1);
+	assert_eq!(
+		upper.to_string(),
+		FileCachePath::from(Path::new("/a/Foo.txt")).to_string()
+	);
+	assert_ne!(upper, CaseInsensitivePath::from(Path::new("/a/bar.txt")));
+}
+
+#[test]
+fn test_auto_detects_the_root_file_system() {
+	let dir = VirtualFs::new();
+	let detected = CaseSensitivity::detect(dir.root()).unwrap();
+	#[cfg(target_os = "linux")]
+	assert_eq!(detected, CaseSensitivity::Sensitive);
+	// The probe file is gone again
+	assert_eq!(std::fs::read_dir(dir.root()).unwrap().count(), 0);
+
+	// The probe goes into the given directory, never into the watched root
+	let root = dir.path("watched");
+	let probe_dir = dir.path("db");
+	std::fs::create_dir_all(&root).unwrap();
+	std::fs::create_dir_all(&probe_dir).unwrap();
+	let auto = FileCache::builder(root.to_string_lossy().as_ref())
+		.case_sensitivity(CaseSensitivity::Auto)
+		.case_probe_dir(&probe_dir)
+		.build();
+	assert_eq!(auto.case_sensitivity(), detected);
+	assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+	assert_eq!(std::fs::read_dir(&probe_dir).unwrap().count(), 0);
+
+	// By default the root is probed, or its parent when it must stay untouched
+	let auto = |outside_root: bool| {
+		FileCache::builder(root.to_string_lossy().as_ref())
+			.case_sensitivity(CaseSensitivity::Auto)
+			.probe_outside_root(outside_root)
+			.build()
+			.case_sensitivity()
+	};
+	assert_eq!(auto(false), detected);
+	assert_eq!(auto(true), detected);
+	assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+	assert_eq!(std::fs::read_dir(dir.root()).unwrap().count(), 2);
+	// A probe directory that can't be written falls back to the temporary directory
+	let missing = FileCache::builder(root.to_string_lossy().as_ref())
+		.case_sensitivity(CaseSensitivity::Auto)
+		.case_probe_dir(&dir.path("missing"))
+		.build();
+	assert_eq!(
+		missing.case_sensitivity(),
+		CaseSensitivity::detect(&std::env::temp_dir()).unwrap()
+	);
+	// A root that isn't a directory can't be probed
+	assert_eq!(
+		cache(CaseSensitivity::Auto).case_sensitivity(),
+		CaseSensitivity::Sensitive
+	);
+}
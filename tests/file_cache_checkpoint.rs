@@ -0,0 +1,81 @@
+//! Integration tests: named checkpoints and "changed since" diffs
+
+mod common;
+
+use assert_cmd::Command;
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::IgnoreConfig;
+use std::sync::Arc;
+
+fn scan(vfs: &VirtualFs) -> Arc<FileCache> {
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+	cache
+}
+
+#[test]
+fn test_changed_since_checkpoint() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"CREATE keep.txt 1\nCREATE grow.txt 1\nCREATE gone.txt 1\nCREATE sub/x.txt 1",
+	)
+	.unwrap();
+	let db_dir = VirtualFs::new();
+	let db = redb::Database::create(db_dir.path("checkpoint.redb")).unwrap();
+	assert_eq!(scan(&vfs).record_checkpoint(&db, "build-1").unwrap(), 4);
+
+	vfs.replay_script("CREATE grow.txt 10\nDELETE gone.txt\nCREATE sub/new.txt 1")
+		.unwrap();
+	let diff = scan(&vfs).changed_since_checkpoint(&db, "build-1").unwrap();
+
+	assert_eq!(
+		diff.added
+			.iter()
+			.map(|m| m.path.0.clone())
+			.collect::<Vec<_>>(),
+		vec![vfs.path("sub/new.txt")]
+	);
+	assert_eq!(
+		diff.modified
+			.iter()
+			.map(|(_, m)| m.path.0.clone())
+			.collect::<Vec<_>>(),
+		vec![vfs.path("grow.txt")]
+	);
+	assert_eq!(
+		diff.removed
+			.iter()
+			.map(|m| m.path.0.clone())
+			.collect::<Vec<_>>(),
+		vec![vfs.path("gone.txt")]
+	);
+	assert!(scan(&vfs).changed_since_checkpoint(&db, "missing").is_err());
+}
+
+#[test]
+fn test_checkpoint_cli_save_and_diff() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 1\nCREATE b.txt 1").unwrap();
+	let linkfield = || Command::cargo_bin("linkfield").unwrap();
+	linkfield()
+		.args(["checkpoint", "save", "v1"])
+		.arg(vfs.root())
+		.assert()
+		.success();
+
+	vfs.replay_script("DELETE a.txt\nCREATE c.txt 1").unwrap();
+	let output = linkfield()
+		.args(["checkpoint", "diff", "v1"])
+		.arg(vfs.root())
+		.output()
+		.unwrap();
+	assert!(output.status.success());
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	let expected = format!(
+		"D {}\nA {}\n",
+		vfs.path("a.txt").display(),
+		vfs.path("c.txt").display()
+	);
+	assert_eq!(stdout, expected);
+}
@@ -0,0 +1,33 @@
+//! Integration test: membership checks on cached paths
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+
+#[test]
+fn test_contains_path_and_sets() {
+	let vfs = VirtualFs::new();
+	let cache = FileCache::new_root("root");
+	let a = vfs.create_file("dir/a.txt", 1);
+	let b = vfs.create_file("dir/b.txt", 1);
+	cache.update_file(&a);
+	cache.update_file(&b);
+	let missing = vfs.path("dir/missing.txt");
+
+	assert!(cache.contains_path(&a));
+	assert!(!cache.contains_path(&missing));
+	// Directories are not files
+	assert!(!cache.contains_path(&vfs.path("dir")));
+
+	assert!(cache.contains_any_of(&[&missing, &b]));
+	assert!(!cache.contains_any_of(&[&missing]));
+	assert!(!cache.contains_any_of(&[]));
+	assert!(cache.contains_all_of(&[&a, &b]));
+	assert!(!cache.contains_all_of(&[&a, &missing]));
+	assert!(cache.contains_all_of(&[]));
+
+	cache.remove_file(&a);
+	assert!(!cache.contains_path(&a));
+	assert_eq!(cache.contains_path(&b), cache.get(&b).is_some());
+}
@@ -0,0 +1,96 @@
+//! Integration tests: duplicate file report
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::file_cache::dedup::HashGranularity;
+use linkfield::ignore_config::IgnoreConfig;
+
+fn write(vfs: &VirtualFs, name: &str, contents: &[u8]) {
+	let path = vfs.path(name);
+	std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+	std::fs::write(path, contents).unwrap();
+}
+
+#[test]
+fn test_deduplication_report() {
+	let vfs = VirtualFs::new();
+	let big = vec![7u8; 4096];
+	write(&vfs, "a/big.bin", &big);
+	write(&vfs, "b/big_copy.bin", &big);
+	write(&vfs, "c/big_copy2.bin", &big);
+	write(&vfs, "small1.txt", b"hello");
+	write(&vfs, "small2.txt", b"hello");
+	// Same size as the small pair, different content
+	write(&vfs, "small3.txt", b"world");
+	write(&vfs, "unique.txt", b"only one");
+
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+	let report = cache.deduplication_report();
+
+	assert_eq!(report.duplicate_groups.len(), 2);
+	let biggest = &report.duplicate_groups[0];
+	assert_eq!(biggest.size_each, 4096);
+	assert_eq!(biggest.paths.len(), 3);
+	assert_eq!(biggest.paths[0].0, vfs.path("a/big.bin"));
+	let small = &report.duplicate_groups[1];
+	let small_paths: Vec<_> = small.paths.iter().map(|p| p.0.clone()).collect();
+	assert_eq!(
+		small_paths,
+		vec![vfs.path("small1.txt"), vfs.path("small2.txt")]
+	);
+	assert_eq!(report.total_wasted_bytes, 2 * 4096 + 5);
+
+	let table = report.format_table();
+	assert!(table.contains("big_copy.bin"));
+	assert!(table.contains("2 duplicate groups, 8197 bytes wasted"));
+
+	// --min-size drops the tiny group
+	let filtered = cache.deduplication_report_with(HashGranularity::Full, 100);
+	assert_eq!(filtered.duplicate_groups.len(), 1);
+	assert_eq!(filtered.total_wasted_bytes, 2 * 4096);
+}
+
+#[test]
+fn test_prefix_granularity_groups_shared_prefix() {
+	let vfs = VirtualFs::new();
+	write(&vfs, "x.bin", b"same-prefix-AAAA");
+	write(&vfs, "y.bin", b"same-prefix-BBBB");
+
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+
+	assert!(cache.deduplication_report().duplicate_groups.is_empty());
+	let prefix = cache.deduplication_report_with(HashGranularity::Prefix(11), 1);
+	assert_eq!(prefix.duplicate_groups.len(), 1);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_locked_files_are_not_hashed() {
+	use fs2::FileExt;
+
+	let vfs = VirtualFs::new();
+	write(&vfs, "a.bin", b"same contents");
+	write(&vfs, "b.bin", b"same contents");
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+
+	let writer = std::fs::OpenOptions::new()
+		.write(true)
+		.open(vfs.path("b.bin"))
+		.unwrap();
+	writer.lock_exclusive().unwrap();
+	assert!(linkfield::platform::is_file_locked(&vfs.path("b.bin")));
+	assert!(!linkfield::platform::is_file_locked(&vfs.path("a.bin")));
+	assert!(cache.deduplication_report().duplicate_groups.is_empty());
+	let unchecked = cache.deduplication_report_with_options(HashGranularity::Full, 1, false);
+	assert_eq!(unchecked.duplicate_groups.len(), 1);
+
+	writer.unlock().unwrap();
+	drop(writer);
+	assert!(!linkfield::platform::is_file_locked(&vfs.path("b.bin")));
+	assert_eq!(cache.deduplication_report().duplicate_groups.len(), 1);
+}
@@ -0,0 +1,105 @@
+//! Integration tests: text and JSON reports of a diff
+
+mod common;
+
+use assert_cmd::Command;
+use common::VirtualFs;
+use linkfield::file_cache::diff::diff_file_maps;
+use linkfield::file_cache::{DiffReport, DiffResult, FileCache};
+use linkfield::ignore_config::IgnoreConfig;
+
+fn known_diff(vfs: &VirtualFs) -> DiffResult {
+	vfs.replay_script("CREATE keep.txt 1\nCREATE grow.txt 5\nCREATE gone.txt 7")
+		.unwrap();
+	let before = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	vfs.replay_script(
+		"CREATE grow.txt 50\nDELETE gone.txt\nCREATE small.txt 2\nCREATE big.txt 300\nCREATE mid.txt 40",
+	)
+	.unwrap();
+	let after = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	diff_file_maps(&before.file_map(), &after.file_map())
+}
+
+#[test]
+fn test_diff_report_lists_largest_changes_first() {
+	let vfs = VirtualFs::new();
+	let diff = known_diff(&vfs);
+
+	let report = FileCache::diff_report(&diff, 2);
+
+	assert!(
+		report.starts_with("3 added, 1 removed, 1 modified\n"),
+		"{report}"
+	);
+	let big = format!("  + {} (300 bytes)", vfs.path("big.txt").display());
+	let mid = format!("  + {} (40 bytes)", vfs.path("mid.txt").display());
+	assert!(
+		report.find(&big).unwrap() < report.find(&mid).unwrap(),
+		"{report}"
+	);
+	assert!(!report.contains("small.txt"), "{report}");
+	assert!(report.contains("  ... and 1 more added\n"), "{report}");
+	assert!(report.contains(&format!("  - {} (7 bytes)", vfs.path("gone.txt").display())));
+	assert!(report.contains(&format!(
+		"  ~ {} (5 -> 50 bytes)",
+		vfs.path("grow.txt").display()
+	)));
+	assert!(!report.contains("more removed"));
+}
+
+#[test]
+fn test_diff_report_json_keeps_totals() {
+	let vfs = VirtualFs::new();
+	let diff = known_diff(&vfs);
+
+	let json: serde_json::Value =
+		serde_json::from_str(&DiffReport::new(&diff, 1).to_json()).unwrap();
+
+	assert_eq!(json["added_total"], 3);
+	assert_eq!(json["added"].as_array().unwrap().len(), 1);
+	assert_eq!(json["added"][0]["size"], 300);
+	assert_eq!(json["modified"][0]["old_size"], 5);
+	assert_eq!(json["modified"][0]["new_size"], 50);
+}
+
+#[test]
+fn test_diff_report_of_empty_diff() {
+	assert_eq!(
+		FileCache::diff_report(&DiffResult::default(), 10),
+		"0 added, 0 removed, 0 modified\n"
+	);
+}
+
+#[test]
+fn test_scan_cli_prints_the_report() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 1\nCREATE b.txt 2").unwrap();
+	let scan = |args: &[&str]| {
+		let output = Command::cargo_bin("linkfield")
+			.unwrap()
+			.arg("scan")
+			.arg(vfs.root())
+			.args(args)
+			.output()
+			.unwrap();
+		assert!(output.status.success());
+		String::from_utf8(output.stdout).unwrap()
+	};
+	scan(&[]);
+
+	vfs.replay_script("DELETE a.txt\nCREATE c.txt 30\nCREATE d.txt 40")
+		.unwrap();
+	let report = scan(&["--report", "1"]);
+	assert!(
+		report.starts_with("2 added, 1 removed, 0 modified\n"),
+		"{report}"
+	);
+	assert!(report.contains(&format!("  + {} (40 bytes)", vfs.path("d.txt").display())));
+	assert!(report.contains("  ... and 1 more added\n"), "{report}");
+
+	vfs.create_file("b.txt", 20);
+	let json: serde_json::Value =
+		serde_json::from_str(&scan(&["--report", "--output-format", "json"])).unwrap();
+	assert_eq!(json["modified_total"], 1);
+	assert_eq!(json["modified"][0]["new_size"], 20);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::file_cache::db::update_redb_batch_commit;
+use linkfield::file_cache::dir_index::read_dir_index;
+use linkfield::file_cache::meta::FileCachePath;
+use linkfield::ignore_config::IgnoreConfig;
+use std::path::Path;
+
+fn children(database: &redb::Database, dir: &Path) -> Vec<String> {
+	read_dir_index(database, dir)
+		.unwrap()
+		.map(|index| index.children)
+		.unwrap_or_default()
+}
+
+#[test]
+fn test_dir_index_tracks_adds_removes_and_renames() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("docs/b.txt", 2);
+	vfs.create_file("docs/a.txt", 1);
+	vfs.create_file("docs/nested/c.txt", 3);
+	vfs.create_file("docs2/d.txt", 4);
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+	let dir = VirtualFs::new();
+	let database = linkfield::db::open_or_create_db(&dir.path("i.redb")).unwrap();
+	let all: Vec<_> = cache
+		.all_files()
+		.into_iter()
+		.map(|meta| (meta.path.clone(), meta))
+		.collect();
+	update_redb_batch_commit(&database, &[], &all).unwrap();
+
+	assert_eq!(children(&database, &vfs.path("docs")), ["a.txt", "b.txt"]);
+	assert_eq!(children(&database, &vfs.path("docs/nested")), ["c.txt"]);
+	let files = FileCache::files_in_directory_from_db(&database, &vfs.path("docs")).unwrap();
+	let mut sizes: Vec<u64> = files.iter().map(|meta| meta.size).collect();
+	sizes.sort_unstable();
+	assert_eq!(sizes, [1, 2]);
+
+	update_redb_batch_commit(&database, &[FileCachePath(vfs.path("docs/a.txt"))], &[]).unwrap();
+	assert_eq!(children(&database, &vfs.path("docs")), ["b.txt"]);
+
+	// Renaming a file into another directory moves it between the two lists
+	let moved = all
+		.iter()
+		.find(|(path, _)| path.0 == vfs.path("docs/b.txt"))
+		.map(|(_, meta)| {
+			let mut meta = meta.clone();
+			meta.path = FileCachePath(vfs.path("docs2/b.txt"));
+			(meta.path.clone(), meta)
+		})
+		.unwrap();
+	update_redb_batch_commit(
+		&database,
+		&[FileCachePath(vfs.path("docs/b.txt"))],
+		&[moved],
+	)
+	.unwrap();
+	assert!(
+		read_dir_index(&database, &vfs.path("docs"))
+			.unwrap()
+			.is_none()
+	);
+	assert_eq!(children(&database, &vfs.path("docs2")), ["b.txt", "d.txt"]);
+
+	FileCache::remove_prefix_from_db_only(&database, &vfs.path("docs")).unwrap();
+	assert!(children(&database, &vfs.path("docs/nested")).is_empty());
+	assert_eq!(children(&database, &vfs.path("docs2")), ["b.txt", "d.txt"]);
+	assert_eq!(
+		FileCache::files_in_directory_from_db(&database, &vfs.path("docs2"))
+			.unwrap()
+			.len(),
+		2
+	);
+}
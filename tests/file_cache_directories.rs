@@ -0,0 +1,191 @@
+//! Integration tests: directory tracking in the file cache
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::IgnoreConfig;
+
+#[test]
+fn test_directory_tracking_and_removal() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"CREATE top.txt 1\n\
+		 CREATE a/one.txt 1\n\
+		 CREATE a/deep/two.txt 1\n\
+		 CREATE b/three.txt 1",
+	)
+	.unwrap();
+	std::fs::create_dir(vfs.path("empty")).unwrap();
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+
+	// root, a, a/deep, b, empty
+	assert_eq!(cache.watch_dir_count(), 5);
+	assert!(cache.contains_directory(&vfs.path("a/deep")));
+	assert!(cache.contains_directory(&vfs.path("empty")));
+	assert!(!cache.contains_directory(&vfs.path("top.txt")));
+	let mut subdirs: Vec<_> = cache
+		.subdirectories_of(&vfs.path("a"))
+		.map(|d| d.0)
+		.collect();
+	subdirs.sort();
+	assert_eq!(subdirs, vec![vfs.path("a/deep")]);
+
+	cache.remove_directory(&vfs.path("a"));
+	assert_eq!(cache.watch_dir_count(), 3);
+	// root, b, empty and the two remaining files: no entries left over from a/ and a/deep/
+	assert_eq!(cache.entries.len(), 5);
+	assert!(!cache.contains_directory(&vfs.path("a/deep")));
+	let mut remaining: Vec<_> = cache.all_files().into_iter().map(|m| m.path.0).collect();
+	remaining.sort();
+	assert_eq!(
+		remaining,
+		vec![vfs.path("b/three.txt"), vfs.path("top.txt")]
+	);
+}
+
+#[test]
+fn test_scan_collect_descends_into_subdirectories() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"CREATE top.txt 1\n\
+		 CREATE a/b/c/deep.txt 1\n\
+		 CREATE skip/hidden.txt 1\n\
+		 CREATE a/skip/also_hidden.txt 1",
+	)
+	.unwrap();
+	let cache = FileCache::new_root("root");
+	let errors = cache.scan_dir_collect_with_ignore(
+		vfs.root(),
+		&IgnoreConfig::new(&["skip/"]).unwrap(),
+		None,
+	);
+	assert!(errors.is_empty());
+
+	let mut files: Vec<_> = cache.all_files().into_iter().map(|m| m.path.0).collect();
+	files.sort();
+	assert_eq!(files, vec![vfs.path("a/b/c/deep.txt"), vfs.path("top.txt")]);
+	assert!(cache.contains_directory(&vfs.path("a/b/c")));
+	// Ignored directories are not descended into
+	assert!(!cache.contains_directory(&vfs.path("skip")));
+	assert!(!cache.contains_directory(&vfs.path("a/skip")));
+}
+
+#[test]
+fn test_len_counts_files_not_directories() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE top.txt 1\nCREATE a/one.txt 1\nCREATE a/deep/two.txt 1")
+		.unwrap();
+	let cache = FileCache::new_root("root");
+	assert!(cache.is_empty());
+	assert_eq!(cache.len(), 0);
+
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+	assert!(!cache.is_empty());
+	assert_eq!(cache.len(), 3);
+	assert!(cache.capacity() >= cache.entries.len());
+	let pairs: Vec<_> = (&*cache).into_iter().collect();
+	assert_eq!(pairs.len(), 3);
+	assert!(pairs.iter().all(|(path, meta)| *path == meta.path));
+
+	cache.remove_directory(&vfs.path("a"));
+	assert_eq!(cache.len(), 1);
+
+	// Rescanning replaces the cached files instead of adding to the count
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+	assert_eq!(cache.len(), 3);
+	let top = vfs.path("top.txt");
+	assert_eq!(cache.remove_files_where(None, |meta| meta.path.0 == top), 1);
+	assert_eq!(cache.len(), 2);
+	cache.clear();
+	assert!(cache.is_empty());
+}
+
+#[test]
+fn test_remove_prefix_removes_subtree_from_cache_and_db() {
+	use linkfield::file_cache::db::{FILE_CACHE_TABLE, update_redb_batch_commit};
+	use redb::ReadableTableMetadata;
+
+	let vfs = VirtualFs::new();
+	for i in 0..1000 {
+		vfs.create_file(&format!("big/nested/f{i}.txt"), 1);
+	}
+	vfs.create_file("big2/keep.txt", 1);
+	vfs.create_file("keep.txt", 1);
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+	let dir = VirtualFs::new();
+	let database = linkfield::db::open_or_create_db(&dir.path("p.redb")).unwrap();
+	let all: Vec<_> = cache
+		.all_files()
+		.into_iter()
+		.map(|meta| (meta.path.clone(), meta))
+		.collect();
+	update_redb_batch_commit(&database, &[], &all).unwrap();
+	let stored = || {
+		database
+			.begin_read()
+			.unwrap()
+			.open_table(FILE_CACHE_TABLE)
+			.unwrap()
+			.len()
+			.unwrap()
+	};
+	assert_eq!(stored(), 1002);
+
+	assert_eq!(cache.remove_prefix(Some(&database), &vfs.path("big")), 1000);
+	assert_eq!(cache.len(), 2);
+	assert!(!cache.contains_directory(&vfs.path("big/nested")));
+	assert_eq!(stored(), 2);
+
+	// Cold eviction leaves the sibling with a shared name prefix alone
+	update_redb_batch_commit(&database, &[], &all).unwrap();
+	assert_eq!(
+		FileCache::remove_prefix_from_db_only(&database, &vfs.path("big")).unwrap(),
+		1000
+	);
+	assert_eq!(stored(), 2);
+}
+
+#[test]
+fn test_apply_rename_moves_subtree_in_one_batch() {
+	use linkfield::file_cache::db::FILE_CACHE_TABLE;
+	use redb::ReadableTable;
+
+	let vfs = VirtualFs::new();
+	for i in 0..1000 {
+		vfs.create_file(&format!("old/nested/f{i}.txt"), 1);
+	}
+	vfs.create_file("old2/keep.txt", 1);
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+	let dir = VirtualFs::new();
+	let database = linkfield::db::open_or_create_db(&dir.path("r.redb")).unwrap();
+
+	std::fs::rename(vfs.path("old"), vfs.path("new")).unwrap();
+	let moved = cache.apply_rename(Some(&database), &vfs.path("old"), &vfs.path("new"));
+	assert_eq!(moved, 1000);
+	assert_eq!(cache.len(), 1001);
+	assert!(cache.get(&vfs.path("old/nested/f0.txt")).is_none());
+	let meta = cache.get(&vfs.path("new/nested/f999.txt")).unwrap();
+	assert_eq!(meta.path.0, vfs.path("new/nested/f999.txt"));
+	assert!(cache.all_paths().any(|p| p.0 == vfs.path("old2/keep.txt")));
+	assert!(cache.contains_directory(&vfs.path("new/nested")));
+	assert!(!cache.contains_directory(&vfs.path("old")));
+
+	let read_txn = database.begin_read().unwrap();
+	let table = read_txn.open_table(FILE_CACHE_TABLE).unwrap();
+	let stored: Vec<_> = table
+		.iter()
+		.unwrap()
+		.map(|entry| entry.unwrap().0.value().to_string())
+		.collect();
+	assert_eq!(stored.len(), 1000);
+	let new_prefix = vfs.path("new");
+	assert!(
+		stored
+			.iter()
+			.all(|key| std::path::Path::new(key).starts_with(&new_prefix))
+	);
+}
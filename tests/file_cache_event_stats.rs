@@ -0,0 +1,69 @@
+//! Integration test: counting the file events the watcher applies to the cache
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::file_cache::event_stats::{EVENT_RATE_WINDOW_SECS, EventCounts};
+use linkfield::move_heuristics::MoveHeuristics;
+use linkfield::watcher::replay_events;
+use notify_debouncer_full::DebouncedEvent;
+use notify_debouncer_full::notify::Event;
+use notify_debouncer_full::notify::event::{
+	CreateKind, DataChange, EventKind, ModifyKind, RemoveKind, RenameMode,
+};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn event(kind: EventKind, paths: Vec<PathBuf>) -> DebouncedEvent {
+	let event = paths.into_iter().fold(Event::new(kind), Event::add_path);
+	DebouncedEvent::new(event, Instant::now())
+}
+
+#[test]
+fn test_event_counts_skip_directory_events() {
+	let vfs = VirtualFs::new();
+	let cache = FileCache::new_root("root");
+	let heuristics = Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5))));
+	let a = vfs.create_file("a.txt", 1);
+	let b = vfs.create_file("b.txt", 2);
+	std::fs::create_dir(vfs.path("dir")).unwrap();
+	let events = vec![
+		event(EventKind::Create(CreateKind::File), vec![a.clone()]),
+		event(EventKind::Create(CreateKind::File), vec![b.clone()]),
+		event(EventKind::Create(CreateKind::Folder), vec![vfs.path("dir")]),
+		event(
+			EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+			vec![a.clone()],
+		),
+	];
+	let file_cache = Arc::new(Mutex::new(cache.clone()));
+	replay_events(events, &file_cache, &heuristics);
+
+	vfs.rename_file("a.txt", "c.txt");
+	vfs.delete_file("b.txt");
+	let events = vec![
+		event(
+			EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+			vec![a, vfs.path("c.txt")],
+		),
+		event(EventKind::Remove(RemoveKind::File), vec![b]),
+	];
+	replay_events(events, &file_cache, &heuristics);
+
+	assert_eq!(
+		cache.event_counts(),
+		EventCounts {
+			creates: 2,
+			removes: 1,
+			modifies: 1,
+			renames: 1,
+		}
+	);
+	assert_eq!(cache.watch_event_count(), 5);
+	let rate = cache.event_rate();
+	assert!(rate.window_secs >= 1.0);
+	assert!(rate.window_secs <= EVENT_RATE_WINDOW_SECS as f64);
+	assert!((rate.events_per_second * rate.window_secs - 5.0).abs() < 1e-9);
+}
@@ -0,0 +1,123 @@
+//! Integration test: path list export for other tools
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::{BackupExportOptions, FileCache};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+fn cache_with_files(names: &[&str]) -> (VirtualFs, Arc<FileCache>) {
+	let vfs = VirtualFs::new();
+	let cache = FileCache::new_root("root");
+	for name in names {
+		cache.update_file(&vfs.create_file(name, 4));
+	}
+	(vfs, cache)
+}
+
+#[test]
+fn test_export_path_list_newline() {
+	let (vfs, cache) = cache_with_files(&["b.txt", "a.rs"]);
+	let mut out = Vec::new();
+	cache.export_path_list(&mut out, b'\n').unwrap();
+	let expected = format!(
+		"{}\n{}\n",
+		vfs.path("a.rs").display(),
+		vfs.path("b.txt").display()
+	);
+	assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+	let mut filtered = Vec::new();
+	cache
+		.export_path_list_filtered(&mut filtered, b'\n', |m| {
+			m.extension.as_deref() == Some("rs")
+		})
+		.unwrap();
+	assert_eq!(
+		String::from_utf8(filtered).unwrap(),
+		format!("{}\n", vfs.path("a.rs").display())
+	);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_export_null_path_list_roundtrips_through_xargs() {
+	use std::io::Write;
+	use std::process::{Command, Stdio};
+	let (vfs, cache) = cache_with_files(&["plain.txt", "with\nnewline.txt", "with space.txt"]);
+	let mut out = Vec::new();
+	cache.export_path_list(&mut out, b'\0').unwrap();
+
+	let mut child = Command::new("xargs")
+		.args(["-0", "-n1", "printf", "%s\\0"])
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.spawn()
+		.unwrap();
+	child.stdin.take().unwrap().write_all(&out).unwrap();
+	let output = child.wait_with_output().unwrap();
+	assert!(output.status.success());
+	assert_eq!(output.stdout, out);
+	let records = out.split(|b| *b == 0).filter(|r| !r.is_empty()).count();
+	assert_eq!(records, 3);
+	drop(vfs);
+}
+
+#[test]
+fn test_all_paths_matches_all_files() {
+	let (vfs, cache) = cache_with_files(&["b.txt", "a.rs", "c.md"]);
+	let paths = cache.all_paths_vec();
+	assert_eq!(
+		paths.iter().map(|p| p.0.clone()).collect::<Vec<_>>(),
+		vec![vfs.path("a.rs"), vfs.path("b.txt"), vfs.path("c.md")]
+	);
+	let mut from_files: Vec<_> = cache.all_files().into_iter().map(|m| m.path).collect();
+	from_files.sort();
+	assert_eq!(paths, from_files);
+	assert_eq!(cache.all_paths().count(), 3);
+}
+
+#[test]
+fn test_export_for_backup_is_stable() {
+	let (vfs, cache) = cache_with_files(&["b.txt", "a.rs"]);
+	let export = |options| {
+		let mut out = Vec::new();
+		cache
+			.export_for_backup_with_options(&mut out, options)
+			.unwrap();
+		out
+	};
+	let mut first = Vec::new();
+	cache.export_for_backup(&mut first).unwrap();
+	assert_eq!(first, export(BackupExportOptions::default()));
+	let records: serde_json::Value = serde_json::from_slice(&first).unwrap();
+	assert_eq!(
+		records[0],
+		serde_json::json!({"path": vfs.path("a.rs"), "size": 4, "extension": "rs"})
+	);
+
+	// Rewriting a file with the same contents leaves the export as it was
+	let with_modified = BackupExportOptions {
+		include_modified: true,
+		..BackupExportOptions::default()
+	};
+	let before = export(with_modified);
+	vfs.set_mtime(
+		"a.rs",
+		SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000),
+	);
+	cache.update_file(&vfs.path("a.rs"));
+	assert_eq!(export(BackupExportOptions::default()), first);
+	assert_ne!(export(with_modified), before);
+
+	let everything: serde_json::Value = serde_json::from_slice(&export(BackupExportOptions {
+		include_modified: true,
+		include_created: true,
+		include_hash: true,
+	}))
+	.unwrap();
+	let fields: Vec<_> = everything[0].as_object().unwrap().keys().cloned().collect();
+	assert_eq!(fields.len(), 6, "{fields:?}");
+	assert!(everything[0]["content_hash"].is_null());
+}
@@ -0,0 +1,118 @@
+//! Integration tests: normalizing the cached extensions of files
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::{ExtensionMapping, ExtensionNormalizer, FileCache, ScanConfig};
+use linkfield::ignore_config::IgnoreConfig;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn extension(cache: &FileCache, vfs: &VirtualFs, name: &str) -> Option<String> {
+	let path = vfs.path(name);
+	cache
+		.all_files()
+		.into_iter()
+		.find(|meta| meta.path.0 == path)
+		.and_then(|meta| meta.extension)
+}
+
+#[test]
+fn test_normalized_extensions_are_queried_together() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"CREATE a.jpg 10\nCREATE b.jpeg 20\nCREATE c.jpeg 30\nCREATE index.htm 5\nCREATE page.html 5",
+	)
+	.unwrap();
+	let db_dir = VirtualFs::new();
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	cache
+		.set_db(db::open_or_create_db(&db_dir.path("cache.redb")).unwrap())
+		.unwrap();
+	let database = cache.detach_db().unwrap();
+
+	assert_eq!(cache.rename_extension(Some(&database), "jpeg", "jpg"), 2);
+	assert_eq!(cache.rename_extension(Some(&database), "jpeg", "jpg"), 0);
+	let mapping = HashMap::from([("htm", "html"), ("jpg", "jpg")]);
+	assert_eq!(cache.normalize_extensions(Some(&database), &mapping), 1);
+
+	assert_eq!(
+		cache.path_count_by_extension_sorted(),
+		vec![
+			(Some("jpg".to_string()), 3, 60),
+			(Some("html".to_string()), 2, 10),
+		]
+	);
+	// The files on disk keep their names, the database has the normalized metadata
+	assert!(vfs.path("b.jpeg").exists());
+	let mut stored_extensions = Vec::new();
+	FileCache::stream_from_redb(&database, |_, meta| stored_extensions.push(meta.extension))
+		.unwrap();
+	stored_extensions.sort();
+	assert_eq!(
+		stored_extensions,
+		[
+			Some("html"),
+			Some("html"),
+			Some("jpg"),
+			Some("jpg"),
+			Some("jpg")
+		]
+		.map(|ext| ext.map(str::to_string))
+	);
+}
+
+#[test]
+fn test_extension_normalizer_mappings() {
+	let mut normalizer = ExtensionNormalizer::default();
+	assert_eq!(normalizer.normalize("jpeg"), "jpg");
+	assert_eq!(normalizer.normalize("JPG"), "jpg");
+	assert_eq!(normalizer.normalize("HTM"), "html");
+	assert_eq!(normalizer.normalize("rs"), "rs");
+	normalizer.add_mapping(".TGZ", "tar.gz");
+	assert_eq!(normalizer.normalize("tgz"), "tar.gz");
+	normalizer.add_mapping("jpeg", "jpeg");
+	assert_eq!(normalizer.normalize("JPEG"), "jpeg");
+	assert_eq!(
+		ExtensionNormalizer::lowercase_only().normalize("JPEG"),
+		"jpeg"
+	);
+
+	assert_eq!(
+		"jpeg=.jpg".parse::<ExtensionMapping>().unwrap(),
+		ExtensionMapping {
+			from: "jpeg".to_string(),
+			to: "jpg".to_string(),
+		}
+	);
+	assert!("jpeg".parse::<ExtensionMapping>().is_err());
+	assert!("=jpg".parse::<ExtensionMapping>().is_err());
+}
+
+#[test]
+fn test_scan_stores_canonical_extensions() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.jpeg 10\nCREATE b.JPG 20\nCREATE c.Htm 5\nCREATE d.txt 1")
+		.unwrap();
+	let config = ScanConfig {
+		extension_normalizer: Some(Arc::new(ExtensionNormalizer::default())),
+		..ScanConfig::default()
+	};
+	let cache =
+		FileCache::populate_from_dir_with_config(vfs.root(), &IgnoreConfig::empty(), &config);
+	assert_eq!(extension(&cache, &vfs, "a.jpeg").as_deref(), Some("jpg"));
+	assert_eq!(extension(&cache, &vfs, "b.JPG").as_deref(), Some("jpg"));
+	assert_eq!(extension(&cache, &vfs, "c.Htm").as_deref(), Some("html"));
+	assert_eq!(extension(&cache, &vfs, "d.txt").as_deref(), Some("txt"));
+
+	// Files updated after the scan, e.g. by the watcher, are normalized too
+	vfs.create_file("e.jpeg", 3);
+	cache.update_file(&vfs.path("e.jpeg"));
+	assert_eq!(extension(&cache, &vfs, "e.jpeg").as_deref(), Some("jpg"));
+
+	// Without a normalizer extensions are stored as found
+	let plain = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	assert_eq!(extension(&plain, &vfs, "a.jpeg").as_deref(), Some("jpeg"));
+	assert_eq!(extension(&plain, &vfs, "b.JPG").as_deref(), Some("JPG"));
+}
@@ -0,0 +1,137 @@
+//! Integration tests: file types of cached entries, and scans skipping special files
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::meta::FileType;
+use linkfield::file_cache::{FileCache, FileMeta, ScanConfig};
+use linkfield::ignore_config::IgnoreConfig;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[test]
+fn test_from_path_reads_file_types() {
+	let vfs = VirtualFs::new();
+	let file = vfs.create_file("dir/a.txt", 3);
+	let file_type = |path: &std::path::Path| FileMeta::from_path(path).unwrap().file_type;
+	assert_eq!(file_type(&file), FileType::Regular);
+	assert_eq!(file_type(&vfs.path("dir")), FileType::Directory);
+	#[cfg(target_os = "linux")]
+	{
+		assert_eq!(file_type("/dev/null".as_ref()), FileType::Device);
+		assert_eq!(file_type("/dev/zero".as_ref()), FileType::Device);
+	}
+}
+
+#[test]
+fn test_update_file_tracks_directories() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("dir/a.txt", 3);
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	cache.update_file(&vfs.path("dir"));
+	assert!(cache.all_entries().is_empty());
+	assert!(cache.contains_directory(&vfs.path("dir")));
+}
+
+#[cfg(unix)]
+fn types(files: Vec<FileMeta>) -> BTreeMap<PathBuf, FileType> {
+	files
+		.into_iter()
+		.map(|meta| (meta.path.0, meta.file_type))
+		.collect()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_scans_skip_special_files_unless_included() {
+	let vfs = VirtualFs::new();
+	let file = vfs.create_file("a.txt", 3);
+	let pipe = vfs.path("pipe");
+	let status = std::process::Command::new("mkfifo")
+		.arg(&pipe)
+		.status()
+		.unwrap();
+	assert!(status.success());
+	let socket = vfs.path("socket");
+	let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	let regular = BTreeMap::from([(file.clone(), FileType::Regular)]);
+	assert_eq!(types(cache.all_entries()), regular);
+
+	let cache = FileCache::populate_from_dir_with_config(
+		vfs.root(),
+		&IgnoreConfig::empty(),
+		&ScanConfig {
+			include_special_files: true,
+			..ScanConfig::default()
+		},
+	);
+	assert_eq!(
+		types(cache.all_entries()),
+		BTreeMap::from([
+			(file, FileType::Regular),
+			(pipe, FileType::Pipe),
+			(socket, FileType::Socket),
+		])
+	);
+	assert_eq!(types(cache.all_files()), regular);
+
+	// Moving the directory keeps entries of every type
+	let moved = vfs.path("moved");
+	assert_eq!(cache.apply_rename(None, vfs.root(), &moved), 3);
+	assert_eq!(cache.all_entries().len(), 3);
+	assert!(
+		cache
+			.all_entries()
+			.iter()
+			.all(|meta| meta.path.0.starts_with(&moved))
+	);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_update_file_skips_special_files_and_symlinks_like_a_scan() {
+	let vfs = VirtualFs::new();
+	let file = vfs.create_file("a.txt", 3);
+	let link = vfs.path("link.txt");
+	std::os::unix::fs::symlink(&file, &link).unwrap();
+	let pipe = vfs.path("pipe");
+	let status = std::process::Command::new("mkfifo")
+		.arg(&pipe)
+		.status()
+		.unwrap();
+	assert!(status.success());
+
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	for path in [&file, &link, &pipe] {
+		cache.update_file(path);
+	}
+	assert_eq!(
+		types(cache.all_entries()),
+		BTreeMap::from([(file.clone(), FileType::Regular)])
+	);
+
+	// The settings of a scan stay with the cache for later updates
+	let cache = FileCache::populate_from_dir_with_config(
+		vfs.root(),
+		&IgnoreConfig::empty(),
+		&ScanConfig {
+			include_special_files: true,
+			..ScanConfig::default()
+		},
+	);
+	cache.clear();
+	assert_eq!(cache.update_files(&[link.clone(), pipe.clone()]), 1);
+	assert_eq!(
+		types(cache.all_entries()),
+		BTreeMap::from([(pipe, FileType::Pipe)])
+	);
+	// Followed symlinks are recorded as the file they point to
+	cache.set_follow_symlinks(true);
+	cache.update_file(&link);
+	assert_eq!(
+		cache.get(&link).map(|meta| meta.file_type),
+		Some(FileType::Regular)
+	);
+}
@@ -0,0 +1,77 @@
+//! Integration tests: how long ago the cache last changed
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::FileCache;
+use linkfield::file_cache::db::{DEFAULT_LOAD_BATCH_SIZE, update_redb_batch_commit};
+use linkfield::file_cache::freshness::{LAST_UPDATED_KEY, METADATA_TABLE, stored_last_updated};
+use linkfield::ignore_config::IgnoreConfig;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+const PAUSE: Duration = Duration::from_millis(50);
+
+#[test]
+fn test_age_resets_on_updates() {
+	let vfs = VirtualFs::new();
+	let file = vfs.create_file("a.txt", 1);
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	std::thread::sleep(PAUSE);
+	assert!(cache.cache_age() >= PAUSE);
+
+	let before = cache.last_updated_at();
+	cache.update_file(&file);
+	assert!(cache.cache_age() < PAUSE);
+	assert!(cache.last_updated_at() > before);
+
+	std::thread::sleep(PAUSE);
+	cache.remove_file(&file);
+	assert!(cache.cache_age() < PAUSE);
+
+	std::thread::sleep(PAUSE);
+	cache.diff_and_update(&HashMap::new(), None);
+	assert!(cache.cache_age() < PAUSE);
+	assert!(!cache.warn_if_stale(Duration::from_secs(60)));
+}
+
+#[test]
+fn test_age_survives_restarts() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("a.txt", 1);
+	let db = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	assert_eq!(stored_last_updated(&db).unwrap(), None);
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	let rows: Vec<_> = cache
+		.all_files()
+		.into_iter()
+		.map(|meta| (meta.path.clone(), meta))
+		.collect();
+	update_redb_batch_commit(&db, &[], &rows).unwrap();
+	let stored = stored_last_updated(&db).unwrap().unwrap();
+	assert!(SystemTime::now().duration_since(stored).unwrap() < Duration::from_secs(60));
+
+	// Pretend the last write was an hour ago
+	let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+	let millis = hour_ago
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.unwrap()
+		.as_millis() as u64;
+	let txn = db.begin_write().unwrap();
+	txn.open_table(METADATA_TABLE)
+		.unwrap()
+		.insert(LAST_UPDATED_KEY, millis)
+		.unwrap();
+	txn.commit().unwrap();
+
+	let restarted = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	restarted
+		.load_from_redb_batched(&db, DEFAULT_LOAD_BATCH_SIZE, None)
+		.unwrap();
+	let age = restarted.cache_age();
+	assert!(age >= Duration::from_secs(3600), "{age:?}");
+	assert!(age < Duration::from_secs(3660), "{age:?}");
+	assert!(restarted.warn_if_stale(Duration::from_secs(60)));
+	assert!(!restarted.warn_if_stale(Duration::from_secs(3600)));
+}
@@ -0,0 +1,64 @@
+//! Integration tests: removing cached files by glob pattern or extension
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::IgnoreConfig;
+
+const EXTENSIONS: [&str; 4] = ["tmp", "txt", "rs", "o"];
+
+fn mixed_files(vfs: &VirtualFs) {
+	for i in 0..100 {
+		let dir = if i % 2 == 0 { "src" } else { "build" };
+		vfs.create_file(&format!("{dir}/file{i}.{}", EXTENSIONS[i % 4]), 1);
+	}
+}
+
+#[test]
+fn test_extension_remove_keeps_other_files() {
+	let vfs = VirtualFs::new();
+	mixed_files(&vfs);
+	let db_dir = VirtualFs::new();
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	cache
+		.set_db(db::open_or_create_db(&db_dir.path("cache.redb")).unwrap())
+		.unwrap();
+	let database = cache.detach_db().unwrap();
+
+	assert_eq!(cache.extension_remove(Some(&database), "tmp"), 25);
+	let files = cache.all_files();
+	assert_eq!(files.len(), 75);
+	assert!(
+		files
+			.iter()
+			.all(|meta| meta.extension.as_deref() != Some("tmp"))
+	);
+	assert_eq!(
+		FileCache::stream_from_redb(&database, |_, _| {}).unwrap(),
+		75
+	);
+	// A leading dot is accepted and nothing is left to remove
+	assert_eq!(cache.extension_remove(Some(&database), ".tmp"), 0);
+}
+
+#[test]
+fn test_glob_remove_matches_full_paths() {
+	let vfs = VirtualFs::new();
+	mixed_files(&vfs);
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+
+	// Even files are under src/, odd ones under build/: build/ holds the .txt and .o files
+	assert_eq!(cache.glob_remove(None, "**/build/*.o").unwrap(), 25);
+	assert_eq!(cache.glob_remove(None, "**/*.{rs,tmp}").unwrap(), 50);
+	let remaining = cache.all_files();
+	assert_eq!(remaining.len(), 25);
+	assert!(
+		remaining
+			.iter()
+			.all(|meta| meta.path.0.starts_with(vfs.path("build"))
+				&& meta.extension.as_deref() == Some("txt"))
+	);
+	assert!(cache.glob_remove(None, "[").is_err());
+}
@@ -0,0 +1,45 @@
+//! Integration test: hard links are grouped by device and inode and counted once
+#![cfg(unix)]
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::IgnoreConfig;
+use std::os::unix::fs::MetadataExt;
+
+#[test]
+fn test_hard_links_share_a_group() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("original.bin", 100);
+	vfs.create_file("other.bin", 10);
+	std::fs::create_dir(vfs.path("sub")).unwrap();
+	std::fs::hard_link(vfs.path("original.bin"), vfs.path("sub/link.bin")).unwrap();
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+
+	let groups = cache.find_hard_links();
+	assert_eq!(groups.len(), 1);
+	let metadata = std::fs::metadata(vfs.path("original.bin")).unwrap();
+	assert_eq!(
+		groups.keys().next(),
+		Some(&(metadata.dev(), metadata.ino()))
+	);
+	let mut paths: Vec<_> = groups
+		.values()
+		.next()
+		.unwrap()
+		.iter()
+		.map(|meta| meta.path.0.clone())
+		.collect();
+	paths.sort();
+	assert_eq!(
+		paths,
+		vec![vfs.path("original.bin"), vfs.path("sub/link.bin")]
+	);
+
+	assert_eq!(cache.hard_link_count(), 2);
+	assert_eq!(cache.total_size_deduped(), 110);
+	let apparent: u64 = cache.all_files().iter().map(|meta| meta.size).sum();
+	assert_eq!(apparent, 210);
+}
@@ -0,0 +1,130 @@
+//! Integration tests: looking files up by content through the hash index
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::db::update_redb_batch_commit;
+use linkfield::file_cache::meta::FileCachePath;
+use linkfield::file_cache::{FileCache, HashWorkerPool, ensure_file_cache_table};
+use std::collections::BTreeSet;
+
+fn sorted(paths: Vec<FileCachePath>) -> BTreeSet<std::path::PathBuf> {
+	paths.into_iter().map(|path| path.0).collect()
+}
+
+fn hash_of(cache: &FileCache, path: &std::path::Path) -> u64 {
+	cache.get(path).and_then(|meta| meta.content_hash).unwrap()
+}
+
+#[test]
+fn test_duplicates_share_an_index_entry() {
+	let vfs = VirtualFs::new();
+	let cache = FileCache::builder(vfs.root().to_string_lossy().as_ref())
+		.enable_hash_index(true)
+		.build();
+	cache
+		.set_db(db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap())
+		.unwrap();
+	let database = cache.database().unwrap();
+	let pool = HashWorkerPool::new(1).unwrap();
+	let index = cache.hash_index().unwrap();
+
+	let (a, b, c) = (vfs.path("a.txt"), vfs.path("dir/b.txt"), vfs.path("c.txt"));
+	std::fs::create_dir(vfs.path("dir")).unwrap();
+	std::fs::write(&a, "same").unwrap();
+	std::fs::write(&b, "same").unwrap();
+	std::fs::write(&c, "other").unwrap();
+	cache.update_files(&[a.clone(), b.clone(), c.clone()]);
+	// Files are indexed once they are stored with a hash
+	assert_eq!(index.unique_file_count(), 0);
+	cache.populate_missing_hashes(Some(&database), 10, &pool);
+	let same = hash_of(&cache, &a);
+	assert_eq!(hash_of(&cache, &b), same);
+	assert_eq!(
+		sorted(index.lookup_by_hash(same)),
+		BTreeSet::from([a.clone(), b.clone()])
+	);
+	assert_eq!(
+		sorted(index.lookup_by_hash(hash_of(&cache, &c))),
+		BTreeSet::from([c.clone()])
+	);
+	assert_eq!(index.unique_file_count(), 2);
+
+	// A file whose contents change moves to its new hash
+	std::fs::write(&b, "other").unwrap();
+	cache.update_files(std::slice::from_ref(&b));
+	assert_eq!(
+		sorted(index.lookup_by_hash(same)),
+		BTreeSet::from([a.clone()])
+	);
+	cache.populate_missing_hashes(Some(&database), 10, &pool);
+	let other = hash_of(&cache, &c);
+	assert_eq!(
+		sorted(index.lookup_by_hash(other)),
+		BTreeSet::from([b.clone(), c.clone()])
+	);
+
+	// Renamed directories move their files, removed ones take them out of the index
+	let moved = vfs.path("moved/b.txt");
+	cache.apply_rename(Some(&database), &vfs.path("dir"), &vfs.path("moved"));
+	assert_eq!(
+		sorted(index.lookup_by_hash(other)),
+		BTreeSet::from([moved, c.clone()])
+	);
+	cache.remove_prefix(Some(&database), &vfs.path("moved"));
+	assert_eq!(sorted(index.lookup_by_hash(other)), BTreeSet::from([c]));
+
+	// Removed files leave the index, and so does a hash without files
+	cache.remove_files_where(Some(&database), |meta| meta.path.0 == a);
+	assert!(index.lookup_by_hash(same).is_empty());
+	assert_eq!(index.unique_file_count(), 1);
+}
+
+#[test]
+fn test_hash_index_is_off_by_default() {
+	let vfs = VirtualFs::new();
+	let file = vfs.create_file("a.txt", 4);
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	assert!(cache.hash_index().is_none());
+	cache.update_file(&file);
+	assert_eq!(cache.all_files()[0].content_hash, None);
+
+	// Without a database the index holds nothing
+	let indexed = FileCache::builder("root").enable_hash_index(true).build();
+	indexed.update_file(&file);
+	assert_eq!(indexed.hash_index().unwrap().unique_file_count(), 0);
+}
+
+#[test]
+fn test_set_db_indexes_hashed_files() {
+	let vfs = VirtualFs::new();
+	let (a, b, c) = (vfs.path("a.txt"), vfs.path("b.txt"), vfs.path("c.txt"));
+	std::fs::write(&a, "same").unwrap();
+	std::fs::write(&b, "same").unwrap();
+	std::fs::write(&c, "same").unwrap();
+	let pool = HashWorkerPool::new(1).unwrap();
+	// `c` is only stored, e.g. by an earlier run that kept its files in the database
+	let database = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	ensure_file_cache_table(&database).unwrap();
+	let earlier = FileCache::new_root("root");
+	earlier.update_file(&c);
+	earlier.populate_missing_hashes(None, 10, &pool);
+	let stored: Vec<_> = earlier
+		.all_files()
+		.into_iter()
+		.map(|meta| (meta.path.clone(), meta))
+		.collect();
+	update_redb_batch_commit(&database, &[], &stored).unwrap();
+
+	let cache = FileCache::builder("root").enable_hash_index(true).build();
+	cache.update_file(&a);
+	cache.update_file(&b);
+	cache.populate_missing_hashes(None, 10, &pool);
+	cache.set_db(database).unwrap();
+	let hash = hash_of(&cache, &a);
+	assert_eq!(
+		sorted(cache.hash_index().unwrap().lookup_by_hash(hash)),
+		BTreeSet::from([a, b, c])
+	);
+}
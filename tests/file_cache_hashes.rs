@@ -0,0 +1,187 @@
+//! Integration tests: backfilling content hashes of cached files
+
+mod common;
+
+use assert_cmd::cargo::CommandCargoExt;
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::db::{DEFAULT_LOAD_BATCH_SIZE, update_redb_batch_commit};
+use linkfield::file_cache::dedup::{HashGranularity, content_hash};
+use linkfield::file_cache::{FileCache, FileMeta, HashWorkerPool, ensure_file_cache_table};
+use linkfield::ignore_config::IgnoreConfig;
+use std::process::Command;
+use std::sync::Arc;
+
+fn stored_files(database: &redb::Database) -> Vec<FileMeta> {
+	let cache = FileCache::new_root("root");
+	cache
+		.load_from_redb_batched(database, DEFAULT_LOAD_BATCH_SIZE, None)
+		.unwrap();
+	cache.all_files()
+}
+
+fn store(database: &redb::Database, files: &[FileMeta]) {
+	let batch: Vec<_> = files
+		.iter()
+		.map(|meta| (meta.path.clone(), meta.clone()))
+		.collect();
+	update_redb_batch_commit(database, &[], &batch).unwrap();
+}
+
+fn scanned_and_stored(vfs: &VirtualFs, database: &redb::Database) -> Arc<FileCache> {
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	ensure_file_cache_table(database).unwrap();
+	store(database, &cache.all_files());
+	cache
+}
+
+#[test]
+fn test_backfill_hashes_every_file_in_batches() {
+	let vfs = VirtualFs::new();
+	for name in ["a.txt", "b.txt", "sub/c.txt", "sub/d.txt", "e.bin"] {
+		vfs.create_file(name, 100);
+	}
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("h.redb")).unwrap();
+	let cache = scanned_and_stored(&vfs, &database);
+	assert_eq!(cache.files_missing_hash().count(), 5);
+
+	let pool = HashWorkerPool::new(2).unwrap();
+	let mut rounds = Vec::new();
+	loop {
+		let tried = cache.populate_missing_hashes(Some(&database), 2, &pool);
+		if tried == 0 {
+			break;
+		}
+		rounds.push(tried);
+	}
+	assert_eq!(rounds, [2, 2, 1]);
+	assert_eq!(cache.files_missing_hash().count(), 0);
+
+	let stored = stored_files(&database);
+	assert_eq!(stored.len(), 5);
+	for meta in &stored {
+		let expected = content_hash(&meta.path.0, HashGranularity::Full).unwrap();
+		assert_eq!(meta.content_hash, Some(expected), "{}", meta.path);
+	}
+}
+
+#[test]
+fn test_rescan_keeps_hashes_of_unchanged_files() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("same.txt", 10);
+	vfs.create_file("changed.txt", 10);
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("h.redb")).unwrap();
+	let cache = scanned_and_stored(&vfs, &database);
+	let pool = HashWorkerPool::new(1).unwrap();
+	assert_eq!(cache.populate_missing_hashes(Some(&database), 10, &pool), 2);
+
+	vfs.create_file("changed.txt", 20);
+	let rescanned = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	assert_eq!(rescanned.files_missing_hash().count(), 2);
+	store(&database, &rescanned.all_files());
+
+	let stored = stored_files(&database);
+	let hash_of = |name: &str| {
+		stored
+			.iter()
+			.find(|meta| meta.path.0 == vfs.path(name))
+			.unwrap()
+			.content_hash
+	};
+	assert!(hash_of("same.txt").is_some());
+	assert_eq!(hash_of("changed.txt"), None);
+}
+
+#[test]
+fn test_unreadable_files_end_the_backfill() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("kept.txt", 10);
+	vfs.create_file("gone.txt", 10);
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	vfs.delete_file("gone.txt");
+
+	let pool = HashWorkerPool::new(1).unwrap();
+	assert_eq!(cache.populate_missing_hashes(None, 10, &pool), 2);
+	assert_eq!(cache.populate_missing_hashes(None, 10, &pool), 0);
+	let missing: Vec<_> = cache.files_missing_hash().map(|meta| meta.path.0).collect();
+	assert_eq!(missing, [vfs.path("gone.txt")]);
+}
+
+#[test]
+fn test_backfill_hashes_command() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("a.txt", 10);
+	vfs.create_file("b.txt", 20);
+	vfs.create_file("c.txt", 30);
+	{
+		let database = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+		scanned_and_stored(&vfs, &database);
+	}
+
+	let output = Command::cargo_bin("linkfield")
+		.unwrap()
+		.args(["backfill-hashes", "--batch-size", "2"])
+		.arg(vfs.root())
+		.output()
+		.unwrap();
+	assert!(output.status.success());
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	let lines: Vec<_> = stdout.lines().collect();
+	assert_eq!(
+		lines,
+		[
+			"2/3 files processed",
+			"3/3 files processed",
+			"hashed 3 files, 0 could not be hashed"
+		]
+	);
+	let database = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	// The database was scanned too, but is left out of the backfill
+	let unhashed: Vec<_> = stored_files(&database)
+		.into_iter()
+		.filter(|meta| meta.content_hash.is_none())
+		.map(|meta| meta.path.0)
+		.collect();
+	assert_eq!(unhashed, [vfs.path("linkfield.redb")]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_locked_files_are_left_unhashed() {
+	use fs2::FileExt;
+	use linkfield::file_cache::ScanConfig;
+
+	let vfs = VirtualFs::new();
+	vfs.create_file("free.txt", 10);
+	vfs.create_file("locked.txt", 10);
+	let writer = std::fs::OpenOptions::new()
+		.write(true)
+		.open(vfs.path("locked.txt"))
+		.unwrap();
+	writer.lock_exclusive().unwrap();
+
+	let locked = FileMeta::from_path_with_hash(&vfs.path("locked.txt"), true).unwrap();
+	assert_eq!(locked.content_hash, None);
+	let unchecked = FileMeta::from_path_with_hash(&vfs.path("locked.txt"), false).unwrap();
+	assert!(unchecked.content_hash.is_some());
+
+	let pool = HashWorkerPool::new(1).unwrap();
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	assert!(cache.skip_locked_files());
+	assert_eq!(cache.populate_missing_hashes(None, 10, &pool), 2);
+	let missing: Vec<_> = cache.files_missing_hash().map(|meta| meta.path.0).collect();
+	assert_eq!(missing, [vfs.path("locked.txt")]);
+
+	let config = ScanConfig {
+		skip_locked_files: false,
+		..ScanConfig::default()
+	};
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_with_config(vfs.root(), &IgnoreConfig::empty(), &config);
+	assert!(!cache.skip_locked_files());
+	assert_eq!(cache.populate_missing_hashes(None, 10, &pool), 2);
+	assert_eq!(cache.files_missing_hash().count(), 0);
+	writer.unlock().unwrap();
+}
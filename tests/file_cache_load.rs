@@ -0,0 +1,66 @@
+//! Integration tests: streaming and batched loading of the `file_cache` table
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::FileCache;
+use linkfield::file_cache::db::update_redb_batch_commit;
+use linkfield::file_cache::meta::{FileCachePath, FileMeta};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Database holding `count` files, and their metadata by path
+fn stored_files(vfs: &VirtualFs, count: usize) -> (redb::Database, BTreeMap<PathBuf, FileMeta>) {
+	let database = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	let files: BTreeMap<_, _> = (0..count)
+		.map(|i| {
+			let path = vfs.create_file(&format!("dir{}/file{i}.txt", i % 3), i as u64);
+			(path.clone(), FileMeta::from_path(&path).unwrap())
+		})
+		.collect();
+	let entries: Vec<_> = files
+		.iter()
+		.map(|(path, meta)| (FileCachePath::from(path.as_path()), meta.clone()))
+		.collect();
+	update_redb_batch_commit(&database, &[], &entries).unwrap();
+	(database, files)
+}
+
+#[test]
+fn test_stream_from_redb_visits_every_entry_once() {
+	let vfs = VirtualFs::new();
+	let (database, files) = stored_files(&vfs, 25);
+
+	let mut seen = BTreeMap::new();
+	let count = FileCache::stream_from_redb(&database, |path, meta| {
+		assert_eq!(path, meta.path);
+		assert!(seen.insert(path.0, meta).is_none());
+	})
+	.unwrap();
+
+	assert_eq!(count, 25);
+	assert_eq!(seen, files);
+}
+
+#[test]
+fn test_load_from_redb_batched_reports_each_batch() {
+	let vfs = VirtualFs::new();
+	let (database, files) = stored_files(&vfs, 25);
+
+	let cache = FileCache::new_root("root");
+	let mut progress = Vec::new();
+	let mut on_batch = |loaded: usize| progress.push(loaded);
+	let count = cache
+		.load_from_redb_batched(&database, 10, Some(&mut on_batch))
+		.unwrap();
+
+	assert_eq!(count, 25);
+	assert_eq!(progress, vec![10, 20, 25]);
+	let loaded: BTreeMap<_, _> = cache
+		.all_files()
+		.into_iter()
+		.map(|meta| (meta.path.0.clone(), meta))
+		.collect();
+	assert_eq!(loaded, files);
+}
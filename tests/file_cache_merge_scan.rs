@@ -0,0 +1,80 @@
+//! Integration tests: merging scans of several roots into one cache
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::IgnoreConfig;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+fn cached(cache: &FileCache) -> HashSet<PathBuf> {
+	cache.all_paths().map(|path| path.0).collect()
+}
+
+#[test]
+fn test_merge_scan_keeps_files_from_other_roots() {
+	let first = VirtualFs::new();
+	let second = VirtualFs::new();
+	first
+		.replay_script("CREATE a.txt 1\nCREATE gone.txt 1")
+		.unwrap();
+	second.replay_script("CREATE sub/b.txt 1").unwrap();
+	let db_dir = VirtualFs::new();
+	let database = db::open_or_create_db(&db_dir.path("cache.redb")).unwrap();
+	let ignore = IgnoreConfig::empty();
+	let cache = FileCache::new_root("roots");
+
+	let diff = cache.merge_scan(first.root(), &ignore, Some(&database));
+	assert_eq!(diff.added.len(), 2);
+	let diff = cache.merge_scan(second.root(), &ignore, Some(&database));
+	assert_eq!((diff.added.len(), diff.removed.len()), (1, 0));
+	let both: HashSet<_> = [
+		first.path("a.txt"),
+		first.path("gone.txt"),
+		second.path("sub/b.txt"),
+	]
+	.into_iter()
+	.collect();
+	assert_eq!(cached(&cache), both);
+
+	// Rescanning one root only removes what disappeared from that root
+	first.delete_file("gone.txt");
+	second.create_file("sub/b.txt", 5);
+	let diff = cache.merge_scan(second.root(), &ignore, Some(&database));
+	assert_eq!((diff.modified.len(), diff.removed.len()), (1, 0));
+	assert!(cached(&cache).contains(&first.path("gone.txt")));
+	let diff = cache.merge_scan(first.root(), &ignore, Some(&database));
+	assert_eq!(diff.removed.len(), 1);
+	assert_eq!(cached(&cache).len(), 2);
+	assert_eq!(
+		FileCache::stream_from_redb(&database, |_, _| {}).unwrap(),
+		2
+	);
+}
+
+#[test]
+fn test_rescan_into_db_updates_the_stored_files() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE kept.txt 1\nCREATE gone.txt 1")
+		.unwrap();
+	let db_dir = VirtualFs::new();
+	let database = db::open_or_create_db(&db_dir.path("cache.redb")).unwrap();
+	let ignore = IgnoreConfig::empty();
+	FileCache::new_root("root").merge_scan(vfs.root(), &ignore, Some(&database));
+
+	vfs.delete_file("gone.txt");
+	vfs.create_file("new.txt", 3);
+	// The watch mode's cache holds no files once they are committed
+	let cache = FileCache::new_root("root");
+	let diff = cache
+		.rescan_into_db(&database, vfs.root(), &ignore)
+		.unwrap();
+	assert_eq!((diff.added.len(), diff.removed.len()), (1, 1));
+	assert!(cache.is_empty());
+	let mut stored = Vec::new();
+	FileCache::stream_from_redb(&database, |path, _| stored.push(path.0)).unwrap();
+	stored.sort();
+	assert_eq!(stored, [vfs.path("kept.txt"), vfs.path("new.txt")]);
+}
@@ -0,0 +1,66 @@
+//! Integration tests: ephemeral caches filled from a directory without a database
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::diff::diff_file_maps;
+use linkfield::file_cache::{FileCache, ScanConfig};
+use linkfield::ignore_config::IgnoreConfig;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn test_populate_from_dir_snapshot_diffs_against_later_scan() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE keep.txt 1\nCREATE grow.txt 1\nCREATE gone.txt 1\nCREATE skip.log 1")
+		.unwrap();
+	let ignore = IgnoreConfig::new(&["*.log"]).unwrap();
+	let before = FileCache::populate_from_dir(vfs.root(), &ignore);
+	assert_eq!(before.all_files().len(), 3);
+
+	vfs.replay_script("CREATE grow.txt 10\nDELETE gone.txt\nCREATE new.txt 1")
+		.unwrap();
+	let after = FileCache::populate_from_dir(vfs.root(), &ignore);
+
+	let diff = diff_file_maps(&before.file_map(), &after.file_map());
+	let names = |metas: Vec<&PathBuf>| -> Vec<String> {
+		metas
+			.iter()
+			.map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+			.collect()
+	};
+	assert_eq!(
+		names(diff.added.iter().map(|m| &m.path.0).collect()),
+		["new.txt"]
+	);
+	assert_eq!(
+		names(diff.removed.iter().map(|m| &m.path.0).collect()),
+		["gone.txt"]
+	);
+	assert_eq!(
+		names(diff.modified.iter().map(|(_, m)| &m.path.0).collect()),
+		["grow.txt"]
+	);
+}
+
+#[test]
+fn test_populate_from_dir_with_config_reports_progress() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 1\nCREATE sub/b.txt 1")
+		.unwrap();
+	let updates = Arc::new(Mutex::new(Vec::new()));
+	let sink = Arc::clone(&updates);
+	let config = ScanConfig {
+		on_progress: Some(Arc::new(move |progress| {
+			sink.lock().unwrap().push(progress)
+		})),
+		..ScanConfig::default()
+	};
+
+	let cache =
+		FileCache::populate_from_dir_with_config(vfs.root(), &IgnoreConfig::empty(), &config);
+
+	assert_eq!(cache.all_files().len(), 2);
+	let last = *updates.lock().unwrap().last().unwrap();
+	assert_eq!(last.files_scanned, 2);
+}
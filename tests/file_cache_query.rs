@@ -0,0 +1,114 @@
+//! Integration tests: combining filters with `FileCache::query`
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::{FileCache, FileCategory, FileMeta, SortKey};
+use linkfield::ignore_config::IgnoreConfig;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+fn day(n: u64) -> SystemTime {
+	SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + n * 86_400)
+}
+
+/// Files of several kinds and sizes, `<name>` modified on day `<n>` of its entry
+fn sample_cache(vfs: &VirtualFs) -> Arc<FileCache> {
+	let files = [
+		("photos/beach.jpg", 5_000, 1),
+		("photos/beach_raw.png", 50_000, 2),
+		("photos/old.jpeg", 800, 3),
+		("docs/report.pdf", 12_000, 4),
+		("docs/notes.txt", 300, 5),
+		("src/main.rs", 2_000, 6),
+		("src/beach.rs", 1_000, 7),
+		("backup.zip", 90_000, 8),
+	];
+	for (name, size, modified) in files {
+		vfs.create_file(name, size);
+		vfs.set_mtime(name, day(modified));
+	}
+	FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty())
+}
+
+fn names(files: &[FileMeta]) -> Vec<String> {
+	files
+		.iter()
+		.map(|meta| {
+			meta.path
+				.0
+				.file_name()
+				.unwrap()
+				.to_string_lossy()
+				.into_owned()
+		})
+		.collect()
+}
+
+#[test]
+fn test_query_without_filters_returns_every_file() {
+	let vfs = VirtualFs::new();
+	let cache = sample_cache(&vfs);
+	assert_eq!(cache.query().collect().len(), 8);
+	assert_eq!(cache.query().limit(3).collect().len(), 3);
+	assert_eq!(cache.query().offset(6).collect().len(), 2);
+}
+
+#[test]
+fn test_query_combines_filters() {
+	let vfs = VirtualFs::new();
+	let cache = sample_cache(&vfs);
+
+	let images = cache
+		.query()
+		.category(FileCategory::Image)
+		.size_range(1_000, 60_000)
+		.sort_by(SortKey::Size)
+		.collect();
+	assert_eq!(names(&images), ["beach.jpg", "beach_raw.png"]);
+
+	let recent_beach = cache
+		.query()
+		.name_contains("beach")
+		.modified_since(day(2))
+		.modified_before(day(7))
+		.collect();
+	assert_eq!(names(&recent_beach), ["beach_raw.png"]);
+
+	let jpegs = cache
+		.query()
+		.extension("jpg")
+		.extension(".jpeg")
+		.sort_by(SortKey::Path)
+		.collect();
+	assert_eq!(names(&jpegs), ["beach.jpg", "old.jpeg"]);
+
+	let none = cache
+		.query()
+		.extension("rs")
+		.category(FileCategory::Document)
+		.collect();
+	assert!(none.is_empty());
+}
+
+#[test]
+fn test_query_sorts_before_paging() {
+	let vfs = VirtualFs::new();
+	let cache = sample_cache(&vfs);
+	let by_modified = |offset, limit| {
+		names(
+			&cache
+				.query()
+				.sort_by(SortKey::Modified)
+				.offset(offset)
+				.limit(limit)
+				.collect(),
+		)
+	};
+	assert_eq!(by_modified(0, 2), ["beach.jpg", "beach_raw.png"]);
+	assert_eq!(by_modified(6, 5), ["beach.rs", "backup.zip"]);
+	assert!(by_modified(8, 5).is_empty());
+
+	let by_name = cache.query().sort_by(SortKey::Name).limit(3).collect();
+	assert_eq!(names(&by_name), ["backup.zip", "beach.jpg", "beach.rs"]);
+}
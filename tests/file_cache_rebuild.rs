@@ -0,0 +1,125 @@
+//! Integration tests: rebuilding a damaged database from a fresh scan
+
+mod common;
+
+use assert_cmd::cargo::CommandCargoExt;
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::db::{DEFAULT_LOAD_BATCH_SIZE, FILE_CACHE_TABLE};
+use linkfield::file_cache::{FileCache, ensure_file_cache_table};
+use linkfield::ignore_config::IgnoreConfig;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn stored_paths(database: &redb::Database) -> Vec<PathBuf> {
+	let cache = FileCache::new_root("root");
+	cache
+		.load_from_redb_batched(database, DEFAULT_LOAD_BATCH_SIZE, None)
+		.unwrap();
+	let mut paths: Vec<_> = cache.all_paths().map(|path| path.0).collect();
+	paths.sort();
+	paths
+}
+
+fn paths_of<'a>(files: impl Iterator<Item = &'a linkfield::file_cache::FileMeta>) -> Vec<PathBuf> {
+	let mut paths: Vec<_> = files.map(|meta| meta.path.0.clone()).collect();
+	paths.sort();
+	paths
+}
+
+#[test]
+fn test_rebuild_replaces_corrupt_and_stale_rows() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("kept.txt", 10);
+	vfs.create_file("changed.txt", 10);
+	vfs.create_file("gone.txt", 10);
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("r.redb")).unwrap();
+	ensure_file_cache_table(&database).unwrap();
+	FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty())
+		.set_db(database)
+		.unwrap();
+	let database = db::open_or_create_db(&dir.path("r.redb")).unwrap();
+
+	// Garbage written behind linkfield's back breaks the checksum and the row itself
+	let txn = database.begin_write().unwrap();
+	txn.open_table(FILE_CACHE_TABLE)
+		.unwrap()
+		.insert(vfs.path("kept.txt").to_str().unwrap(), [0xff; 4].as_slice())
+		.unwrap();
+	txn.commit().unwrap();
+	assert!(FileCache::verify_cache_checksum(&database).is_err());
+	vfs.create_file("changed.txt", 20);
+	vfs.delete_file("gone.txt");
+	vfs.create_file("new.txt", 5);
+
+	let cache = FileCache::new_root("root");
+	let diff = cache
+		.rebuild_from_scratch(&database, vfs.root(), &IgnoreConfig::empty())
+		.unwrap();
+	// The corrupt row could not be read, so its file counts as added
+	assert_eq!(
+		paths_of(diff.added.iter()),
+		[vfs.path("kept.txt"), vfs.path("new.txt")]
+	);
+	assert_eq!(paths_of(diff.removed.iter()), [vfs.path("gone.txt")]);
+	assert_eq!(
+		paths_of(diff.modified.iter().map(|(_, new)| new)),
+		[vfs.path("changed.txt")]
+	);
+
+	FileCache::verify_cache_checksum(&database).unwrap();
+	let expected = [
+		vfs.path("changed.txt"),
+		vfs.path("kept.txt"),
+		vfs.path("new.txt"),
+	];
+	assert_eq!(stored_paths(&database), expected);
+	assert_eq!(cache.len(), 3);
+	assert!(!cache.is_stale_with_db(&database).unwrap());
+}
+
+#[test]
+fn test_rebuild_recreates_a_table_of_the_wrong_type() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("a.txt", 1);
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("r.redb")).unwrap();
+	let txn = database.begin_write().unwrap();
+	txn.open_table(redb::TableDefinition::<u64, u64>::new("file_cache"))
+		.unwrap()
+		.insert(1, 2)
+		.unwrap();
+	txn.commit().unwrap();
+
+	let cache = FileCache::new_root("root");
+	let diff = cache
+		.rebuild_from_scratch(&database, vfs.root(), &IgnoreConfig::empty())
+		.unwrap();
+	assert_eq!(paths_of(diff.added.iter()), [vfs.path("a.txt")]);
+	assert_eq!(stored_paths(&database), [vfs.path("a.txt")]);
+}
+
+#[test]
+fn test_rebuild_cache_command_needs_force() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("a.txt", 1);
+	let run = |force: bool| {
+		let mut command = Command::cargo_bin("linkfield").unwrap();
+		command.arg("rebuild-cache").arg(vfs.root());
+		if force {
+			command.arg("--force");
+		}
+		command.output().unwrap()
+	};
+
+	let refused = run(false);
+	assert!(!refused.status.success());
+	assert!(String::from_utf8_lossy(&refused.stderr).contains("--force"));
+	assert!(!vfs.path("linkfield.redb").exists());
+
+	let rebuilt = run(true);
+	assert!(rebuilt.status.success());
+	let stdout = String::from_utf8(rebuilt.stdout).unwrap();
+	assert!(stdout.contains(&format!("A {}", vfs.path("a.txt").display())));
+}
@@ -0,0 +1,142 @@
+//! Integration tests: finding recently created and modified files
+
+mod common;
+
+use common::{VirtualFs, test_meta};
+use linkfield::file_cache::{FileCache, FileMeta};
+use linkfield::ignore_config::IgnoreConfig;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+fn hours_ago(n: u64) -> SystemTime {
+	SystemTime::now() - Duration::from_secs(n * 3600)
+}
+
+fn file(name: &str, created: Option<SystemTime>, modified: Option<SystemTime>) -> FileMeta {
+	FileMeta {
+		modified,
+		created,
+		..test_meta(format!("root/{name}"), 1)
+	}
+}
+
+/// `new` and `old` have creation times, `unknown_*` only modification times
+fn sample_cache() -> Arc<FileCache> {
+	let cache = FileCache::new_root("root");
+	cache.insert_meta(file("new.txt", Some(hours_ago(2)), Some(hours_ago(1))));
+	cache.insert_meta(file("old.txt", Some(hours_ago(48)), Some(hours_ago(1))));
+	cache.insert_meta(file("unknown_new.txt", None, Some(hours_ago(3))));
+	cache.insert_meta(file("unknown_old.txt", None, Some(hours_ago(30))));
+	cache
+}
+
+fn names(files: impl Iterator<Item = FileMeta>) -> Vec<String> {
+	let mut names: Vec<_> = files
+		.map(|meta| {
+			meta.path
+				.0
+				.file_name()
+				.unwrap()
+				.to_string_lossy()
+				.to_string()
+		})
+		.collect();
+	names.sort();
+	names
+}
+
+#[test]
+fn test_find_recently_created_with_and_without_fallback() {
+	let cache = sample_cache();
+	let day_ago = hours_ago(24);
+	assert_eq!(names(cache.find_recently_created(day_ago)), ["new.txt"]);
+
+	cache.set_created_fallback_to_modified(true);
+	assert_eq!(
+		names(cache.find_recently_created(day_ago)),
+		["new.txt", "unknown_new.txt"]
+	);
+	// The fallback only applies without a creation time
+	assert_eq!(
+		names(cache.find_recently_created(hours_ago(1) - Duration::from_secs(60))),
+		Vec::<String>::new()
+	);
+
+	cache.set_created_fallback_to_modified(false);
+	assert_eq!(names(cache.find_recently_created(day_ago)), ["new.txt"]);
+}
+
+#[test]
+fn test_find_recently_modified() {
+	let cache = sample_cache();
+	assert_eq!(
+		names(cache.find_recently_modified(hours_ago(24))),
+		["new.txt", "old.txt", "unknown_new.txt"]
+	);
+	assert_eq!(
+		names(cache.find_recently_modified(hours_ago(2))),
+		["new.txt", "old.txt"]
+	);
+	assert!(
+		cache
+			.find_recently_modified(SystemTime::now())
+			.next()
+			.is_none()
+	);
+}
+
+#[test]
+fn test_find_recently_modified_on_scanned_files() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("fresh.txt", 1);
+	vfs.create_file("stale.txt", 1);
+	vfs.set_mtime("stale.txt", hours_ago(72));
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	assert_eq!(
+		names(cache.find_recently_modified(hours_ago(24))),
+		["fresh.txt"]
+	);
+}
+
+#[test]
+fn test_changed_files_since_mtime_matches_disk() {
+	let vfs = VirtualFs::new();
+	for name in [
+		"src/new.rs",
+		"src/old.rs",
+		"src/nested/new.rs",
+		"docs/new.md",
+	] {
+		vfs.create_file(name, 1);
+	}
+	vfs.set_mtime("src/old.rs", hours_ago(72));
+	let last_build = hours_ago(24);
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+
+	let src = vfs.path("src");
+	let mut cached: Vec<_> = cache
+		.changed_files_since_mtime(&src, last_build)
+		.map(|meta| meta.path.0)
+		.collect();
+	cached.sort();
+	assert_eq!(
+		cached,
+		[vfs.path("src/nested/new.rs"), vfs.path("src/new.rs")]
+	);
+	assert_eq!(
+		FileCache::changed_files_since_mtime_from_disk(&src, last_build),
+		cached
+	);
+	assert_eq!(
+		FileCache::changed_files_since_mtime_from_disk(vfs.root(), last_build).len(),
+		3
+	);
+
+	// Only the disk walk sees changes the cache hasn't caught up with
+	vfs.set_mtime("src/new.rs", hours_ago(48));
+	assert_eq!(cache.changed_files_since_mtime(&src, last_build).count(), 2);
+	assert_eq!(
+		FileCache::changed_files_since_mtime_from_disk(&src, last_build),
+		[vfs.path("src/nested/new.rs")]
+	);
+}
@@ -0,0 +1,100 @@
+//! Integration tests: moving the cache to a new watch root
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::error::LinkfieldError;
+use linkfield::file_cache::db::update_redb_batch_commit;
+use linkfield::file_cache::{FileCache, ensure_file_cache_table};
+use linkfield::ignore_config::IgnoreConfig;
+use linkfield::persisted_config::PersistedConfig;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+fn stored_sizes(db: &redb::Database) -> BTreeMap<PathBuf, u64> {
+	let mut stored = BTreeMap::new();
+	FileCache::stream_from_redb(db, |path, meta| {
+		stored.insert(path.0, meta.size);
+	})
+	.unwrap();
+	stored
+}
+
+#[test]
+fn test_migrate_root_moves_every_path() {
+	let vfs = VirtualFs::new();
+	for i in 0..100 {
+		vfs.create_file(&format!("old/dir{}/f{i}.txt", i % 7), i);
+	}
+	// A sibling sharing the string prefix stays where it is
+	vfs.create_file("old2/keep.txt", 5);
+	let (old_root, new_root) = (vfs.path("old"), vfs.path("new"));
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	let db = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	let rows: Vec<_> = cache
+		.all_files()
+		.into_iter()
+		.map(|meta| (meta.path.clone(), meta))
+		.collect();
+	update_redb_batch_commit(&db, &[], &rows).unwrap();
+	PersistedConfig {
+		watch_paths: vec![old_root.clone()],
+		..PersistedConfig::default()
+	}
+	.save(&db)
+	.unwrap();
+
+	assert_eq!(cache.migrate_root(&db, &old_root, &new_root).unwrap(), 100);
+	let expected: BTreeMap<_, _> = (0..100)
+		.map(|i| (new_root.join(format!("dir{}/f{i}.txt", i % 7)), i))
+		.chain([(vfs.path("old2/keep.txt"), 5)])
+		.collect();
+	let cached: BTreeMap<_, _> = cache
+		.all_files()
+		.into_iter()
+		.map(|meta| (meta.path.0, meta.size))
+		.collect();
+	assert_eq!(cached, expected);
+	assert_eq!(stored_sizes(&db), expected);
+	assert!(cache.contains_directory(&new_root.join("dir3")));
+	assert!(!cache.contains_directory(&old_root.join("dir3")));
+	assert_eq!(
+		PersistedConfig::load(&db).unwrap().unwrap().watch_paths,
+		[new_root]
+	);
+}
+
+#[test]
+fn test_migrate_root_fails_on_conflicts() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("old/a.txt", 1);
+	vfs.create_file("old/b.txt", 2);
+	vfs.create_file("new/b.txt", 3);
+	let (old_root, new_root) = (vfs.path("old"), vfs.path("new"));
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	let db = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	ensure_file_cache_table(&db).unwrap();
+
+	let error = cache.migrate_root(&db, &old_root, &new_root).unwrap_err();
+	assert!(
+		matches!(&error, LinkfieldError::Io(e) if e.kind() == std::io::ErrorKind::AlreadyExists),
+		"{error:?}"
+	);
+	assert!(error.to_string().contains("b.txt"), "{error}");
+	// Nothing moved
+	assert_eq!(cache.all_files().len(), 3);
+	assert!(stored_sizes(&db).is_empty());
+
+	// A file that is only stored conflicts as well
+	let other = FileCache::populate_from_dir(&old_root, &IgnoreConfig::empty());
+	let stored = cache
+		.all_files()
+		.into_iter()
+		.filter(|meta| meta.path.0.starts_with(&new_root))
+		.map(|meta| (meta.path.clone(), meta))
+		.collect::<Vec<_>>();
+	update_redb_batch_commit(&db, &[], &stored).unwrap();
+	assert!(other.migrate_root(&db, &old_root, &new_root).is_err());
+	assert_eq!(other.all_files().len(), 2);
+}
@@ -0,0 +1,36 @@
+//! Integration tests: attaching a database to a cache scanned in memory
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::IgnoreConfig;
+use std::collections::HashSet;
+
+#[test]
+fn test_set_db_persists_scanned_entries() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 1\nCREATE sub/b.txt 2\nCREATE sub/deep/c.txt 3")
+		.unwrap();
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	assert!(!cache.has_db());
+
+	let db_dir = VirtualFs::new();
+	let db = redb::Database::create(db_dir.path("late.redb")).unwrap();
+	assert_eq!(cache.set_db(db).unwrap(), 3);
+	assert!(cache.has_db());
+
+	let db = cache.detach_db().unwrap();
+	assert!(!cache.has_db());
+	assert_eq!(cache.all_files().len(), 3);
+	let mut stored = HashSet::new();
+	FileCache::stream_from_redb(&db, |path, _| {
+		stored.insert(path.0);
+	})
+	.unwrap();
+	let expected: HashSet<_> = ["a.txt", "sub/b.txt", "sub/deep/c.txt"]
+		.iter()
+		.map(|name| vfs.path(name))
+		.collect();
+	assert_eq!(stored, expected);
+}
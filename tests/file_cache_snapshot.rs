@@ -0,0 +1,47 @@
+//! Integration test: bootstrap a cache from a JSON export
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::file_cache::db::FILE_CACHE_TABLE;
+use linkfield::ignore_config::IgnoreConfig;
+use redb::ReadableTableMetadata;
+
+#[test]
+fn test_export_then_import_snapshot() {
+	let vfs = VirtualFs::new();
+	for i in 0..2500 {
+		vfs.create_file(&format!("d{}/f{i}.txt", i % 7), i % 13);
+	}
+	let source = FileCache::new_root("root");
+	source.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+	let dir = VirtualFs::new();
+	let snapshot = dir.path("snapshot.json");
+	source
+		.export_json(std::fs::File::create(&snapshot).unwrap())
+		.unwrap();
+
+	let database = linkfield::db::open_or_create_db(&dir.path("s.redb")).unwrap();
+	let imported = FileCache::from_snapshot_file("root", &snapshot, &database).unwrap();
+	assert_eq!(imported.all_files().len(), 2500);
+	let stored = database
+		.begin_read()
+		.unwrap()
+		.open_table(FILE_CACHE_TABLE)
+		.unwrap()
+		.len()
+		.unwrap();
+	assert_eq!(stored, 2500);
+	let mut expected = source.all_files();
+	let mut actual = imported.all_files();
+	expected.sort_by(|a, b| a.path.cmp(&b.path));
+	actual.sort_by(|a, b| a.path.cmp(&b.path));
+	assert_eq!(actual, expected);
+
+	let empty = VirtualFs::new();
+	let other_db = linkfield::db::open_or_create_db(&empty.path("e.redb")).unwrap();
+	assert!(
+		FileCache::from_snapshot_reader("root", &b"{\"not\":\"a list\"}"[..], &other_db).is_err()
+	);
+}
@@ -0,0 +1,85 @@
+//! Integration tests: estimating staleness to skip the initial scan
+
+mod common;
+
+use common::{VirtualFs, run_watch_until_ready};
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::IgnoreConfig;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn test_estimate_staleness_counts_changed_and_deleted_files() {
+	let vfs = VirtualFs::new();
+	for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+		vfs.create_file(name, 10);
+	}
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+
+	let estimate = cache.estimate_staleness(100);
+	assert_eq!((estimate.sample_size, estimate.mismatches), (4, 0));
+	assert!(estimate.staleness_ratio.abs() < f64::EPSILON);
+
+	vfs.set_mtime("a.txt", SystemTime::now() - Duration::from_secs(3600));
+	let estimate = cache.estimate_staleness(100);
+	assert_eq!((estimate.sample_size, estimate.mismatches), (4, 1));
+	assert!((estimate.staleness_ratio - 0.25).abs() < f64::EPSILON);
+
+	vfs.delete_file("b.txt");
+	let estimate = cache.estimate_staleness(100);
+	assert_eq!(estimate.mismatches, 2);
+	assert!((estimate.staleness_ratio - 0.5).abs() < f64::EPSILON);
+
+	// The sample never exceeds the requested size
+	let estimate = cache.estimate_staleness(2);
+	assert_eq!(estimate.sample_size, 2);
+	assert!(estimate.mismatches <= 2);
+}
+
+#[test]
+fn test_empty_cache_is_never_fresh() {
+	let estimate = FileCache::new_root("root").estimate_staleness(100);
+	assert_eq!((estimate.sample_size, estimate.mismatches), (0, 0));
+	assert!((estimate.staleness_ratio - 1.0).abs() < f64::EPSILON);
+}
+
+#[test]
+fn test_stale_ratio_reflects_changed_fraction_of_large_cache() {
+	let vfs = VirtualFs::new();
+	let names: Vec<_> = (0..200).map(|i| format!("dir{}/f{i}.txt", i % 7)).collect();
+	for name in &names {
+		vfs.create_file(name, 10);
+	}
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	assert!(cache.stale_ratio(200).abs() < f64::EPSILON);
+	assert!(!cache.needs_rescan(0.05, 200));
+
+	let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+	for name in names.iter().step_by(20) {
+		vfs.set_mtime(name, hour_ago);
+	}
+	for name in names.iter().skip(10).step_by(20) {
+		vfs.delete_file(name);
+	}
+	// 10 touched and 10 deleted out of 200
+	assert!((cache.stale_ratio(200) - 0.1).abs() < f64::EPSILON);
+	assert!(cache.needs_rescan(0.1, 200));
+	assert!(!cache.needs_rescan(0.2, 200));
+	assert!(cache.needs_rescan(0.0, 200));
+	assert!(FileCache::new_root("root").needs_rescan(1.0, 10));
+}
+
+#[test]
+fn test_second_start_skips_scan_of_unchanged_files() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("a.txt", 3);
+	vfs.create_file("sub/b.txt", 4);
+
+	let first = run_watch_until_ready(&vfs, &[]);
+	assert!(!first.contains("skipping initial scan"));
+	let second = run_watch_until_ready(&vfs, &[]);
+	assert!(second.contains("Stored files are fresh, skipping initial scan"));
+
+	// A threshold of 0 always scans
+	let third = run_watch_until_ready(&vfs, &["--staleness-threshold", "0"]);
+	assert!(!third.contains("skipping initial scan"));
+}
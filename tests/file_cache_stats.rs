@@ -0,0 +1,216 @@
+//! Integration tests: aggregate size queries over a scanned tree
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::file_cache::stats::{
+	DEFAULT_SIZE_BUCKETS, DirCountThreshold, DirectorySize, format_extension_table,
+};
+use linkfield::ignore_config::IgnoreConfig;
+use std::sync::Arc;
+
+fn scanned(vfs: &VirtualFs) -> Arc<FileCache> {
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+	cache
+}
+
+#[test]
+fn test_directory_sizes() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"CREATE top.bin 100\n\
+		 CREATE a/one.bin 10\n\
+		 CREATE a/two.bin 20\n\
+		 CREATE a/deep/three.bin 300\n\
+		 CREATE b/four.bin 5",
+	)
+	.unwrap();
+	let cache = scanned(&vfs);
+	let sizes = cache.directory_sizes(vfs.root());
+
+	assert_eq!(
+		sizes[vfs.root()],
+		DirectorySize {
+			file_count: 5,
+			total_bytes: 435,
+			direct_bytes: 100,
+		}
+	);
+	assert_eq!(
+		sizes[&vfs.path("a")],
+		DirectorySize {
+			file_count: 3,
+			total_bytes: 330,
+			direct_bytes: 30,
+		}
+	);
+	assert_eq!(sizes[&vfs.path("a/deep")].total_bytes, 300);
+	assert_eq!(sizes[&vfs.path("b")].direct_bytes, 5);
+
+	let top: Vec<_> = cache
+		.top_n_directories(vfs.root(), 2)
+		.into_iter()
+		.map(|(dir, _)| dir)
+		.collect();
+	assert_eq!(top, vec![vfs.root().to_path_buf(), vfs.path("a")]);
+}
+
+#[test]
+fn test_entry_counts_by_dir() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"CREATE top.txt 1\n\
+		 CREATE a/one.txt 1\n\
+		 CREATE a/two.txt 1\n\
+		 CREATE a/deep/three.txt 1\n\
+		 CREATE b/four.txt 1",
+	)
+	.unwrap();
+	std::fs::create_dir(vfs.path("empty")).unwrap();
+	let cache = scanned(&vfs);
+
+	let direct = cache.entry_count_by_dir(vfs.root(), 1);
+	assert_eq!(direct.len(), 4);
+	assert_eq!(direct[vfs.root()], 1);
+	assert_eq!(direct[&vfs.path("a")], 2);
+	assert_eq!(direct[&vfs.path("b")], 1);
+	assert_eq!(direct[&vfs.path("empty")], 0);
+	assert!(!direct.contains_key(&vfs.path("a/deep")));
+	assert_eq!(
+		cache.entry_count_by_dir(vfs.root(), 2)[&vfs.path("a/deep")],
+		1
+	);
+
+	let recursive = cache.entry_count_recursive_by_dir(vfs.root());
+	assert_eq!(recursive[vfs.root()], 5);
+	assert_eq!(recursive[&vfs.path("a")], 3);
+	assert_eq!(recursive[&vfs.path("a/deep")], 1);
+
+	let thresholds: Vec<DirCountThreshold> = [
+		format!("{}:2", vfs.path("a").display()),
+		format!("{}:5", vfs.root().display()),
+	]
+	.iter()
+	.map(|s| s.parse().unwrap())
+	.collect();
+	let exceeded = cache.check_dir_count_thresholds(&thresholds);
+	assert_eq!(exceeded, vec![(thresholds[0].clone(), 3)]);
+	assert!("no-count".parse::<DirCountThreshold>().is_err());
+}
+
+#[test]
+fn test_path_count_by_extension_sorted_by_total_size() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"CREATE a.txt 10\n\
+		 CREATE b.txt 15\n\
+		 CREATE c.txt 5\n\
+		 CREATE movie.mp4 400\n\
+		 CREATE Makefile 60\n\
+		 CREATE sub/d.rs 30\n\
+		 CREATE sub/e.rs 20",
+	)
+	.unwrap();
+	let cache = scanned(&vfs);
+
+	let rows = cache.path_count_by_extension_sorted();
+	assert_eq!(
+		rows,
+		vec![
+			(Some("mp4".to_string()), 1, 400),
+			(None, 1, 60),
+			(Some("rs".to_string()), 2, 50),
+			(Some("txt".to_string()), 3, 30),
+		]
+	);
+	assert_eq!(cache.top_extensions(2), rows[..2].to_vec());
+
+	let table = format_extension_table(&cache.top_extensions(2));
+	let lines: Vec<_> = table.lines().collect();
+	assert_eq!(lines.len(), 3);
+	assert!(lines[0].starts_with("extension"));
+	assert!(lines[1].starts_with("mp4 "));
+	assert!(lines[1].ends_with(" 400"));
+	assert!(lines[2].starts_with("(none) "));
+	assert_eq!(lines[1].len(), lines[2].len());
+}
+
+#[test]
+fn test_size_summary_and_distribution() {
+	let vfs = VirtualFs::new();
+	let cache = scanned(&vfs);
+	assert_eq!(cache.total_size_bytes(), 0);
+	assert_eq!(cache.median_file_size(), 0);
+	assert!(cache.average_file_size().abs() < f64::EPSILON);
+
+	let sizes = [0, 999, 1_000, 5_000, 20_000, 20_000, 2_500_000, 150_000_000];
+	for (i, size) in sizes.iter().enumerate() {
+		vfs.create_file(&format!("f{i}.bin"), *size);
+	}
+	let cache = scanned(&vfs);
+	assert_eq!(cache.total_size_bytes(), 152_546_999);
+	assert!((cache.average_file_size() - 152_546_999.0 / 8.0).abs() < 1e-6);
+	assert_eq!(cache.median_file_size(), 12_500);
+	assert_eq!(
+		cache.size_distribution(&DEFAULT_SIZE_BUCKETS),
+		[
+			(0, 2),
+			(1_000, 2),
+			(10_000, 2),
+			(100_000, 0),
+			(1_000_000, 1),
+			(10_000_000, 0),
+			(100_000_000, 1),
+		]
+	);
+	// Files below the first bound are left out
+	assert_eq!(
+		cache.size_distribution(&[10_000, 1_000_000]),
+		[(10_000, 2), (1_000_000, 2)]
+	);
+	vfs.create_file("odd.bin", 1);
+	assert_eq!(scanned(&vfs).median_file_size(), 5_000);
+}
+
+#[test]
+fn test_size_on_disk_rounds_up_to_blocks() {
+	let vfs = VirtualFs::new();
+	for (name, size) in [("empty", 0), ("one", 1), ("block", 4096), ("more", 5000)] {
+		vfs.create_file(name, size);
+	}
+	let cache = scanned(&vfs);
+	for meta in cache.all_files() {
+		let on_disk = meta.size_on_disk();
+		assert!(on_disk >= meta.size, "{}", meta.path);
+		if meta.size > 0 {
+			assert!(on_disk > 0, "{}", meta.path);
+		}
+		#[cfg(unix)]
+		assert!(meta.blocks_used().is_some());
+	}
+	let on_disk = |name: &str| {
+		cache
+			.all_files()
+			.into_iter()
+			.find(|meta| meta.path.0 == vfs.path(name))
+			.unwrap()
+			.size_on_disk()
+	};
+	// A single byte still takes up a whole block
+	let block = on_disk("one");
+	assert!(block >= 512);
+	assert_eq!(on_disk("empty"), 0);
+	assert_eq!(on_disk("more") % block, 0);
+	assert_eq!(cache.total_size_bytes(), 9097);
+	assert!(cache.total_size_on_disk() >= cache.total_size_bytes());
+	assert_eq!(
+		cache.total_size_on_disk(),
+		cache
+			.all_files()
+			.iter()
+			.map(|meta| meta.size_on_disk())
+			.sum::<u64>()
+	);
+}
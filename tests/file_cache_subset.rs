@@ -0,0 +1,58 @@
+//! Integration tests: derived caches with a filtered subset of entries
+
+mod common;
+
+use common::{VirtualFs, test_meta};
+
+use linkfield::file_cache::db::FILE_CACHE_TABLE;
+use linkfield::file_cache::meta::FileCachePath;
+use linkfield::file_cache::{FileCache, FileMeta, ensure_file_cache_table};
+use redb::ReadableTableMetadata;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn cache_with_1000_files() -> Arc<FileCache> {
+	let cache = FileCache::new_root("root");
+	for i in 0..1000 {
+		let extension = if i % 10 == 0 { "rs" } else { "txt" };
+		let path = PathBuf::from(format!("root/dir{}/file{i}.{extension}", i % 7));
+		cache.insert_meta(test_meta(path, i));
+	}
+	cache
+}
+
+fn is_rust(_: &FileCachePath, meta: &FileMeta) -> bool {
+	meta.extension.as_deref() == Some("rs")
+}
+
+#[test]
+fn test_clone_subset() {
+	let cache = cache_with_1000_files();
+	let subset = cache.clone_subset(is_rust);
+	assert_eq!(subset.all_files().len(), 100);
+	assert!(subset.all_files().iter().all(|m| is_rust(&m.path, m)));
+	let rust_file = std::path::Path::new("root/dir0/file0.rs");
+	assert_eq!(subset.get(rust_file).unwrap().size, 0);
+
+	// The source cache is unaffected
+	assert_eq!(cache.all_files().len(), 1000);
+	assert!(
+		cache
+			.get(std::path::Path::new("root/dir1/file1.txt"))
+			.is_some()
+	);
+}
+
+#[test]
+fn test_clone_subset_to_db() {
+	let cache = cache_with_1000_files();
+	let dir = VirtualFs::new();
+	let db = redb::Database::create(dir.path("subset.redb")).unwrap();
+	ensure_file_cache_table(&db).unwrap();
+	let subset = cache.clone_subset_to_db(is_rust, &db);
+	assert_eq!(subset.all_files().len(), 100);
+
+	let txn = db.begin_read().unwrap();
+	let table = txn.open_table(FILE_CACHE_TABLE).unwrap();
+	assert_eq!(table.len().unwrap(), 100);
+}
@@ -0,0 +1,82 @@
+//! Integration tests: picking up changes another writer made to a shared database
+
+mod common;
+
+use common::{VirtualFs, test_meta};
+
+use linkfield::db;
+use linkfield::file_cache::db::{
+	DEFAULT_LOAD_BATCH_SIZE, update_redb_batch_commit, update_redb_single_remove,
+};
+use linkfield::file_cache::meta::FileCachePath;
+use linkfield::file_cache::{FileCache, FileMeta};
+use std::path::PathBuf;
+
+fn write(database: &redb::Database, files: &[FileMeta]) {
+	let batch: Vec<_> = files
+		.iter()
+		.map(|meta| (meta.path.clone(), meta.clone()))
+		.collect();
+	update_redb_batch_commit(database, &[], &batch).unwrap();
+}
+
+fn sizes(cache: &FileCache) -> Vec<(PathBuf, u64)> {
+	let mut sizes: Vec<_> = cache
+		.all_files()
+		.into_iter()
+		.map(|meta| (meta.path.0, meta.size))
+		.collect();
+	sizes.sort();
+	sizes
+}
+
+#[test]
+fn test_apply_external_changes_follows_another_writer() {
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("shared.redb")).unwrap();
+	write(
+		&database,
+		&[
+			test_meta("root/a.txt", 1),
+			test_meta("root/b.txt", 2),
+			test_meta("root/sub/c.txt", 3),
+		],
+	);
+
+	let cache = FileCache::new_root("root");
+	assert!(cache.is_stale_with_db(&database).unwrap());
+	cache
+		.load_from_redb_batched(&database, DEFAULT_LOAD_BATCH_SIZE, None)
+		.unwrap();
+	assert!(!cache.is_stale_with_db(&database).unwrap());
+
+	// The other writer modifies one file, adds one and removes one
+	write(
+		&database,
+		&[test_meta("root/a.txt", 10), test_meta("root/sub/d.txt", 4)],
+	);
+	update_redb_single_remove(&database, &FileCachePath(PathBuf::from("root/b.txt"))).unwrap();
+	assert!(cache.is_stale_with_db(&database).unwrap());
+
+	assert_eq!(cache.apply_external_changes(&database).unwrap(), 3);
+	assert_eq!(
+		sizes(&cache),
+		[
+			(PathBuf::from("root/a.txt"), 10),
+			(PathBuf::from("root/sub/c.txt"), 3),
+			(PathBuf::from("root/sub/d.txt"), 4),
+		]
+	);
+	assert!(!cache.is_stale_with_db(&database).unwrap());
+	assert_eq!(cache.apply_external_changes(&database).unwrap(), 0);
+}
+
+#[test]
+fn test_empty_database_is_not_stale_once_synced() {
+	let dir = VirtualFs::new();
+	let database = db::open_or_create_db(&dir.path("empty.redb")).unwrap();
+	let cache = FileCache::new_root("root");
+	assert!(cache.is_stale_with_db(&database).unwrap());
+	assert_eq!(cache.apply_external_changes(&database).unwrap(), 0);
+	assert!(!cache.is_stale_with_db(&database).unwrap());
+}
@@ -0,0 +1,86 @@
+//! Integration tests: the most recent and largest files under one directory
+
+mod common;
+
+use common::test_meta;
+use linkfield::file_cache::{FileCache, FileMeta};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+fn file(path: &str, size: u64, modified_secs: Option<u64>) -> FileMeta {
+	FileMeta {
+		modified: modified_secs.map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+		..test_meta(path, size)
+	}
+}
+
+fn sample_cache() -> Arc<FileCache> {
+	let cache = FileCache::new_root("root");
+	for meta in [
+		file("root/docs/a", 10, Some(100)),
+		file("root/docs/b", 50, Some(400)),
+		file("root/docs/c", 30, Some(300)),
+		file("root/docs/old/d", 40, Some(200)),
+		file("root/docs/old/e", 50, None),
+		file("root/docsx/f", 99, Some(999)),
+		file("root/other/g", 100, Some(1000)),
+	] {
+		cache.insert_meta(meta);
+	}
+	cache
+}
+
+fn paths(files: &[FileMeta]) -> Vec<&str> {
+	files
+		.iter()
+		.map(|meta| meta.path.0.to_str().unwrap())
+		.collect()
+}
+
+#[test]
+fn test_most_recently_modified_in_dir() {
+	let cache = sample_cache();
+	let docs = Path::new("root/docs");
+	assert_eq!(
+		paths(&cache.most_recently_modified_in_dir(docs, 3)),
+		["root/docs/b", "root/docs/c", "root/docs/old/d"]
+	);
+	// Files without a modification time are left out, and `docsx` is not under `docs`
+	assert_eq!(cache.most_recently_modified_in_dir(docs, 10).len(), 4);
+	assert_eq!(
+		paths(&cache.most_recently_modified_in_dir(Path::new("root/docs/old"), 10)),
+		["root/docs/old/d"]
+	);
+	assert!(cache.most_recently_modified_in_dir(docs, 0).is_empty());
+	assert!(
+		cache
+			.most_recently_modified_in_dir(Path::new("root/missing"), 5)
+			.is_empty()
+	);
+}
+
+#[test]
+fn test_largest_files_in_dir() {
+	let cache = sample_cache();
+	let docs = Path::new("root/docs");
+	// Ties are broken by path
+	assert_eq!(
+		paths(&cache.largest_files_in_dir(docs, 2)),
+		["root/docs/b", "root/docs/old/e"]
+	);
+	assert_eq!(
+		paths(&cache.largest_files_in_dir(docs, 10)),
+		[
+			"root/docs/b",
+			"root/docs/old/e",
+			"root/docs/old/d",
+			"root/docs/c",
+			"root/docs/a"
+		]
+	);
+	assert_eq!(
+		paths(&cache.largest_files_in_dir(Path::new("root"), 1)),
+		["root/other/g"]
+	);
+}
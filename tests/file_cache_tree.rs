@@ -0,0 +1,102 @@
+//! Integration tests: the cached files as a nested directory tree
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::{DirectoryNode, DirectoryTree, FileCache};
+use linkfield::ignore_config::IgnoreConfig;
+use std::path::{Path, PathBuf};
+
+fn sample_tree() -> (VirtualFs, DirectoryTree) {
+	let vfs = VirtualFs::new();
+	for (name, size) in [
+		("top.txt", 1),
+		("src/main.rs", 2),
+		("src/lib/mod.rs", 3),
+		("src/lib/util.rs", 4),
+		("docs/readme.md", 5),
+	] {
+		vfs.create_file(name, size);
+	}
+	let tree = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty()).to_tree();
+	(vfs, tree)
+}
+
+fn child_names(node: &DirectoryNode) -> Vec<&str> {
+	match node {
+		DirectoryNode::Directory(children) => children.keys().map(String::as_str).collect(),
+		DirectoryNode::File(_) => panic!("not a directory"),
+	}
+}
+
+#[test]
+fn test_at_path_finds_files_and_directories() {
+	let (vfs, tree) = sample_tree();
+	assert_eq!(tree.root, vfs.root());
+	let keys: Vec<_> = tree.children.keys().map(String::as_str).collect();
+	assert_eq!(keys, ["docs", "src", "top.txt"]);
+
+	let lib = tree.at_path(&vfs.path("src/lib")).unwrap();
+	assert_eq!(child_names(lib), ["mod.rs", "util.rs"]);
+	// Relative paths are resolved from the root
+	assert_eq!(tree.at_path(Path::new("src/lib")), Some(lib));
+	match tree.at_path(&vfs.path("src/lib/util.rs")) {
+		Some(DirectoryNode::File(meta)) => {
+			assert_eq!(meta.size, 4);
+			assert_eq!(meta.path.0, vfs.path("src/lib/util.rs"));
+		}
+		other => panic!("expected a file, got {other:?}"),
+	}
+	assert_eq!(tree.at_path(Path::new("src/missing.rs")), None);
+	assert_eq!(tree.at_path(Path::new("top.txt/below")), None);
+}
+
+#[test]
+fn test_iter_depth_first_visits_parents_first() {
+	let (vfs, tree) = sample_tree();
+	let paths: Vec<PathBuf> = tree.iter_depth_first().map(|(path, _)| path).collect();
+	let expected: Vec<_> = [
+		"docs",
+		"docs/readme.md",
+		"src",
+		"src/lib",
+		"src/lib/mod.rs",
+		"src/lib/util.rs",
+		"src/main.rs",
+		"top.txt",
+	]
+	.iter()
+	.map(|name| vfs.path(name))
+	.collect();
+	assert_eq!(paths, expected);
+	for (path, node) in tree.iter_depth_first() {
+		assert_eq!(tree.at_path(&path), Some(node));
+	}
+}
+
+#[test]
+fn test_tree_json_round_trip() {
+	let (_vfs, tree) = sample_tree();
+	let json = serde_json::to_value(&tree).unwrap();
+	assert_eq!(
+		json["children"]["src"]["directory"]["main.rs"]["file"]["size"],
+		2
+	);
+	let parsed: DirectoryTree = serde_json::from_value(json).unwrap();
+	assert_eq!(parsed, tree);
+}
+
+#[test]
+fn test_stale_file_never_hides_a_directory() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("a/b.txt", 1);
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	// "a" turns into a file but the entry below it is still cached
+	std::fs::remove_dir_all(vfs.path("a")).unwrap();
+	vfs.create_file("a", 2);
+	cache.update_file(&vfs.path("a"));
+
+	let tree = cache.to_tree();
+	let a = tree.at_path(Path::new("a")).unwrap();
+	assert_eq!(child_names(a), ["b.txt"]);
+}
@@ -0,0 +1,124 @@
+//! Integration tests: app lifecycle states and the `health` subcommand
+
+mod common;
+
+use assert_cmd::cargo::CommandCargoExt;
+use common::{VirtualFs, run_watch_until_ready};
+use linkfield::health::{self, AppState, AppStateTracker, HEARTBEAT_STALE_AFTER, StateFile};
+use std::process::Command;
+
+fn health_code(vfs: &VirtualFs) -> i32 {
+	Command::cargo_bin("linkfield")
+		.unwrap()
+		.arg("health")
+		.arg(vfs.root())
+		.output()
+		.unwrap()
+		.status
+		.code()
+		.unwrap()
+}
+
+#[test]
+fn test_state_file_round_trip_and_exit_codes() {
+	let vfs = VirtualFs::new();
+	let state_file = health::state_file_path(&vfs.path("linkfield.redb"));
+	let tracker = AppStateTracker::new(Some(state_file.clone()));
+	assert_eq!(
+		health::read_state_file(&state_file).unwrap().state,
+		AppState::Starting
+	);
+
+	tracker.set(AppState::Degraded {
+		reason: "watcher failed".to_string(),
+	});
+	tracker.mark_ready();
+	let file = health::read_state_file(&state_file).unwrap();
+	assert_eq!(
+		file.state.exit_code(),
+		2,
+		"degraded must not be overwritten by ready"
+	);
+
+	tracker.set(AppState::Ready);
+	let file = health::read_state_file(&state_file).unwrap();
+	assert_eq!(file.pid, std::process::id());
+	assert_eq!(file.state.exit_code(), 0);
+	assert_eq!(health_code(&vfs), 0);
+}
+
+#[test]
+fn test_dead_process_or_stale_heartbeat_is_not_ready() {
+	let vfs = VirtualFs::new();
+	let state_file = health::state_file_path(&vfs.path("linkfield.redb"));
+	let write = |file: &StateFile| health::write_state_file(&state_file, file).unwrap();
+	let ready = StateFile::current(AppState::Ready);
+	write(&ready);
+	assert_eq!(health_code(&vfs), 0);
+
+	write(&StateFile {
+		heartbeat: ready.heartbeat - 2 * HEARTBEAT_STALE_AFTER.as_secs(),
+		..ready.clone()
+	});
+	assert_eq!(health_code(&vfs), 1, "stale heartbeat");
+
+	let mut exited = Command::new(std::env::current_exe().unwrap())
+		.arg("--list")
+		.stdout(std::process::Stdio::null())
+		.spawn()
+		.unwrap();
+	let pid = exited.id();
+	exited.wait().unwrap();
+	write(&StateFile { pid, ..ready });
+	assert_eq!(health_code(&vfs), 1, "process is gone");
+}
+
+#[test]
+fn test_watch_startup_state_transitions() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 5\nCREATE sub/b.txt 5")
+		.unwrap();
+	// Not running yet
+	assert_eq!(health_code(&vfs), 1);
+
+	let stdout = run_watch_until_ready(&vfs, &[]);
+	assert_eq!(health_code(&vfs), 1, "shutting down is not ready");
+
+	let states: Vec<&str> = stdout
+		.lines()
+		.filter(|line| line.contains("App state changed"))
+		.map(|line| {
+			["Starting", "Scanning", "Ready", "ShuttingDown", "Degraded"]
+				.into_iter()
+				.find(|name| line.contains(name))
+				.unwrap_or("?")
+		})
+		.collect();
+	assert_eq!(states, ["Starting", "Scanning", "Ready", "ShuttingDown"]);
+}
+
+#[test]
+fn test_scan_progress_is_reported_before_ready() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 5\nCREATE sub/b.txt 5")
+		.unwrap();
+	// The first run stores how many files to expect
+	run_watch_until_ready(&vfs, &[]);
+	vfs.create_file("c.txt", 5);
+
+	let stdout = run_watch_until_ready(&vfs, &["--staleness-threshold", "0"]);
+	let lines: Vec<&str> = stdout
+		.lines()
+		.filter(|line| line.contains("App state changed"))
+		.collect();
+	let ready = lines
+		.iter()
+		.position(|line| line.contains("Ready"))
+		.unwrap();
+	assert!(
+		lines[..ready]
+			.iter()
+			.any(|line| line.contains("Scanning") && !line.contains("progress: 0.0 }")),
+		"no progress before ready: {lines:#?}"
+	);
+}
@@ -0,0 +1,149 @@
+//! Integration tests: the watcher reloading ignore patterns when the ignore file changes
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::{FileCache, ScanConfig};
+use linkfield::ignore_config::IgnoreConfig;
+use linkfield::move_heuristics::MoveHeuristics;
+use linkfield::watcher::{WatchConfig, WatcherHandle, start_watcher_with_config};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+fn watch(
+	vfs: &VirtualFs,
+	ignore_file: &Path,
+) -> (Arc<FileCache>, Arc<RwLock<IgnoreConfig>>, WatcherHandle) {
+	let (ignore, _) = IgnoreConfig::from_file_with_patterns(ignore_file).unwrap();
+	let cache = FileCache::populate_from_dir(vfs.root(), &ignore);
+	let ignore = Arc::new(RwLock::new(ignore));
+	let watcher = start_watcher_with_config(
+		vfs.root(),
+		Arc::new(Mutex::new(cache.clone())),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		ignore.clone(),
+		WatchConfig {
+			ignore_file: Some(ignore_file.to_path_buf()),
+			..WatchConfig::default()
+		},
+	);
+	(cache, ignore, watcher)
+}
+
+fn cached(cache: &FileCache) -> HashSet<PathBuf> {
+	cache.all_paths().map(|path| path.0).collect()
+}
+
+fn wait_until(what: &str, condition: impl Fn() -> bool) {
+	let deadline = Instant::now() + Duration::from_secs(10);
+	while !condition() {
+		assert!(Instant::now() < deadline, "timed out waiting for {what}");
+		std::thread::sleep(Duration::from_millis(50));
+	}
+}
+
+#[test]
+fn test_editing_ignore_file_updates_cache() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE keep.txt 1\nCREATE a.log 1\nCREATE logs/b.txt 1")
+		.unwrap();
+	std::fs::write(vfs.path(".linkfieldignore"), "*.log\n").unwrap();
+	let (cache, ignore, watcher) = watch(&vfs, &vfs.path(".linkfieldignore"));
+	assert!(!cached(&cache).contains(&vfs.path("a.log")));
+	assert!(cached(&cache).contains(&vfs.path("logs/b.txt")));
+
+	std::fs::write(vfs.path(".linkfieldignore"), "logs/\n").unwrap();
+	wait_until("the new patterns", || {
+		ignore.read().unwrap().patterns() == ["logs/"]
+	});
+	wait_until("the cache to follow the patterns", || {
+		let paths = cached(&cache);
+		paths.contains(&vfs.path("a.log")) && !paths.contains(&vfs.path("logs/b.txt"))
+	});
+	assert!(cached(&cache).contains(&vfs.path("keep.txt")));
+	assert!(ignore.read().unwrap().is_ignored(vfs.path("logs")));
+
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+}
+
+#[test]
+fn test_ignore_file_outside_watch_root_is_watched() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE keep.txt 1\nCREATE drop.tmp 1")
+		.unwrap();
+	let config_dir = VirtualFs::new();
+	let ignore_file = config_dir.path(".linkfieldignore");
+	let (cache, _ignore, watcher) = watch(&vfs, &ignore_file);
+	assert!(cached(&cache).contains(&vfs.path("drop.tmp")));
+
+	std::fs::write(&ignore_file, "*.tmp\n").unwrap();
+	wait_until("drop.tmp to leave the cache", || {
+		!cached(&cache).contains(&vfs.path("drop.tmp"))
+	});
+	assert!(cached(&cache).contains(&vfs.path("keep.txt")));
+
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+}
+
+#[test]
+fn test_ignore_reload_updates_the_attached_database() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"CREATE keep.txt 1\nCREATE a.log 1\nCREATE skip/b.txt 1\nCREATE skip/deep/c.txt 1\nCREATE skip.txt 1",
+	)
+	.unwrap();
+	let ignore_file = vfs.path(".linkfieldignore");
+	std::fs::write(&ignore_file, "skip/\n.linkfieldignore\n").unwrap();
+	let (ignore, _) = IgnoreConfig::from_file_with_patterns(&ignore_file).unwrap();
+	let db_dir = VirtualFs::new();
+	let database = db::open_or_create_db(&db_dir.path("cache.redb")).unwrap();
+	// Like the watch mode: the scan commits to the database and keeps no files in memory
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_with_config_and_commit(
+		&database,
+		vfs.root(),
+		&ignore,
+		100,
+		&ScanConfig::default(),
+	);
+	cache.set_db(database).unwrap();
+	assert!(cache.is_empty());
+	let stored = |cache: &FileCache| {
+		let mut paths = HashSet::new();
+		FileCache::stream_from_redb(&cache.database().unwrap(), |path, _| {
+			paths.insert(path.0);
+		})
+		.unwrap();
+		paths
+	};
+	let expected =
+		|names: &[&str]| -> HashSet<PathBuf> { names.iter().map(|name| vfs.path(name)).collect() };
+	assert_eq!(stored(&cache), expected(&["keep.txt", "a.log", "skip.txt"]));
+
+	let ignore = Arc::new(RwLock::new(ignore));
+	let watcher = start_watcher_with_config(
+		vfs.root(),
+		Arc::new(Mutex::new(cache.clone())),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		ignore.clone(),
+		WatchConfig {
+			ignore_file: Some(ignore_file.clone()),
+			..WatchConfig::default()
+		},
+	);
+	std::fs::write(&ignore_file, "*.log\n.linkfieldignore\n").unwrap();
+	let after = expected(&["keep.txt", "skip.txt", "skip/b.txt", "skip/deep/c.txt"]);
+	wait_until("the database to follow the patterns", || {
+		stored(&cache) == after
+	});
+	// Only the files under the directory that is no longer ignored were read
+	assert_eq!(cached(&cache), expected(&["skip/b.txt", "skip/deep/c.txt"]));
+
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+}
@@ -0,0 +1,81 @@
+//! Integration tests: turning a .gitignore into linkfield ignore patterns
+
+mod common;
+
+use common::VirtualFs;
+
+use assert_cmd::Command;
+use linkfield::ignore_config::{IGNORE_FILE_NAME, IgnoreConfig};
+
+const GITIGNORE: &str = "\
+# Build output
+target/
+*.log
+**/node_modules/
+/dist
+docs/*.pdf
+!keep.log
+
+.DS_Store
+";
+
+#[test]
+fn test_from_gitignore_file_skips_git_specific_patterns() {
+	let dir = VirtualFs::new();
+	let gitignore = dir.path(".gitignore");
+	std::fs::write(&gitignore, GITIGNORE).unwrap();
+
+	let (config, skipped) = IgnoreConfig::from_gitignore_file(&gitignore).unwrap();
+	assert_eq!(
+		config.patterns(),
+		["target/", "*.log", "**/node_modules/", ".DS_Store"]
+	);
+	assert_eq!(
+		skipped,
+		[
+			"/dist: anchored to the repository root",
+			"docs/*.pdf: anchored to the repository root",
+			"!keep.log: negation patterns are not imported",
+		]
+	);
+	assert!(config.is_ignored("keep.log"));
+	assert!(config.is_ignored("/repo/web/debug.log"));
+	assert!(!config.is_ignored("/repo/dist/app.js"));
+
+	assert!(IgnoreConfig::from_gitignore_file(&dir.path("missing")).is_err());
+}
+
+#[test]
+fn test_import_gitignore_appends_new_patterns() {
+	let dir = VirtualFs::new();
+	std::fs::write(dir.path(".gitignore"), GITIGNORE).unwrap();
+	let output = dir.path(IGNORE_FILE_NAME);
+	std::fs::write(&output, "*.log").unwrap();
+
+	let result = Command::cargo_bin("linkfield")
+		.unwrap()
+		.current_dir(dir.root())
+		.arg("import-gitignore")
+		.output()
+		.unwrap();
+	assert!(result.status.success(), "{result:?}");
+	let stdout = String::from_utf8(result.stdout).unwrap();
+	assert!(stdout.contains("Added 3 patterns"), "{stdout}");
+	assert!(stdout.contains("skipped !keep.log"), "{stdout}");
+	assert_eq!(
+		std::fs::read_to_string(&output).unwrap(),
+		"*.log\n# Imported from .gitignore\ntarget/\n**/node_modules/\n.DS_Store\n"
+	);
+
+	// Everything is already there the second time
+	let other = dir.path("other.ignore");
+	Command::cargo_bin("linkfield")
+		.unwrap()
+		.current_dir(dir.root())
+		.args(["import-gitignore", ".gitignore", "--output"])
+		.arg(&other)
+		.assert()
+		.success();
+	let (config, _) = IgnoreConfig::from_file_with_patterns(&other).unwrap();
+	assert_eq!(config.patterns().len(), 4);
+}
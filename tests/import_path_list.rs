@@ -0,0 +1,129 @@
+//! Integration tests: adding the files of an external path list without a scan
+
+mod common;
+
+use assert_cmd::Command;
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::db::DEFAULT_LOAD_BATCH_SIZE;
+use linkfield::file_cache::{FileCache, ensure_file_cache_table};
+use linkfield::ignore_config::IgnoreConfig;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+fn stored_paths(db: &redb::Database, root: &std::path::Path) -> BTreeSet<PathBuf> {
+	let cache = FileCache::new_root(root.to_string_lossy().as_ref());
+	cache
+		.load_from_redb_batched(db, DEFAULT_LOAD_BATCH_SIZE, None)
+		.unwrap();
+	cache
+		.all_files()
+		.into_iter()
+		.map(|meta| meta.path.0)
+		.collect()
+}
+
+#[test]
+fn test_import_path_list_skips_missing_files() {
+	let vfs = VirtualFs::new();
+	let files: Vec<_> = ["a.txt", "docs/b.md", "docs/deep/c.rs"]
+		.iter()
+		.map(|name| vfs.create_file(name, 3))
+		.collect();
+	let list = format!(
+		"{}\n{}\n{}\n\n{}\n{}",
+		files[0].display(),
+		vfs.path("missing.txt").display(),
+		files[1].display(),
+		vfs.path("docs").display(),
+		files[2].display(),
+	);
+	let db = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	ensure_file_cache_table(&db).unwrap();
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+
+	// A batch size of 2 makes the last file go in a batch of its own
+	let imported = cache
+		.import_path_list(&db, list.as_bytes(), b'\n', 2, &IgnoreConfig::empty())
+		.unwrap();
+	assert_eq!(imported, 3);
+	let expected: BTreeSet<_> = files.into_iter().collect();
+	let cached: BTreeSet<_> = cache
+		.all_files()
+		.into_iter()
+		.map(|meta| meta.path.0)
+		.collect();
+	assert_eq!(cached, expected);
+	assert_eq!(stored_paths(&db, vfs.root()), expected);
+}
+
+#[test]
+fn test_import_path_list_stores_paths_like_a_scan() {
+	let vfs = VirtualFs::new();
+	let kept = vfs.create_file("docs/kept.md", 3);
+	vfs.create_file("docs/build.log", 3);
+	vfs.create_file("target/out.bin", 3);
+	let outside = VirtualFs::new();
+	let elsewhere = outside.create_file("elsewhere.txt", 3);
+	let list = [
+		vfs.path("docs/../docs/kept.md"),
+		vfs.path("docs/build.log"),
+		vfs.path("target/out.bin"),
+		elsewhere,
+		vfs.root()
+			.join("..")
+			.join(outside.path("elsewhere.txt").file_name().unwrap()),
+	]
+	.iter()
+	.map(|path| format!("{}\n", path.display()))
+	.collect::<String>();
+	let db = db::open_or_create_db(&outside.path("linkfield.redb")).unwrap();
+	ensure_file_cache_table(&db).unwrap();
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	let ignore = IgnoreConfig::new(&["*.log", "target/"]).unwrap();
+
+	let imported = cache
+		.import_path_list(&db, list.as_bytes(), b'\n', 10, &ignore)
+		.unwrap();
+	assert_eq!(imported, 1);
+	// Stored under the same key the scan uses
+	let scanned = FileCache::populate_from_dir(vfs.root(), &ignore);
+	let scanned: BTreeSet<_> = scanned
+		.all_files()
+		.into_iter()
+		.map(|meta| meta.path.0)
+		.collect();
+	assert_eq!(scanned, BTreeSet::from([kept.clone()]));
+	assert_eq!(stored_paths(&db, vfs.root()), scanned);
+}
+
+#[test]
+fn test_import_cli_reads_null_separated_lists() {
+	let vfs = VirtualFs::new();
+	let plain = vfs.create_file("plain.txt", 1);
+	let odd = vfs.create_file("with\nnewline.txt", 2);
+	let list = vfs.path("files.lst");
+	let mut bytes = Vec::new();
+	// The database itself is never imported
+	for path in [&plain, &odd, &vfs.path("linkfield.redb")] {
+		bytes.extend(path.as_os_str().as_encoded_bytes());
+		bytes.push(0);
+	}
+	std::fs::write(&list, bytes).unwrap();
+
+	let output = Command::cargo_bin("linkfield")
+		.unwrap()
+		.args(["import", "--null-separated", "--from-file"])
+		.arg(&list)
+		.arg(vfs.root())
+		.output()
+		.unwrap();
+	assert!(output.status.success(), "{output:?}");
+	assert!(
+		String::from_utf8(output.stdout)
+			.unwrap()
+			.contains("imported 2 files")
+	);
+	let db = db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap();
+	assert_eq!(stored_paths(&db, vfs.root()), BTreeSet::from([plain, odd]));
+}
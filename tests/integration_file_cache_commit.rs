@@ -1,12 +1,14 @@
 //! Integration test: file cache is committed to redb in batches, not kept fully in memory
 
+mod common;
+#[path = "sequential_scan.rs"]
+mod sequential_scan;
+
+use common::VirtualFs;
 use linkfield::file_cache::FileCache;
 use linkfield::file_cache::db::{FILE_CACHE_TABLE, ensure_file_cache_table};
 use redb::{Database, ReadableTableMetadata};
-use std::fs::{self, File};
-use std::io::Write;
 use sysinfo::{ProcessesToUpdate, System};
-use tempfile::tempdir;
 use tracing::info;
 use tracing_subscriber;
 
@@ -30,19 +32,15 @@ fn test_file_cache_batch_commit() {
 	let mem_before = process.memory();
 	info!("Memory before file creation: {} KB", mem_before);
 
-	let temp = tempdir().unwrap();
-	let db_path = temp.path().join("test.redb");
-	let db = Database::create(&db_path).unwrap();
+	let vfs = VirtualFs::new();
+	let db = Database::create(vfs.path("test.redb")).unwrap();
 	ensure_file_cache_table(&db).unwrap();
 
 	// Create a directory with many files
-	let dir = temp.path().join("files");
-	fs::create_dir(&dir).unwrap();
 	for i in 0..5000 {
-		let file_path = dir.join(format!("file_{i}.txt"));
-		let mut f = File::create(&file_path).unwrap();
-		writeln!(f, "hello {i}").unwrap();
+		vfs.create_file(&format!("files/file_{i}.txt"), 8);
 	}
+	let dir = vfs.path("files");
 
 	sys.refresh_processes(ProcessesToUpdate::All, true);
 	let mem_after_files = sys.process(pid).unwrap().memory();
@@ -106,19 +104,15 @@ fn test_file_cache_sequential_streaming() {
 	let mem_before = process.memory();
 	info!("Memory before file creation: {} KB", mem_before);
 
-	let temp = tempdir().unwrap();
-	let db_path = temp.path().join("test_seq.redb");
-	let db = Database::create(&db_path).unwrap();
+	let vfs = VirtualFs::new();
+	let db = Database::create(vfs.path("test_seq.redb")).unwrap();
 	ensure_file_cache_table(&db).unwrap();
 
 	// Create a directory with many files
-	let dir = temp.path().join("files");
-	fs::create_dir(&dir).unwrap();
 	for i in 0..5000 {
-		let file_path = dir.join(format!("file_{i}.txt"));
-		let mut f = File::create(&file_path).unwrap();
-		writeln!(f, "hello {i}").unwrap();
+		vfs.create_file(&format!("files/file_{i}.txt"), 8);
 	}
+	let dir = vfs.path("files");
 
 	sys.refresh_processes(ProcessesToUpdate::All, true);
 	let mem_after_files = sys.process(pid).unwrap().memory();
@@ -166,6 +160,3 @@ fn test_file_cache_sequential_streaming() {
 		"Memory was not released after dropping cache"
 	);
 }
-
-#[path = "sequential_scan.rs"]
-mod sequential_scan;
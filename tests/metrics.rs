@@ -0,0 +1,79 @@
+//! Integration tests: the `--metrics-port` listener
+
+mod common;
+
+use assert_cmd::cargo::CommandCargoExt;
+use common::VirtualFs;
+use linkfield::health::{AppState, AppStateTracker};
+use linkfield::metrics;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+fn get(addr: SocketAddr, path: &str) -> String {
+	let mut stream = TcpStream::connect(addr).unwrap();
+	write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+	let mut response = String::new();
+	stream.read_to_string(&mut response).unwrap();
+	response
+}
+
+#[test]
+fn test_health_and_cache_age_are_served() {
+	let app_state = AppStateTracker::new(None);
+	let addr = metrics::serve(
+		0,
+		app_state.clone(),
+		Box::new(|| Duration::from_millis(12_500)),
+	)
+	.unwrap();
+
+	let starting = get(addr, "/health");
+	assert!(starting.starts_with("HTTP/1.1 503"), "{starting}");
+	assert!(starting.ends_with("Starting\n"));
+	app_state.set(AppState::Ready);
+	let ready = get(addr, "/health");
+	assert!(ready.starts_with("HTTP/1.1 200"), "{ready}");
+
+	let metrics = get(addr, "/metrics");
+	assert!(metrics.starts_with("HTTP/1.1 200"), "{metrics}");
+	assert!(metrics.contains("# TYPE linkfield_cache_age_seconds gauge\n"));
+	assert!(metrics.contains("\nlinkfield_cache_age_seconds 12.5\n"));
+
+	assert!(get(addr, "/missing").starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+fn test_watch_serves_health_on_metrics_port() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("a.txt", 1);
+	let port = TcpListener::bind("127.0.0.1:0")
+		.unwrap()
+		.local_addr()
+		.unwrap()
+		.port();
+	let mut child = Command::cargo_bin("linkfield")
+		.unwrap()
+		.arg(vfs.root())
+		.args(["--metrics-port", &port.to_string()])
+		.stdin(Stdio::piped())
+		.stdout(Stdio::null())
+		.spawn()
+		.unwrap();
+	let addr = SocketAddr::from(([127, 0, 0, 1], port));
+	let deadline = Instant::now() + Duration::from_secs(30);
+	loop {
+		let ready = TcpStream::connect(addr)
+			.is_ok_and(|_| get(addr, "/health").starts_with("HTTP/1.1 200"));
+		if ready {
+			break;
+		}
+		assert!(Instant::now() < deadline, "never served a ready /health");
+		std::thread::sleep(Duration::from_millis(50));
+	}
+	assert!(get(addr, "/metrics").contains("linkfield_cache_age_seconds "));
+
+	child.stdin.take().unwrap().write_all(b"\n").unwrap();
+	assert!(child.wait().unwrap().success());
+}
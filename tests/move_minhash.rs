@@ -0,0 +1,190 @@
+//! Integration tests: pairing moves of files with similar contents by MinHash signature
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::{FileCache, FileMeta};
+use linkfield::minhash::{self, SIGNATURE_LEN};
+use linkfield::move_heuristics::{
+	FileEventKind, MoveHeuristics, MoveHeuristicsConfig, make_file_event, score_pair,
+};
+use linkfield::watcher::{WatchEvent, replay_events};
+use notify_debouncer_full::DebouncedEvent;
+use notify_debouncer_full::notify::Event;
+use notify_debouncer_full::notify::event::{
+	CreateKind, DataChange, EventKind, ModifyKind, RemoveKind,
+};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// `len` bytes of xorshift noise, so no two windows of a file repeat
+fn noise(seed: u64, len: usize) -> Vec<u8> {
+	let mut state = seed;
+	(0..len)
+		.map(|_| {
+			state ^= state << 13;
+			state ^= state >> 7;
+			state ^= state << 17;
+			state.to_le_bytes()[0]
+		})
+		.collect()
+}
+
+#[test]
+fn test_signature_distance() {
+	let vfs = VirtualFs::new();
+	let original = noise(1, 300_000);
+	let mut edited = original.clone();
+	// A few bytes changed in the sampled head, and a block in the unsampled middle
+	edited[100..104].copy_from_slice(b"edit");
+	edited[150_000..151_000].fill(0);
+	let files = [
+		("original.bin", original.clone()),
+		("copy.bin", original),
+		("edited.bin", edited),
+		("other.bin", noise(2, 300_000)),
+	];
+	for (name, contents) in &files {
+		std::fs::write(vfs.path(name), contents).unwrap();
+	}
+	let signature = |name| minhash::file_signature(&vfs.path(name)).unwrap();
+
+	let original = signature("original.bin");
+	assert_eq!(minhash::distance(&original, &signature("copy.bin")), 0);
+	assert!(minhash::distance(&original, &signature("edited.bin")) < 2);
+	assert_eq!(
+		minhash::distance(&original, &signature("other.bin")),
+		SIGNATURE_LEN as u32
+	);
+
+	// Small files are read whole
+	assert_eq!(
+		minhash::file_signature(&vfs.create_file("small.txt", 3)).unwrap(),
+		minhash::signature(&[&[0, 0, 0]])
+	);
+}
+
+fn heuristics(use_minhash: bool) -> MoveHeuristics {
+	MoveHeuristics::with_config(MoveHeuristicsConfig {
+		max_age: Duration::from_secs(60),
+		use_minhash,
+		..MoveHeuristicsConfig::default()
+	})
+}
+
+/// Remove event for `path` carrying the signature read before it was deleted
+fn signed_remove(path: &Path) -> linkfield::move_heuristics::FileEvent {
+	let mut event = make_file_event(
+		path.to_path_buf(),
+		FileEventKind::Remove,
+		FileMeta::from_path(path),
+	);
+	event.minhash = Some(minhash::file_signature(path).unwrap());
+	std::fs::remove_file(path).unwrap();
+	event
+}
+
+#[test]
+fn test_similar_contents_pair_renamed_files() {
+	let vfs = VirtualFs::new();
+	let contents = noise(3, 500_000);
+	let mut reencoded = contents.clone();
+	reencoded.extend(noise(4, 100));
+	let write = |name: &str, contents: &[u8]| {
+		let path = vfs.path(name);
+		std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+		std::fs::write(&path, contents).unwrap();
+		path
+	};
+
+	let remove = signed_remove(&write("shows/Episode 1.mkv", &contents));
+	let renamed = write("library/x9f3.mkv", &reencoded);
+	let mut create = make_file_event(
+		renamed.clone(),
+		FileEventKind::Create,
+		FileMeta::from_path(&renamed),
+	);
+	create.minhash = Some(minhash::file_signature(&renamed).unwrap());
+	// Names and sizes differ too much to pair on their own
+	let unsigned = make_file_event(
+		renamed.clone(),
+		FileEventKind::Create,
+		FileMeta::from_path(&renamed),
+	);
+	assert!(score_pair(&remove, &unsigned) <= 0.5);
+
+	let mut without = heuristics(false);
+	without.add_remove(remove.clone());
+	assert!(without.pair_create(&unsigned).is_none());
+
+	let mut with = heuristics(true);
+	with.add_remove(remove.clone());
+	let candidate = with.pair_create(&create).unwrap();
+	assert_eq!(candidate.from.path, remove.path);
+	assert!(candidate.to.minhash.is_some());
+	assert!(candidate.score > 0.5);
+
+	// Different contents get nothing from the signature
+	let mut with = heuristics(true);
+	with.add_remove(remove);
+	let unrelated = write("library/b7c1.mkv", &noise(5, 500_100));
+	let mut create = make_file_event(
+		unrelated.clone(),
+		FileEventKind::Create,
+		FileMeta::from_path(&unrelated),
+	);
+	create.minhash = Some(minhash::file_signature(&unrelated).unwrap());
+	assert!(with.pair_batch(&[create])[0].is_none());
+}
+
+fn event(kind: EventKind, path: &Path) -> DebouncedEvent {
+	DebouncedEvent::new(
+		Event::new(kind).add_path(path.to_path_buf()),
+		Instant::now(),
+	)
+}
+
+#[test]
+fn test_watcher_keeps_signatures_for_removes() {
+	let vfs = VirtualFs::new();
+	let original = vfs.path("shows/Episode 1.mkv");
+	std::fs::create_dir_all(original.parent().unwrap()).unwrap();
+	std::fs::write(&original, noise(6, 400_000)).unwrap();
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	let file_cache = Arc::new(Mutex::new(cache.clone()));
+	let heuristics = Arc::new(Mutex::new(heuristics(true)));
+
+	// The signature is read when the file is modified, while it can still be read
+	let mut contents = noise(7, 400_000);
+	std::fs::write(&original, &contents).unwrap();
+	replay_events(
+		[event(
+			EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+			&original,
+		)],
+		&file_cache,
+		&heuristics,
+	);
+	assert!(cache.signature(&original).is_some());
+
+	std::fs::remove_file(&original).unwrap();
+	contents.extend(noise(8, 100));
+	let renamed = vfs.path("library/x9f3.mkv");
+	std::fs::create_dir_all(renamed.parent().unwrap()).unwrap();
+	std::fs::write(&renamed, &contents).unwrap();
+	let events = replay_events(
+		[
+			event(EventKind::Remove(RemoveKind::File), &original),
+			event(EventKind::Create(CreateKind::File), &renamed),
+		],
+		&file_cache,
+		&heuristics,
+	);
+	assert!(
+		matches!(&events[..], [WatchEvent::Remove(_), WatchEvent::Move { from, to, .. }] if *from == original && *to == renamed),
+		"{events:?}"
+	);
+	assert!(cache.signature(&original).is_none());
+	assert!(cache.signature(&renamed).is_some());
+}
@@ -0,0 +1,119 @@
+//! Integration tests: move detection counters persisted across watcher restarts
+
+mod common;
+
+use assert_cmd::Command;
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::{FileCache, FileMeta};
+use linkfield::ignore_config::IgnoreConfig;
+use linkfield::move_heuristics::{FileEventKind, MoveHeuristics, make_file_event};
+use linkfield::watcher::start_watcher;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Rename `from` to `to` in `vfs` and feed the events to `heuristics`
+fn detect_move(vfs: &VirtualFs, heuristics: &mut MoveHeuristics, from: &str, to: &str) {
+	let meta = FileMeta::from_path(&vfs.path(from)).unwrap();
+	vfs.replay_script(&format!("RENAME {from} {to}")).unwrap();
+	heuristics.add_remove(make_file_event(
+		vfs.path(from),
+		FileEventKind::Remove,
+		Some(meta),
+	));
+	let moved = FileMeta::from_path(&vfs.path(to)).unwrap();
+	let pair = heuristics.pair_create(&make_file_event(
+		vfs.path(to),
+		FileEventKind::Create,
+		Some(moved),
+	));
+	assert!(pair.is_some(), "{from} -> {to} should be detected");
+}
+
+#[test]
+fn test_move_stats_accumulate_across_restarts() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 100\nCREATE c.txt 200")
+		.unwrap();
+	let db_path = vfs.path("linkfield.redb");
+	{
+		let database = db::open_or_create_db(&db_path).unwrap();
+		let mut heuristics = MoveHeuristics::new(Duration::from_secs(5));
+		detect_move(&vfs, &mut heuristics, "a.txt", "b.txt");
+		// A new file at the old path suggests a delete-and-recreate save, not a move
+		vfs.create_file("a.txt", 100);
+		let create = make_file_event(vfs.path("a.txt"), FileEventKind::Create, None);
+		assert!(heuristics.pair_create(&create).is_none());
+		assert_eq!(heuristics.statistics().false_positives_suspected, 1);
+		heuristics.save_to_redb(&database).unwrap();
+	}
+	{
+		let database = db::open_or_create_db(&db_path).unwrap();
+		let mut heuristics = MoveHeuristics::load_from_redb(&database)
+			.unwrap()
+			.expect("state was saved");
+		detect_move(&vfs, &mut heuristics, "c.txt", "d.txt");
+		let stats = heuristics.statistics();
+		assert_eq!((stats.moves_detected, stats.total_creates_received), (2, 3));
+		heuristics.save_to_redb(&database).unwrap();
+		assert_eq!(
+			MoveHeuristics::stored_stats(&database).unwrap(),
+			[
+				("moves_detected", 2),
+				("removes_received", 2),
+				("creates_received", 3),
+				("removes_expired", 0),
+				("false_positive_suspected", 1),
+			]
+		);
+	}
+
+	let output = Command::cargo_bin("linkfield")
+		.unwrap()
+		.args(["stats", "moves"])
+		.arg(vfs.root())
+		.output()
+		.unwrap();
+	assert!(output.status.success());
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	let rows: Vec<Vec<&str>> = stdout
+		.lines()
+		.map(|line| line.split_whitespace().collect())
+		.collect();
+	assert_eq!(rows[0], ["counter", "value"]);
+	assert_eq!(rows[1], ["moves_detected", "2"]);
+	assert_eq!(rows[5], ["false_positive_suspected", "1"]);
+}
+
+#[test]
+fn test_watcher_persists_move_stats_without_a_final_save() {
+	let vfs = VirtualFs::new();
+	let file = vfs.create_file("a.txt", 100);
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	cache.update_file(&file);
+	cache
+		.set_db(db::open_or_create_db(&vfs.path("linkfield.redb")).unwrap())
+		.unwrap();
+	let database = cache.database().unwrap();
+	let watcher = start_watcher(
+		vfs.root(),
+		Arc::new(Mutex::new(cache)),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::empty())),
+	);
+	vfs.delete_file("a.txt");
+	vfs.create_file("b.txt", 100);
+	let deadline = Instant::now() + Duration::from_secs(10);
+	while watcher.heuristics_stats().total_creates_received == 0 {
+		assert!(Instant::now() < deadline, "the events never arrived");
+		std::thread::sleep(Duration::from_millis(50));
+	}
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+
+	// The watcher wrote its counters itself; nothing called `save_to_redb`
+	let stats = MoveHeuristics::stored_stats(&database).unwrap();
+	assert_eq!(stats[1].0, "removes_received");
+	assert_eq!(stats[2].0, "creates_received");
+	assert!(stats[1].1 >= 1 && stats[2].1 >= 1, "{stats:?}");
+}
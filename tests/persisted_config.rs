@@ -0,0 +1,77 @@
+//! Integration tests: watcher settings persisted in the database
+
+mod common;
+
+use common::VirtualFs;
+
+use assert_cmd::Command;
+use linkfield::db;
+use linkfield::file_cache::FileCache;
+use linkfield::file_cache::db::DEFAULT_LOAD_BATCH_SIZE;
+use linkfield::persisted_config::PersistedConfig;
+use std::path::PathBuf;
+
+fn sample_config() -> PersistedConfig {
+	PersistedConfig {
+		watch_paths: vec![PathBuf::from("/data/photos")],
+		ignore_patterns: vec!["*.tmp".to_string(), "cache/".to_string()],
+		move_threshold: 0.75,
+		move_max_age_secs: 30,
+		scan_threads: Some(4),
+	}
+}
+
+#[test]
+fn test_config_survives_restart() {
+	let dir = VirtualFs::new();
+	let db_path = dir.path("config.redb");
+	{
+		let database = db::open_or_create_db(&db_path).unwrap();
+		db::migrate(&database).unwrap();
+		assert_eq!(PersistedConfig::load(&database).unwrap(), None);
+		sample_config().save(&database).unwrap();
+	}
+
+	// Restart: reopen the database and reload the cache as the app does
+	let database = db::open_or_create_db(&db_path).unwrap();
+	FileCache::new_root("root")
+		.load_from_redb_batched(&database, DEFAULT_LOAD_BATCH_SIZE, None)
+		.unwrap();
+	let restored = PersistedConfig::load(&database).unwrap().unwrap();
+	assert_eq!(restored, sample_config());
+
+	let merged = restored.merged_with_cli(PathBuf::from("/data/music"), &[]);
+	assert_eq!(merged.watch_paths, vec![PathBuf::from("/data/music")]);
+	assert_eq!(merged.ignore_patterns, sample_config().ignore_patterns);
+	let merged = merged.merged_with_cli(PathBuf::from("/data/music"), &["*.log".to_string()]);
+	assert_eq!(merged.ignore_patterns, vec!["*.log"]);
+	assert_eq!(merged.heuristics_config().min_score, 0.75);
+
+	assert!(PersistedConfig::reset(&database).unwrap());
+	assert!(!PersistedConfig::reset(&database).unwrap());
+	assert_eq!(PersistedConfig::load(&database).unwrap(), None);
+}
+
+#[test]
+fn test_config_cli_show_and_reset() {
+	let dir = VirtualFs::new();
+	let db_path = dir.path("cli.redb");
+	sample_config()
+		.save(&db::open_or_create_db(&db_path).unwrap())
+		.unwrap();
+	let run = |action: &str| {
+		let output = Command::cargo_bin("linkfield")
+			.unwrap()
+			.args(["config", action])
+			.arg(&db_path)
+			.output()
+			.unwrap();
+		assert!(output.status.success());
+		String::from_utf8(output.stdout).unwrap()
+	};
+
+	let shown: PersistedConfig = toml::from_str(&run("show")).unwrap();
+	assert_eq!(shown, sample_config());
+	assert!(run("reset").starts_with("Deleted persisted config"));
+	assert!(run("show").starts_with("No persisted config"));
+}
@@ -0,0 +1,84 @@
+//! Property-based tests: `FileMeta` serialization round-trips and `score_pair` bounds
+
+use linkfield::file_cache::FileMeta;
+use linkfield::file_cache::meta::{FileCachePath, FileType};
+use linkfield::move_heuristics::{FileEvent, FileEventKind, score_pair};
+use proptest::prelude::*;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+fn system_time() -> impl Strategy<Value = Option<SystemTime>> {
+	proptest::option::of(
+		any::<u64>().prop_map(|nanos| SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos)),
+	)
+}
+
+fn file_type() -> impl Strategy<Value = FileType> {
+	prop_oneof![
+		Just(FileType::Regular),
+		Just(FileType::Directory),
+		Just(FileType::Symlink),
+		Just(FileType::Device),
+		Just(FileType::Socket),
+		Just(FileType::Pipe),
+		Just(FileType::Unknown),
+	]
+}
+
+prop_compose! {
+	fn file_meta()(
+		path in "[a-zA-Z0-9_ ./-]{0,64}",
+		size in any::<u64>(),
+		modified in system_time(),
+		created in system_time(),
+		extension in proptest::option::of("[a-z0-9]{0,8}"),
+		inode in proptest::option::of(any::<u64>()),
+		content_hash in proptest::option::of(any::<u64>()),
+		is_virtual in any::<bool>(),
+		file_type in file_type(),
+		device in proptest::option::of(any::<u64>()),
+	) -> FileMeta {
+		FileMeta {
+			path: FileCachePath(PathBuf::from(path)),
+			size,
+			modified,
+			created,
+			extension,
+			inode,
+			content_hash,
+			is_virtual,
+			file_type,
+			device,
+		}
+	}
+}
+
+prop_compose! {
+	fn file_event()(
+		path in "[a-zA-Z0-9_./-]{0,32}",
+		create in any::<bool>(),
+		meta in proptest::option::of(file_meta()),
+		minhash in proptest::option::of(any::<[u64; 8]>()),
+	) -> FileEvent {
+		FileEvent {
+			path: PathBuf::from(path),
+			kind: if create { FileEventKind::Create } else { FileEventKind::Remove },
+			meta,
+			time: Instant::now(),
+			minhash,
+		}
+	}
+}
+
+proptest! {
+	#[test]
+	fn filemeta_roundtrips_through_bincode(meta in file_meta()) {
+		prop_assert_eq!(FileMeta::deserialize(&meta.serialize().unwrap()).unwrap(), meta);
+	}
+
+	#[test]
+	fn score_pair_is_bounded(remove in file_event(), create in file_event()) {
+		let score = score_pair(&remove, &create);
+		prop_assert!((0.0..=1.0).contains(&score), "score out of range: {}", score);
+	}
+}
@@ -0,0 +1,82 @@
+//! Integration tests: the tokio scan finds what the sync scan finds. Run with
+//! `--features tokio`.
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::{ExtensionNormalizer, FileCache, ScanConfig};
+use linkfield::ignore_config::IgnoreConfig;
+use std::sync::Arc;
+
+fn runtime() -> tokio::runtime::Runtime {
+	tokio::runtime::Runtime::new().unwrap()
+}
+
+#[test]
+fn test_async_scan_matches_sync_scan() {
+	let vfs = VirtualFs::new();
+	for i in 0..60 {
+		vfs.create_file(&format!("d{}/sub{}/f{i}.txt", i % 4, i % 3), i);
+	}
+	vfs.create_file("top.JPEG", 2);
+	vfs.create_file("skip.log", 1);
+	vfs.create_file("node_modules/pkg/index.js", 3);
+	#[cfg(unix)]
+	std::os::unix::fs::symlink(vfs.path("d0"), vfs.path("link")).unwrap();
+	let ignore = IgnoreConfig::new(&["*.log", "node_modules/"]).unwrap();
+
+	let sync = FileCache::populate_from_dir(vfs.root(), &ignore).file_map();
+	// One permit reads the directories one at a time
+	for permits in [1, 8] {
+		let config = ScanConfig {
+			max_concurrent_dirs: permits,
+			..ScanConfig::default()
+		};
+		assert!(format!("{config:?}").contains(&format!("max_concurrent_dirs: {permits}")));
+		let scanned = runtime().block_on(FileCache::scan_dir_async_with_config(
+			vfs.root(),
+			&ignore,
+			&config,
+		));
+		assert_eq!(scanned, sync);
+	}
+	assert_eq!(sync.len(), 61);
+}
+
+#[test]
+fn test_async_scan_feeds_diff_and_update() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("a.jpeg", 1);
+	vfs.create_file("b/c.txt", 2);
+	let ignore = IgnoreConfig::empty();
+	let cache = FileCache::populate_from_dir(vfs.root(), &ignore);
+
+	vfs.delete_file("a.jpeg");
+	vfs.create_file("b/new.jpeg", 3);
+	let config = ScanConfig {
+		extension_normalizer: Some(Arc::new(ExtensionNormalizer::default())),
+		..ScanConfig::default()
+	};
+	let scanned = runtime().block_on(FileCache::scan_dir_async_with_config(
+		vfs.root(),
+		&ignore,
+		&config,
+	));
+	assert_eq!(
+		scanned[&vfs.path("b/new.jpeg").as_path().into()]
+			.extension
+			.as_deref(),
+		Some("jpg")
+	);
+	let diff = cache.diff_and_update(&scanned, None);
+	assert_eq!((diff.added.len(), diff.removed.len()), (1, 1));
+	assert_eq!(cache.all_files().len(), 2);
+
+	// A missing directory has nothing to scan
+	assert!(
+		runtime()
+			.block_on(FileCache::scan_dir_async(&vfs.path("missing"), &ignore))
+			.is_empty()
+	);
+}
@@ -0,0 +1,63 @@
+//! Integration tests: previewing the changes a scan would make
+
+mod common;
+
+use assert_cmd::Command;
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::file_cache::meta::FileCachePath;
+use linkfield::ignore_config::IgnoreConfig;
+
+#[test]
+fn test_dry_run_scan_reports_stale_cache_without_changing_it() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE keep.txt 1\nCREATE grow.txt 1\nCREATE gone.txt 1")
+		.unwrap();
+	let cache = FileCache::populate_from_dir(vfs.root(), &IgnoreConfig::empty());
+	vfs.replay_script("CREATE grow.txt 10\nDELETE gone.txt\nCREATE sub/new.txt 1")
+		.unwrap();
+	let before = cache.file_map();
+
+	let result = cache.dry_run_scan(vfs.root(), &IgnoreConfig::empty());
+
+	let paths = |names: &[&str]| -> Vec<FileCachePath> {
+		names
+			.iter()
+			.map(|name| FileCachePath(vfs.path(name)))
+			.collect()
+	};
+	assert_eq!(result.would_add, paths(&["sub/new.txt"]));
+	assert_eq!(result.would_update, paths(&["grow.txt"]));
+	assert_eq!(result.would_remove, paths(&["gone.txt"]));
+	assert_eq!(result.unchanged, 1);
+	assert_eq!(cache.file_map(), before);
+}
+
+#[test]
+fn test_scan_cli_dry_run_leaves_database_alone() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.txt 1\nCREATE b.txt 1").unwrap();
+	let linkfield = || Command::cargo_bin("linkfield").unwrap();
+	let scan = |dry_run: bool| {
+		let mut command = linkfield();
+		command.arg("scan").arg(vfs.root());
+		if dry_run {
+			command.arg("--dry-run");
+		}
+		let output = command.output().unwrap();
+		assert!(output.status.success());
+		String::from_utf8(output.stdout).unwrap()
+	};
+	scan(false);
+
+	vfs.replay_script("DELETE a.txt\nCREATE c.txt 1").unwrap();
+	let expected = format!(
+		"D {}\nA {}\n",
+		vfs.path("a.txt").display(),
+		vfs.path("c.txt").display()
+	);
+	assert_eq!(scan(true), expected);
+	assert_eq!(scan(true), expected);
+	assert_eq!(scan(false), expected);
+	assert_eq!(scan(true), "");
+}
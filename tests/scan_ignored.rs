@@ -0,0 +1,92 @@
+//! Integration tests: counting entries skipped by ignore rules during a scan
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::{IgnoreConfig, SIZE_RULE_KEY, SizeIgnoreRule};
+use std::sync::Arc;
+
+fn ignore_rules() -> IgnoreConfig {
+	IgnoreConfig::new(&["*.log", "node_modules/"])
+		.unwrap()
+		.with_size_rule(SizeIgnoreRule {
+			min_bytes: None,
+			max_bytes: Some(1000),
+		})
+}
+
+#[test]
+fn test_last_scan_ignored_count_matches_skipped_entries() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"CREATE keep.txt 5\nCREATE a.log 5\nCREATE sub/b.log 5\nCREATE sub/keep.rs 5\n\
+		 CREATE node_modules/x.js 5\nCREATE node_modules/y.js 5\nCREATE big.bin 5000",
+	)
+	.unwrap();
+	let ignore = ignore_rules();
+
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	cache.scan_dir_collect_with_ignore(vfs.root(), &ignore, None);
+
+	// a.log, sub/b.log, big.bin and node_modules itself, not its contents
+	assert_eq!(cache.last_scan_ignored_count(), 4);
+	let stats = ignore.pattern_statistics();
+	assert_eq!(
+		stats,
+		vec![
+			("*.log".to_string(), 2),
+			(SIZE_RULE_KEY.to_string(), 1),
+			("node_modules/".to_string(), 1),
+		]
+	);
+}
+
+#[test]
+fn test_last_scan_ignored_count_resets_between_scans() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE a.log 5\nCREATE b.txt 5").unwrap();
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::new(&["*.log"]).unwrap(), None);
+	assert_eq!(cache.last_scan_ignored_count(), 1);
+
+	cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+	assert_eq!(cache.last_scan_ignored_count(), 0);
+}
+
+#[test]
+fn test_committing_scans_skip_the_same_entries() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"CREATE keep.txt 5\nCREATE a.log 5\nCREATE sub/b.log 5\nCREATE sub/node_modules/x.js 5\n\
+		 CREATE node_modules/y.js 5\nCREATE big.bin 5000",
+	)
+	.unwrap();
+	let db_dir = VirtualFs::new();
+
+	let ignore = ignore_rules();
+	let database = db::open_or_create_db(&db_dir.path("commit.redb")).unwrap();
+	let cache = Arc::new(FileCache::new_root(vfs.root().to_string_lossy().as_ref()));
+	cache.scan_dir_collect_with_ignore_and_commit(&database, vfs.root(), &ignore, None, 2, None);
+	// a.log, sub/b.log, big.bin and both node_modules directories
+	assert_eq!(cache.last_scan_ignored_count(), 5);
+	assert_eq!(ignore.pattern_statistics()[0], ("*.log".to_string(), 2));
+
+	let ignore = ignore_rules();
+	let database = db::open_or_create_db(&db_dir.path("stream.redb")).unwrap();
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	cache
+		.scan_dir_with_redb_streaming(&database, vfs.root(), &ignore, 2)
+		.unwrap();
+	assert_eq!(cache.last_scan_ignored_count(), 5);
+	assert_eq!(
+		ignore.pattern_statistics(),
+		vec![
+			("*.log".to_string(), 2),
+			("node_modules/".to_string(), 2),
+			(SIZE_RULE_KEY.to_string(), 1),
+		]
+	);
+}
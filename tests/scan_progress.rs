@@ -0,0 +1,77 @@
+//! Integration tests: scan progress reporting through `ScanConfig::on_progress`, and the
+//! metadata read timings passed to `ScanConfig::on_extension_timing`
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::{ExtensionTimings, FileCache, ProgressStyle, ScanConfig, ScanProgress};
+use linkfield::ignore_config::IgnoreConfig;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn test_progress_callback_fires_during_scan() {
+	let vfs = VirtualFs::new();
+	for dir in 0..10 {
+		for file in 0..500 {
+			vfs.create_file(&format!("d{dir}/f{file}.txt"), 1);
+		}
+	}
+	let updates: Arc<Mutex<Vec<ScanProgress>>> = Arc::default();
+	let sink = updates.clone();
+	let config = ScanConfig {
+		on_progress: Some(Arc::new(move |progress| {
+			sink.lock().unwrap().push(progress);
+		})),
+		..ScanConfig::default()
+	};
+	let cache = FileCache::new_root("root");
+	let errors = cache.scan_dir_with_config(vfs.root(), &IgnoreConfig::empty(), &config);
+	assert!(errors.is_empty());
+
+	let updates = updates.lock().unwrap();
+	// One update per 500 files plus the final one
+	assert_eq!(updates.len(), 11);
+	// Parallel directory scans may deliver the interval updates out of order
+	let mut counts: Vec<_> = updates[..10].iter().map(|p| p.files_scanned).collect();
+	counts.sort_unstable();
+	assert_eq!(counts, (1..=10).map(|n| n * 500).collect::<Vec<_>>());
+	let last = updates.last().unwrap();
+	assert_eq!(last.files_scanned, 5000);
+	// The root and its ten subdirectories
+	assert_eq!(last.dirs_scanned, 11);
+	assert_eq!(cache.len(), 5000);
+}
+
+#[test]
+fn test_extension_timings_cover_every_scanned_file() {
+	let vfs = VirtualFs::new();
+	for name in ["a.rs", "b.rs", "sub/c.rs", "mail/d.eml", "README"] {
+		vfs.create_file(name, 1);
+	}
+	let timings: Arc<Mutex<Option<ExtensionTimings>>> = Arc::default();
+	let sink = timings.clone();
+	let config = ScanConfig {
+		progress_style: ProgressStyle::None,
+		on_extension_timing: Some(Arc::new(move |timings: &ExtensionTimings| {
+			*sink.lock().unwrap() = Some(timings.clone());
+		})),
+		..ScanConfig::default()
+	};
+	let cache = FileCache::new_root("root");
+	cache.scan_dir_with_config(vfs.root(), &IgnoreConfig::empty(), &config);
+
+	let timings = timings.lock().unwrap().take().expect("callback not called");
+	let mut counts: Vec<_> = timings
+		.iter()
+		.map(|(extension, timing)| (extension.clone(), timing.count))
+		.collect();
+	counts.sort();
+	assert_eq!(
+		counts,
+		[
+			(None, 1),
+			(Some("eml".to_string()), 1),
+			(Some("rs".to_string()), 3)
+		]
+	);
+}
@@ -0,0 +1,49 @@
+//! Integration tests: unreadable directories are reported and retried
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::{FileCache, ScanConfig};
+use linkfield::ignore_config::IgnoreConfig;
+use std::time::Duration;
+
+#[test]
+fn test_retry_succeeds_once_directory_is_readable() {
+	let vfs = VirtualFs::new();
+	let late = vfs.path("late");
+	let cache = FileCache::new_root("root");
+	let ignore = IgnoreConfig::empty();
+
+	let errors = cache.scan_dir_collect_with_ignore(&late, &ignore, None);
+	assert_eq!(errors.len(), 1);
+	assert_eq!(errors[0].path, late);
+	assert!(cache.all_files().is_empty());
+
+	// The directory shows up while the retry is waiting
+	vfs.create_file("late/found.txt", 3);
+	let config = ScanConfig {
+		max_error_retries: 3,
+		retry_base_delay: Duration::from_millis(10),
+		..ScanConfig::default()
+	};
+	let remaining = cache.retry_scan_errors(None, &ignore, errors, &config);
+	assert!(remaining.is_empty());
+	let files: Vec<_> = cache.all_files().into_iter().map(|m| m.path.0).collect();
+	assert_eq!(files, vec![vfs.path("late/found.txt")]);
+}
+
+#[test]
+fn test_retry_gives_up_after_max_attempts() {
+	let vfs = VirtualFs::new();
+	let cache = FileCache::new_root("root");
+	let ignore = IgnoreConfig::empty();
+	let errors = cache.scan_dir_collect_with_ignore(&vfs.path("never"), &ignore, None);
+	let config = ScanConfig {
+		max_error_retries: 2,
+		retry_base_delay: Duration::from_millis(1),
+		..ScanConfig::default()
+	};
+	let remaining = cache.retry_scan_errors(None, &ignore, errors, &config);
+	assert_eq!(remaining.len(), 1);
+	assert_eq!(remaining[0].path, vfs.path("never"));
+}
@@ -0,0 +1,90 @@
+//! Integration tests: streaming scans that diff against the database batch by batch
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::db;
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::IgnoreConfig;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+fn stored_paths(db: &redb::Database) -> HashSet<PathBuf> {
+	let mut paths = HashSet::new();
+	FileCache::stream_from_redb(db, |path, _| {
+		paths.insert(path.0);
+	})
+	.unwrap();
+	paths
+}
+
+#[test]
+fn test_streaming_scan_adds_updates_and_removes() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"CREATE keep.txt 1\nCREATE grow.txt 1\nCREATE gone.txt 1\nCREATE a/b/deep.txt 1\nCREATE skip.log 1",
+	)
+	.unwrap();
+	let db_dir = VirtualFs::new();
+	let database = db::open_or_create_db(&db_dir.path("stream.redb")).unwrap();
+	let ignore = IgnoreConfig::new(&["*.log"]).unwrap();
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+
+	let first = cache
+		.scan_dir_with_redb_streaming(&database, vfs.root(), &ignore, 2)
+		.unwrap();
+	assert_eq!((first.added, first.modified, first.removed), (4, 0, 0));
+	assert_eq!(cache.last_scan_ignored_count(), 1);
+	// Nothing is kept in the in-memory tree
+	assert!(cache.all_files().is_empty());
+
+	vfs.replay_script("CREATE grow.txt 10\nDELETE gone.txt\nCREATE a/new.txt 1")
+		.unwrap();
+	let second = cache
+		.scan_dir_with_redb_streaming(&database, vfs.root(), &ignore, 2)
+		.unwrap();
+	assert_eq!(
+		(
+			second.added,
+			second.modified,
+			second.unchanged,
+			second.removed
+		),
+		(1, 1, 2, 1)
+	);
+	let expected: HashSet<_> = ["keep.txt", "grow.txt", "a/b/deep.txt", "a/new.txt"]
+		.iter()
+		.map(|name| vfs.path(name))
+		.collect();
+	assert_eq!(stored_paths(&database), expected);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_streaming_scan_keeps_files_under_unreadable_directories() {
+	use std::os::unix::fs::PermissionsExt;
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE top.txt 1\nCREATE locked/inner.txt 1")
+		.unwrap();
+	let db_dir = VirtualFs::new();
+	let database = db::open_or_create_db(&db_dir.path("stream.redb")).unwrap();
+	let cache = FileCache::new_root("root");
+	cache
+		.scan_dir_with_redb_streaming(&database, vfs.root(), &IgnoreConfig::empty(), 10)
+		.unwrap();
+
+	let locked = vfs.path("locked");
+	std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+	let unreadable = std::fs::read_dir(&locked).is_err();
+	let summary = cache
+		.scan_dir_with_redb_streaming(&database, vfs.root(), &IgnoreConfig::empty(), 10)
+		.unwrap();
+	std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+	// Running as root can read the directory anyway
+	if unreadable {
+		assert_eq!(summary.errors.len(), 1);
+		assert_eq!(summary.removed, 0);
+	}
+	assert!(stored_paths(&database).contains(&vfs.path("locked/inner.txt")));
+}
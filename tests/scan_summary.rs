@@ -0,0 +1,70 @@
+//! Integration tests: scan summary output formats
+
+mod common;
+
+use common::{VirtualFs, run_watch_until_ready};
+use linkfield::file_cache::summary::{OutputFormat, ScanSummary};
+use std::time::Duration;
+
+fn sample_summary() -> ScanSummary {
+	let extensions = [
+		("rs", 12),
+		("txt", 40),
+		("md", 3),
+		("toml", 2),
+		("json", 7),
+		("lock", 1),
+	];
+	ScanSummary::new(
+		65,
+		9,
+		123_456,
+		Duration::from_millis(1500),
+		extensions.map(|(ext, count)| (ext.to_string(), count)),
+	)
+}
+
+#[test]
+fn test_human_readable_lists_top_five_extensions() {
+	let summary = sample_summary();
+	let extensions: Vec<_> = summary
+		.top_extensions
+		.iter()
+		.map(|e| e.extension.as_str())
+		.collect();
+	assert_eq!(extensions, vec!["txt", "rs", "json", "md", "toml"]);
+	let text = summary.render(OutputFormat::HumanReadable).unwrap();
+	assert!(text.contains("65 files"));
+	assert!(text.contains("1.50s"));
+	assert!(text.contains("txt (40)"));
+	assert_eq!(summary.render(OutputFormat::None), None);
+}
+
+#[test]
+fn test_csv_summary_parses() {
+	let text = sample_summary().render(OutputFormat::Csv).unwrap();
+	let mut reader = csv::Reader::from_reader(text.as_bytes());
+	let headers = reader.headers().unwrap().clone();
+	assert_eq!(headers.len(), 5);
+	let rows: Vec<_> = reader.records().map(Result::unwrap).collect();
+	assert_eq!(rows.len(), 1);
+	assert_eq!(&rows[0][0], "65");
+	assert_eq!(&rows[0][2], "123456");
+	assert!(rows[0][4].starts_with("txt:40;rs:12"));
+}
+
+#[test]
+fn test_json_summary_from_watch_run() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("a.txt", 3);
+	vfs.create_file("sub/b.txt", 4);
+	let stdout = run_watch_until_ready(&vfs, &["--output-format", "json"]);
+	let line = stdout
+		.lines()
+		.find(|line| line.starts_with('{'))
+		.expect("no JSON summary on stdout");
+	let summary: serde_json::Value = serde_json::from_str(line).unwrap();
+	assert!(summary["files_scanned"].as_u64().unwrap() >= 2);
+	assert_eq!(summary["dirs_scanned"], 2);
+	assert_eq!(summary["top_extensions"][0]["extension"], "txt");
+}
@@ -0,0 +1,42 @@
+//! Integration test: symlink cycles don't make the scan recurse forever
+#![cfg(unix)]
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::IgnoreConfig;
+use std::os::unix::fs::symlink;
+
+#[test]
+fn test_scan_terminates_on_symlink_cycle() {
+	let vfs = VirtualFs::new();
+	vfs.create_file("a/file.txt", 1);
+	vfs.create_file("a/b/inner.txt", 1);
+	// a/b/loop -> a, and a link from the root back to itself
+	symlink(vfs.path("a"), vfs.path("a/b/loop")).unwrap();
+	symlink(vfs.root(), vfs.path("self")).unwrap();
+
+	let cache = FileCache::new_root("root");
+	let errors = cache.scan_dir_collect_with_ignore(vfs.root(), &IgnoreConfig::empty(), None);
+	assert!(errors.is_empty());
+	let mut paths: Vec<_> = cache.all_paths().map(|p| p.0).collect();
+	paths.sort();
+	assert_eq!(
+		paths,
+		vec![vfs.path("a/b/inner.txt"), vfs.path("a/file.txt")]
+	);
+
+	let dir = VirtualFs::new();
+	let database = linkfield::db::open_or_create_db(&dir.path("s.redb")).unwrap();
+	let cache = FileCache::new_root("root");
+	let errors = cache.scan_dir_collect_with_ignore_and_commit(
+		&database,
+		vfs.root(),
+		&IgnoreConfig::empty(),
+		None,
+		100,
+		None,
+	);
+	assert!(errors.is_empty());
+}
@@ -43,7 +43,7 @@ pub fn scan_dir_sequential_streaming(
 			batch.push((meta.path.clone(), meta.clone()));
 			batch_keys.push(key);
 			if batch.len() >= batch_size {
-				update_redb_batch_commit(db, &[], &batch);
+				update_redb_batch_commit(db, &[], &batch).unwrap();
 				for key in &batch_keys {
 					cache.entries.remove(key);
 				}
@@ -57,7 +57,7 @@ pub fn scan_dir_sequential_streaming(
 		}
 	}
 	if !batch.is_empty() {
-		update_redb_batch_commit(db, &[], &batch);
+		update_redb_batch_commit(db, &[], &batch).unwrap();
 		for key in &batch_keys {
 			cache.entries.remove(key);
 		}
@@ -0,0 +1,140 @@
+//! Golden bytes of the stored `FileMeta` encoding. Existing databases hold values in this
+//! layout, so a change here must come with a `FileMeta::FORMAT_VERSION` bump, a migration
+//! re-encoding the stored values and new golden bytes.
+
+use linkfield::file_cache::FileMeta;
+use linkfield::file_cache::meta::{FileCachePath, FileType};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// The `FileMeta::serialize` output, format version first, for:
+///
+/// ```text
+/// FileMeta {
+///     path: FileCachePath("photos/2024/beach.jpg"),
+///     size: 1_234_567,
+///     modified: Some(UNIX_EPOCH + 1_700_000_000s + 123_456_789ns),
+///     created: Some(UNIX_EPOCH + 1_600_000_000s),
+///     extension: Some("jpg"),
+///     inode: Some(987_654),
+///     content_hash: Some(0x0123_4567_89ab_cdef),
+///     is_virtual: false,
+///     file_type: FileType::Regular,
+///     device: Some(2049),
+/// }
+/// ```
+const GOLDEN_FULL: &str = concat!(
+	"06",                                           // format version
+	"1570686f746f732f323032342f62656163682e6a7067", // path, length-prefixed
+	"fc87d61200",                                   // size
+	"01fc00f15365fc15cd5b07",                       // modified: seconds, nanoseconds
+	"01fc00105e5f00",                               // created
+	"01036a7067",                                   // extension
+	"01fc06120f00",                                 // inode
+	"01fdefcdab8967452301",                         // content_hash
+	"00",                                           // is_virtual
+	"00",                                           // file_type
+	"01fb0108",                                     // device
+);
+
+/// The `FileMeta::serialize` output, format version first, for:
+///
+/// ```text
+/// FileMeta {
+///     path: FileCachePath("bundle.zip/fifo"),
+///     size: 0,
+///     modified: None,
+///     created: None,
+///     extension: None,
+///     inode: None,
+///     content_hash: None,
+///     is_virtual: true,
+///     file_type: FileType::Pipe,
+///     device: None,
+/// }
+/// ```
+const GOLDEN_MINIMAL: &str = concat!(
+	"06",                               // format version
+	"0f62756e646c652e7a69702f6669666f", // path
+	"00",                               // size
+	"000000",                           // modified, created, extension
+	"0000",                             // inode, content_hash
+	"01",                               // is_virtual
+	"05",                               // file_type
+	"00",                               // device
+);
+
+fn full() -> FileMeta {
+	FileMeta {
+		path: FileCachePath(PathBuf::from("photos/2024/beach.jpg")),
+		size: 1_234_567,
+		modified: Some(SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789)),
+		created: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+		extension: Some("jpg".to_string()),
+		inode: Some(987_654),
+		content_hash: Some(0x0123_4567_89ab_cdef),
+		is_virtual: false,
+		file_type: FileType::Regular,
+		device: Some(2049),
+	}
+}
+
+fn minimal() -> FileMeta {
+	FileMeta {
+		path: FileCachePath(PathBuf::from("bundle.zip/fifo")),
+		size: 0,
+		modified: None,
+		created: None,
+		extension: None,
+		inode: None,
+		content_hash: None,
+		is_virtual: true,
+		file_type: FileType::Pipe,
+		device: None,
+	}
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+	(0..hex.len())
+		.step_by(2)
+		.map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+		.collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn assert_stable(golden: &str, expected: &FileMeta) {
+	let golden = from_hex(golden);
+	assert_eq!(
+		golden[0],
+		FileMeta::format_version(),
+		"FileMeta::FORMAT_VERSION changed; add new golden bytes for the new layout"
+	);
+	assert_eq!(&FileMeta::deserialize(&golden).unwrap(), expected);
+	assert_eq!(
+		to_hex(&expected.serialize().unwrap()),
+		to_hex(&golden),
+		"the FileMeta encoding changed without a FORMAT_VERSION bump"
+	);
+}
+
+#[test]
+fn test_full_file_meta_encoding_is_stable() {
+	assert_stable(GOLDEN_FULL, &full());
+}
+
+#[test]
+fn test_minimal_file_meta_encoding_is_stable() {
+	assert_stable(GOLDEN_MINIMAL, &minimal());
+}
+
+#[test]
+fn test_other_format_versions_are_rejected() {
+	let mut golden = from_hex(GOLDEN_FULL);
+	golden[0] = FileMeta::format_version() - 1;
+	let err = FileMeta::deserialize(&golden).unwrap_err();
+	assert!(err.to_string().contains("format version"));
+	assert!(FileMeta::deserialize(&[]).is_err());
+}
@@ -0,0 +1,79 @@
+//! Integration tests: bounded shutdown of the scan and watcher threads
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::{FileCache, ensure_file_cache_table};
+use linkfield::ignore_config::IgnoreConfig;
+use linkfield::move_heuristics::MoveHeuristics;
+use linkfield::shutdown::{ShutdownResult, shutdown};
+use linkfield::watcher::start_watcher;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+fn watcher_for(vfs: &VirtualFs, cache: &Arc<FileCache>) -> linkfield::watcher::WatcherHandle {
+	start_watcher(
+		vfs.root(),
+		Arc::new(Mutex::new(cache.clone())),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::empty())),
+	)
+}
+
+#[test]
+fn test_shutdown_cancels_long_running_scan() {
+	let vfs = VirtualFs::new();
+	for dir in 0..20 {
+		for file in 0..100 {
+			vfs.create_file(&format!("d{dir}/f{file}.bin"), 1);
+		}
+	}
+	let db_dir = VirtualFs::new();
+	let db = redb::Database::create(db_dir.path("shutdown.redb")).unwrap();
+	ensure_file_cache_table(&db).unwrap();
+	let cache = FileCache::new_root(vfs.root().to_string_lossy().as_ref());
+	let cancel = cache.scan_cancellation_token();
+	let watcher = watcher_for(&vfs, &cache);
+
+	let scan_cache = cache.clone();
+	let root = vfs.root().to_path_buf();
+	let scan = std::thread::spawn(move || {
+		// One commit per file keeps the scan busy well past the shutdown request
+		scan_cache.scan_dir_collect_with_ignore_and_commit(
+			&db,
+			&root,
+			&IgnoreConfig::empty(),
+			None,
+			1,
+			None,
+		);
+		db
+	});
+	std::thread::sleep(Duration::from_millis(50));
+
+	let timeout = Duration::from_secs(10);
+	let started = Instant::now();
+	let (result, db) = shutdown(watcher, scan, &cancel, timeout);
+	assert_eq!(result, ShutdownResult::Clean);
+	assert!(started.elapsed() < timeout);
+	assert!(db.is_some(), "scan thread result should be returned");
+}
+
+#[test]
+fn test_shutdown_reports_threads_still_running() {
+	let vfs = VirtualFs::new();
+	let cache = FileCache::new_root("root");
+	let cancel = cache.scan_cancellation_token();
+	let watcher = watcher_for(&vfs, &cache);
+	// A worker that ignores cancellation
+	let stuck = std::thread::spawn(|| std::thread::sleep(Duration::from_secs(2)));
+
+	let (result, value) = shutdown(watcher, stuck, &cancel, Duration::from_millis(200));
+	assert_eq!(
+		result,
+		ShutdownResult::TimedOut {
+			threads_still_running: 1
+		}
+	);
+	assert!(value.is_none());
+}
@@ -0,0 +1,93 @@
+//! Stress test: many threads creating, renaming and deleting files while the watcher runs.
+//! Run with `cargo test --test stress_watcher -- --nocapture` to see the timings.
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::IgnoreConfig;
+use linkfield::move_heuristics::MoveHeuristics;
+use linkfield::watcher::start_watcher;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 10;
+const FILES_PER_THREAD: usize = 100;
+
+fn files_on_disk(vfs: &VirtualFs) -> BTreeSet<PathBuf> {
+	std::fs::read_dir(vfs.root())
+		.unwrap()
+		.map(|entry| entry.unwrap().path())
+		.filter(|path| path.is_file())
+		.collect()
+}
+
+fn cached_files(cache: &Mutex<Arc<FileCache>>) -> BTreeSet<PathBuf> {
+	cache
+		.lock()
+		.unwrap()
+		.all_paths()
+		.map(|path| path.0)
+		.collect()
+}
+
+#[test]
+fn test_cache_matches_disk_after_concurrent_churn() {
+	let vfs = VirtualFs::new();
+	let root = vfs.root().to_path_buf();
+	let cache = Arc::new(Mutex::new(FileCache::new_root(
+		root.to_string_lossy().as_ref(),
+	)));
+	let watcher = start_watcher(
+		&root,
+		cache.clone(),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::empty())),
+	);
+
+	let start = Instant::now();
+	let workers: Vec<_> = (0..THREADS)
+		.map(|thread| {
+			let root = root.clone();
+			std::thread::spawn(move || {
+				for i in 0..FILES_PER_THREAD {
+					let tmp = root.join(format!("t{thread}_{i}.tmp"));
+					let done = root.join(format!("t{thread}_{i}.txt"));
+					std::fs::write(&tmp, format!("{thread} {i}")).unwrap();
+					std::fs::rename(&tmp, &done).unwrap();
+					if i % 2 == 0 {
+						std::fs::remove_file(&done).unwrap();
+					}
+				}
+			})
+		})
+		.collect();
+	for worker in workers {
+		worker.join().expect("worker thread panicked");
+	}
+	println!("churn took {:.2?}", start.elapsed());
+
+	let expected = files_on_disk(&vfs);
+	assert_eq!(expected.len(), THREADS * FILES_PER_THREAD / 2);
+	let deadline = Instant::now() + Duration::from_secs(10);
+	while cached_files(&cache) != expected && Instant::now() < deadline {
+		std::thread::sleep(Duration::from_millis(100));
+	}
+	println!("cache settled after {:.2?}", start.elapsed());
+
+	watcher.stop();
+	watcher
+		.into_join_handle()
+		.join()
+		.expect("watcher thread panicked");
+	let cached = cached_files(&cache);
+	let missing: Vec<_> = expected.difference(&cached).collect();
+	let extra: Vec<_> = cached.difference(&expected).collect();
+	assert!(
+		missing.is_empty() && extra.is_empty(),
+		"missing from cache: {missing:?}, not on disk: {extra:?}"
+	);
+	assert_eq!(cache.lock().unwrap().len(), expected.len());
+}
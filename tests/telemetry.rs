@@ -0,0 +1,17 @@
+#![cfg(feature = "opentelemetry")]
+
+use tracing_subscriber::prelude::*;
+
+#[test]
+fn test_otlp_layer_initializes_subscriber() {
+	let (layer, guard) =
+		linkfield::telemetry::otlp_layer("http://127.0.0.1:4318/v1/traces").unwrap();
+	let subscriber = tracing_subscriber::registry().with(layer);
+	tracing::subscriber::with_default(subscriber, || {
+		let span = tracing::info_span!("test_span");
+		let _enter = span.enter();
+		tracing::info!("inside span");
+	});
+	// Nothing listens on the endpoint, so flushing fails but must not panic
+	guard.shutdown();
+}
@@ -0,0 +1,124 @@
+//! Integration tests: rejecting unusable paths and flags before a watch run starts
+
+mod common;
+
+use assert_cmd::Command;
+use clap::Parser;
+use common::VirtualFs;
+use linkfield::args::{Cli, ParsedArgs, ValidationError, validate_args};
+
+fn parse(args: &[&str]) -> ParsedArgs {
+	ParsedArgs::from(&Cli::try_parse_from(args).unwrap())
+}
+
+#[test]
+fn test_valid_args_pass() {
+	let vfs = VirtualFs::new();
+	let root = vfs.root().to_str().unwrap();
+	assert_eq!(validate_args(&parse(&["linkfield", root])), Ok(()));
+	assert_eq!(validate_args(&parse(&["linkfield", "watch", root])), Ok(()));
+	assert_eq!(
+		validate_args(&parse(&["linkfield", root, "--no-scan"])),
+		Ok(())
+	);
+}
+
+#[test]
+fn test_missing_watch_root() {
+	let vfs = VirtualFs::new();
+	let missing = vfs.path("missing");
+	let args = parse(&["linkfield", missing.to_str().unwrap()]);
+	assert_eq!(
+		validate_args(&args),
+		Err(vec![ValidationError::WatchRootDoesNotExist {
+			path: missing
+		}])
+	);
+}
+
+#[test]
+fn test_misplaced_paths() {
+	let vfs = VirtualFs::new();
+	let file = vfs.create_file("notes.txt", 3);
+	std::fs::create_dir(vfs.path("db.redb")).unwrap();
+	let mut args = parse(&["linkfield", vfs.root().to_str().unwrap()]);
+	args.watch_root = file;
+	args.db_path = vfs.path("db.redb");
+	assert_eq!(
+		validate_args(&args),
+		Err(vec![
+			ValidationError::WatchRootIsFile,
+			ValidationError::DbPathIsDirectory
+		])
+	);
+
+	args.watch_root = vfs.root().to_path_buf();
+	args.db_path = vfs.path("missing/db.redb");
+	assert_eq!(
+		validate_args(&args),
+		Err(vec![ValidationError::DbDirNotWritable {
+			path: vfs.path("missing")
+		}])
+	);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_read_only_db_dir() {
+	use std::os::unix::fs::PermissionsExt;
+	let vfs = VirtualFs::new();
+	let locked = vfs.path("locked");
+	std::fs::create_dir(&locked).unwrap();
+	std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o555)).unwrap();
+	let mut args = parse(&["linkfield", vfs.root().to_str().unwrap()]);
+	args.db_path = locked.join("linkfield.redb");
+	let result = validate_args(&args);
+	std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+	assert_eq!(
+		result,
+		Err(vec![ValidationError::DbDirNotWritable { path: locked }])
+	);
+}
+
+#[test]
+fn test_incompatible_flags_are_reported_with_the_rest() {
+	let vfs = VirtualFs::new();
+	let missing = vfs.path("missing");
+	let args = parse(&[
+		"linkfield",
+		missing.to_str().unwrap(),
+		"--no-scan",
+		"--staleness-threshold",
+		"0.5",
+	]);
+	let errors = validate_args(&args).unwrap_err();
+	assert_eq!(
+		errors,
+		[
+			ValidationError::WatchRootDoesNotExist { path: missing },
+			ValidationError::IncompatibleFlags {
+				a: "--no-scan".to_string(),
+				b: "--staleness-threshold".to_string(),
+			}
+		]
+	);
+	assert_eq!(
+		errors[1].to_string(),
+		"--no-scan can't be used with --staleness-threshold"
+	);
+}
+
+#[test]
+fn test_watch_exits_listing_every_problem() {
+	let vfs = VirtualFs::new();
+	let output = Command::cargo_bin("linkfield")
+		.unwrap()
+		.arg(vfs.path("missing"))
+		.args(["--no-scan", "--staleness-threshold", "0.5"])
+		.output()
+		.unwrap();
+	assert_eq!(output.status.code(), Some(2), "{output:?}");
+	let stderr = String::from_utf8(output.stderr).unwrap();
+	assert!(stderr.contains("does not exist"), "{stderr}");
+	assert!(stderr.contains("--no-scan can't be used"), "{stderr}");
+}
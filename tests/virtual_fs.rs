@@ -0,0 +1,57 @@
+//! Integration test: scripted file system activity through `VirtualFs`
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileMeta;
+use linkfield::move_heuristics::{FileEventKind, MoveHeuristics, make_file_event};
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn test_replay_script() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script(
+		"# set up\n\
+		 CREATE a.txt 1024\n\
+		 CREATE bar.txt 10\n\
+		 CREATE nested/c.bin 3\n\
+		 DELETE bar.txt\n\
+		 RENAME a.txt b.txt\n",
+	)
+	.unwrap();
+	assert_eq!(
+		vfs.list_files(),
+		vec![vfs.path("b.txt"), vfs.path("nested/c.bin")]
+	);
+	assert_eq!(std::fs::metadata(vfs.path("b.txt")).unwrap().len(), 1024);
+	assert!(vfs.replay_script("FROB x").is_err());
+}
+
+#[test]
+fn test_scripted_move_is_detected() {
+	let vfs = VirtualFs::new();
+	vfs.replay_script("CREATE docs/report.pdf 4096").unwrap();
+	let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+	vfs.set_mtime("docs/report.pdf", mtime);
+	let before = FileMeta::from_path(&vfs.path("docs/report.pdf")).unwrap();
+	assert_eq!(before.modified, Some(mtime));
+
+	vfs.replay_script("RENAME docs/report.pdf archive/report.pdf")
+		.unwrap();
+	let after = FileMeta::from_path(&vfs.path("archive/report.pdf")).unwrap();
+
+	let mut heuristics = MoveHeuristics::new(Duration::from_secs(5));
+	heuristics.add_remove(make_file_event(
+		vfs.path("docs/report.pdf"),
+		FileEventKind::Remove,
+		Some(before),
+	));
+	let pair = heuristics
+		.pair_create(&make_file_event(
+			vfs.path("archive/report.pdf"),
+			FileEventKind::Create,
+			Some(after),
+		))
+		.expect("move should be detected");
+	assert_eq!(pair.from.path, vfs.path("docs/report.pdf"));
+}
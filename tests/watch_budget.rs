@@ -0,0 +1,40 @@
+//! Integration test: watching within an inotify watch budget. Kept in its own binary so no
+//! other watcher in the process holds inotify watches.
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::FileCache;
+use linkfield::ignore_config::IgnoreConfig;
+use linkfield::move_heuristics::MoveHeuristics;
+use linkfield::watcher::{WatchConfig, start_watcher_with_config};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+#[test]
+fn test_watch_budget_prefers_shallow_directories() {
+	let vfs = VirtualFs::new();
+	for dir in ["a/deep", "b", "skip"] {
+		std::fs::create_dir_all(vfs.path(dir)).unwrap();
+	}
+	let config = WatchConfig {
+		max_inotify_watches: Some(3),
+		..WatchConfig::default()
+	};
+	let watcher = start_watcher_with_config(
+		vfs.root(),
+		Arc::new(Mutex::new(FileCache::new_root("root"))),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::new(&["skip/"]).unwrap())),
+		config,
+	);
+
+	let expected: HashSet<_> = [vfs.root().to_path_buf(), vfs.path("a"), vfs.path("b")]
+		.into_iter()
+		.collect();
+	assert_eq!(watcher.watched_paths(), expected);
+	assert!(watcher.failed_paths().is_empty());
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+}
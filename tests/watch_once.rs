@@ -0,0 +1,70 @@
+//! Integration tests: `linkfield watch --once` scans, reports and exits
+
+mod common;
+
+use assert_cmd::cargo::CommandCargoExt;
+use common::VirtualFs;
+use std::process::Command;
+
+fn sample_dir() -> VirtualFs {
+	let vfs = VirtualFs::new();
+	for name in ["a.txt", "b.txt", "sub/c.rs"] {
+		vfs.create_file(name, 10);
+	}
+	vfs
+}
+
+#[test]
+fn test_watch_once_prints_json_summary() {
+	let vfs = sample_dir();
+	let output = Command::cargo_bin("linkfield")
+		.unwrap()
+		.args(["watch", "--once", "--output-format", "json"])
+		.arg(vfs.root())
+		.output()
+		.unwrap();
+	assert_eq!(output.status.code(), Some(0));
+	let stdout = String::from_utf8(output.stdout).unwrap();
+	let summary: serde_json::Value = stdout
+		.lines()
+		.find_map(|line| serde_json::from_str(line).ok())
+		.expect("no JSON summary on stdout");
+	assert_eq!(summary["files_scanned"], 3);
+	assert_eq!(summary["total_bytes"], 30);
+	// Without --persist no database is created
+	assert!(!vfs.path("linkfield.redb").exists());
+}
+
+#[test]
+fn test_watch_once_writes_output_file_and_persists() {
+	let vfs = sample_dir();
+	let out_dir = VirtualFs::new();
+	let out_file = out_dir.path("summary.json");
+	let status = Command::cargo_bin("linkfield")
+		.unwrap()
+		.args([
+			"watch",
+			"--once",
+			"--persist",
+			"--output-format",
+			"json",
+			"--output",
+		])
+		.arg(&out_file)
+		.arg(vfs.root())
+		.status()
+		.unwrap();
+	assert_eq!(status.code(), Some(0));
+	let summary: serde_json::Value =
+		serde_json::from_str(&std::fs::read_to_string(&out_file).unwrap()).unwrap();
+	assert_eq!(summary["files_scanned"], 3);
+
+	let exported = Command::cargo_bin("linkfield")
+		.unwrap()
+		.arg("export")
+		.arg(vfs.root())
+		.output()
+		.unwrap();
+	let paths = String::from_utf8(exported.stdout).unwrap();
+	assert_eq!(paths.lines().count(), 3);
+}
@@ -0,0 +1,110 @@
+//! Integration tests: following symlinked directories while scanning and watching
+#![cfg(unix)]
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::file_cache::{FileCache, ScanConfig};
+use linkfield::ignore_config::IgnoreConfig;
+use linkfield::move_heuristics::MoveHeuristics;
+use linkfield::watcher::{WatchConfig, WatchEvent, WatcherHandle, start_watcher_with_config};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+fn start(root: &Path, follow_symlinks: bool) -> WatcherHandle {
+	start_watcher_with_config(
+		root,
+		Arc::new(Mutex::new(FileCache::new_root("root"))),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::empty())),
+		WatchConfig {
+			follow_symlinks,
+			..WatchConfig::default()
+		},
+	)
+}
+
+/// Create `name` in `target` and return the path of the first create event seen within
+/// `wait`
+fn create_after_writing(
+	watcher: &WatcherHandle,
+	target: &VirtualFs,
+	name: &str,
+	wait: Duration,
+) -> Option<PathBuf> {
+	let events = watcher.subscribe();
+	target.create_file(name, 1);
+	let deadline = Instant::now() + wait;
+	while let Ok(event) = events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+		if let WatchEvent::Create(path) = &*event {
+			return Some(path.clone());
+		}
+	}
+	None
+}
+
+#[test]
+fn test_scan_skips_symlinks_unless_followed() {
+	let vfs = VirtualFs::new();
+	let target = VirtualFs::new();
+	vfs.create_file("own.txt", 1);
+	target.create_file("sub/linked.txt", 1);
+	symlink(target.root(), vfs.path("link")).unwrap();
+	symlink(vfs.path("own.txt"), vfs.path("alias.txt")).unwrap();
+
+	let paths = |config: &ScanConfig| {
+		let cache =
+			FileCache::populate_from_dir_with_config(vfs.root(), &IgnoreConfig::empty(), config);
+		let mut paths: Vec<_> = cache.all_paths().map(|path| path.0).collect();
+		paths.sort();
+		paths
+	};
+	assert_eq!(paths(&ScanConfig::default()), [vfs.path("own.txt")]);
+	let follow = ScanConfig {
+		follow_symlinks: true,
+		..ScanConfig::default()
+	};
+	assert_eq!(
+		paths(&follow),
+		[
+			vfs.path("alias.txt"),
+			vfs.path("link/sub/linked.txt"),
+			vfs.path("own.txt")
+		]
+	);
+}
+
+#[test]
+fn test_symlinked_directory_is_watched_only_when_followed() {
+	let vfs = VirtualFs::new();
+	let target = VirtualFs::new();
+	std::fs::create_dir_all(target.path("sub")).unwrap();
+	symlink(target.root(), vfs.path("link")).unwrap();
+
+	let watcher = start(vfs.root(), false);
+	let create = create_after_writing(&watcher, &target, "sub/a.txt", Duration::from_secs(2));
+	assert_eq!(create, None);
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+
+	let watcher = start(vfs.root(), true);
+	let create = create_after_writing(&watcher, &target, "sub/b.txt", Duration::from_secs(5));
+	assert_eq!(create, Some(vfs.path("link/sub/b.txt")));
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+}
+
+#[test]
+fn test_symlinked_root_events_use_the_given_path() {
+	let links = VirtualFs::new();
+	let target = VirtualFs::new();
+	symlink(target.root(), links.path("root")).unwrap();
+
+	let watcher = start(&links.path("root"), true);
+	let create = create_after_writing(&watcher, &target, "c.txt", Duration::from_secs(5));
+	assert_eq!(create, Some(links.path("root/c.txt")));
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+}
@@ -0,0 +1,344 @@
+//! Integration tests: watcher handle reporting
+
+mod common;
+
+use common::VirtualFs;
+use linkfield::events::{EventBroadcaster, SUBSCRIBER_CAPACITY};
+use linkfield::file_cache::FileCache;
+use linkfield::hooks::{EventHooks, HOOK_WORKERS};
+use linkfield::ignore_config::IgnoreConfig;
+use linkfield::move_heuristics::MoveHeuristics;
+use linkfield::watcher::{WatchConfig, WatchEvent, start_watcher, start_watcher_with_config};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+fn start(path: &std::path::Path) -> linkfield::watcher::WatcherHandle {
+	start_watcher(
+		path,
+		Arc::new(Mutex::new(FileCache::new_root("root"))),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::empty())),
+	)
+}
+
+#[test]
+fn test_watched_paths_contains_watch_root() {
+	let vfs = VirtualFs::new();
+	let watcher = start(vfs.root());
+	assert!(watcher.watched_paths().contains(vfs.root()));
+	assert!(watcher.failed_paths().is_empty());
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+}
+
+#[test]
+fn test_cache_age_resets_on_events() {
+	let vfs = VirtualFs::new();
+	let watcher = start(vfs.root());
+	std::thread::sleep(Duration::from_millis(300));
+	let idle = watcher.cache_age();
+	assert!(idle >= Duration::from_millis(300), "{idle:?}");
+	vfs.create_file("a.txt", 1);
+	let deadline = Instant::now() + Duration::from_secs(10);
+	while watcher.cache_age() >= idle {
+		assert!(Instant::now() < deadline, "the cache never changed");
+		std::thread::sleep(Duration::from_millis(50));
+	}
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+}
+
+#[test]
+fn test_failed_paths_records_missing_directory() {
+	let vfs = VirtualFs::new();
+	let missing = vfs.path("missing");
+	let watcher = start(&missing);
+	assert!(watcher.watched_paths().is_empty());
+	assert!(watcher.failed_paths().contains(&missing));
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Seen {
+	Create(PathBuf),
+	Remove(PathBuf),
+	Rename(PathBuf, PathBuf),
+	Move(PathBuf, PathBuf, f64),
+}
+
+fn wait_for(seen: &Mutex<Vec<Seen>>, expected: &Seen) {
+	let deadline = Instant::now() + Duration::from_secs(10);
+	while !seen.lock().unwrap().contains(expected) {
+		assert!(
+			Instant::now() < deadline,
+			"never saw {expected:?}, got {:?}",
+			seen.lock().unwrap()
+		);
+		std::thread::sleep(Duration::from_millis(50));
+	}
+}
+
+#[test]
+fn test_callbacks_fire_with_paths() {
+	let vfs = VirtualFs::new();
+	std::fs::create_dir(vfs.path("other")).unwrap();
+	let seen = Arc::new(Mutex::new(Vec::new()));
+	let all_events = Arc::new(Mutex::new(Vec::new()));
+	let (s1, s2, s3, s4, all) = (
+		seen.clone(),
+		seen.clone(),
+		seen.clone(),
+		seen.clone(),
+		all_events.clone(),
+	);
+	let config = WatchConfig {
+		on_create: Some(Arc::new(move |p: &Path| {
+			s1.lock().unwrap().push(Seen::Create(p.to_path_buf()));
+		})),
+		on_remove: Some(Arc::new(move |p: &Path| {
+			s2.lock().unwrap().push(Seen::Remove(p.to_path_buf()));
+		})),
+		on_rename: Some(Arc::new(move |from: &Path, to: &Path| {
+			s3.lock()
+				.unwrap()
+				.push(Seen::Rename(from.to_path_buf(), to.to_path_buf()));
+		})),
+		on_move: Some(Arc::new(move |from: &Path, to: &Path, score| {
+			s4.lock()
+				.unwrap()
+				.push(Seen::Move(from.to_path_buf(), to.to_path_buf(), score));
+		})),
+		on_event: Some(Arc::new(move |event: &WatchEvent| {
+			all.lock().unwrap().push(event.clone());
+		})),
+		..WatchConfig::default()
+	};
+	let watcher = start_watcher_with_config(
+		vfs.root(),
+		Arc::new(Mutex::new(FileCache::new_root("root"))),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::empty())),
+		config,
+	);
+
+	vfs.create_file("a.txt", 3);
+	wait_for(&seen, &Seen::Create(vfs.path("a.txt")));
+	vfs.rename_file("a.txt", "b.txt");
+	wait_for(&seen, &Seen::Rename(vfs.path("a.txt"), vfs.path("b.txt")));
+	vfs.rename_file("b.txt", "other/b.txt");
+	wait_for(
+		&seen,
+		&Seen::Move(vfs.path("b.txt"), vfs.path("other/b.txt"), 1.0),
+	);
+	vfs.delete_file("other/b.txt");
+	wait_for(&seen, &Seen::Remove(vfs.path("other/b.txt")));
+
+	assert!(
+		all_events
+			.lock()
+			.unwrap()
+			.contains(&WatchEvent::Remove(vfs.path("other/b.txt")))
+	);
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+}
+
+#[test]
+fn test_two_subscribers_receive_same_create() {
+	let vfs = VirtualFs::new();
+	let watcher = start(vfs.root());
+	let first = watcher.subscribe();
+	let second = first.clone();
+
+	vfs.create_file("shared.txt", 1);
+	let expected = WatchEvent::Create(vfs.path("shared.txt"));
+	for receiver in [&first, &second] {
+		let deadline = Instant::now() + Duration::from_secs(10);
+		loop {
+			let remaining = deadline.saturating_duration_since(Instant::now());
+			let event = receiver
+				.recv_timeout(remaining)
+				.expect("no create event received");
+			if *event == expected {
+				break;
+			}
+		}
+	}
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+	// The event loop has exited and dropped its sender
+	assert!(first.recv().is_none());
+}
+
+#[test]
+fn test_handlers_see_every_event_and_slow_subscribers_drop_events() {
+	let broadcaster = EventBroadcaster::new();
+	let receiver = broadcaster.subscribe();
+	let handled = Arc::new(Mutex::new(Vec::new()));
+	let handled_by = handled.clone();
+	broadcaster.add_handler(Arc::new(move |event: &WatchEvent| {
+		handled_by.lock().unwrap().push(event.clone());
+	}));
+
+	for i in 0..=SUBSCRIBER_CAPACITY {
+		broadcaster.publish(WatchEvent::Create(PathBuf::from(format!("{i}.txt"))));
+	}
+	assert_eq!(handled.lock().unwrap().len(), SUBSCRIBER_CAPACITY + 1);
+	let received = std::iter::from_fn(|| receiver.try_recv().ok()).count();
+	assert_eq!(received, SUBSCRIBER_CAPACITY);
+	// A subscriber that caught up receives again
+	broadcaster.publish(WatchEvent::Create(PathBuf::from("late.txt")));
+	assert_eq!(
+		*receiver.try_recv().unwrap(),
+		WatchEvent::Create(PathBuf::from("late.txt"))
+	);
+}
+
+/// Lines the hook script appended to `log`, waiting until there are `count` of them
+#[cfg(unix)]
+fn wait_for_hook_lines(log: &Path, count: usize) -> Vec<String> {
+	let deadline = Instant::now() + Duration::from_secs(10);
+	loop {
+		let lines: Vec<_> = std::fs::read_to_string(log)
+			.unwrap_or_default()
+			.lines()
+			.map(str::to_string)
+			.collect();
+		if lines.len() >= count || Instant::now() > deadline {
+			return lines;
+		}
+		std::thread::sleep(Duration::from_millis(50));
+	}
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hooks_run_with_quoted_paths() {
+	let vfs = VirtualFs::new();
+	std::fs::create_dir(vfs.path("other")).unwrap();
+	// The script and its log live outside the watched directory
+	let scripts = VirtualFs::new();
+	let script = scripts.path("hook.sh");
+	let log = scripts.path("hook.log");
+	std::fs::write(
+		&script,
+		format!(
+			"printf '%s|' \"$@\" >> '{}'\necho >> '{}'\n",
+			log.display(),
+			log.display()
+		),
+	)
+	.unwrap();
+	let hook = |event: &str, args: &str| Some(format!("sh '{}' {event} {args}", script.display()));
+	let config = WatchConfig {
+		hooks: EventHooks {
+			on_create: hook("create", "{path}"),
+			on_move: hook("move", "{from} {to}"),
+			on_remove: hook("remove", "{path}"),
+		},
+		..WatchConfig::default()
+	};
+	let watcher = start_watcher_with_config(
+		vfs.root(),
+		Arc::new(Mutex::new(FileCache::new_root("root"))),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::empty())),
+		config,
+	);
+
+	vfs.create_file("it's here.txt", 3);
+	assert_eq!(wait_for_hook_lines(&log, 1).len(), 1);
+	vfs.rename_file("it's here.txt", "other/moved.txt");
+	assert_eq!(wait_for_hook_lines(&log, 2).len(), 2);
+	vfs.delete_file("other/moved.txt");
+	let lines = wait_for_hook_lines(&log, 3);
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+
+	let created = vfs.path("it's here.txt");
+	let moved = vfs.path("other/moved.txt");
+	assert_eq!(
+		lines,
+		[
+			format!("create|{}|", created.display()),
+			format!("move|{}|{}|", created.display(), moved.display()),
+			format!("remove|{}|", moved.display()),
+		]
+	);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hooks_run_on_a_bounded_pool() {
+	let vfs = VirtualFs::new();
+	let scripts = VirtualFs::new();
+	let log = scripts.path("hook.log");
+	let config = WatchConfig {
+		hooks: EventHooks {
+			on_create: Some(format!(
+				"echo start >> '{0}'; sleep 0.3; echo end >> '{0}'",
+				log.display()
+			)),
+			..EventHooks::default()
+		},
+		..WatchConfig::default()
+	};
+	let watcher = start_watcher_with_config(
+		vfs.root(),
+		Arc::new(Mutex::new(FileCache::new_root("root"))),
+		Arc::new(Mutex::new(MoveHeuristics::new(Duration::from_secs(5)))),
+		Arc::new(RwLock::new(IgnoreConfig::empty())),
+		config,
+	);
+
+	let files = HOOK_WORKERS * 3;
+	for i in 0..files {
+		vfs.create_file(&format!("f{i}.txt"), 3);
+	}
+	let lines = wait_for_hook_lines(&log, files * 2);
+	watcher.stop();
+	watcher.into_join_handle().join().unwrap();
+
+	assert_eq!(lines.len(), files * 2);
+	let mut running = 0usize;
+	let mut most = 0;
+	for line in &lines {
+		if line == "start" {
+			running += 1;
+			most = most.max(running);
+		} else {
+			running -= 1;
+		}
+	}
+	assert!(most <= HOOK_WORKERS, "{most} hooks ran at once");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hook_paths_are_substituted_once() {
+	let scripts = VirtualFs::new();
+	let log = scripts.path("hook.log");
+	let pwned = scripts.path("pwned");
+	let hooks = EventHooks {
+		on_move: Some(format!(
+			"printf '%s|%s\\n' {{from}} {{to}} >> '{}'",
+			log.display()
+		)),
+		..EventHooks::default()
+	};
+	// The placeholder in `from` must not pick up `to`, which would close the quotes
+	let from = scripts.path("{to}");
+	let to = PathBuf::from(format!(
+		"{}/x;touch {}",
+		scripts.root().display(),
+		pwned.display()
+	));
+	let runner = hooks.runner(Duration::from_secs(5)).unwrap();
+	runner.run(&WatchEvent::Rename {
+		from: from.clone(),
+		to: to.clone(),
+	});
+	let lines = wait_for_hook_lines(&log, 1);
+	assert_eq!(lines, [format!("{}|{}", from.display(), to.display())]);
+	assert!(!pwned.exists());
+}